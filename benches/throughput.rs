@@ -0,0 +1,201 @@
+//! Ingest and query throughput benchmarks
+//!
+//! Run with `cargo bench`; criterion keeps the previous run's results under
+//! `target/criterion` and reports regressions against them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+use tokio::sync::RwLock;
+
+use vctsdb::datasets::DatasetBuilder;
+use vctsdb::ingestion::formats::{CsvParser, JsonParser};
+use vctsdb::ingestion::parser::Parser;
+use vctsdb::storage::lsm::{DataBlock, FlushManager, MemTable, Query, QueryRouter, SSTable};
+use vctsdb::storage::{DataPoint, SyncPolicy, TimeSeries, WriteAheadLog};
+
+/// Batch sizes, in points, for the ingestion benchmarks
+const BATCH_SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+/// Batch sizes, in points, for the WAL benchmarks; every per-point write syncs
+const WAL_BATCH_SIZES: [usize; 2] = [100, 1_000];
+
+/// Series counts for the cardinality-sensitive benchmarks
+const CARDINALITIES: [usize; 3] = [1, 100, 1_000];
+
+/// Points per series for the cardinality-sensitive benchmarks
+const POINTS_PER_SERIES: usize = 100;
+
+fn series_by_name(dataset: &DatasetBuilder) -> HashMap<String, TimeSeries> {
+    dataset.series().into_iter().map(|s| (s.name().to_string(), s)).collect()
+}
+
+fn parse_json(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_json");
+    let parser = JsonParser::new();
+    for size in BATCH_SIZES {
+        let inputs = DatasetBuilder::new(10, size / 10).to_json_objects();
+        let inputs: Vec<&[u8]> = inputs.iter().map(|input| input.as_slice()).collect();
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &inputs, |b, inputs| {
+            b.iter(|| parser.parse_batch(inputs).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn parse_csv(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_csv");
+    let parser = CsvParser::new();
+    for size in BATCH_SIZES {
+        let input = DatasetBuilder::new(10, size / 10).to_csv();
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &input, |b, input| {
+            b.iter(|| parser.parse(input).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn wal_write(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("wal_write");
+    for size in WAL_BATCH_SIZES {
+        let dataset = DatasetBuilder::new(10, size / 10);
+        let series = series_by_name(&dataset);
+        let points = dataset.points();
+        let batch: Vec<(&TimeSeries, &DataPoint)> =
+            points.iter().map(|point| (&series[&point.tags()["series"]], point)).collect();
+        let (batch, policy) = (&batch, SyncPolicy::Always);
+        let new_wal = move || {
+            let dir = tempdir().unwrap();
+            let wal = WriteAheadLog::new(dir.path()).unwrap().with_sync_policy(policy);
+            (dir, wal)
+        };
+        group.throughput(Throughput::Elements(size as u64));
+
+        // One durable write per point
+        group.bench_function(BenchmarkId::new("per_point", size), |b| {
+            b.to_async(&runtime).iter_batched(
+                new_wal,
+                |(dir, wal)| async move {
+                    for (series, point) in batch {
+                        wal.write(series, point).await.unwrap();
+                    }
+                    // Keep the directory alive until the writes are done
+                    drop(dir);
+                },
+                BatchSize::PerIteration,
+            )
+        });
+
+        // The same points as one durable group commit
+        group.bench_function(BenchmarkId::new("batch", size), |b| {
+            b.to_async(&runtime).iter_batched(
+                new_wal,
+                |(dir, wal)| async move {
+                    wal.write_batch(batch).await.unwrap();
+                    drop(dir);
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn memtable_insert(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("memtable_insert");
+    for cardinality in CARDINALITIES {
+        let dataset = DatasetBuilder::new(cardinality, POINTS_PER_SERIES);
+        let series = series_by_name(&dataset);
+        let points = dataset.points();
+        let (series, points) = (&series, &points);
+        group.throughput(Throughput::Elements(points.len() as u64));
+        group.bench_function(BenchmarkId::from_parameter(cardinality), |b| {
+            b.to_async(&runtime).iter_batched(
+                || MemTable::new(points.len()),
+                |memtable| async move {
+                    for point in points {
+                        memtable.insert(&series[&point.tags()["series"]], point).await.unwrap();
+                    }
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn range_query(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("range_query");
+    for cardinality in CARDINALITIES {
+        let dataset = DatasetBuilder::new(cardinality, POINTS_PER_SERIES);
+        let dir = tempdir().unwrap();
+
+        // Flush the dataset to an SSTable so the query exercises the block path
+        let router = runtime.block_on(async {
+            let series = series_by_name(&dataset);
+            let memtable = Arc::new(RwLock::new(MemTable::new(dataset.len())));
+            for point in dataset.points() {
+                let memtable = memtable.read().await;
+                memtable.insert(&series[&point.tags()["series"]], &point).await.unwrap();
+            }
+            let mut flush_manager = FlushManager::new(dir.path().to_path_buf());
+            flush_manager.start_flush(memtable.clone()).await.unwrap();
+            let sstable = flush_manager.wait_for_flush().await.unwrap().unwrap();
+            memtable.read().await.release_flushed(&sstable).await.unwrap();
+            QueryRouter::new(memtable, Arc::new(RwLock::new(vec![sstable])))
+        });
+
+        // Roughly the middle half of the time range of one series
+        let start = dataset.end_timestamp() / 4;
+        let end = dataset.end_timestamp() / 4 * 3;
+        let query = Query::with_series(start, end, dataset.series_name(0));
+        group.bench_function(BenchmarkId::from_parameter(cardinality), |b| {
+            b.to_async(&runtime).iter(|| router.route_query(&query))
+        });
+    }
+    group.finish();
+}
+
+/// Reads every block of one SSTable, decoding through the file or a memory map
+fn sstable_read(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("sstable_read");
+    let dataset = DatasetBuilder::new(100, POINTS_PER_SERIES);
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("bench.sst");
+    let block_count = runtime.block_on(async {
+        let sstable = SSTable::new(&path).unwrap();
+        let mut by_series: HashMap<String, Vec<DataPoint>> = HashMap::new();
+        for point in dataset.points() {
+            by_series.entry(point.tags()["series"].clone()).or_default().push(point);
+        }
+        for (series_name, points) in &by_series {
+            sstable.write_block(DataBlock::from_points(series_name, points)).await.unwrap();
+        }
+        by_series.len()
+    });
+
+    group.throughput(Throughput::Elements(dataset.len() as u64));
+    for (name, mmap_reads) in [("buffered", false), ("mmap", true)] {
+        let sstable = SSTable::open(&path).unwrap().with_mmap_reads(mmap_reads);
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                for block_index in 0..block_count {
+                    sstable.read_block(block_index).await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse_json, parse_csv, wal_write, memtable_insert, range_query, sstable_read);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+//! Registers a custom aggregate function and queries with it
+//!
+//! Run with `cargo run --example custom_aggregate`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+
+use vctsdb::query::parser::{ArgKind, FunctionSignature, Lexer, Parser, QueryValidator, Schema};
+use vctsdb::query::{ExecutionConfig, QueryExecutor, TimeRange};
+use vctsdb::storage::lsm::MemTable;
+use vctsdb::storage::{DataPoint, TimeSeries};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Request latencies, each weighted by the number of requests it covers
+    let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+    {
+        let series = TimeSeries::new("latency".to_string())?;
+        let guard = memtable.write().await;
+        for (timestamp, latency, requests) in [(1, 120.0, "10"), (2, 80.0, "30"), (3, 300.0, "1")] {
+            let mut tags = HashMap::new();
+            tags.insert("requests".to_string(), requests.to_string());
+            guard.insert(&series, &DataPoint::new(timestamp, latency, tags)).await?;
+        }
+    }
+
+    // The validator only needs the signature, the executor the evaluator
+    let mut schema = Schema::new();
+    schema.add_value_field("value".to_string());
+    let mut validator = QueryValidator::new().with_schema(schema);
+    validator.register_function("weighted_mean", FunctionSignature::new(vec![ArgKind::Column]));
+
+    let mut executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+    executor.register_function(
+        "weighted_mean",
+        Arc::new(|points, _args| {
+            let weight = |point: &DataPoint| point.tags()["requests"].parse::<f64>().unwrap_or(0.0);
+            let total: f64 = points.iter().map(|point| weight(point)).sum();
+            points.iter().map(|point| point.value() * weight(point)).sum::<f64>() / total
+        }),
+    );
+
+    let tokens = Lexer::new("SELECT weighted_mean(value) AS latency FROM latency").tokenize()?;
+    let mut query = Parser::new(&tokens).with_validator(validator).parse()?;
+    query.time_range = Some(TimeRange::Absolute { start: 0, end: 10 });
+
+    for group in executor.execute_grouped(&query).await? {
+        println!("weighted mean latency: {:?}", group.value("latency"));
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! Synthetic datasets for benchmarks and tests
+//!
+//! Builds metrics-shaped data with a configurable number of series and points,
+//! and renders it in the wire formats the ingestion parsers accept.
+
+use std::collections::HashMap;
+
+use crate::storage::data::{DataPoint, TimeSeries};
+
+/// Builds a deterministic dataset of `series_count` series with
+/// `points_per_series` points each
+#[derive(Debug, Clone)]
+pub struct DatasetBuilder {
+    series_count: usize,
+    points_per_series: usize,
+    start_timestamp: i64,
+    interval: i64,
+    hosts: usize,
+}
+
+impl DatasetBuilder {
+    /// Creates a builder for `series_count` series of `points_per_series` points,
+    /// starting at 1s and spaced 10s apart
+    pub fn new(series_count: usize, points_per_series: usize) -> Self {
+        Self {
+            series_count: series_count.max(1),
+            points_per_series,
+            start_timestamp: 1_000_000_000,
+            interval: 10_000_000_000,
+            hosts: 16,
+        }
+    }
+
+    /// Sets the timestamp of the first point in every series
+    pub fn with_start_timestamp(mut self, start_timestamp: i64) -> Self {
+        self.start_timestamp = start_timestamp;
+        self
+    }
+
+    /// Sets the spacing between consecutive points of a series, in nanoseconds
+    pub fn with_interval(mut self, interval: i64) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how many distinct `host` tag values the series are spread across
+    pub fn with_hosts(mut self, hosts: usize) -> Self {
+        self.hosts = hosts.max(1);
+        self
+    }
+
+    /// Total number of points in the dataset
+    pub fn len(&self) -> usize {
+        self.series_count * self.points_per_series
+    }
+
+    /// Returns true if the dataset has no points
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Timestamp of the last point in every series
+    pub fn end_timestamp(&self) -> i64 {
+        self.start_timestamp + self.interval * self.points_per_series.saturating_sub(1) as i64
+    }
+
+    /// Name of the series at `index`
+    pub fn series_name(&self, index: usize) -> String {
+        format!("metric_{}", index)
+    }
+
+    /// Builds the series, in index order
+    pub fn series(&self) -> Vec<TimeSeries> {
+        (0..self.series_count)
+            .map(|i| TimeSeries::new(self.series_name(i)).expect("generated series names are valid"))
+            .collect()
+    }
+
+    /// Builds the points, timestamp-major as they would arrive from scrapers
+    ///
+    /// Point `k` belongs to series `k % series_count` and carries `series` and
+    /// `host` tags.
+    pub fn points(&self) -> Vec<DataPoint> {
+        let mut points = Vec::with_capacity(self.len());
+        for step in 0..self.points_per_series {
+            let timestamp = self.start_timestamp + self.interval * step as i64;
+            for series in 0..self.series_count {
+                let mut tags = HashMap::new();
+                tags.insert("series".to_string(), self.series_name(series));
+                tags.insert("host".to_string(), format!("host-{}", series % self.hosts));
+                let value = ((series * 31 + step * 7) % 1000) as f64 / 10.0;
+                points.push(DataPoint::new(timestamp, value, tags));
+            }
+        }
+        points
+    }
+
+    /// Renders each point as a standalone JSON object
+    pub fn to_json_objects(&self) -> Vec<Vec<u8>> {
+        self.points()
+            .iter()
+            .map(|point| {
+                format!(
+                    r#"{{"timestamp": {}, "value": {}, "series": "{}"}}"#,
+                    point.timestamp(),
+                    point.value(),
+                    point.tags()["series"]
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    /// Renders the dataset as CSV with a `timestamp,value,series,host` header
+    pub fn to_csv(&self) -> Vec<u8> {
+        let mut csv = String::from("timestamp,value,series,host\n");
+        for point in self.points() {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                point.timestamp(),
+                point.value(),
+                point.tags()["series"],
+                point.tags()["host"]
+            ));
+        }
+        csv.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingestion::formats::{CsvParser, JsonParser};
+    use crate::ingestion::parser::Parser;
+
+    #[test]
+    fn test_dataset_builder_shapes_and_renders() {
+        let dataset = DatasetBuilder::new(3, 4).with_interval(1000).with_hosts(2);
+        assert_eq!(dataset.len(), 12);
+        assert_eq!(dataset.end_timestamp(), 1_000_000_000 + 3000);
+
+        let series: Vec<String> = dataset.series().iter().map(|s| s.name().to_string()).collect();
+        assert_eq!(series, vec!["metric_0", "metric_1", "metric_2"]);
+
+        let points = dataset.points();
+        assert_eq!(points.len(), 12);
+        assert_eq!(points[4].tags()["series"], "metric_1");
+        assert_eq!(points[4].tags()["host"], "host-1");
+        assert_eq!(points[5].tags()["host"], "host-0");
+        assert_eq!(points[4].timestamp(), 1_000_001_000);
+
+        // Both renderings parse back to the same timestamps and values
+        let inputs = dataset.to_json_objects();
+        let inputs: Vec<&[u8]> = inputs.iter().map(|input| input.as_slice()).collect();
+        let from_json = JsonParser::new().parse_batch(&inputs).unwrap();
+        let from_csv = CsvParser::new().parse(&dataset.to_csv()).unwrap();
+        for parsed in [from_json, from_csv] {
+            let pairs: Vec<(i64, f64)> = parsed.iter().map(|p| (p.timestamp(), p.value())).collect();
+            let expected: Vec<(i64, f64)> = points.iter().map(|p| (p.timestamp(), p.value())).collect();
+            assert_eq!(pairs, expected);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+use std::io::Read;
+
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+use super::parser::{ParserError, ParserResult};
+
+/// Default cap on the size of a decompressed request body
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Content encodings accepted on the ingestion path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    /// Uncompressed input
+    Identity,
+    Gzip,
+    /// zlib-wrapped deflate (RFC 1950), or raw deflate as sent by some clients
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// Parses a `Content-Encoding` header value; an empty value means identity
+    pub fn parse(value: &str) -> ParserResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Ok(ContentEncoding::Gzip),
+            "deflate" => Ok(ContentEncoding::Deflate),
+            other => Err(ParserError::UnsupportedEncoding(other.to_string())),
+        }
+    }
+}
+
+/// Decompresses `input`, refusing to produce more than `max_size` bytes
+///
+/// The input is decoded as a stream, so a small compressed body cannot expand
+/// past the limit in memory. Identity input is returned without copying.
+pub fn decompress(
+    encoding: ContentEncoding,
+    input: &[u8],
+    max_size: usize,
+) -> ParserResult<Cow<'_, [u8]>> {
+    let decoder: Box<dyn Read + '_> = match encoding {
+        ContentEncoding::Identity => return Ok(Cow::Borrowed(input)),
+        ContentEncoding::Gzip => Box::new(GzDecoder::new(input)),
+        ContentEncoding::Deflate if is_zlib_header(input) => Box::new(ZlibDecoder::new(input)),
+        ContentEncoding::Deflate => Box::new(DeflateDecoder::new(input)),
+    };
+
+    // Read one byte past the limit to tell "exactly at the limit" from "over it"
+    let mut output = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut output)
+        .map_err(|e| ParserError::Decompression(format!("{:?} input is corrupt or truncated: {}", encoding, e)))?;
+    if output.len() > max_size {
+        return Err(ParserError::DecompressedTooLarge(max_size));
+    }
+    Ok(Cow::Owned(output))
+}
+
+/// Checks for a zlib stream header: deflate method and a valid header checksum
+fn is_zlib_header(input: &[u8]) -> bool {
+    match input {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn compress<W: Write>(mut encoder: W, input: &[u8]) -> W {
+        encoder.write_all(input).unwrap();
+        encoder
+    }
+
+    #[test]
+    fn test_decompress_round_trips_and_rejects_bad_input() {
+        let input = br#"{"timestamp": 1000, "value": 42.5, "series": "cpu"}"#;
+        let gzip = compress(GzEncoder::new(Vec::new(), Compression::default()), input).finish().unwrap();
+        let zlib = compress(ZlibEncoder::new(Vec::new(), Compression::default()), input).finish().unwrap();
+        let raw = compress(DeflateEncoder::new(Vec::new(), Compression::default()), input).finish().unwrap();
+
+        let max = DEFAULT_MAX_DECOMPRESSED_SIZE;
+        assert_eq!(&*decompress(ContentEncoding::Gzip, &gzip, max).unwrap(), input);
+        assert_eq!(&*decompress(ContentEncoding::Deflate, &zlib, max).unwrap(), input);
+        assert_eq!(&*decompress(ContentEncoding::Deflate, &raw, max).unwrap(), input);
+
+        // Truncated and corrupt streams fail rather than yielding partial input
+        let truncated = &gzip[..gzip.len() - 10];
+        assert!(matches!(
+            decompress(ContentEncoding::Gzip, truncated, max),
+            Err(ParserError::Decompression(_))
+        ));
+        assert!(matches!(
+            decompress(ContentEncoding::Gzip, b"not gzip at all", max),
+            Err(ParserError::Decompression(_))
+        ));
+
+        // Output is capped
+        assert!(decompress(ContentEncoding::Gzip, &gzip, input.len()).is_ok());
+        assert!(matches!(
+            decompress(ContentEncoding::Gzip, &gzip, input.len() - 1),
+            Err(ParserError::DecompressedTooLarge(_))
+        ));
+
+        assert!(matches!(ContentEncoding::parse("br"), Err(ParserError::UnsupportedEncoding(_))));
+        assert_eq!(ContentEncoding::parse(" GZIP ").unwrap(), ContentEncoding::Gzip);
+    }
+}
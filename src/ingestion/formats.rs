@@ -1,16 +1,45 @@
-use serde_json::{Value, Error as JsonError};
-use std::collections::HashMap;
-use std::io::Read;
+use serde_json::{Map, Value, Error as JsonError};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::ops::RangeInclusive;
 use csv::{Reader, ReaderBuilder, StringRecord};
 use std::str::FromStr;
 
-use super::parser::{Parser, ParserError, ParserResult};
-use crate::storage::data::DataPoint;
+use super::parser::{Confidence, Parser, ParserError, ParserResult, TimestampPrecision};
+use crate::storage::data::{DataPoint, Value as PointValue};
+
+/// Bytes buffered by `parse_stream` readers, bounding the rows the CSV delimiter
+/// is sniffed from
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How a JSON parser treats tag values that are not strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonStringTags {
+    /// Such values fail the parse with `ParserError::InvalidFieldType`
+    #[default]
+    Reject,
+    /// Numbers and booleans are written as in JSON, objects and arrays as
+    /// compact JSON; nulls are dropped
+    Stringify,
+}
 
 /// Parser for JSON input format
+///
+/// Tags come from three places, later ones overriding earlier ones: string
+/// fields at the top level of a point, the entries of its tags object, and
+/// its series field.
 pub struct JsonParser {
     /// Field mapping configuration
     field_mapping: HashMap<String, String>,
+    /// Name of the field holding a point's tag object
+    tags_field: String,
+    /// Handling of non-string values in the tag object
+    non_string_tags: NonStringTags,
+    /// Timestamps accepted by the parser
+    timestamp_range: RangeInclusive<i64>,
+    /// Unit of incoming timestamps; `None` takes them as nanoseconds unchecked
+    timestamp_precision: Option<TimestampPrecision>,
 }
 
 impl JsonParser {
@@ -21,16 +50,52 @@ impl JsonParser {
         field_mapping.insert("value".to_string(), "value".to_string());
         field_mapping.insert("series".to_string(), "series".to_string());
         
-        Self { field_mapping }
+        Self::with_field_mapping(field_mapping)
     }
 
     /// Creates a new JsonParser with custom field mapping
     pub fn with_field_mapping(field_mapping: HashMap<String, String>) -> Self {
-        Self { field_mapping }
+        Self {
+            field_mapping,
+            tags_field: "tags".to_string(),
+            non_string_tags: NonStringTags::default(),
+            timestamp_range: i64::MIN..=i64::MAX,
+            timestamp_precision: None,
+        }
+    }
+
+    /// Sets the name of the field holding a point's tag object, `tags` by default
+    pub fn with_tags_field(mut self, field: &str) -> Self {
+        self.tags_field = field.to_string();
+        self
+    }
+
+    /// Sets how non-string values in the tag object are treated
+    pub fn with_non_string_tags(mut self, non_string_tags: NonStringTags) -> Self {
+        self.non_string_tags = non_string_tags;
+        self
     }
 
-    /// Extracts a field from JSON value with type coercion
-    fn extract_field<T: From<f64>>(&self, value: &Value, field: &str) -> ParserResult<T> {
+    /// Restricts the timestamps accepted by the parser
+    pub fn with_timestamp_range(mut self, range: RangeInclusive<i64>) -> Self {
+        self.timestamp_range = range;
+        self
+    }
+
+    /// Sets the unit of incoming timestamps, which are scaled to nanoseconds
+    ///
+    /// Timestamps whose magnitude points to another unit are rejected, see
+    /// [`TimestampPrecision::to_nanos`]. The timestamp range applies to the
+    /// scaled timestamps.
+    pub fn with_timestamp_precision(mut self, precision: TimestampPrecision) -> Self {
+        self.timestamp_precision = Some(precision);
+        self
+    }
+
+    /// Extracts a point value, inferring its type from the JSON
+    ///
+    /// Integers become `I64`, other numbers `F64` and booleans `Bool`.
+    fn extract_value(&self, value: &Value, field: &str) -> ParserResult<PointValue> {
         let field_name = self.field_mapping.get(field)
             .ok_or_else(|| ParserError::MissingField(field.to_string()))?;
 
@@ -38,9 +103,13 @@ impl JsonParser {
             .ok_or_else(|| ParserError::MissingField(field_name.to_string()))?;
 
         match field_value {
-            Value::Number(n) => n.as_f64()
-                .ok_or_else(|| ParserError::InvalidFieldType(format!("{} must be a number", field_name)))
-                .map(|f| T::from(f)),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => Ok(PointValue::I64(i)),
+                None => n.as_f64()
+                    .map(PointValue::F64)
+                    .ok_or_else(|| ParserError::InvalidFieldType(format!("{} must be a number", field_name))),
+            },
+            Value::Bool(b) => Ok(PointValue::Bool(*b)),
             _ => Err(ParserError::InvalidFieldType(format!("{} must be a number", field_name))),
         }
     }
@@ -53,69 +122,236 @@ impl JsonParser {
         let field_value = value.get(field_name)
             .ok_or_else(|| ParserError::MissingField(field_name.to_string()))?;
 
-        match field_value {
+        let timestamp = match field_value {
             Value::Number(n) => {
                 if let Some(i) = n.as_i64() {
-                    Ok(i)
+                    match self.timestamp_precision {
+                        Some(precision) => precision.to_nanos(i)?,
+                        None => i,
+                    }
                 } else if let Some(f) = n.as_f64() {
-                    Ok(f as i64)
+                    // Fractional timestamps keep their sub-unit part once scaled
+                    if let Some(precision) = self.timestamp_precision {
+                        precision.to_nanos(f as i64)?;
+                    }
+                    let f = f * self.timestamp_precision.map_or(1, TimestampPrecision::nanos_per_unit) as f64;
+                    // i64::MAX as f64 rounds up to 2^63, which is itself out of range
+                    if !(f >= i64::MIN as f64 && f < i64::MAX as f64) {
+                        return Err(ParserError::InvalidTimestamp(format!(
+                            "{} in field {} does not fit in a 64-bit nanosecond timestamp",
+                            n, field_name
+                        )));
+                    }
+                    f as i64
                 } else {
-                    Err(ParserError::InvalidFieldType(format!("{} must be a number", field_name)))
+                    return Err(ParserError::InvalidFieldType(format!("{} must be a number", field_name)));
                 }
             }
-            _ => Err(ParserError::InvalidFieldType(format!("{} must be a number", field_name))),
+            _ => return Err(ParserError::InvalidFieldType(format!("{} must be a number", field_name))),
+        };
+
+        check_timestamp_range(timestamp, &self.timestamp_range)
+    }
+
+    /// Builds a point from one JSON object, on top of tags shared by its batch
+    ///
+    /// The object's own tags override shared tags of the same key, and its
+    /// series field overrides both.
+    fn parse_object(&self, obj: &Map<String, Value>, shared_tags: &HashMap<String, String>) -> ParserResult<DataPoint> {
+        let object = Value::Object(obj.clone());
+        let timestamp: i64 = self.extract_timestamp(&object, "timestamp")?;
+        let value = self.extract_value(&object, "value")?;
+
+        let mut tags = shared_tags.clone();
+        let mapped: HashSet<&String> = self.field_mapping.values().collect();
+        for (key, value) in obj {
+            if let Value::String(value) = value {
+                if !mapped.contains(key) && *key != self.tags_field {
+                    tags.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        if let Some(own_tags) = obj.get(&self.tags_field) {
+            tags.extend(self.extract_tags(own_tags)?);
         }
+        if let Some(series) = obj.get(self.field_mapping.get("series").unwrap()) {
+            if let Some(series_str) = series.as_str() {
+                tags.insert("series".to_string(), series_str.to_string());
+            }
+        }
+
+        Ok(DataPoint::with_value(timestamp, value, tags))
     }
-}
 
-impl Parser for JsonParser {
-    fn parse(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>> {
-        let value: Value = serde_json::from_slice(input)
-            .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+    /// Reads a JSON tag object, treating non-string values as configured
+    fn extract_tags(&self, value: &Value) -> ParserResult<HashMap<String, String>> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| ParserError::InvalidFieldType(format!("{} must be an object", self.tags_field)))?;
+        let mut tags = HashMap::with_capacity(obj.len());
+        for (key, value) in obj {
+            let value = match (value, self.non_string_tags) {
+                (Value::String(value), _) => value.clone(),
+                (_, NonStringTags::Reject) => {
+                    return Err(ParserError::InvalidFieldType(format!("tag {} must be a string", key)));
+                }
+                (Value::Null, NonStringTags::Stringify) => continue,
+                (value, NonStringTags::Stringify) => value.to_string(),
+            };
+            tags.insert(key.clone(), value);
+        }
+        Ok(tags)
+    }
 
-        let mut points = Vec::new();
+    /// Parses JSON from `reader`, handing each point to `sink` as it is read
+    ///
+    /// A top-level array is read one element at a time, so memory use is
+    /// bounded by the largest point rather than the whole input. A single
+    /// object or a batch object is read whole, as by [`Parser::parse`].
+    /// Returns the number of points parsed.
+    pub fn parse_stream<R: Read, F: FnMut(DataPoint)>(&self, input: R, mut sink: F) -> ParserResult<usize> {
+        let mut input = BufReader::with_capacity(STREAM_BUFFER_SIZE, input);
+        let starts_array = loop {
+            let buffered = input.fill_buf().map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+            if buffered.is_empty() {
+                break false;
+            }
+            match buffered.iter().position(|byte| !byte.is_ascii_whitespace()) {
+                Some(start) => break buffered[start] == b'[',
+                None => {
+                    let skipped = buffered.len();
+                    input.consume(skipped);
+                }
+            }
+        };
+
+        if !starts_array {
+            let value: Value = serde_json::from_reader(input)
+                .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+            let mut count = 0;
+            self.emit_points(value, &mut |point| {
+                sink(point);
+                count += 1;
+            })?;
+            return Ok(count);
+        }
+
+        let mut deserializer = serde_json::Deserializer::from_reader(input);
+        let mut failure = None;
+        let streamed = serde::Deserializer::deserialize_seq(
+            &mut deserializer,
+            PointStream { parser: self, sink: &mut sink, failure: &mut failure },
+        );
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        let count = streamed.map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+        deserializer.end().map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+        Ok(count)
+    }
 
-        // Handle both single object and array of objects
+    /// Hands every point of a parsed JSON document to `sink`
+    fn emit_points(&self, value: Value, sink: &mut impl FnMut(DataPoint)) -> ParserResult<()> {
+        // Handle a single object, an array of objects, and a batch object
+        // `{"tags": {...}, "points": [...]}` whose tags are shared by every point
         match value {
-            Value::Object(obj) => {
-                let timestamp: i64 = self.extract_timestamp(&Value::Object(obj.clone()), "timestamp")?;
-                let value: f64 = self.extract_field(&Value::Object(obj.clone()), "value")?;
-                
-                let mut tags = HashMap::new();
-                if let Some(series) = obj.get(self.field_mapping.get("series").unwrap()) {
-                    if let Some(series_str) = series.as_str() {
-                        tags.insert("series".to_string(), series_str.to_string());
+            Value::Object(obj) if obj.get("points").is_some_and(Value::is_array) => {
+                let shared_tags = match obj.get(&self.tags_field) {
+                    Some(tags) => self.extract_tags(tags)?,
+                    None => HashMap::new(),
+                };
+                for item in obj["points"].as_array().unwrap() {
+                    if let Value::Object(point) = item {
+                        sink(self.parse_object(point, &shared_tags)?);
                     }
                 }
-
-                points.push(DataPoint::new(timestamp, value, tags));
+            }
+            Value::Object(obj) => {
+                sink(self.parse_object(&obj, &HashMap::new())?);
             }
             Value::Array(arr) => {
                 for item in arr {
                     if let Value::Object(obj) = item {
-                        let timestamp: i64 = self.extract_timestamp(&Value::Object(obj.clone()), "timestamp")?;
-                        let value: f64 = self.extract_field(&Value::Object(obj.clone()), "value")?;
-                        
-                        let mut tags = HashMap::new();
-                        if let Some(series) = obj.get(self.field_mapping.get("series").unwrap()) {
-                            if let Some(series_str) = series.as_str() {
-                                tags.insert("series".to_string(), series_str.to_string());
-                            }
-                        }
-
-                        points.push(DataPoint::new(timestamp, value, tags));
+                        sink(self.parse_object(&obj, &HashMap::new())?);
                     }
                 }
             }
             _ => return Err(ParserError::InvalidFormat("Input must be a JSON object or array".to_string())),
         }
 
+        Ok(())
+    }
+}
+
+/// Reads a top-level JSON array, handing each object in it to a sink as a point
+struct PointStream<'a, F> {
+    parser: &'a JsonParser,
+    sink: &'a mut F,
+    /// Why a point failed to parse, which serde's error type can't carry
+    failure: &'a mut Option<ParserError>,
+}
+
+impl<'de, F: FnMut(DataPoint)> serde::de::Visitor<'de> for PointStream<'_, F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of points")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+        let mut count = 0;
+        while let Some(item) = seq.next_element::<Value>()? {
+            if let Value::Object(obj) = item {
+                match self.parser.parse_object(&obj, &HashMap::new()) {
+                    Ok(point) => {
+                        (self.sink)(point);
+                        count += 1;
+                    }
+                    Err(e) => {
+                        let message = e.to_string();
+                        *self.failure = Some(e);
+                        return Err(serde::de::Error::custom(message));
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl Parser for JsonParser {
+    fn parse(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>> {
+        let value: Value = serde_json::from_slice(input)
+            .map_err(|e| ParserError::InvalidFormat(e.to_string()))?;
+
+        let mut points = Vec::new();
+        self.emit_points(value, &mut |point| points.push(point))?;
         Ok(points)
     }
 
     fn supported_formats(&self) -> Vec<&'static str> {
         vec!["application/json", "json"]
     }
+
+    /// Inputs starting with an object or array are taken to be JSON
+    fn can_parse(&self, input: &[u8]) -> Confidence {
+        match input.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{' | b'[') => Confidence::High,
+            _ => Confidence::None,
+        }
+    }
+}
+
+/// How a CSV parser treats columns that are neither mapped fields nor declared tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownColumns {
+    /// Every such column becomes a tag
+    #[default]
+    AsTags,
+    /// Such columns are dropped
+    Ignore,
+    /// Such columns fail the parse with `ParserError::UnknownColumn`
+    Reject,
 }
 
 /// Parser for CSV input format
@@ -126,10 +362,22 @@ pub struct CsvParser {
     has_headers: bool,
     /// Column indices for required fields
     column_indices: HashMap<String, usize>,
-    /// Delimiter character
-    delimiter: u8,
+    /// Delimiter character; `None` sniffs it from the input
+    delimiter: Option<u8>,
     /// Additional tag columns to extract
     tag_columns: HashMap<String, usize>,
+    /// Header names accepted as tag columns regardless of `unknown_columns`
+    declared_tags: HashSet<String>,
+    /// Handling of columns that are neither mapped fields nor declared tags
+    unknown_columns: UnknownColumns,
+    /// Timestamps accepted by the parser
+    timestamp_range: RangeInclusive<i64>,
+    /// Unit of incoming timestamps; `None` takes them as nanoseconds unchecked
+    timestamp_precision: Option<TimestampPrecision>,
+    /// Header names of extra value columns, each read as its own series
+    value_columns: Vec<String>,
+    /// Column index of each extra value column, found by `detect_headers`
+    value_column_indices: Vec<(String, usize)>,
 }
 
 impl CsvParser {
@@ -144,8 +392,14 @@ impl CsvParser {
             field_mapping,
             has_headers: true,
             column_indices: HashMap::new(),
-            delimiter: b',',
+            delimiter: None,
             tag_columns: HashMap::new(),
+            declared_tags: HashSet::new(),
+            unknown_columns: UnknownColumns::default(),
+            timestamp_range: i64::MIN..=i64::MAX,
+            timestamp_precision: None,
+            value_columns: Vec::new(),
+            value_column_indices: Vec::new(),
         }
     }
 
@@ -164,8 +418,14 @@ impl CsvParser {
             field_mapping,
             has_headers: false,
             column_indices,
-            delimiter: b',',
+            delimiter: None,
             tag_columns,
+            declared_tags: HashSet::new(),
+            unknown_columns: UnknownColumns::default(),
+            timestamp_range: i64::MIN..=i64::MAX,
+            timestamp_precision: None,
+            value_columns: Vec::new(),
+            value_column_indices: Vec::new(),
         }
     }
 
@@ -175,14 +435,32 @@ impl CsvParser {
             field_mapping,
             has_headers,
             column_indices: HashMap::new(),
-            delimiter: b',',
+            delimiter: None,
             tag_columns: HashMap::new(),
+            declared_tags: HashSet::new(),
+            unknown_columns: UnknownColumns::default(),
+            timestamp_range: i64::MIN..=i64::MAX,
+            timestamp_precision: None,
+            value_columns: Vec::new(),
+            value_column_indices: Vec::new(),
         }
     }
 
+    /// Sets whether the first row is a header
+    ///
+    /// With headers, columns are located by name. Column indices set by
+    /// [`CsvParser::with_column_indices`] must then agree with the header row,
+    /// or parsing fails instead of reading mislabeled columns.
+    pub fn with_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
     /// Sets the delimiter character
+    ///
+    /// Without one, the delimiter is sniffed from each input; see [`sniff_delimiter`].
     pub fn with_delimiter(mut self, delimiter: u8) -> Self {
-        self.delimiter = delimiter;
+        self.delimiter = Some(delimiter);
         self
     }
 
@@ -192,6 +470,68 @@ impl CsvParser {
         self
     }
 
+    /// Restricts the timestamps accepted by the parser
+    pub fn with_timestamp_range(mut self, range: RangeInclusive<i64>) -> Self {
+        self.timestamp_range = range;
+        self
+    }
+
+    /// Sets the unit of incoming timestamps, which are scaled to nanoseconds
+    ///
+    /// Timestamps whose magnitude points to another unit are rejected, see
+    /// [`TimestampPrecision::to_nanos`]. The timestamp range applies to the
+    /// scaled timestamps.
+    pub fn with_timestamp_precision(mut self, precision: TimestampPrecision) -> Self {
+        self.timestamp_precision = Some(precision);
+        self
+    }
+
+    /// Reads each of the named columns as a value of its own series
+    ///
+    /// For wide CSVs such as `timestamp,cpu,mem,disk,series`, every row yields
+    /// one point per non-empty value column, with the `series` tag set to
+    /// `series.column`, or to the column name if the row has no series. A
+    /// `value` column is then optional. Requires headers.
+    pub fn with_value_columns(mut self, columns: Vec<String>) -> Self {
+        self.value_columns = columns;
+        self
+    }
+
+    /// Declares the header names accepted as tag columns
+    pub fn with_declared_tags(mut self, tags: &[&str]) -> Self {
+        self.declared_tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    /// Sets how columns that are neither mapped fields nor declared tags are
+    /// treated; strict parsing is `UnknownColumns::Reject`
+    ///
+    /// Without headers, columns are only known by index, so any column past
+    /// the configured indices is an unknown one.
+    pub fn with_unknown_columns(mut self, unknown_columns: UnknownColumns) -> Self {
+        self.unknown_columns = unknown_columns;
+        self
+    }
+
+    /// Parses a timestamp column, distinguishing overflow from malformed input
+    fn parse_timestamp(&self, value: &str) -> ParserResult<i64> {
+        let timestamp = match value.parse::<i64>() {
+            Ok(timestamp) => timestamp,
+            Err(_) if value.parse::<i128>().is_ok() => {
+                return Err(ParserError::InvalidTimestamp(format!(
+                    "{} does not fit in a 64-bit nanosecond timestamp",
+                    value
+                )));
+            }
+            Err(_) => return self.parse_value(value),
+        };
+        let timestamp = match self.timestamp_precision {
+            Some(precision) => precision.to_nanos(timestamp)?,
+            None => timestamp,
+        };
+        check_timestamp_range(timestamp, &self.timestamp_range)
+    }
+
     /// Parse value from string with type inference
     fn parse_value<T: FromStr>(&self, value: &str) -> ParserResult<T> {
         value.parse::<T>().map_err(|_| {
@@ -199,8 +539,22 @@ impl CsvParser {
         })
     }
 
-    /// Extract field from a record by name or index
-    fn extract_field<T: FromStr>(&self, record: &StringRecord, headers: Option<&StringRecord>, field: &str) -> ParserResult<T> {
+    /// Parses a value column, inferring its type
+    ///
+    /// `true` and `false` become `Bool`, integers `I64` and other numbers `F64`.
+    fn parse_point_value(&self, value: &str) -> ParserResult<PointValue> {
+        match value {
+            "true" => Ok(PointValue::Bool(true)),
+            "false" => Ok(PointValue::Bool(false)),
+            _ => match value.parse::<i64>() {
+                Ok(i) => Ok(PointValue::I64(i)),
+                Err(_) => self.parse_value(value).map(PointValue::F64),
+            },
+        }
+    }
+
+    /// Extract the unparsed text of a field from a record by name or index
+    fn extract_raw<'r>(&self, record: &'r StringRecord, headers: Option<&StringRecord>, field: &str) -> ParserResult<&'r str> {
         let field_name = self.field_mapping.get(field)
             .ok_or_else(|| ParserError::MissingField(field.to_string()))?;
 
@@ -220,11 +574,20 @@ impl CsvParser {
             return Err(ParserError::MissingField(format!("No mapping for {}", field)));
         };
 
-        self.parse_value(field_value)
+        Ok(field_value)
     }
     
+    /// Rejects a headerless record with a column past the configured indices
+    fn check_known_indices(&self, record: &StringRecord) -> ParserResult<()> {
+        let known: HashSet<usize> = self.column_indices.values().chain(self.tag_columns.values()).copied().collect();
+        match (0..record.len()).find(|i| !known.contains(i)) {
+            Some(i) => Err(ParserError::UnknownColumn(format!("column {}", i))),
+            None => Ok(()),
+        }
+    }
+
     /// Detect headers and column indices from the first record
-    fn detect_headers(&mut self, reader: &mut Reader<&[u8]>) -> ParserResult<()> {
+    fn detect_headers<R: Read>(&mut self, reader: &mut Reader<R>) -> ParserResult<()> {
         if !self.has_headers {
             return Ok(());
         }
@@ -232,43 +595,90 @@ impl CsvParser {
         let headers = reader.headers()
             .map_err(|e| ParserError::InvalidFormat(format!("Failed to read CSV headers: {}", e)))?;
         
-        // Map required fields to column indices
-        for field in &["timestamp", "value"] {
-            if let Some(mapped_name) = self.field_mapping.get(*field) {
-                if let Some(pos) = headers.iter().position(|h| h == mapped_name) {
+        // Map required fields to column indices, checking any preset index
+        // against the header rather than trusting it
+        let preset = std::mem::take(&mut self.column_indices);
+        for field in &["timestamp", "value", "series"] {
+            let mapped_name = self.field_mapping.get(*field);
+            let pos = mapped_name.and_then(|name| headers.iter().position(|h| h == name));
+            if let (Some(&expected), Some(name)) = (preset.get(*field), mapped_name) {
+                if pos != Some(expected) {
+                    return Err(ParserError::InvalidFormat(format!(
+                        "CSV header {} is not at column {} configured for {}",
+                        name, expected, field
+                    )));
+                }
+            }
+            match pos {
+                // The series column is found among the tag columns unless preset
+                Some(_) if *field == "series" && !preset.contains_key("series") => {}
+                Some(pos) => {
                     self.column_indices.insert(field.to_string(), pos);
                 }
+                None => {}
             }
         }
         
         // Check if we found all required fields
-        if !self.column_indices.contains_key("timestamp") || !self.column_indices.contains_key("value") {
+        let has_values = self.column_indices.contains_key("value") || !self.value_columns.is_empty();
+        if !self.column_indices.contains_key("timestamp") || !has_values {
             return Err(ParserError::InvalidFormat("CSV headers must contain timestamp and value fields".to_string()));
         }
+
+        self.value_column_indices.clear();
+        for column in &self.value_columns {
+            let pos = headers.iter().position(|h| h == column).ok_or_else(|| {
+                ParserError::InvalidFormat(format!("CSV headers are missing value column {}", column))
+            })?;
+            self.value_column_indices.push((column.clone(), pos));
+        }
         
-        // Detect additional tag columns (any column that isn't timestamp or value)
+        // Detect additional tag columns (any column that isn't timestamp or a value)
         for (i, header) in headers.iter().enumerate() {
-            if i != self.column_indices["timestamp"] && i != self.column_indices["value"] {
-                self.tag_columns.insert(header.to_string(), i);
+            if self.column_indices.get("timestamp") == Some(&i)
+                || self.column_indices.get("value") == Some(&i)
+                || self.value_column_indices.iter().any(|(_, pos)| *pos == i)
+            {
+                continue;
+            }
+            let known = self.declared_tags.contains(header)
+                || self.tag_columns.contains_key(header)
+                || self.field_mapping.get("series").is_some_and(|series| series == header);
+            match self.unknown_columns {
+                _ if known => {}
+                UnknownColumns::AsTags => {}
+                UnknownColumns::Ignore => continue,
+                UnknownColumns::Reject => return Err(ParserError::UnknownColumn(header.to_string())),
             }
+            self.tag_columns.insert(header.to_string(), i);
         }
         
         Ok(())
     }
-}
 
-impl Parser for CsvParser {
-    fn parse(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>> {
-        // Create a CSV reader
+    /// Parses CSV from `reader`, handing each point to `sink` as its row is read
+    ///
+    /// Rows are read one at a time, so memory use does not grow with the
+    /// input. Without a configured delimiter, it is sniffed from the first
+    /// buffered rows. Returns the number of points parsed.
+    pub fn parse_stream<R: Read, F: FnMut(DataPoint)>(&self, input: R, mut sink: F) -> ParserResult<usize> {
+        // Sniff the delimiter from the first buffered rows
+        let mut input = BufReader::with_capacity(STREAM_BUFFER_SIZE, input);
+        let delimiter = match self.delimiter {
+            Some(delimiter) => delimiter,
+            None => sniff_delimiter(input.fill_buf().map_err(|e| ParserError::InvalidFormat(e.to_string()))?),
+        };
         let mut reader = ReaderBuilder::new()
             .has_headers(self.has_headers)
-            .delimiter(self.delimiter)
+            .delimiter(delimiter)
             .from_reader(input);
         
         // Clone self to detect headers in a mutable copy
         let mut parser_with_headers = self.clone();
-        if self.has_headers && self.column_indices.is_empty() {
+        if self.has_headers {
             parser_with_headers.detect_headers(&mut reader)?;
+        } else if !self.value_columns.is_empty() {
+            return Err(ParserError::InvalidFormat("Value columns require CSV headers".to_string()));
         }
         
         let headers = if self.has_headers {
@@ -278,15 +688,24 @@ impl Parser for CsvParser {
             None
         };
         
-        let mut points = Vec::new();
+        let mut count = 0;
         
         // Process each record
         for result in reader.records() {
             let record = result.map_err(|e| 
                 ParserError::InvalidFormat(format!("Failed to read CSV record: {}", e)))?;
+            if headers.is_none() && self.unknown_columns == UnknownColumns::Reject {
+                parser_with_headers.check_known_indices(&record)?;
+            }
             
-            let timestamp: i64 = parser_with_headers.extract_field(&record, headers.as_ref(), "timestamp")?;
-            let value: f64 = parser_with_headers.extract_field(&record, headers.as_ref(), "value")?;
+            let timestamp = parser_with_headers
+                .parse_timestamp(parser_with_headers.extract_raw(&record, headers.as_ref(), "timestamp")?)?;
+            let value = if parser_with_headers.column_indices.contains_key("value") {
+                Some(parser_with_headers
+                    .parse_point_value(parser_with_headers.extract_raw(&record, headers.as_ref(), "value")?)?)
+            } else {
+                None
+            };
             
             // Extract tags
             let mut tags = HashMap::new();
@@ -308,14 +727,73 @@ impl Parser for CsvParser {
                 }
             }
             
-            points.push(DataPoint::new(timestamp, value, tags));
+            // Each extra value column is a series of its own
+            for (column, idx) in &parser_with_headers.value_column_indices {
+                let raw = record.get(*idx).unwrap_or_default();
+                if raw.is_empty() {
+                    continue;
+                }
+                let column_value = parser_with_headers.parse_point_value(raw)?;
+                let mut column_tags = tags.clone();
+                let series = match tags.get("series") {
+                    Some(series) => format!("{}.{}", series, column),
+                    None => column.clone(),
+                };
+                column_tags.insert("series".to_string(), series);
+                sink(DataPoint::with_value(timestamp, column_value, column_tags));
+                count += 1;
+            }
+
+            if let Some(value) = value {
+                sink(DataPoint::with_value(timestamp, value, tags));
+                count += 1;
+            }
         }
         
+        Ok(count)
+    }
+}
+
+impl Parser for CsvParser {
+    fn parse(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>> {
+        let mut points = Vec::new();
+        self.parse_stream(input, |point| points.push(point))?;
         Ok(points)
     }
-    
+
     fn supported_formats(&self) -> Vec<&'static str> {
-        vec!["text/csv", "csv"]
+        vec!["text/csv", "csv", "text/tab-separated-values", "tsv"]
+    }
+
+    /// Looks for a header of several columns that the first rows agree with
+    ///
+    /// A headered parser is only confident when the header names the mapped
+    /// timestamp column.
+    fn can_parse(&self, input: &[u8]) -> Confidence {
+        let rows: Vec<StringRecord> = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(self.delimiter.unwrap_or_else(|| sniff_delimiter(input)))
+            .from_reader(input)
+            .records()
+            .take(SNIFFED_ROWS)
+            .map_while(Result::ok)
+            .collect();
+        let Some(header) = rows.first().filter(|header| header.len() > 1) else {
+            return Confidence::None;
+        };
+        if rows.iter().any(|row| row.len() != header.len()) {
+            return Confidence::None;
+        }
+        if !self.has_headers {
+            return Confidence::Medium;
+        }
+        let timestamp = self.field_mapping.get("timestamp").map(String::as_str);
+        if header.iter().any(|name| Some(name) == timestamp) {
+            Confidence::High
+        } else {
+            Confidence::Low
+        }
     }
 }
 
@@ -328,10 +806,385 @@ impl Clone for CsvParser {
             column_indices: self.column_indices.clone(),
             delimiter: self.delimiter,
             tag_columns: self.tag_columns.clone(),
+            declared_tags: self.declared_tags.clone(),
+            unknown_columns: self.unknown_columns,
+            timestamp_range: self.timestamp_range.clone(),
+            timestamp_precision: self.timestamp_precision,
+            value_columns: self.value_columns.clone(),
+            value_column_indices: self.value_column_indices.clone(),
+        }
+    }
+}
+
+/// Delimiters [`sniff_delimiter`] chooses between, in order of preference
+const SNIFFED_DELIMITERS: [u8; 3] = [b',', b'\t', b'|'];
+
+/// Number of leading rows [`sniff_delimiter`] looks at
+const SNIFFED_ROWS: usize = 5;
+
+/// Picks the delimiter that splits the first rows of `input` most consistently
+///
+/// Each candidate is scored by how many of the first rows have as many fields
+/// as the header row, then by that field count; candidates that leave the
+/// header as a single field are skipped. Ties go to the earlier of comma, tab
+/// and pipe, and comma is returned when no candidate splits the header.
+pub fn sniff_delimiter(input: &[u8]) -> u8 {
+    let mut best = (b',', 0, 0);
+    for delimiter in SNIFFED_DELIMITERS {
+        let counts: Vec<usize> = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_reader(input)
+            .records()
+            .take(SNIFFED_ROWS)
+            .map_while(Result::ok)
+            .map(|record| record.len())
+            .collect();
+        let Some(&fields) = counts.first().filter(|fields| **fields > 1) else {
+            continue;
+        };
+        let consistent = counts.iter().filter(|count| **count == fields).count();
+        if (consistent, fields) > (best.1, best.2) {
+            best = (delimiter, consistent, fields);
+        }
+    }
+    best.0
+}
+
+/// Rejects timestamps outside the range a parser was configured to accept
+fn check_timestamp_range(timestamp: i64, range: &RangeInclusive<i64>) -> ParserResult<i64> {
+    if range.contains(&timestamp) {
+        Ok(timestamp)
+    } else {
+        Err(ParserError::InvalidTimestamp(format!(
+            "{} is outside the accepted range {}..={}",
+            timestamp,
+            range.start(),
+            range.end()
+        )))
+    }
+}
+
+/// Parser for InfluxDB line protocol input
+///
+/// Each line has the form `measurement,tag1=v1 field1=1.0,field2=2i [timestamp]`
+/// and produces one DataPoint per field, with the `series` tag set to
+/// `measurement.field`. Integer fields keep an integer value (unsigned ones that
+/// exceed `i64` fall back to f64) and boolean fields a boolean value, string
+/// fields are skipped, and a missing timestamp defaults to the current time.
+#[derive(Debug, Clone, Default)]
+pub struct LineProtocolParser;
+
+impl LineProtocolParser {
+    /// Creates a new LineProtocolParser
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Parses a single non-empty line into its data points
+    fn parse_line(&self, line: &str, line_number: usize) -> ParserResult<Vec<DataPoint>> {
+        let sections = split_unescaped(line, ' ');
+        if sections.len() < 2 || sections.len() > 3 {
+            return Err(ParserError::InvalidFormat(format!(
+                "Line {}: expected measurement, fields and optional timestamp",
+                line_number
+            )));
+        }
+
+        // Measurement and tags
+        let key_parts = split_unescaped(&sections[0], ',');
+        let measurement = unescape(&key_parts[0]);
+        if measurement.is_empty() {
+            return Err(ParserError::MissingField(format!("Line {}: measurement", line_number)));
+        }
+
+        let mut tags = HashMap::new();
+        for tag in &key_parts[1..] {
+            let (key, value) = split_pair(tag).ok_or_else(|| {
+                ParserError::InvalidFormat(format!("Line {}: invalid tag '{}'", line_number, tag))
+            })?;
+            tags.insert(unescape(&key), unescape(&value));
+        }
+
+        // Optional trailing timestamp
+        let timestamp = match sections.get(2) {
+            Some(ts) => ts.parse::<i64>().map_err(|_| {
+                ParserError::InvalidFieldType(format!(
+                    "Line {}: timestamp '{}' must be an integer",
+                    line_number, ts
+                ))
+            })?,
+            None => chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        };
+
+        // One point per field
+        let mut points = Vec::new();
+        for field in split_unescaped(&sections[1], ',') {
+            let (key, raw_value) = split_pair(&field).ok_or_else(|| {
+                ParserError::InvalidFormat(format!("Line {}: invalid field '{}'", line_number, field))
+            })?;
+            let value = match parse_field_value(&raw_value) {
+                Some(Ok(value)) => value,
+                Some(Err(())) => {
+                    return Err(ParserError::InvalidFieldType(format!(
+                        "Line {}: invalid value '{}' for field {}",
+                        line_number, raw_value, key
+                    )))
+                }
+                None => continue, // String field
+            };
+
+            let mut point_tags = tags.clone();
+            point_tags.insert(
+                "series".to_string(),
+                format!("{}.{}", measurement, unescape(&key)),
+            );
+            points.push(DataPoint::with_value(timestamp, value, point_tags));
+        }
+
+        Ok(points)
+    }
+}
+
+impl Parser for LineProtocolParser {
+    fn parse(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>> {
+        let text = std::str::from_utf8(input)
+            .map_err(|e| ParserError::InvalidFormat(format!("Input is not valid UTF-8: {}", e)))?;
+
+        let mut points = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            points.extend(self.parse_line(line, i + 1)?);
+        }
+
+        Ok(points)
+    }
+
+    fn supported_formats(&self) -> Vec<&'static str> {
+        vec!["application/x-influx", "influx"]
+    }
+}
+
+/// Parser for the Prometheus text exposition format
+///
+/// Each sample line `metric_name{label="v"} 1.23 [timestamp_ms]` produces one
+/// DataPoint whose labels become tags and whose metric name becomes the `series`
+/// tag. Histogram and summary samples (`_bucket`, `_sum`, `_count`) are emitted
+/// as separate series. Comment lines, including HELP and TYPE, are skipped, and
+/// a missing timestamp defaults to the current time.
+#[derive(Debug, Clone, Default)]
+pub struct PrometheusTextParser;
+
+impl PrometheusTextParser {
+    /// Creates a new PrometheusTextParser
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Parses a single sample line
+    fn parse_line(&self, line: &str, line_number: usize) -> ParserResult<DataPoint> {
+        let invalid = |reason: &str| {
+            ParserError::InvalidFormat(format!("Line {}: {}", line_number, reason))
+        };
+
+        let name_end = line
+            .find(|c: char| c == '{' || c.is_whitespace())
+            .ok_or_else(|| invalid("missing sample value"))?;
+        let name = &line[..name_end];
+        if name.is_empty() {
+            return Err(invalid("missing metric name"));
+        }
+
+        let mut tags = HashMap::new();
+        let mut rest = &line[name_end..];
+        if rest.starts_with('{') {
+            let (labels, remaining) = parse_prometheus_labels(&rest[1..])
+                .ok_or_else(|| invalid("malformed label set"))?;
+            tags = labels;
+            rest = remaining;
+        }
+        tags.insert("series".to_string(), name.to_string());
+
+        let mut fields = rest.split_whitespace();
+        let raw_value = fields.next().ok_or_else(|| invalid("missing sample value"))?;
+        let value = match raw_value {
+            "+Inf" | "Inf" => f64::INFINITY,
+            "-Inf" => f64::NEG_INFINITY,
+            "NaN" => f64::NAN,
+            _ => raw_value.parse::<f64>().map_err(|_| {
+                ParserError::InvalidFieldType(format!(
+                    "Line {}: invalid sample value '{}'",
+                    line_number, raw_value
+                ))
+            })?,
+        };
+
+        // Exposition timestamps are milliseconds, ours are nanoseconds
+        let timestamp = match fields.next() {
+            Some(ts) => ts
+                .parse::<i64>()
+                .ok()
+                .and_then(|ms| ms.checked_mul(1_000_000))
+                .ok_or_else(|| {
+                    ParserError::InvalidFieldType(format!(
+                        "Line {}: invalid timestamp '{}'",
+                        line_number, ts
+                    ))
+                })?,
+            None => chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        };
+
+        if fields.next().is_some() {
+            return Err(invalid("unexpected trailing content"));
         }
+
+        Ok(DataPoint::new(timestamp, value, tags))
     }
 }
 
+impl Parser for PrometheusTextParser {
+    fn parse(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>> {
+        let text = std::str::from_utf8(input)
+            .map_err(|e| ParserError::InvalidFormat(format!("Input is not valid UTF-8: {}", e)))?;
+
+        let mut points = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            points.push(self.parse_line(line, i + 1)?);
+        }
+
+        Ok(points)
+    }
+
+    fn supported_formats(&self) -> Vec<&'static str> {
+        vec!["text/plain; version=0.0.4", "prometheus"]
+    }
+}
+
+/// Parses a Prometheus label set following the opening brace
+///
+/// Returns the labels and the remainder of the line after the closing brace.
+fn parse_prometheus_labels(input: &str) -> Option<(HashMap<String, String>, &str)> {
+    let mut labels = HashMap::new();
+    let mut rest = input.trim_start();
+
+    loop {
+        if let Some(remaining) = rest.strip_prefix('}') {
+            return Some((labels, remaining));
+        }
+
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        rest = rest[eq + 1..].trim_start().strip_prefix('"')?;
+
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (_, '\\') => match chars.next()? {
+                    (_, 'n') => value.push('\n'),
+                    (_, c) => value.push(c),
+                },
+                (i, '"') => break i,
+                (_, c) => value.push(c),
+            }
+        };
+        labels.insert(key.to_string(), value);
+
+        rest = rest[end + 1..].trim_start();
+        if let Some(remaining) = rest.strip_prefix(',') {
+            rest = remaining.trim_start();
+        }
+    }
+}
+
+/// Splits on a delimiter, ignoring backslash-escaped delimiters and those inside double quotes
+fn split_unescaped(input: &str, delimiter: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            c if c == delimiter && !in_quotes => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    parts
+}
+
+/// Splits a `key=value` pair on the first unescaped equals sign
+fn split_pair(input: &str) -> Option<(String, String)> {
+    let mut parts = split_unescaped(input, '=').into_iter();
+    let key = parts.next()?;
+    let value = parts.collect::<Vec<_>>().join("=");
+    if key.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some((key, value))
+}
+
+/// Removes backslash escapes
+fn unescape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                output.push(next);
+            }
+        } else {
+            output.push(c);
+        }
+    }
+    output
+}
+
+/// Parses a line protocol field value, returning None for string fields
+fn parse_field_value(raw: &str) -> Option<Result<PointValue, ()>> {
+    if raw.starts_with('"') {
+        return None;
+    }
+
+    let parsed = match raw {
+        "t" | "T" | "true" | "True" | "TRUE" => Ok(PointValue::Bool(true)),
+        "f" | "F" | "false" | "False" | "FALSE" => Ok(PointValue::Bool(false)),
+        _ => {
+            if let Some(int) = raw.strip_suffix('i') {
+                int.parse::<i64>().map(PointValue::I64).map_err(|_| ())
+            } else if let Some(uint) = raw.strip_suffix('u') {
+                uint.parse::<u64>()
+                    .map(|u| i64::try_from(u).map_or(PointValue::F64(u as f64), PointValue::I64))
+                    .map_err(|_| ())
+            } else {
+                raw.parse::<f64>().map(PointValue::F64).map_err(|_| ())
+            }
+        }
+    };
+
+    Some(parsed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -376,6 +1229,33 @@ mod tests {
         assert_eq!(points[1].value(), 43.5);
     }
 
+    #[test]
+    fn test_json_parser_batch_object_shares_tags() {
+        let parser = JsonParser::new();
+        let input = r#"{
+            "tags": {"host": "web-1", "region": "us-east", "series": "default"},
+            "points": [
+                {"timestamp": 1000, "value": 0.5, "series": "cpu"},
+                {"timestamp": 1000, "value": 2048, "series": "mem", "tags": {"region": "eu-west"}},
+                {"timestamp": 1000, "value": 12}
+            ]
+        }"#.as_bytes();
+
+        let points = parser.parse(input).unwrap();
+        assert_eq!(points.len(), 3);
+        assert!(points.iter().all(|p| p.tags()["host"] == "web-1"));
+
+        // Per-point tags override shared ones, and the series field overrides both
+        assert_eq!(points[0].tags()["series"], "cpu");
+        assert_eq!(points[0].tags()["region"], "us-east");
+        assert_eq!(points[1].tags()["series"], "mem");
+        assert_eq!(points[1].tags()["region"], "eu-west");
+        assert_eq!(points[2].tags()["series"], "default");
+
+        let invalid = r#"{"tags": {"host": 1}, "points": []}"#.as_bytes();
+        assert!(matches!(parser.parse(invalid), Err(ParserError::InvalidFieldType(_))));
+    }
+
     #[test]
     fn test_json_parser_invalid_input() {
         let parser = JsonParser::new();
@@ -500,6 +1380,29 @@ mod tests {
         assert_eq!(points[2].value(), 45.0);
     }
 
+    #[test]
+    fn test_parsers_infer_value_types() {
+        let points = JsonParser::new()
+            .parse(br#"[{"timestamp": 1, "value": 9007199254740993},
+                        {"timestamp": 2, "value": 1.0},
+                        {"timestamp": 3, "value": true}]"#)
+            .unwrap();
+        let values: Vec<PointValue> = points.iter().map(|p| p.typed_value()).collect();
+        assert_eq!(
+            values,
+            vec![PointValue::I64(9007199254740993), PointValue::F64(1.0), PointValue::Bool(true)]
+        );
+
+        let input = "timestamp,value,series\n1,7,s\n2,7.5,s\n3,false,s".as_bytes();
+        let points = CsvParser::new().parse(input).unwrap();
+        let values: Vec<PointValue> = points.iter().map(|p| p.typed_value()).collect();
+        assert_eq!(values, vec![PointValue::I64(7), PointValue::F64(7.5), PointValue::Bool(false)]);
+
+        let points = LineProtocolParser::new().parse(b"disk used=3i,ok=t,free=0.5 1").unwrap();
+        let values: Vec<PointValue> = points.iter().map(|p| p.typed_value()).collect();
+        assert_eq!(values, vec![PointValue::I64(3), PointValue::Bool(true), PointValue::F64(0.5)]);
+    }
+
     #[test]
     fn test_csv_parser_invalid_input() {
         let parser = CsvParser::new();
@@ -520,4 +1423,363 @@ mod tests {
         let result = parser.parse(input);
         assert!(matches!(result, Err(ParserError::InvalidFieldType(_))));
     }
+
+    #[test]
+    fn test_csv_parser_checks_preset_indices_against_headers() {
+        let mut tag_columns = HashMap::new();
+        tag_columns.insert("series".to_string(), 2);
+
+        // Indices that agree with the header are accepted
+        let parser = CsvParser::with_column_indices(0, 1, tag_columns.clone()).with_headers(true);
+        let points = parser.parse(b"timestamp,value,series\n1000,1.5,cpu").unwrap();
+        assert_eq!((points[0].timestamp(), points[0].value()), (1000, 1.5));
+        assert_eq!(points[0].tags()["series"], "cpu");
+
+        // Stale indices would swap timestamp and value, so they are rejected
+        let parser = CsvParser::with_column_indices(1, 0, tag_columns).with_headers(true);
+        assert!(matches!(parser.parse(b"timestamp,value,series\n1000,1.5,cpu"), Err(ParserError::InvalidFormat(_))));
+        assert!(matches!(parser.parse(b"time,value,series\n1000,1.5,cpu"), Err(ParserError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_csv_parser_unknown_columns() {
+        let input = "timestamp,value,series,host,junk\n\
+                    1000,42.5,cpu,server1,abc123"
+            .as_bytes();
+
+        // Lenient parsing turns every extra column into a tag
+        let points = CsvParser::new().parse(input).unwrap();
+        assert_eq!(points[0].tags()["host"], "server1");
+        assert_eq!(points[0].tags()["junk"], "abc123");
+
+        // Strict parsing only accepts declared tags
+        let strict = CsvParser::new()
+            .with_declared_tags(&["host"])
+            .with_unknown_columns(UnknownColumns::Reject);
+        assert!(matches!(strict.parse(input), Err(ParserError::UnknownColumn(column)) if column == "junk"));
+        let declared = "timestamp,value,series,host\n1000,42.5,cpu,server1".as_bytes();
+        assert_eq!(strict.parse(declared).unwrap()[0].tags()["host"], "server1");
+
+        // Or drops undeclared columns
+        let ignoring = CsvParser::new()
+            .with_declared_tags(&["host"])
+            .with_unknown_columns(UnknownColumns::Ignore);
+        let points = ignoring.parse(input).unwrap();
+        assert_eq!(points[0].tags()["series"], "cpu");
+        assert_eq!(points[0].tags()["host"], "server1");
+        assert!(!points[0].tags().contains_key("junk"));
+
+        // Without headers, columns past the configured indices are unknown
+        let mut tag_columns = HashMap::new();
+        tag_columns.insert("series".to_string(), 2);
+        let headerless = CsvParser::with_column_indices(0, 1, tag_columns)
+            .with_unknown_columns(UnknownColumns::Reject);
+        assert_eq!(headerless.parse(b"1000,42.5,cpu").unwrap().len(), 1);
+        assert!(matches!(headerless.parse(b"1000,42.5,cpu,extra"), Err(ParserError::UnknownColumn(_))));
+    }
+
+    #[test]
+    fn test_csv_parser_value_columns() {
+        let parser = CsvParser::new().with_value_columns(vec!["cpu".to_string(), "mem".to_string(), "disk".to_string()]);
+        let input = "timestamp,cpu,mem,disk,series,host\n\
+                    1000,0.5,2048,true,node,server1\n\
+                    2000,0.75,,false,node,server1"
+            .as_bytes();
+
+        let points = parser.parse(input).unwrap();
+        let series: Vec<(&str, i64)> = points.iter().map(|p| (p.tags()["series"].as_str(), p.timestamp())).collect();
+        assert_eq!(
+            series,
+            vec![("node.cpu", 1000), ("node.mem", 1000), ("node.disk", 1000), ("node.cpu", 2000), ("node.disk", 2000)]
+        );
+        let values: Vec<PointValue> = points.iter().map(|p| p.typed_value()).collect();
+        assert_eq!(values[..3], [PointValue::F64(0.5), PointValue::I64(2048), PointValue::Bool(true)]);
+        assert!(points.iter().all(|p| p.tags()["host"] == "server1"));
+
+        // Without a series column the column names are the series
+        let points = parser.parse(b"timestamp,cpu,mem,disk\n1000,1,2,3").unwrap();
+        let series: Vec<&str> = points.iter().map(|p| p.tags()["series"].as_str()).collect();
+        assert_eq!(series, vec!["cpu", "mem", "disk"]);
+
+        assert!(matches!(parser.parse(b"timestamp,cpu,mem\n1000,1,2"), Err(ParserError::InvalidFormat(_))));
+        assert!(matches!(parser.parse(b"timestamp,cpu,mem,disk\n1000,1,high,3"), Err(ParserError::InvalidFieldType(_))));
+    }
+
+    #[test]
+    fn test_parse_stream_delivers_points_before_reading_everything() {
+        /// Counts the bytes handed out, so sinks can see how far reading got
+        struct Counting<'a> {
+            input: &'a [u8],
+            read: std::rc::Rc<std::cell::Cell<usize>>,
+        }
+        impl Read for Counting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.input.read(buf)?;
+                self.read.set(self.read.get() + n);
+                Ok(n)
+            }
+        }
+
+        let csv: String = std::iter::once("timestamp,value,series\n".to_string())
+            .chain((0..20_000).map(|i| format!("{},{},cpu\n", i, i)))
+            .collect();
+        let json = format!(
+            "[{}]",
+            (0..20_000).map(|i| format!(r#"{{"timestamp": {}, "value": {}, "series": "cpu"}}"#, i, i)).collect::<Vec<_>>().join(",")
+        );
+
+        let read = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut first_seen_at = None;
+        let count = CsvParser::new()
+            .parse_stream(Counting { input: csv.as_bytes(), read: read.clone() }, |point| {
+                first_seen_at.get_or_insert((point.timestamp(), read.get()));
+            })
+            .unwrap();
+        assert_eq!(count, 20_000);
+        assert!(matches!(first_seen_at, Some((0, at)) if at < csv.len()));
+
+        read.set(0);
+        let mut timestamps = Vec::new();
+        let mut first_seen_at = None;
+        let count = JsonParser::new()
+            .parse_stream(Counting { input: json.as_bytes(), read: read.clone() }, |point| {
+                first_seen_at.get_or_insert(read.get());
+                timestamps.push(point.timestamp());
+            })
+            .unwrap();
+        assert_eq!(count, 20_000);
+        assert!(first_seen_at.unwrap() < json.len());
+        assert_eq!(timestamps, (0..20_000).collect::<Vec<i64>>());
+
+        // Objects are read whole, and errors surface as from parse
+        let batch = r#" {"tags": {"host": "a"}, "points": [{"timestamp": 1, "value": 2, "series": "cpu"}]}"#;
+        let mut points = Vec::new();
+        assert_eq!(JsonParser::new().parse_stream(batch.as_bytes(), |point| points.push(point)).unwrap(), 1);
+        assert_eq!(points[0].tags()["host"], "a");
+        let invalid = r#"[{"timestamp": 1, "value": 2}, {"timestamp": 2, "value": "x"}]"#;
+        assert!(matches!(
+            JsonParser::new().parse_stream(invalid.as_bytes(), |_| {}),
+            Err(ParserError::InvalidFieldType(_))
+        ));
+    }
+
+    #[test]
+    fn test_line_protocol_parser() {
+        let parser = LineProtocolParser::new();
+        let input = "cpu,host=server\\ 1,region=us\\,west usage_idle=92.5,usage_user=3i 1000\n\
+                    # comment lines are skipped\n\
+                    mem,host=server2 used=1024u,healthy=t,note=\"a, b\" 2000"
+            .as_bytes();
+
+        let points = parser.parse(input).unwrap();
+        assert_eq!(points.len(), 4);
+
+        assert_eq!(points[0].timestamp(), 1000);
+        assert_eq!(points[0].value(), 92.5);
+        assert_eq!(points[0].tags().get("series"), Some(&"cpu.usage_idle".to_string()));
+        assert_eq!(points[0].tags().get("host"), Some(&"server 1".to_string()));
+        assert_eq!(points[0].tags().get("region"), Some(&"us,west".to_string()));
+        assert_eq!(points[1].value(), 3.0);
+        assert_eq!(points[1].tags().get("series"), Some(&"cpu.usage_user".to_string()));
+
+        // Unsigned and boolean fields are numeric, string fields are skipped
+        assert_eq!(points[2].value(), 1024.0);
+        assert_eq!(points[3].value(), 1.0);
+        assert_eq!(points[3].tags().get("series"), Some(&"mem.healthy".to_string()));
+    }
+
+    #[test]
+    fn test_line_protocol_parser_optional_timestamp_and_errors() {
+        let parser = LineProtocolParser::new();
+
+        let points = parser.parse("disk free=10".as_bytes()).unwrap();
+        assert_eq!(points.len(), 1);
+        assert!(points[0].timestamp() > 0);
+
+        assert!(matches!(
+            parser.parse("disk".as_bytes()),
+            Err(ParserError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            parser.parse("disk free=abc 1000".as_bytes()),
+            Err(ParserError::InvalidFieldType(_))
+        ));
+        assert!(matches!(
+            parser.parse("disk free=1 not_a_timestamp".as_bytes()),
+            Err(ParserError::InvalidFieldType(_))
+        ));
+    }
+
+    #[test]
+    fn test_prometheus_text_parser() {
+        let parser = PrometheusTextParser::new();
+        let input = "# HELP http_requests_total Total requests\n\
+                    # TYPE http_requests_total counter\n\
+                    http_requests_total{method=\"post\",path=\"/api \\\"v1\\\"\"} 1027 1680000000000\n\
+                    up 1\n\
+                    # TYPE request_duration histogram\n\
+                    request_duration_bucket{le=\"0.5\"} 24 1680000000000\n\
+                    request_duration_bucket{le=\"+Inf\"} 30 1680000000000\n\
+                    request_duration_sum 12.5 1680000000000\n\
+                    request_duration_count 30 1680000000000"
+            .as_bytes();
+
+        let points = parser.parse(input).unwrap();
+        assert_eq!(points.len(), 6);
+
+        assert_eq!(points[0].timestamp(), 1_680_000_000_000_000_000);
+        assert_eq!(points[0].value(), 1027.0);
+        assert_eq!(points[0].tags().get("series"), Some(&"http_requests_total".to_string()));
+        assert_eq!(points[0].tags().get("method"), Some(&"post".to_string()));
+        assert_eq!(points[0].tags().get("path"), Some(&"/api \"v1\"".to_string()));
+
+        assert_eq!(points[1].tags().get("series"), Some(&"up".to_string()));
+        assert!(points[1].timestamp() > 0);
+
+        // Histogram components become separate series
+        assert_eq!(points[2].tags().get("series"), Some(&"request_duration_bucket".to_string()));
+        assert_eq!(points[3].tags().get("le"), Some(&"+Inf".to_string()));
+        assert_eq!(points[4].tags().get("series"), Some(&"request_duration_sum".to_string()));
+        assert_eq!(points[4].value(), 12.5);
+        assert_eq!(points[5].tags().get("series"), Some(&"request_duration_count".to_string()));
+
+        assert!(matches!(
+            parser.parse("broken{label=\"x\" 1".as_bytes()),
+            Err(ParserError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            parser.parse("metric not_a_number".as_bytes()),
+            Err(ParserError::InvalidFieldType(_))
+        ));
+    }
+
+    #[test]
+    fn test_out_of_range_timestamps() {
+        // JSON: below the configured range, and too large for i64
+        let parser = JsonParser::new().with_timestamp_range(0..=i64::MAX);
+        let err = parser
+            .parse(r#"{"timestamp": -5, "value": 1.0, "series": "cpu"}"#.as_bytes())
+            .unwrap_err();
+        match err {
+            ParserError::InvalidTimestamp(msg) => assert!(msg.contains("-5") && msg.contains("range")),
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(matches!(
+            parser.parse(r#"{"timestamp": 1e30, "value": 1.0}"#.as_bytes()),
+            Err(ParserError::InvalidTimestamp(_))
+        ));
+
+        // CSV: overflowing and out-of-range timestamps, while malformed ones keep their error
+        let parser = CsvParser::new().with_timestamp_range(1000..=2000);
+        let err = parser
+            .parse("timestamp,value\n99999999999999999999,1.0".as_bytes())
+            .unwrap_err();
+        assert!(matches!(err, ParserError::InvalidTimestamp(ref msg) if msg.contains("99999999999999999999")));
+        assert!(matches!(
+            parser.parse("timestamp,value\n3000,1.0".as_bytes()),
+            Err(ParserError::InvalidTimestamp(_))
+        ));
+        assert!(matches!(
+            parser.parse("timestamp,value\nsoon,1.0".as_bytes()),
+            Err(ParserError::InvalidFieldType(_))
+        ));
+        assert_eq!(parser.parse("timestamp,value\n1500,1.0".as_bytes()).unwrap()[0].timestamp(), 1500);
+    }
+
+    #[test]
+    fn test_timestamp_precisions_reach_the_same_instant() {
+        let instant = 1_700_000_000_000_000_000;
+        let precisions = [
+            (TimestampPrecision::Seconds, "1700000000"),
+            (TimestampPrecision::Milliseconds, "1700000000000"),
+            (TimestampPrecision::Microseconds, "1700000000000000"),
+            (TimestampPrecision::Nanoseconds, "1700000000000000000"),
+        ];
+        for (precision, timestamp) in precisions {
+            let json = format!(r#"{{"timestamp": {}, "value": 1.0, "series": "cpu"}}"#, timestamp);
+            let points = JsonParser::new().with_timestamp_precision(precision).parse(json.as_bytes()).unwrap();
+            assert_eq!(points[0].timestamp(), instant, "{:?}", precision);
+
+            let csv = format!("timestamp,value,series\n{},1.0,cpu\n", timestamp);
+            let points = CsvParser::new().with_timestamp_precision(precision).parse(csv.as_bytes()).unwrap();
+            assert_eq!(points[0].timestamp(), instant, "{:?}", precision);
+        }
+
+        // Fractional seconds keep their sub-second part
+        let json = r#"{"timestamp": 1700000000.5, "value": 1.0, "series": "cpu"}"#;
+        let points = JsonParser::new()
+            .with_timestamp_precision(TimestampPrecision::Seconds)
+            .parse(json.as_bytes())
+            .unwrap();
+        assert_eq!(points[0].timestamp(), instant + 500_000_000);
+
+        // A timestamp that is clearly in seconds is not read as milliseconds
+        let json = r#"{"timestamp": 1700000000, "value": 1.0, "series": "cpu"}"#;
+        assert!(matches!(
+            JsonParser::new()
+                .with_timestamp_precision(TimestampPrecision::Milliseconds)
+                .parse(json.as_bytes()),
+            Err(ParserError::InvalidTimestamp(_))
+        ));
+        let csv = "timestamp,value,series\n99999999999999,1.0,cpu\n";
+        assert!(matches!(
+            CsvParser::new().with_timestamp_precision(TimestampPrecision::Seconds).parse(csv.as_bytes()),
+            Err(ParserError::InvalidTimestamp(_))
+        ));
+    }
+
+    #[test]
+    fn test_csv_parser_sniffs_tab_and_pipe_delimiters() {
+        let tsv = "timestamp\tvalue\tseries\thost\n1000\t1.5\tcpu\ta,b\n2000\t2.5\tcpu\tc\n";
+        let pipe = "timestamp|value|series\n1000|1.5|cpu\n2000|2.5|cpu\n";
+        assert_eq!(sniff_delimiter(tsv.as_bytes()), b'\t');
+        assert_eq!(sniff_delimiter(pipe.as_bytes()), b'|');
+        assert_eq!(sniff_delimiter(b"timestamp,value,series\n1000,1.5,cpu\n"), b',');
+
+        let registry = crate::ingestion::ParserRegistry::new();
+        registry
+            .register(std::sync::Arc::new(CsvParser::new()), crate::ingestion::Priority::Normal)
+            .unwrap();
+        let points = registry.parse_with_format("text/tab-separated-values", tsv.as_bytes()).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].tags().get("host"), Some(&"a,b".to_string()));
+        let points = registry.parse_with_autodiscovery(pipe.as_bytes()).unwrap();
+        assert_eq!(points[1].timestamp(), 2000);
+        assert_eq!(points[1].value(), 2.5);
+    }
+
+    #[test]
+    fn test_json_tag_placement() {
+        let tags = |input: &str, parser: &JsonParser| -> Vec<(String, String)> {
+            let points = parser.parse(input.as_bytes()).unwrap();
+            let mut tags: Vec<_> = points[0].tags().clone().into_iter().collect();
+            tags.sort();
+            tags
+        };
+        let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+        let parser = JsonParser::new();
+
+        // Flat
+        let flat = r#"{"timestamp": 1, "value": 1.0, "series": "cpu", "host": "a", "cores": 4}"#;
+        assert_eq!(tags(flat, &parser), vec![pair("host", "a"), pair("series", "cpu")]);
+
+        // Nested, under a configurable field
+        let nested = r#"{"timestamp": 1, "value": 1.0, "series": "cpu", "labels": {"host": "a", "dc": "1"}}"#;
+        assert_eq!(
+            tags(nested, &JsonParser::new().with_tags_field("labels")),
+            vec![pair("dc", "1"), pair("host", "a"), pair("series", "cpu")]
+        );
+
+        // Mixed; the tag object wins over a top-level field of the same name
+        let mixed = r#"{"timestamp": 1, "value": 1.0, "series": "cpu", "host": "a", "dc": "1", "tags": {"host": "b"}}"#;
+        assert_eq!(tags(mixed, &parser), vec![pair("dc", "1"), pair("host", "b"), pair("series", "cpu")]);
+
+        // Non-string tag values are rejected unless stringified
+        let typed = r#"{"timestamp": 1, "value": 1.0, "tags": {"rack": 7, "ssd": true, "zone": null}}"#;
+        assert!(matches!(parser.parse(typed.as_bytes()), Err(ParserError::InvalidFieldType(_))));
+        assert_eq!(
+            tags(typed, &JsonParser::new().with_non_string_tags(NonStringTags::Stringify)),
+            vec![pair("rack", "7"), pair("ssd", "true")]
+        );
+    }
 }
@@ -0,0 +1,287 @@
+//! HTTP ingestion endpoint
+//!
+//! `POST /write` decompresses the request body as its `Content-Encoding` says,
+//! parses it with the [`ParserRegistry`], validates every point, then stores
+//! the batch through [`StorageEngine::ingest_batch`].
+
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use thiserror::Error;
+use tokio::net::TcpListener;
+
+use super::encoding::{self, ContentEncoding, DEFAULT_MAX_DECOMPRESSED_SIZE};
+use super::parser::ParserError;
+use super::registry::ParserRegistry;
+use super::validation::{ValidationError, ValidationMiddleware};
+use crate::storage::data::{DataError, DataPoint, TimeSeries};
+use crate::storage::engine::{EngineError, StorageEngine};
+use crate::storage::lsm::MemTableError;
+
+/// Error type for HTTP writes
+#[derive(Debug, Error)]
+pub enum WriteError {
+    #[error("{0}")]
+    Parse(#[from] ParserError),
+    #[error("{0}")]
+    Validation(#[from] ValidationError),
+    #[error("{0}")]
+    InvalidSeries(#[from] DataError),
+    #[error("Write failed: {0}")]
+    Storage(#[from] EngineError),
+}
+
+impl IntoResponse for WriteError {
+    /// Rejected input, including points the MemTable refuses as out of order or
+    /// duplicate, is a 400 with the error message. An unknown content encoding
+    /// is a 415 and a body decompressing past the size cap a 413; a shutdown in
+    /// progress is a 503 and other storage failures are a 500
+    fn into_response(self) -> Response {
+        let status = match self {
+            WriteError::Parse(ParserError::UnsupportedEncoding(_)) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            WriteError::Parse(ParserError::DecompressedTooLarge(_)) => StatusCode::PAYLOAD_TOO_LARGE,
+            WriteError::Parse(_)
+            | WriteError::Validation(_)
+            | WriteError::InvalidSeries(_)
+            | WriteError::Storage(EngineError::MemTable(
+                MemTableError::InvalidTimestampOrder | MemTableError::DuplicateTimestamp { .. },
+            )) => {
+                StatusCode::BAD_REQUEST
+            }
+            WriteError::Storage(EngineError::ShuttingDown) => StatusCode::SERVICE_UNAVAILABLE,
+            WriteError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Everything a write needs, shared across requests
+pub struct WriteEndpoint {
+    registry: Arc<ParserRegistry>,
+    validator: Arc<ValidationMiddleware>,
+    engine: Arc<StorageEngine>,
+    /// Largest body accepted once decompressed
+    max_decompressed_size: usize,
+}
+
+impl WriteEndpoint {
+    /// Creates an endpoint writing to the given storage engine
+    pub fn new(registry: Arc<ParserRegistry>, validator: Arc<ValidationMiddleware>, engine: Arc<StorageEngine>) -> Self {
+        Self {
+            registry,
+            validator,
+            engine,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Sets the largest body accepted once decompressed
+    pub fn with_max_decompressed_size(mut self, max_size: usize) -> Self {
+        self.max_decompressed_size = max_size;
+        self
+    }
+
+    /// Returns a router serving `POST /write`
+    pub fn router(self) -> Router {
+        Router::new().route("/write", post(write)).with_state(Arc::new(self))
+    }
+
+    /// Parses a request body, using the parser registered for `content_type`
+    ///
+    /// The body is first decompressed as `content_encoding` says, refusing to
+    /// expand past the endpoint's size cap. The full content type is tried
+    /// first, then the media type without its parameters. Bodies without a
+    /// content type, or with one no parser is registered for, fall back to
+    /// autodiscovery.
+    pub fn parse(
+        &self,
+        content_type: Option<&str>,
+        content_encoding: Option<&str>,
+        body: &[u8],
+    ) -> Result<Vec<DataPoint>, ParserError> {
+        let encoding = ContentEncoding::parse(content_encoding.unwrap_or_default())?;
+        let body = encoding::decompress(encoding, body, self.max_decompressed_size)?;
+        let body = &*body;
+        let format = content_type.and_then(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or_default().trim();
+            [content_type.trim(), media_type]
+                .into_iter()
+                .find(|format| self.registry.get_parser(format).is_ok())
+        });
+        match format {
+            Some(format) => self.registry.parse_with_format(format, body),
+            None => self.registry.parse_with_autodiscovery(body),
+        }
+    }
+
+    /// Validates every point, then stores them through the storage engine
+    ///
+    /// Nothing is written if any point fails validation, and a batch that is
+    /// not stored gives its validation counts back.
+    pub async fn write_points(&self, points: &[DataPoint]) -> Result<(), WriteError> {
+        self.validator.validate_batch(points)?;
+        let stored = self.store(points).await;
+        if stored.is_err() {
+            self.validator.release(points);
+        }
+        stored
+    }
+
+    /// Stores validated points as one batch
+    async fn store(&self, points: &[DataPoint]) -> Result<(), WriteError> {
+        // Validated points always carry a series tag
+        let series = points
+            .iter()
+            .map(|point| TimeSeries::new(point.tags()["series"].clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let batch: Vec<(&TimeSeries, &DataPoint)> = series.iter().zip(points).collect();
+        self.engine.ingest_batch(&batch).await?;
+        Ok(())
+    }
+}
+
+/// Handles `POST /write`, answering 204 once the points are stored
+async fn write(State(endpoint): State<Arc<WriteEndpoint>>, headers: HeaderMap, body: Bytes) -> Result<StatusCode, WriteError> {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    let content_encoding = headers
+        .get(header::CONTENT_ENCODING)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()));
+    let points = endpoint.parse(content_type, content_encoding.as_deref(), &body)?;
+    endpoint.write_points(&points).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serves the write endpoint on `listener` until the server fails
+pub async fn serve(listener: TcpListener, endpoint: WriteEndpoint) -> std::io::Result<()> {
+    axum::serve(listener, endpoint.router()).await
+}
+
+/// Serves the write endpoint until `shutdown` completes
+///
+/// Stops accepting connections once `shutdown` completes and returns after the
+/// requests already in flight have been answered, so their points are stored
+/// before the storage engine shuts down.
+pub async fn serve_with_shutdown<F>(listener: TcpListener, endpoint: WriteEndpoint, shutdown: F) -> std::io::Result<()>
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    axum::serve(listener, endpoint.router()).with_graceful_shutdown(shutdown).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingestion::formats::{CsvParser, JsonParser};
+    use crate::ingestion::registry::Priority;
+    use axum::body::Body;
+    use crate::storage::lsm::MemTable;
+    use crate::storage::wal::WriteAheadLog;
+    use axum::http::Request;
+    use tempfile::tempdir;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_write_endpoint() {
+        let dir = tempdir().unwrap();
+        let registry = ParserRegistry::new();
+        registry.register(Arc::new(JsonParser::new()), Priority::Normal).unwrap();
+        registry.register(Arc::new(CsvParser::new()), Priority::Normal).unwrap();
+        let engine = Arc::new(StorageEngine::open(dir.path(), MemTable::new(1000)).await.unwrap());
+        let router = WriteEndpoint::new(Arc::new(registry), Arc::new(ValidationMiddleware::new()), engine.clone()).router();
+
+        let request = |content_type: Option<&str>, body: &'static str| {
+            let mut builder = Request::post("/write");
+            if let Some(content_type) = content_type {
+                builder = builder.header(header::CONTENT_TYPE, content_type);
+            }
+            builder.body(Body::from(body)).unwrap()
+        };
+
+        let csv = "timestamp,value,series\n1000,1.5,cpu\n2000,2.5,cpu\n";
+        let response = router.clone().oneshot(request(Some("text/csv; charset=utf-8"), csv)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let json = r#"{"timestamp": 3000, "value": 3.5, "series": "cpu"}"#;
+        let response = router.clone().oneshot(request(None, json)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let missing_series = r#"{"timestamp": 4000, "value": 4.5}"#;
+        let response = router.clone().oneshot(request(Some("application/json"), missing_series)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // A batch with a point the MemTable refuses is rejected before any of it is logged
+        let stale = "timestamp,value,series\n4000,4.5,cpu\n2500,2.5,cpu\n";
+        let response = router.oneshot(request(Some("text/csv"), stale)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let mut logged = 0;
+        WriteAheadLog::new(dir.path().join("wal"))
+            .unwrap()
+            .replay(|_, _| {
+                logged += 1;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(logged, 3);
+
+        let data = engine.memtable().read().await.get_data().await;
+        let timestamps: Vec<i64> = data["cpu"].iter().map(|point| point.timestamp()).collect();
+        assert_eq!(timestamps, vec![1000, 2000, 3000]);
+    }
+
+    #[tokio::test]
+    async fn test_write_endpoint_decompresses_bodies() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let dir = tempdir().unwrap();
+        let registry = ParserRegistry::new();
+        registry.register(Arc::new(JsonParser::new()), Priority::Normal).unwrap();
+        let engine = Arc::new(StorageEngine::open(dir.path(), MemTable::new(1000)).await.unwrap());
+        let validator = Arc::new(ValidationMiddleware::new());
+        let endpoint = WriteEndpoint::new(Arc::new(registry), validator.clone(), engine.clone());
+        let router = endpoint.with_max_decompressed_size(1024).router();
+
+        let gzip = |body: &str| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let request = |encoding: &str, body: Vec<u8>| {
+            Request::post("/write")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::CONTENT_ENCODING, encoding)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let json = r#"[{"timestamp": 1000, "value": 1.5, "series": "cpu", "host": "a"},
+                       {"timestamp": 2000, "value": 2.5, "series": "cpu", "host": "a"}]"#;
+        let response = router.clone().oneshot(request("gzip", gzip(json))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let data = engine.memtable().read().await.get_data().await;
+        assert_eq!(data["cpu"].len(), 2);
+
+        let response = router.clone().oneshot(request("br", gzip(json))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let large = format!("[{}]", vec![r#"{"timestamp": 3000, "value": 1.0, "series": "cpu"}"#; 40].join(","));
+        let response = router.clone().oneshot(request("gzip", gzip(&large))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // A batch the MemTable refuses gives its validation counts back
+        let before = validator.cardinality_snapshot();
+        let duplicate = r#"[{"timestamp": 3000, "value": 1.0, "series": "mem", "host": "b"},
+                            {"timestamp": 2000, "value": 1.0, "series": "cpu", "host": "c"}]"#;
+        let response = router.oneshot(request("identity", duplicate.as_bytes().to_vec())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!engine.memtable().read().await.get_data().await.contains_key("mem"));
+        assert_eq!(validator.cardinality_snapshot(), before);
+    }
+}
@@ -1,13 +1,18 @@
 //! Ingestion module for VCTSDB
 //! Handles data ingestion from various formats and sources.
 
+pub mod encoding;
 pub mod formats;
+pub mod http;
 pub mod parser;
 pub mod registry;
 pub mod validation;
 
-pub use validation::{ValidationMiddleware, ValidationConfig, ValidationError};
-pub use registry::{ParserRegistry, Priority, RegistryError};
+pub use validation::{CardinalitySnapshot, ValidationMiddleware, ValidationConfig, ValidationError};
+pub use encoding::ContentEncoding;
+pub use http::{WriteEndpoint, WriteError};
+pub use registry::{detect_format, ParserRegistry, Priority, RegistryError};
+pub use parser::{Confidence, TimestampPrecision};
 
 #[cfg(test)]
 mod tests {
@@ -21,7 +26,7 @@ mod tests {
 
     #[test]
     fn test_validation_integration() {
-        let mut validator = ValidationMiddleware::new();
+        let validator = ValidationMiddleware::new();
         let mut tags = HashMap::new();
         tags.insert("series".to_string(), "test_series".to_string());
         tags.insert("host".to_string(), "server1".to_string());
@@ -94,7 +99,7 @@ mod tests {
     #[test]
     fn test_ingestion_throughput() {
         let parser = JsonParser::new();
-        let mut validator = ValidationMiddleware::new();
+        let validator = ValidationMiddleware::new();
         
         // Prepare a batch of test data
         let test_data = r#"{
@@ -253,4 +258,26 @@ mod tests {
         assert_eq!(json_points[0].timestamp(), 1000);
         assert_eq!(csv_points[0].timestamp(), 1000);
     }
+
+    #[test]
+    fn test_lenient_batch_keeps_good_inputs() {
+        let parser = JsonParser::new();
+        let inputs: Vec<&[u8]> = vec![
+            br#"{"timestamp": 1000, "value": 1.0, "series": "cpu"}"#,
+            br#"{"timestamp": "bad", "value": 2.0, "series": "cpu"}"#,
+            br#"[{"timestamp": 3000, "value": 3.0, "series": "cpu"},
+                 {"timestamp": 4000, "value": 4.0, "series": "cpu"}]"#,
+            b"{ not json",
+        ];
+
+        let (points, errors) = parser.parse_batch_lenient(&inputs);
+        let timestamps: Vec<i64> = points.iter().map(|p| p.timestamp()).collect();
+        assert_eq!(timestamps, vec![1000, 3000, 4000]);
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], (1, ParserError::InvalidFieldType(_))));
+        assert!(matches!(errors[1], (3, ParserError::InvalidFormat(_))));
+
+        // The strict variant still fails the whole batch
+        assert!(matches!(parser.parse_batch(&inputs), Err(ParserError::BatchError(_))));
+    }
 }
@@ -12,31 +12,107 @@ pub enum ParserError {
     MissingField(String),
     #[error("Invalid field type: {0}")]
     InvalidFieldType(String),
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+    #[error("Undeclared column: {0}")]
+    UnknownColumn(String),
     #[error("Data validation error: {0}")]
     ValidationError(#[from] DataError),
     #[error("Batch processing error: {0}")]
     BatchError(String),
+    #[error("Unsupported content encoding: {0}")]
+    UnsupportedEncoding(String),
+    #[error("Decompression error: {0}")]
+    Decompression(String),
+    #[error("Decompressed input exceeds {0} bytes")]
+    DecompressedTooLarge(usize),
 }
 
 /// Result type for parser operations
 pub type ParserResult<T> = Result<T, ParserError>;
 
+/// How likely a parser is to accept an input, judged from its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// The input is not in the parser's format
+    None,
+    /// Nothing rules the parser's format out
+    Low,
+    /// The start of the input parses
+    Medium,
+    /// The input has the format's distinctive shape
+    High,
+}
+
+/// Bytes of input the default [`Parser::can_parse`] looks at
+const CONFIDENCE_PREFIX_LEN: usize = 1024;
+
+/// Unit of incoming timestamps, which parsers scale to nanoseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampPrecision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl TimestampPrecision {
+    /// Returns the number of nanoseconds in one unit
+    pub fn nanos_per_unit(self) -> i64 {
+        match self {
+            TimestampPrecision::Nanoseconds => 1,
+            TimestampPrecision::Microseconds => 1_000,
+            TimestampPrecision::Milliseconds => 1_000_000,
+            TimestampPrecision::Seconds => 1_000_000_000,
+        }
+    }
+
+    /// Guesses the unit of a timestamp from its magnitude
+    ///
+    /// Instants between 2001 and 2286 have 10 digits in seconds, 13 in
+    /// milliseconds, 16 in microseconds and 19 in nanoseconds. Timestamps with
+    /// any other number of digits are not guessed.
+    pub fn infer(timestamp: i64) -> Option<Self> {
+        match timestamp.unsigned_abs() {
+            1_000_000_000..=9_999_999_999 => Some(TimestampPrecision::Seconds),
+            1_000_000_000_000..=9_999_999_999_999 => Some(TimestampPrecision::Milliseconds),
+            1_000_000_000_000_000..=9_999_999_999_999_999 => Some(TimestampPrecision::Microseconds),
+            1_000_000_000_000_000_000.. => Some(TimestampPrecision::Nanoseconds),
+            _ => None,
+        }
+    }
+
+    /// Scales a timestamp in this unit to nanoseconds
+    ///
+    /// Fails if the timestamp's magnitude points to a different unit, e.g. a
+    /// 10-digit timestamp read as milliseconds, or if it overflows.
+    pub fn to_nanos(self, timestamp: i64) -> ParserResult<i64> {
+        if let Some(inferred) = Self::infer(timestamp).filter(|inferred| *inferred != self) {
+            return Err(ParserError::InvalidTimestamp(format!(
+                "{} looks like {:?}, but {:?} were expected",
+                timestamp, inferred, self
+            )));
+        }
+        timestamp.checked_mul(self.nanos_per_unit()).ok_or_else(|| {
+            ParserError::InvalidTimestamp(format!(
+                "{} {:?} does not fit in a 64-bit nanosecond timestamp",
+                timestamp, self
+            ))
+        })
+    }
+}
+
 /// Trait for parsing input data into DataPoints
 pub trait Parser {
     /// Parses a single input into a vector of DataPoints
     fn parse(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>>;
 
     /// Parses a batch of inputs into a vector of DataPoints
+    ///
+    /// Fails with a `BatchError` describing every failed input if any input
+    /// fails; see [`Parser::parse_batch_lenient`] to keep the successful ones.
     fn parse_batch(&self, inputs: &[&[u8]]) -> ParserResult<Vec<DataPoint>> {
-        let mut results = Vec::new();
-        let mut errors = Vec::new();
-
-        for (i, input) in inputs.iter().enumerate() {
-            match self.parse(input) {
-                Ok(points) => results.extend(points),
-                Err(e) => errors.push((i, e)),
-            }
-        }
+        let (results, errors) = self.parse_batch_lenient(inputs);
 
         if !errors.is_empty() {
             let error_msg = errors
@@ -50,6 +126,41 @@ pub trait Parser {
         Ok(results)
     }
 
+    /// Parses a batch of inputs, keeping the points of every input that parsed
+    ///
+    /// Failures are returned alongside the points, each with the index of the
+    /// input that produced it.
+    fn parse_batch_lenient(&self, inputs: &[&[u8]]) -> (Vec<DataPoint>, Vec<(usize, ParserError)>) {
+        let mut results = Vec::new();
+        let mut errors = Vec::new();
+
+        for (i, input) in inputs.iter().enumerate() {
+            match self.parse(input) {
+                Ok(points) => results.extend(points),
+                Err(e) => errors.push((i, e)),
+            }
+        }
+
+        (results, errors)
+    }
+
     /// Returns the supported input formats
     fn supported_formats(&self) -> Vec<&'static str>;
+
+    /// Judges how likely `input` is to be in this parser's format, without parsing all of it
+    ///
+    /// Used by autodiscovery to rank parsers before trying them. The default
+    /// parses the first non-blank line within the first kilobyte, which is a
+    /// whole input for line-oriented formats.
+    fn can_parse(&self, input: &[u8]) -> Confidence {
+        let prefix = &input[..input.len().min(CONFIDENCE_PREFIX_LEN)];
+        let first_line = prefix
+            .split(|&b| b == b'\n')
+            .find(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .unwrap_or_default();
+        match self.parse(first_line) {
+            Ok(points) if !points.is_empty() => Confidence::Medium,
+            _ => Confidence::Low,
+        }
+    }
 }
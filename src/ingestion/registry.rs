@@ -3,7 +3,8 @@ use std::sync::{Arc, RwLock};
 
 use thiserror::Error;
 
-use super::parser::{Parser, ParserResult};
+use super::encoding::{self, ContentEncoding, DEFAULT_MAX_DECOMPRESSED_SIZE};
+use super::parser::{Confidence, Parser, ParserResult};
 use crate::storage::data::DataPoint;
 
 /// Errors that can occur during parser registration and lookup
@@ -46,6 +47,8 @@ pub struct ParserRegistry {
     parsers: RwLock<HashMap<String, Vec<ParserEntry>>>,
     /// Default parsers to try when format is unknown
     default_parsers: RwLock<Vec<ParserEntry>>,
+    /// Largest body `parse_with_encoding` will decompress
+    max_decompressed_size: usize,
 }
 
 impl ParserRegistry {
@@ -54,9 +57,16 @@ impl ParserRegistry {
         Self {
             parsers: RwLock::new(HashMap::new()),
             default_parsers: RwLock::new(Vec::new()),
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
         }
     }
 
+    /// Sets the largest decompressed body accepted by `parse_with_encoding`
+    pub fn with_max_decompressed_size(mut self, max_size: usize) -> Self {
+        self.max_decompressed_size = max_size;
+        self
+    }
+
     /// Register a parser for specific formats with a given priority
     pub fn register<P>(
         &self,
@@ -120,9 +130,37 @@ impl ParserRegistry {
         Err(RegistryError::NoParserFound(format.to_string()))
     }
 
-    /// Parse data with autodiscovery (tries each parser until one succeeds)
+    /// Parse data with autodiscovery
+    ///
+    /// The input is first sniffed with [`detect_format`]; if that identifies a
+    /// format with registered parsers, only those parsers are tried. Otherwise
+    /// every parser is tried until one succeeds. Either way, parsers are tried
+    /// from most to least confident in the input (see [`Parser::can_parse`]),
+    /// ties in priority order then registration order, and parsers that rule
+    /// the input out are skipped.
     pub fn parse_with_autodiscovery(&self, input: &[u8]) -> ParserResult<Vec<DataPoint>> {
-        // First try with known format if provided
+        if let Some(format) = detect_format(input) {
+            let candidates: Vec<Arc<dyn Parser + Send + Sync>> = self
+                .parsers
+                .read()
+                .unwrap()
+                .get(&format)
+                .map(|entries| entries.iter().map(|entry| Arc::clone(&entry.parser)).collect())
+                .unwrap_or_default();
+            let candidates = rank_by_confidence(candidates, input);
+
+            if !candidates.is_empty() {
+                let mut last_error = None;
+                for parser in candidates {
+                    match parser.parse(input) {
+                        Ok(points) => return Ok(points),
+                        Err(err) => last_error = Some(err),
+                    }
+                }
+                return Err(last_error.unwrap());
+            }
+        }
+
         let default_parsers = self.default_parsers.read().unwrap();
         
         if default_parsers.is_empty() {
@@ -131,10 +169,21 @@ impl ParserRegistry {
             ));
         }
 
-        // Try each parser in priority order
+        let candidates = rank_by_confidence(
+            default_parsers.iter().map(|entry| Arc::clone(&entry.parser)).collect(),
+            input,
+        );
+        drop(default_parsers);
+        if candidates.is_empty() {
+            return Err(super::parser::ParserError::InvalidFormat(
+                "No registered parser recognizes the input".to_string(),
+            ));
+        }
+
+        // Try each parser from most to least confident
         let mut last_error = None;
-        for entry in default_parsers.iter() {
-            match entry.parser.parse(input) {
+        for parser in candidates {
+            match parser.parse(input) {
                 Ok(points) => return Ok(points),
                 Err(err) => last_error = Some(err),
             }
@@ -154,6 +203,23 @@ impl ParserRegistry {
         }
     }
 
+    /// Parse data using a specific format, decompressing it first
+    ///
+    /// `content_encoding` is a `Content-Encoding` header value: `gzip` and
+    /// `deflate` bodies are decompressed before parsing, and an empty value or
+    /// `identity` passes the input through. Decompressed output is capped by
+    /// [`ParserRegistry::with_max_decompressed_size`].
+    pub fn parse_with_encoding(
+        &self,
+        content_encoding: &str,
+        format: &str,
+        input: &[u8],
+    ) -> ParserResult<Vec<DataPoint>> {
+        let encoding = ContentEncoding::parse(content_encoding)?;
+        let decompressed = encoding::decompress(encoding, input, self.max_decompressed_size)?;
+        self.parse_with_format(format, &decompressed)
+    }
+
     /// Unregister a parser
     pub fn unregister<P>(&self, parser: &Arc<P>, format: Option<&str>) -> RegistryResult<()> 
     where 
@@ -193,6 +259,49 @@ impl ParserRegistry {
     }
 }
 
+/// Orders parsers from most to least confident in `input`, dropping those that rule it out
+///
+/// The sort is stable, so parsers of equal confidence keep their order.
+fn rank_by_confidence(
+    parsers: Vec<Arc<dyn Parser + Send + Sync>>,
+    input: &[u8],
+) -> Vec<Arc<dyn Parser + Send + Sync>> {
+    let mut ranked: Vec<(Confidence, Arc<dyn Parser + Send + Sync>)> = parsers
+        .into_iter()
+        .map(|parser| (parser.can_parse(input), parser))
+        .filter(|(confidence, _)| *confidence != Confidence::None)
+        .collect();
+    ranked.sort_by_key(|(confidence, _)| std::cmp::Reverse(*confidence));
+    ranked.into_iter().map(|(_, parser)| parser).collect()
+}
+
+/// Guesses the format of a payload by sniffing its first line
+///
+/// Returns the registry format key (`json`, `csv`, `prometheus` or `influx`),
+/// or `None` when the content is ambiguous. Tab- and pipe-delimited input is
+/// reported as `csv`, whose parser sniffs the delimiter.
+pub fn detect_format(input: &[u8]) -> Option<String> {
+    let start = input.iter().position(|b| !b.is_ascii_whitespace())?;
+    let text = &input[start..];
+    let first_line = text.split(|&b| b == b'\n').next().unwrap_or(text);
+    let first_line = String::from_utf8_lossy(first_line);
+    let first_line = first_line.trim();
+
+    let format = match text[0] {
+        b'{' | b'[' => "json",
+        b'#' if first_line.starts_with("# HELP") || first_line.starts_with("# TYPE") => {
+            "prometheus"
+        }
+        b'#' => return None,
+        _ if first_line.contains('{') && first_line.contains("=\"") => "prometheus",
+        _ if first_line.contains('=') && first_line.contains(' ') => "influx",
+        _ if first_line.contains([',', '\t', '|']) && !first_line.contains('=') => "csv",
+        _ => return None,
+    };
+
+    Some(format.to_string())
+}
+
 impl Default for ParserRegistry {
     fn default() -> Self {
         Self::new()
@@ -301,4 +410,111 @@ mod tests {
         // Should still be registered for "json" format
         assert!(registry.get_parser("json").is_ok());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_detect_format_routes_autodiscovery() {
+        use crate::ingestion::formats::CsvParser;
+        use crate::ingestion::parser::ParserResult;
+
+        /// Accepts anything, to simulate a parser that misparses foreign input
+        struct LenientParser;
+
+        impl Parser for LenientParser {
+            fn parse(&self, _input: &[u8]) -> ParserResult<Vec<DataPoint>> {
+                Ok(Vec::new())
+            }
+
+            fn supported_formats(&self) -> Vec<&'static str> {
+                vec!["json"]
+            }
+        }
+
+        assert_eq!(detect_format(b"  {\"value\": 1}"), Some("json".to_string()));
+        assert_eq!(detect_format(b"[1, 2]"), Some("json".to_string()));
+        assert_eq!(detect_format(b"timestamp,value,series\n1000,1,a"), Some("csv".to_string()));
+        assert_eq!(detect_format(b"cpu,host=a usage=1 1000"), Some("influx".to_string()));
+        assert_eq!(detect_format(b"# TYPE up gauge\nup 1"), Some("prometheus".to_string()));
+        assert_eq!(detect_format(b"up{job=\"api\"} 1"), Some("prometheus".to_string()));
+        assert_eq!(detect_format(b"   "), None);
+        assert_eq!(detect_format(b"plain"), None);
+
+        // The lenient parser outranks CSV but is never consulted for CSV content
+        let registry = ParserRegistry::new();
+        registry.register(Arc::new(LenientParser), Priority::High).unwrap();
+        registry.register(Arc::new(CsvParser::new()), Priority::Normal).unwrap();
+
+        let csv_data = "timestamp,value,series\n1000,42.5,test_series".as_bytes();
+        let points = registry.parse_with_autodiscovery(csv_data).unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].value(), 42.5);
+
+        // Ambiguous content falls back to the priority loop
+        assert!(registry.parse_with_autodiscovery(b"plain").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_autodiscovery_ranks_parsers_by_confidence() {
+        use crate::ingestion::formats::CsvParser;
+        use crate::ingestion::parser::ParserResult;
+
+        /// Accepts anything, to simulate a parser that misparses foreign input
+        struct LenientParser;
+
+        impl Parser for LenientParser {
+            fn parse(&self, _input: &[u8]) -> ParserResult<Vec<DataPoint>> {
+                Ok(Vec::new())
+            }
+
+            fn supported_formats(&self) -> Vec<&'static str> {
+                vec!["lenient"]
+            }
+        }
+
+        let json = br#"[{"timestamp": 1000, "value": 1.5, "series": "cpu"}]"#;
+        // The `=` in the header keeps detect_format from calling this CSV
+        let csv = b"timestamp,value,series,a=b\n1000,42.5,cpu,x";
+        assert_eq!(detect_format(csv), None);
+
+        assert_eq!(JsonParser::new().can_parse(json), Confidence::High);
+        assert_eq!(JsonParser::new().can_parse(csv), Confidence::None);
+        assert_eq!(CsvParser::new().can_parse(csv), Confidence::High);
+        assert_eq!(CsvParser::new().can_parse(json), Confidence::Low);
+        assert_eq!(CsvParser::new().can_parse(b"plain"), Confidence::None);
+        assert_eq!(LenientParser.can_parse(csv), Confidence::Low);
+
+        // The lenient parser outranks CSV by priority, but not by confidence
+        let registry = ParserRegistry::new();
+        registry.register(Arc::new(LenientParser), Priority::High).unwrap();
+        registry.register(Arc::new(CsvParser::new()), Priority::Normal).unwrap();
+        let points = registry.parse_with_autodiscovery(csv).unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].tags()["a=b"], "x");
+    }
+
+    #[test]
+    fn test_parse_with_encoding() {
+        use crate::ingestion::formats::CsvParser;
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let registry = ParserRegistry::new().with_max_decompressed_size(1024);
+        registry.register(Arc::new(CsvParser::new()), Priority::Normal).unwrap();
+
+        let input = "timestamp,value,series\n1000,42.5,cpu\n2000,43.5,cpu";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let points = registry.parse_with_encoding("gzip", "csv", &gzip).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].value(), 43.5);
+        let points = registry.parse_with_encoding("", "csv", input.as_bytes()).unwrap();
+        assert_eq!(points.len(), 2);
+
+        assert!(matches!(
+            registry.parse_with_encoding("gzip", "csv", &gzip[..gzip.len() / 2]),
+            Err(crate::ingestion::parser::ParserError::Decompression(_))
+        ));
+    }
+}
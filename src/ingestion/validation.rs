@@ -1,7 +1,12 @@
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
-use crate::storage::data::{DataPoint, DataError};
+use crate::storage::data::{CharacterSet, DataPoint, DataError};
 
 #[derive(Error, Debug)]
 pub enum ValidationError {
@@ -9,6 +14,10 @@ pub enum ValidationError {
     CardinalityLimitExceeded(String, usize, usize),
     #[error("Value sanity check failed: {0}")]
     ValueSanityCheck(String),
+    #[error("Point has {0} tags, more than the limit of {1}")]
+    TooManyTags(usize, usize),
+    #[error("Write rate limit exceeded for series {0}: {1} points/s > {2}")]
+    RateLimitExceeded(String, usize, usize),
     #[error("Data validation error: {0}")]
     DataError(#[from] DataError),
 }
@@ -20,10 +29,19 @@ pub struct ValidationConfig {
     pub max_series: usize,
     /// Maximum number of unique tag values per tag key
     pub max_tag_values: usize,
+    /// Maximum number of tags on a single point, including `series`
+    pub max_tags_per_point: usize,
     /// Maximum allowed value (for sanity checking)
     pub max_value: f64,
     /// Minimum allowed value (for sanity checking)
     pub min_value: f64,
+    /// Reject NaN and infinite values
+    pub reject_non_finite: bool,
+    /// Characters accepted in tag keys and values
+    pub tag_charset: CharacterSet,
+    /// Maximum points accepted per series in any one-second window; `None`
+    /// disables rate limiting
+    pub max_points_per_second: Option<usize>,
 }
 
 impl Default for ValidationConfig {
@@ -31,17 +49,80 @@ impl Default for ValidationConfig {
         Self {
             max_series: 100_000,
             max_tag_values: 10_000,
+            max_tags_per_point: 256,
             max_value: f64::MAX,
             min_value: f64::MIN,
+            reject_non_finite: true,
+            tag_charset: CharacterSet::default(),
+            max_points_per_second: None,
         }
     }
 }
 
+/// Unique-value counts tracked by a [`ValidationMiddleware`] at one moment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CardinalitySnapshot {
+    /// Number of distinct series
+    pub series: usize,
+    /// Number of distinct values per tag key, excluding `series`
+    pub tag_values: BTreeMap<String, usize>,
+}
+
+/// Number of lock shards used for cardinality tracking
+const SHARD_COUNT: usize = 16;
+
+/// Window over which per-series write rates are measured
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
 /// Validation middleware for data points
+///
+/// Cardinality counters are split across independently locked shards so that
+/// `validate` can be called concurrently from many ingestion tasks.
 pub struct ValidationMiddleware {
     config: ValidationConfig,
-    series_counts: HashMap<String, usize>,
-    tag_value_counts: HashMap<String, HashMap<String, usize>>,
+    /// Point counts per series, sharded by series name
+    series_counts: Vec<Mutex<HashMap<String, usize>>>,
+    /// Number of distinct series across all shards
+    series_total: AtomicUsize,
+    /// Point counts per tag value, sharded by tag key
+    tag_value_counts: Vec<Mutex<HashMap<String, HashMap<String, usize>>>>,
+    /// Arrival times of each series' points within the rate window, sharded by
+    /// series name
+    rate_windows: Vec<Mutex<RateShard>>,
+    hasher: RandomState,
+}
+
+/// Rate windows of the series in one shard
+#[derive(Debug)]
+struct RateShard {
+    /// Arrival times per series; each buffer holds at most
+    /// `max_points_per_second` entries
+    windows: HashMap<String, VecDeque<Instant>>,
+    /// When windows idle for a whole rate window were last dropped
+    pruned_at: Instant,
+}
+
+impl RateShard {
+    fn new() -> Self {
+        Self {
+            windows: HashMap::new(),
+            pruned_at: Instant::now(),
+        }
+    }
+
+    /// Drops the windows of series without an arrival in the last rate window,
+    /// at most once per rate window
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.pruned_at) < RATE_WINDOW {
+            return;
+        }
+        self.windows.retain(|_, window| {
+            window
+                .back()
+                .is_some_and(|arrival| now.duration_since(*arrival) < RATE_WINDOW)
+        });
+        self.pruned_at = now;
+    }
 }
 
 impl ValidationMiddleware {
@@ -54,17 +135,148 @@ impl ValidationMiddleware {
     pub fn with_config(config: ValidationConfig) -> Self {
         Self {
             config,
-            series_counts: HashMap::new(),
-            tag_value_counts: HashMap::new(),
+            series_counts: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+            series_total: AtomicUsize::new(0),
+            tag_value_counts: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+            rate_windows: (0..SHARD_COUNT).map(|_| Mutex::new(RateShard::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Returns the shard index owning a key
+    fn shard_for(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) as usize) % SHARD_COUNT
+    }
+
+    /// Records a write to `series`, failing if it already had the maximum
+    /// number of points in the last second
+    ///
+    /// Rejected writes are not recorded, so a flooding series regains capacity
+    /// as its accepted points age out of the window.
+    fn check_rate(&self, series_name: &str, limit: usize) -> Result<(), ValidationError> {
+        let now = Instant::now();
+        let mut shard = self.rate_windows[self.shard_for(series_name)].lock().unwrap();
+        shard.prune(now);
+        let window = shard
+            .windows
+            .entry(series_name.to_string())
+            .or_insert_with(|| VecDeque::with_capacity(limit));
+        while window.front().is_some_and(|arrival| now.duration_since(*arrival) >= RATE_WINDOW) {
+            window.pop_front();
+        }
+        if window.len() >= limit {
+            return Err(ValidationError::RateLimitExceeded(
+                series_name.to_string(),
+                window.len() + 1,
+                limit,
+            ));
+        }
+        window.push_back(now);
+        Ok(())
+    }
+
+    /// Counts a point of `series`, failing if it would exceed the series limit
+    fn record_series(&self, series_name: &str) -> Result<(), ValidationError> {
+        let mut series_counts = self.series_counts[self.shard_for(series_name)].lock().unwrap();
+        if !series_counts.contains_key(series_name) {
+            // Reserve a slot in the global total; the shard lock guarantees the
+            // series is only counted once
+            self.series_total
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+                    (total < self.config.max_series).then_some(total + 1)
+                })
+                .map_err(|total| {
+                    ValidationError::CardinalityLimitExceeded(
+                        series_name.to_string(),
+                        total,
+                        self.config.max_series
+                    )
+                })?;
+            series_counts.insert(series_name.to_string(), 0);
+        }
+        *series_counts.get_mut(series_name).unwrap() += 1;
+        Ok(())
+    }
+
+    /// Counts a tag value, failing if it is new and its key is at the value limit
+    fn record_tag(&self, key: &str, value: &str) -> Result<(), ValidationError> {
+        // All values of a tag key live in the same shard
+        let mut shard = self.tag_value_counts[self.shard_for(key)].lock().unwrap();
+        let tag_values = shard.entry(key.to_string()).or_default();
+        if let Some(count) = tag_values.get_mut(value) {
+            *count += 1;
+            return Ok(());
+        }
+        if tag_values.len() >= self.config.max_tag_values {
+            return Err(ValidationError::CardinalityLimitExceeded(
+                format!("tag:{}", key),
+                tag_values.len(),
+                self.config.max_tag_values
+            ));
+        }
+        tag_values.insert(value.to_string(), 1);
+        Ok(())
+    }
+
+    /// Drops the newest arrival recorded by [`ValidationMiddleware::check_rate`]
+    fn forget_rate(&self, series_name: &str) {
+        let mut shard = self.rate_windows[self.shard_for(series_name)].lock().unwrap();
+        if let Some(window) = shard.windows.get_mut(series_name) {
+            window.pop_back();
+            if window.is_empty() {
+                shard.windows.remove(series_name);
+            }
+        }
+    }
+
+    /// Undoes [`ValidationMiddleware::record_series`], forgetting a series left without points
+    fn forget_series(&self, series_name: &str) {
+        let mut series_counts = self.series_counts[self.shard_for(series_name)].lock().unwrap();
+        if let Some(count) = series_counts.get_mut(series_name) {
+            *count -= 1;
+            if *count == 0 {
+                series_counts.remove(series_name);
+                self.series_total.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+
+    /// Undoes [`ValidationMiddleware::record_tag`], forgetting a value left without points
+    fn forget_tag(&self, key: &str, value: &str) {
+        let mut shard = self.tag_value_counts[self.shard_for(key)].lock().unwrap();
+        let Some(tag_values) = shard.get_mut(key) else { return };
+        if let Some(count) = tag_values.get_mut(value) {
+            *count -= 1;
+            if *count == 0 {
+                tag_values.remove(value);
+            }
+        }
+        if tag_values.is_empty() {
+            shard.remove(key);
         }
     }
 
     /// Validates a data point against the configured rules
-    pub fn validate(&mut self, point: &DataPoint) -> Result<(), ValidationError> {
+    ///
+    /// The point is counted towards the cardinality and rate limits only if it
+    /// passes every check; a rejected point leaves the counters as they were.
+    pub fn validate(&self, point: &DataPoint) -> Result<(), ValidationError> {
         // Validate the data point itself
-        point.validate()?;
+        point.validate_with(self.config.tag_charset)?;
 
-        // Check value sanity
+        // Over-tagged points are rejected before they touch any counters
+        let tag_count = point.tags().len();
+        if tag_count > self.config.max_tags_per_point {
+            return Err(ValidationError::TooManyTags(tag_count, self.config.max_tags_per_point));
+        }
+
+        // Check value sanity; NaN compares false against both bounds
+        if self.config.reject_non_finite && !point.value().is_finite() {
+            return Err(ValidationError::ValueSanityCheck(format!(
+                "Value {} is not finite",
+                point.value()
+            )));
+        }
         if point.value() > self.config.max_value {
             return Err(ValidationError::ValueSanityCheck(format!(
                 "Value {} exceeds maximum allowed value {}",
@@ -84,52 +296,90 @@ impl ValidationMiddleware {
         let series_name = point.tags().get("series")
             .ok_or_else(|| ValidationError::ValueSanityCheck("Missing series tag".to_string()))?;
 
-        // Check series cardinality
-        if !self.series_counts.contains_key(series_name) {
-            if self.series_counts.len() >= self.config.max_series {
-                return Err(ValidationError::CardinalityLimitExceeded(
-                    series_name.clone(),
-                    self.series_counts.len(),
-                    self.config.max_series
-                ));
+        // Check series cardinality, then tag value cardinality (the series tag
+        // is handled as the series), then the write rate, unwinding whatever
+        // this point counted if a later check fails
+        self.record_series(series_name)?;
+        let tags: Vec<(&String, &String)> = point.tags().iter().filter(|(key, _)| *key != "series").collect();
+        let mut recorded = 0;
+        let mut checked = tags
+            .iter()
+            .try_for_each(|(key, value)| {
+                self.record_tag(key, value)?;
+                recorded += 1;
+                Ok(())
+            });
+        if let (Ok(()), Some(limit)) = (&checked, self.config.max_points_per_second) {
+            checked = self.check_rate(series_name, limit);
+        }
+        if checked.is_err() {
+            for (key, value) in &tags[..recorded] {
+                self.forget_tag(key, value);
             }
-            self.series_counts.insert(series_name.clone(), 0);
+            self.forget_series(series_name);
         }
-        *self.series_counts.get_mut(series_name).unwrap() += 1;
+        checked
+    }
 
-        // Check tag value cardinality
-        for (key, value) in point.tags() {
-            if key == "series" {
-                continue; // Skip series tag as it's handled separately
+    /// Validates every point of a batch, counting none of them unless all pass
+    pub fn validate_batch(&self, points: &[DataPoint]) -> Result<(), ValidationError> {
+        for (validated, point) in points.iter().enumerate() {
+            if let Err(e) = self.validate(point) {
+                self.release(&points[..validated]);
+                return Err(e);
             }
+        }
+        Ok(())
+    }
 
-            let tag_values = self.tag_value_counts.entry(key.clone())
-                .or_insert_with(HashMap::new);
-            
-            // Check if this is a new unique value for this tag
-            if !tag_values.contains_key(value) {
-                // Check cardinality limit before adding new value
-                if tag_values.len() >= self.config.max_tag_values {
-                    return Err(ValidationError::CardinalityLimitExceeded(
-                        format!("tag:{}", key),
-                        tag_values.len(),
-                        self.config.max_tag_values
-                    ));
+    /// Gives back what validated points counted, e.g. once storing them failed
+    ///
+    /// Each point's series, tag values and, under a rate limit, the newest
+    /// arrival of its series are released.
+    pub fn release(&self, points: &[DataPoint]) {
+        for point in points {
+            let Some(series_name) = point.tags().get("series") else { continue };
+            for (key, value) in point.tags() {
+                if key != "series" {
+                    self.forget_tag(key, value);
                 }
-                tag_values.insert(value.clone(), 1);
-            } else {
-                // Increment count for existing value
-                *tag_values.get_mut(value).unwrap() += 1;
+            }
+            self.forget_series(series_name);
+            if self.config.max_points_per_second.is_some() {
+                self.forget_rate(series_name);
             }
         }
+    }
 
-        Ok(())
+    /// Returns the current number of distinct series and values per tag key
+    ///
+    /// Only the per-key counts are read, with each shard locked briefly in
+    /// turn, so this is cheap enough to call on a timer. Shards are read one
+    /// after another, so counts may straddle concurrent validations.
+    pub fn cardinality_snapshot(&self) -> CardinalitySnapshot {
+        let mut tag_values = BTreeMap::new();
+        for shard in &self.tag_value_counts {
+            let shard = shard.lock().unwrap();
+            tag_values.extend(shard.iter().map(|(key, values)| (key.clone(), values.len())));
+        }
+        CardinalitySnapshot {
+            series: self.series_total.load(Ordering::Acquire),
+            tag_values,
+        }
     }
 
     /// Resets the internal counters
-    pub fn reset(&mut self) {
-        self.series_counts.clear();
-        self.tag_value_counts.clear();
+    pub fn reset(&self) {
+        for shard in &self.series_counts {
+            shard.lock().unwrap().clear();
+        }
+        for shard in &self.tag_value_counts {
+            shard.lock().unwrap().clear();
+        }
+        for shard in &self.rate_windows {
+            shard.lock().unwrap().windows.clear();
+        }
+        self.series_total.store(0, Ordering::Release);
     }
 }
 
@@ -140,7 +390,7 @@ mod tests {
 
     #[test]
     fn test_validation_middleware() {
-        let mut validator = ValidationMiddleware::with_config(ValidationConfig {
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
             max_series: 2,
             max_tag_values: 2,
             max_value: 100.0,
@@ -206,4 +456,271 @@ mod tests {
             Err(ValidationError::CardinalityLimitExceeded(_, _, _))
         ));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_concurrent_validation_enforces_exact_limit() {
+        use std::sync::Arc;
+
+        let validator = Arc::new(ValidationMiddleware::with_config(ValidationConfig {
+            max_series: 100,
+            ..Default::default()
+        }));
+
+        // 16 threads each validating 20 distinct series
+        let handles: Vec<_> = (0..16)
+            .map(|thread| {
+                let validator = Arc::clone(&validator);
+                std::thread::spawn(move || {
+                    let mut accepted = 0;
+                    for i in 0..20 {
+                        let mut tags = HashMap::new();
+                        tags.insert("series".to_string(), format!("series_{}_{}", thread, i));
+                        let point = DataPoint::new(1000, 1.0, tags);
+                        match validator.validate(&point) {
+                            Ok(()) => accepted += 1,
+                            Err(ValidationError::CardinalityLimitExceeded(_, _, limit)) => {
+                                assert_eq!(limit, 100)
+                            }
+                            Err(e) => panic!("unexpected error: {}", e),
+                        }
+                    }
+                    accepted
+                })
+            })
+            .collect();
+
+        let accepted: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(accepted, 100);
+    }
+
+    #[test]
+    fn test_non_finite_values() {
+        let mut tags = HashMap::new();
+        tags.insert("series".to_string(), "test_series".to_string());
+
+        let validator = ValidationMiddleware::new();
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let point = DataPoint::new(1000, value, tags.clone());
+            assert!(matches!(
+                validator.validate(&point),
+                Err(ValidationError::ValueSanityCheck(_))
+            ));
+        }
+
+        // Opting out leaves only the bounds checks, which NaN slips past
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            reject_non_finite: false,
+            ..Default::default()
+        });
+        assert!(validator.validate(&DataPoint::new(1000, f64::NAN, tags.clone())).is_ok());
+        assert!(validator.validate(&DataPoint::new(1000, f64::INFINITY, tags)).is_err());
+    }
+
+    #[test]
+    fn test_max_tags_per_point() {
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            max_tags_per_point: 3,
+            max_tag_values: 1,
+            ..Default::default()
+        });
+
+        let mut tags = HashMap::new();
+        tags.insert("series".to_string(), "cpu".to_string());
+        tags.insert("host".to_string(), "server1".to_string());
+        tags.insert("region".to_string(), "us-west".to_string());
+        assert!(validator.validate(&DataPoint::new(1000, 42.0, tags.clone())).is_ok());
+
+        tags.insert("env".to_string(), "prod".to_string());
+        assert!(matches!(
+            validator.validate(&DataPoint::new(1000, 42.0, tags.clone())),
+            Err(ValidationError::TooManyTags(4, 3))
+        ));
+
+        // The rejection happens before cardinality counting, so `env` was not
+        // recorded and its single allowed value is still free
+        tags.remove("region");
+        tags.insert("env".to_string(), "dev".to_string());
+        assert!(validator.validate(&DataPoint::new(1000, 42.0, tags)).is_ok());
+    }
+
+    #[test]
+    fn test_cardinality_snapshot() {
+        let validator = ValidationMiddleware::new();
+        assert_eq!(validator.cardinality_snapshot(), CardinalitySnapshot::default());
+
+        for (series, host, region) in [
+            ("cpu", "server1", "us-west"),
+            ("cpu", "server2", "us-west"),
+            ("mem", "server3", "us-east"),
+            ("mem", "server3", "us-east"),
+        ] {
+            let mut tags = HashMap::new();
+            tags.insert("series".to_string(), series.to_string());
+            tags.insert("host".to_string(), host.to_string());
+            tags.insert("region".to_string(), region.to_string());
+            validator.validate(&DataPoint::new(1000, 42.0, tags)).unwrap();
+        }
+
+        let snapshot = validator.cardinality_snapshot();
+        assert_eq!(snapshot.series, 2);
+        assert_eq!(
+            snapshot.tag_values.into_iter().collect::<Vec<_>>(),
+            vec![("host".to_string(), 3), ("region".to_string(), 2)]
+        );
+
+        validator.reset();
+        assert_eq!(validator.cardinality_snapshot(), CardinalitySnapshot::default());
+    }
+
+    #[test]
+    fn test_rate_limit_per_series() {
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            max_points_per_second: Some(5),
+            ..Default::default()
+        });
+        let point = |series: &str, timestamp: i64| {
+            let mut tags = HashMap::new();
+            tags.insert("series".to_string(), series.to_string());
+            DataPoint::new(timestamp, 1.0, tags)
+        };
+
+        for timestamp in 0..5 {
+            validator.validate(&point("flood", timestamp)).unwrap();
+        }
+        assert!(matches!(
+            validator.validate(&point("flood", 5)),
+            Err(ValidationError::RateLimitExceeded(series, 6, 5)) if series == "flood"
+        ));
+
+        // Other series are unaffected, and rejected points don't count toward the limit
+        validator.validate(&point("quiet", 0)).unwrap();
+        let flood_window: usize = validator
+            .rate_windows
+            .iter()
+            .map(|shard| shard.lock().unwrap().windows.get("flood").map_or(0, VecDeque::len))
+            .sum();
+        assert_eq!(flood_window, 5);
+
+        // Without a limit nothing is tracked
+        let unlimited = ValidationMiddleware::new();
+        for timestamp in 0..100 {
+            unlimited.validate(&point("flood", timestamp)).unwrap();
+        }
+        assert!(unlimited.rate_windows.iter().all(|shard| shard.lock().unwrap().windows.is_empty()));
+    }
+
+    #[test]
+    fn test_rate_windows_skip_rejected_and_idle_series() {
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            max_series: 1,
+            max_points_per_second: Some(5),
+            ..Default::default()
+        });
+        let point = |series: &str| {
+            let mut tags = HashMap::new();
+            tags.insert("series".to_string(), series.to_string());
+            DataPoint::new(1000, 1.0, tags)
+        };
+        let tracked = |validator: &ValidationMiddleware, series: &str| {
+            validator.rate_windows[validator.shard_for(series)]
+                .lock()
+                .unwrap()
+                .windows
+                .contains_key(series)
+        };
+
+        // A series over the cardinality limit never gets a rate window
+        validator.validate(&point("cpu")).unwrap();
+        assert!(matches!(
+            validator.validate(&point("mem")),
+            Err(ValidationError::CardinalityLimitExceeded(..))
+        ));
+        assert!(tracked(&validator, "cpu"));
+        assert!(!tracked(&validator, "mem"));
+
+        // Windows idle for a whole rate window are dropped once the shard has
+        // gone a rate window without pruning
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            max_points_per_second: Some(5),
+            ..Default::default()
+        });
+        let shard = validator.shard_for("fresh");
+        let earlier = Instant::now() - 2 * RATE_WINDOW;
+        validator.rate_windows[shard].lock().unwrap().windows.insert("idle".to_string(), VecDeque::from([earlier]));
+        validator.validate(&point("fresh")).unwrap();
+        // Pruned less than a rate window ago
+        assert!(validator.rate_windows[shard].lock().unwrap().windows.contains_key("idle"));
+
+        validator.rate_windows[shard].lock().unwrap().pruned_at = earlier;
+        validator.validate(&point("fresh")).unwrap();
+        let windows = &validator.rate_windows[shard].lock().unwrap().windows;
+        assert_eq!(windows.keys().collect::<Vec<_>>(), vec!["fresh"]);
+    }
+
+    #[test]
+    fn test_rate_rejection_releases_cardinality() {
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            max_series: 1,
+            max_tag_values: 1,
+            max_points_per_second: Some(1),
+            ..Default::default()
+        });
+        let point = |series: &str, host: &str| {
+            let mut tags = HashMap::new();
+            tags.insert("series".to_string(), series.to_string());
+            tags.insert("host".to_string(), host.to_string());
+            DataPoint::new(1000, 1.0, tags)
+        };
+
+        validator.validate(&point("cpu", "server1")).unwrap();
+        assert!(matches!(
+            validator.validate(&point("cpu", "server1")),
+            Err(ValidationError::RateLimitExceeded(..))
+        ));
+        let snapshot = validator.cardinality_snapshot();
+        assert_eq!(snapshot.series, 1);
+        assert_eq!(snapshot.tag_values["host"], 1);
+
+        // A point failing on its tags gives back its series slot
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            max_series: 2,
+            max_tag_values: 1,
+            ..Default::default()
+        });
+        validator.validate(&point("cpu", "server1")).unwrap();
+        assert!(matches!(
+            validator.validate(&point("mem", "server2")),
+            Err(ValidationError::CardinalityLimitExceeded(..))
+        ));
+        assert_eq!(validator.cardinality_snapshot().series, 1);
+        validator.validate(&point("disk", "server1")).unwrap();
+    }
+
+    #[test]
+    fn test_rejected_batch_counts_nothing() {
+        let validator = ValidationMiddleware::with_config(ValidationConfig {
+            max_tag_values: 2,
+            max_points_per_second: Some(10),
+            ..Default::default()
+        });
+        let point = |series: &str, host: &str| {
+            let mut tags = HashMap::new();
+            tags.insert("series".to_string(), series.to_string());
+            tags.insert("host".to_string(), host.to_string());
+            DataPoint::new(1000, 1.0, tags)
+        };
+
+        let batch = [point("cpu", "server1"), point("mem", "server2"), point("cpu", "server3")];
+        assert!(matches!(
+            validator.validate_batch(&batch),
+            Err(ValidationError::CardinalityLimitExceeded(..))
+        ));
+        assert_eq!(validator.cardinality_snapshot(), CardinalitySnapshot::default());
+        assert!(validator.rate_windows.iter().all(|shard| shard.lock().unwrap().windows.is_empty()));
+
+        validator.validate_batch(&batch[..2]).unwrap();
+        validator.release(&batch[..2]);
+        assert_eq!(validator.cardinality_snapshot(), CardinalitySnapshot::default());
+        validator.validate_batch(&[point("cpu", "server3"), point("cpu", "server4")]).unwrap();
+    }
+}
@@ -3,6 +3,7 @@
 //! This crate provides a single-node time series database implementation
 //! optimized for system metrics with infinite retention and high cardinality support.
 
+pub mod datasets;
 pub mod ingestion;
 pub mod metrics;
 pub mod query;
@@ -1,4 +1,7 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
 use tokio::time::{Duration};
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
@@ -30,6 +33,39 @@ async fn main() {
 
     info!("Starting VCTSDB...");
 
+    // Accept writes over HTTP in any of the supported formats
+    let registry = ingestion::ParserRegistry::new();
+    registry.register(Arc::new(ingestion::formats::JsonParser::new()), ingestion::Priority::Normal).unwrap();
+    registry.register(Arc::new(ingestion::formats::CsvParser::new()), ingestion::Priority::Normal).unwrap();
+    registry.register(Arc::new(ingestion::formats::LineProtocolParser), ingestion::Priority::Normal).unwrap();
+    registry.register(Arc::new(ingestion::formats::PrometheusTextParser), ingestion::Priority::Normal).unwrap();
+    let engine = Arc::new(
+        storage::StorageEngine::open("data", storage::MemTable::new(100_000))
+            .await
+            .expect("Failed to open storage"),
+    );
+    // Data is kept forever unless a retention is set on the catalog
+    engine.spawn_retention(Duration::from_secs(60 * 60));
+    let validator = Arc::new(ingestion::ValidationMiddleware::new());
+    metrics::spawn_cardinality_reporter(Arc::clone(&validator), Duration::from_secs(10));
+    let endpoint = ingestion::WriteEndpoint::new(
+        Arc::new(registry),
+        validator,
+        Arc::clone(&engine),
+    );
+    let write_addr = SocketAddr::from(([127, 0, 0, 1], 8086));
+    let listener = TcpListener::bind(write_addr).await.expect("Failed to bind write endpoint");
+    info!("Write endpoint listening on http://{}/write", write_addr);
+    let (stop_writes, writes_stopped) = oneshot::channel::<()>();
+    let write_server = tokio::spawn(async move {
+        let shutdown = async {
+            writes_stopped.await.ok();
+        };
+        if let Err(e) = ingestion::http::serve_with_shutdown(listener, endpoint, shutdown).await {
+            eprintln!("Write endpoint failed: {}", e);
+        }
+    });
+
     // Spawn a task to record test metrics
     tokio::spawn(async {
         let mut interval = tokio::time::interval(Duration::from_secs(1));
@@ -38,7 +74,6 @@ async fn main() {
             // Record some test metrics
             metrics::record_ingestion(42.0);
             metrics::update_memory_usage(1024 * 1024); // 1MB
-            metrics::record_query(15.5);
             metrics::record_wal_write(512);
             metrics::record_sstable_operation("compaction", 1);
         }
@@ -49,4 +84,11 @@ async fn main() {
         .await
         .expect("Failed to listen for ctrl+c");
     info!("Shutting down...");
+
+    // Answer in-flight writes before making the stored data durable
+    stop_writes.send(()).ok();
+    write_server.await.ok();
+    if let Err(e) = engine.shutdown(Duration::from_secs(30)).await {
+        eprintln!("Failed to shut down storage cleanly: {}", e);
+    }
 }
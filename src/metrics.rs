@@ -5,7 +5,14 @@
 
 use metrics::{counter, gauge, histogram};
 use metrics_exporter_prometheus::PrometheusBuilder;
+use std::fmt::Write;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::ingestion::{CardinalitySnapshot, ValidationMiddleware};
+use crate::storage::{DataPoint, LastValueCache};
 
 /// Initialize the metrics collection system
 pub fn init_metrics(addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
@@ -27,6 +34,55 @@ pub fn record_query(duration_ms: f64) {
     histogram!("vctsdb.query.duration_ms").record(duration_ms);
 }
 
+/// Record the cost of one executed query, whether or not it succeeded
+pub fn record_query_execution(
+    duration: Duration,
+    sstables_scanned: usize,
+    blocks_decoded: usize,
+    points_returned: usize,
+) {
+    record_query(duration.as_secs_f64() * 1000.0);
+    counter!("vctsdb.query.executions").increment(1);
+    counter!("vctsdb.query.sstables_scanned").increment(sstables_scanned as u64);
+    counter!("vctsdb.query.blocks_decoded").increment(blocks_decoded as u64);
+    counter!("vctsdb.query.points_returned").increment(points_returned as u64);
+    histogram!("vctsdb.query.points_per_query").record(points_returned as f64);
+}
+
+/// Record a query executed without an index to narrow its scan
+pub fn record_full_scan_query() {
+    counter!("vctsdb.query.full_scans").increment(1);
+}
+
+/// Record a query stopped by cancellation
+pub fn record_query_cancelled() {
+    counter!("vctsdb.query.cancelled").increment(1);
+}
+
+/// Record a query stopped by the executor's memory limit
+pub fn record_query_memory_limit_exceeded() {
+    counter!("vctsdb.query.memory_limit_exceeded").increment(1);
+}
+
+/// Record series and per-tag-key cardinality as gauges
+pub fn record_cardinality(snapshot: &CardinalitySnapshot) {
+    gauge!("vctsdb.cardinality.series").set(snapshot.series as f64);
+    for (key, values) in &snapshot.tag_values {
+        gauge!("vctsdb.cardinality.tag", "key" => key.clone()).set(*values as f64);
+    }
+}
+
+/// Periodically record the cardinality tracked by a validator until the task is aborted
+pub fn spawn_cardinality_reporter(validator: Arc<ValidationMiddleware>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            record_cardinality(&validator.cardinality_snapshot());
+        }
+    })
+}
+
 /// Update memory usage metrics
 pub fn update_memory_usage(bytes: u64) {
     gauge!("vctsdb.memory.usage_bytes").set(bytes as f64);
@@ -37,19 +93,197 @@ pub fn record_wal_write(bytes: u64) {
     counter!("vctsdb.wal.bytes_written").increment(bytes);
 }
 
+/// Record an SSTable skipped by a query after its scan failed
+pub fn record_skipped_sstable_scan() {
+    counter!("vctsdb.query.skipped_sstable_scans").increment(1);
+}
+
+/// Record an SSTable block read answered by the block cache
+pub fn record_block_cache_hit() {
+    counter!("vctsdb.sstable.block_cache.hits").increment(1);
+}
+
+/// Record an SSTable block read that missed the block cache
+pub fn record_block_cache_miss() {
+    counter!("vctsdb.sstable.block_cache.misses").increment(1);
+}
+
 /// Record SSTable operations
 pub fn record_sstable_operation(operation: &str, count: u64) {
     let metric_name = format!("vctsdb.sstable.{}", operation);
     counter!(metric_name).increment(count);
 }
 
+/// Render the latest value of each stored series in Prometheus text exposition format
+///
+/// At most `max_series` series are rendered (ordered by name); the number of
+/// omitted series is reported in a trailing comment.
+pub async fn render_series_exposition(cache: &LastValueCache, max_series: usize) -> String {
+    let snapshot = cache.snapshot().await;
+    let mut output = String::new();
+
+    for (series_name, point) in snapshot.iter().take(max_series) {
+        push_sample(&mut output, series_name, point);
+    }
+
+    if snapshot.len() > max_series {
+        let _ = writeln!(
+            output,
+            "# vctsdb: {} series omitted by cardinality cap of {}",
+            snapshot.len() - max_series,
+            max_series
+        );
+    }
+
+    output
+}
+
+/// Append the exposition line for one point of `metric_name`
+///
+/// The point's tags become labels, except `series`, which names the metric.
+pub(crate) fn push_sample(output: &mut String, metric_name: &str, point: &DataPoint) {
+    let mut labels: Vec<(&String, &String)> = point
+        .tags()
+        .iter()
+        .filter(|(key, _)| key.as_str() != "series")
+        .collect();
+    labels.sort();
+
+    output.push_str(&sanitize_metric_name(metric_name));
+    if !labels.is_empty() {
+        let rendered = labels
+            .iter()
+            .map(|(key, value)| {
+                format!("{}=\"{}\"", sanitize_metric_name(key), escape_label_value(value))
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let _ = write!(output, "{{{}}}", rendered);
+    }
+
+    // Exposition timestamps are milliseconds, ours are nanoseconds
+    let _ = writeln!(
+        output,
+        " {} {}",
+        format_sample_value(point.value()),
+        point.timestamp() / 1_000_000
+    );
+}
+
+/// Replace characters that are invalid in Prometheus metric and label names
+fn sanitize_metric_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Escape a label value per the exposition format
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Format a sample value, spelling out the special float values
+fn format_sample_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    
+    use super::*;
+    use crate::storage::DataPoint;
+    use std::collections::HashMap;
 
     #[test]
     fn test_metrics_initialization() {
         // This is a placeholder test to verify our metrics infrastructure
         assert!(true);
     }
+
+    #[tokio::test]
+    async fn test_series_exposition() {
+        let cache = LastValueCache::new();
+
+        let mut cpu_tags = HashMap::new();
+        cpu_tags.insert("series".to_string(), "cpu.usage".to_string());
+        cpu_tags.insert("host".to_string(), "server1".to_string());
+        cpu_tags.insert("region".to_string(), "us-\"west\"".to_string());
+        cache.update("cpu.usage", &DataPoint::new(2_000_000_000, 42.5, cpu_tags)).await;
+
+        let mut mem_tags = HashMap::new();
+        mem_tags.insert("host".to_string(), "server2".to_string());
+        cache.update("mem_free", &DataPoint::new(3_000_000_000, 1024.0, mem_tags)).await;
+
+        let output = render_series_exposition(&cache, 10).await;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        // Each line is `name{labels} value timestamp_ms`
+        let (name_and_labels, sample) = lines[0].split_once("} ").unwrap();
+        let (name, labels) = name_and_labels.split_once('{').unwrap();
+        assert_eq!(name, "cpu_usage");
+        assert_eq!(labels, r#"host="server1",region="us-\"west\"""#);
+        let fields: Vec<&str> = sample.split(' ').collect();
+        assert_eq!(fields[0].parse::<f64>().unwrap(), 42.5);
+        assert_eq!(fields[1].parse::<i64>().unwrap(), 2000);
+
+        assert_eq!(lines[1], r#"mem_free{host="server2"} 1024 3000"#);
+
+        // The cardinality cap bounds the rendered series
+        let capped = render_series_exposition(&cache, 1).await;
+        assert_eq!(capped.lines().filter(|l| !l.starts_with('#')).count(), 1);
+        assert!(capped.contains("1 series omitted"));
+    }
+
+    #[test]
+    fn test_record_cardinality_gauges() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+        use std::collections::BTreeMap;
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let snapshot = CardinalitySnapshot {
+            series: 12,
+            tag_values: BTreeMap::from([("host".to_string(), 5), ("region".to_string(), 2)]),
+        };
+        ::metrics::with_local_recorder(&recorder, || record_cardinality(&snapshot));
+
+        let mut gauges: Vec<(String, Vec<String>, f64)> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| match value {
+                DebugValue::Gauge(value) => Some((
+                    key.key().name().to_string(),
+                    key.key().labels().map(|l| format!("{}={}", l.key(), l.value())).collect(),
+                    value.into_inner(),
+                )),
+                _ => None,
+            })
+            .collect();
+        gauges.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        assert_eq!(
+            gauges,
+            vec![
+                ("vctsdb.cardinality.series".to_string(), vec![], 12.0),
+                ("vctsdb.cardinality.tag".to_string(), vec!["key=host".to_string()], 5.0),
+                ("vctsdb.cardinality.tag".to_string(), vec!["key=region".to_string()], 2.0),
+            ]
+        );
+    }
 }
@@ -0,0 +1,728 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::query::parser::ast::{BucketFill, FunctionArg, FunctionCall, GroupOrder, Query, SelectItem};
+use crate::query::parser::validator::column_name;
+use crate::storage::data::{DataPoint, Value};
+
+/// Error type for aggregation operations
+#[derive(Debug, thiserror::Error)]
+pub enum AggregationError {
+    #[error("Unsupported aggregate function: {0}")]
+    UnsupportedFunction(String),
+    #[error("Unknown output column: {0}")]
+    UnknownColumn(String),
+    #[error("Invalid argument for {0}: {1}")]
+    InvalidArgument(String, String),
+    #[error("Filling empty buckets would produce {0} buckets")]
+    TooManyBuckets(i128),
+    #[error("{0} selects points rather than an aggregate and cannot be grouped")]
+    RawSelect(String),
+}
+
+/// Most buckets `FILL(null)` may produce for a single group
+const MAX_FILLED_BUCKETS: i128 = 100_000;
+
+/// Aggregated output for one group of points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    /// Group-by tag values, in GROUP BY order; missing tags are empty
+    pub key: Vec<(String, String)>,
+    /// Start timestamp of the bucket, when grouping by `time()`
+    pub bucket: Option<i64>,
+    /// Aggregate results, in SELECT order; `None` for an empty bucket
+    pub columns: Vec<(String, Option<Value>)>,
+    /// Timestamp of the selected sample for each column, for functions such as
+    /// `last_over_time` that return a sample rather than a computed value
+    pub timestamps: Vec<Option<i64>>,
+    /// Time range the group was aggregated over, for queries with several
+    pub range_index: Option<usize>,
+}
+
+impl Group {
+    /// Returns the value of an output column as a float
+    pub fn value(&self, column: &str) -> Option<f64> {
+        self.typed_value(column).map(|value| value.as_f64())
+    }
+
+    /// Returns the value of an output column with its type
+    pub fn typed_value(&self, column: &str) -> Option<Value> {
+        self.columns
+            .iter()
+            .find(|(name, _)| name == column)
+            .and_then(|(_, value)| *value)
+    }
+
+    /// Returns the timestamp of the sample selected for an output column
+    pub fn timestamp(&self, column: &str) -> Option<i64> {
+        self.columns
+            .iter()
+            .position(|(name, _)| name == column)
+            .and_then(|i| self.timestamps[i])
+    }
+}
+
+/// Evaluator of a custom aggregate function
+///
+/// Receives the time-ordered points of a group and the arguments of the call,
+/// e.g. the `0.5` in `my_quantile(value, 0.5)`.
+pub type AggregateEvaluator = Arc<dyn Fn(&[&DataPoint], &[FunctionArg]) -> f64 + Send + Sync>;
+
+/// Aggregate functions registered in addition to the built-in ones
+#[derive(Clone, Default)]
+pub struct CustomAggregates {
+    /// Evaluators by lowercase function name
+    evaluators: HashMap<String, AggregateEvaluator>,
+}
+
+impl CustomAggregates {
+    /// Creates an empty set of custom aggregates
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a function, taking precedence over any built-in of the same name
+    pub fn register(&mut self, name: &str, evaluator: AggregateEvaluator) {
+        self.evaluators.insert(name.to_lowercase(), evaluator);
+    }
+
+    /// Returns the evaluator of a function
+    pub fn get(&self, name: &str) -> Option<&AggregateEvaluator> {
+        self.evaluators.get(&name.to_lowercase())
+    }
+}
+
+impl fmt::Debug for CustomAggregates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.evaluators.keys()).finish()
+    }
+}
+
+/// Groups points by the query's tags and time buckets and evaluates its select list
+///
+/// `range` is the resolved query time range; with `FILL(null)` every bucket it
+/// covers is emitted for each tag group, empty ones with null values. Groups are
+/// ordered by tag values then bucket unless the query orders them by an
+/// aggregate column; ties under an aggregate ordering keep that order.
+pub fn aggregate(
+    points: &[DataPoint],
+    query: &Query,
+    range: (i64, i64),
+) -> Result<Vec<Group>, AggregationError> {
+    aggregate_with(points, query, range, &CustomAggregates::default())
+}
+
+/// Like [`aggregate`], also evaluating the given custom aggregate functions
+pub fn aggregate_with(
+    points: &[DataPoint],
+    query: &Query,
+    range: (i64, i64),
+    custom: &CustomAggregates,
+) -> Result<Vec<Group>, AggregationError> {
+    let calls = query
+        .select
+        .iter()
+        .map(|expr| match &expr.item {
+            SelectItem::Function(call) if !is_transform(&call.name) => Ok(call),
+            _ => Err(AggregationError::RawSelect(column_name(expr))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut grouped: BTreeMap<(Vec<String>, Option<i64>), Vec<&DataPoint>> = BTreeMap::new();
+    for point in points {
+        let key = query
+            .group_by
+            .iter()
+            .map(|tag| point.tags().get(tag).cloned().unwrap_or_default())
+            .collect();
+        let bucket = query.time_bucket.map(|bucket| bucket.floor(point.timestamp()));
+        grouped.entry((key, bucket)).or_default().push(point);
+    }
+
+    if let Some(bucket) = query.time_bucket.filter(|bucket| bucket.fill == BucketFill::Null) {
+        let first = bucket.floor(range.0);
+        let count = (bucket.floor(range.1) as i128 - first as i128) / bucket.width as i128 + 1;
+        if count > MAX_FILLED_BUCKETS {
+            return Err(AggregationError::TooManyBuckets(count));
+        }
+
+        // Without tag grouping the single group exists even if it has no points
+        let mut keys: BTreeSet<Vec<String>> = grouped.keys().map(|(key, _)| key.clone()).collect();
+        if query.group_by.is_empty() {
+            keys.insert(Vec::new());
+        }
+        for key in keys {
+            for i in 0..count as i64 {
+                grouped.entry((key.clone(), Some(first + i * bucket.width))).or_default();
+            }
+        }
+    }
+
+    let mut groups = Vec::with_capacity(grouped.len());
+    for ((key, bucket), members) in grouped {
+        // Staleness is measured from the end of the bucket, or of the query range
+        let end = match (bucket, query.time_bucket) {
+            (Some(start), Some(time_bucket)) => start.saturating_add(time_bucket.width),
+            _ => range.1,
+        };
+        let mut columns = Vec::with_capacity(query.select.len());
+        let mut timestamps = Vec::with_capacity(query.select.len());
+        for (expr, call) in query.select.iter().zip(&calls) {
+            let (value, timestamp) = if members.is_empty() {
+                (None, None)
+            } else if let Some(evaluator) = custom.get(&call.name) {
+                (Some(Value::F64(evaluator(&members, &call.args))), None)
+            } else if call.name.eq_ignore_ascii_case("last_over_time") {
+                match last_over_time(call, &members, end)? {
+                    Some((value, timestamp)) => (Some(value), Some(timestamp)),
+                    None => (None, None),
+                }
+            } else {
+                (Some(evaluate(call, &members)?), None)
+            };
+            columns.push((column_name(expr), value));
+            timestamps.push(timestamp);
+        }
+        groups.push(Group {
+            key: query.group_by.iter().cloned().zip(key).collect(),
+            bucket,
+            columns,
+            timestamps,
+            range_index: None,
+        });
+    }
+
+    if let Some(having) = &query.having {
+        if let Some(column) = having
+            .columns()
+            .into_iter()
+            .find(|column| !query.select.iter().any(|expr| &column_name(expr) == column))
+        {
+            return Err(AggregationError::UnknownColumn(column.to_string()));
+        }
+        groups.retain(|group| having.matches(&|column| group.value(column)));
+    }
+
+    match &query.group_order {
+        None | Some(GroupOrder::Key { descending: false }) => {}
+        Some(GroupOrder::Key { descending: true }) => groups.reverse(),
+        Some(GroupOrder::Aggregate { column, descending }) => {
+            if !query.select.iter().any(|expr| &column_name(expr) == column) {
+                return Err(AggregationError::UnknownColumn(column.clone()));
+            }
+            // Sorting is stable, so equal values keep their key order
+            groups.sort_by(|a, b| {
+                let ordering = compare_values(a.value(column), b.value(column));
+                if *descending { ordering.reverse() } else { ordering }
+            });
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Returns whether a function maps each point to an output point, rather than
+/// reducing a group of points to one value
+pub fn is_transform(name: &str) -> bool {
+    name.eq_ignore_ascii_case("cumulative_sum")
+}
+
+/// Applies a transform function to time-ordered points
+///
+/// `cumulative_sum(value)` replaces each value with the running total of every
+/// value up to and including it, keeping timestamps and tags.
+pub fn transform(function: &FunctionCall, points: Vec<DataPoint>) -> Result<Vec<DataPoint>, AggregationError> {
+    match function.name.to_lowercase().as_str() {
+        "cumulative_sum" => {
+            let mut total = 0.0;
+            Ok(points
+                .into_iter()
+                .map(|point| {
+                    total += point.value();
+                    DataPoint::new(point.timestamp(), total, point.tags().clone())
+                })
+                .collect())
+        }
+        _ => Err(AggregationError::UnsupportedFunction(function.name.clone())),
+    }
+}
+
+/// Evaluates an aggregate function over the time-ordered points of one group
+///
+/// - `percentile(value, p)` interpolates linearly between the two closest ranks,
+///   so `p = 50` over an even number of points is the mean of the middle pair.
+/// - `stddev(value)` is the sample standard deviation (n - 1 denominator).
+/// - `rate(value)` is the change per second between the first and last point.
+///
+/// Results that are undefined for the group size (e.g. `stddev` of one point)
+/// are NaN.
+///
+/// `count` is an integer for any value type. When every value is an integer,
+/// `sum`, `min` and `max` are computed exactly and stay integers (a `sum` that
+/// overflows `i64` falls back to a float); booleans sum to the number of `true`
+/// values and their `min` and `max` are booleans. Every other function works on
+/// the values as floats.
+fn evaluate(function: &FunctionCall, points: &[&DataPoint]) -> Result<Value, AggregationError> {
+    let name = function.name.to_lowercase();
+    if let Some(value) = evaluate_exact(&name, points) {
+        return Ok(value);
+    }
+
+    let values = points.iter().map(|p| p.value());
+    let result = match name.as_str() {
+        "avg" => values.sum::<f64>() / points.len() as f64,
+        "sum" => values.sum(),
+        "min" => values.fold(f64::INFINITY, f64::min),
+        "max" => values.fold(f64::NEG_INFINITY, f64::max),
+        "percentile" => {
+            let p = match function.args.get(1) {
+                Some(FunctionArg::NumberLiteral(p)) if (0.0..=100.0).contains(p) => *p,
+                _ => {
+                    return Err(AggregationError::InvalidArgument(
+                        function.name.clone(),
+                        "percentile must be a number between 0 and 100".to_string(),
+                    ))
+                }
+            };
+            percentile(values.collect(), p)
+        }
+        "stddev" => stddev(&values.collect::<Vec<_>>()),
+        "rate" => rate(points),
+        _ => return Err(AggregationError::UnsupportedFunction(function.name.clone())),
+    };
+    Ok(Value::F64(result))
+}
+
+/// Evaluates the functions that keep integer or boolean values exact
+///
+/// Returns `None` when the function must be computed over floats instead.
+fn evaluate_exact(name: &str, points: &[&DataPoint]) -> Option<Value> {
+    if name == "count" {
+        return Some(Value::I64(points.len() as i64));
+    }
+
+    let integers: Option<Vec<i64>> = points
+        .iter()
+        .map(|p| match p.typed_value() {
+            Value::I64(v) => Some(v),
+            _ => None,
+        })
+        .collect();
+    if let Some(integers) = integers {
+        return match name {
+            "sum" => {
+                let sum: i128 = integers.iter().map(|&v| v as i128).sum();
+                Some(i64::try_from(sum).map_or(Value::F64(sum as f64), Value::I64))
+            }
+            "min" => integers.iter().min().copied().map(Value::I64),
+            "max" => integers.iter().max().copied().map(Value::I64),
+            _ => None,
+        };
+    }
+
+    let booleans: Option<Vec<bool>> = points
+        .iter()
+        .map(|p| match p.typed_value() {
+            Value::Bool(v) => Some(v),
+            _ => None,
+        })
+        .collect();
+    match (name, booleans) {
+        ("sum", Some(booleans)) => Some(Value::I64(booleans.iter().filter(|&&v| v).count() as i64)),
+        ("min", Some(booleans)) => booleans.iter().min().copied().map(Value::Bool),
+        ("max", Some(booleans)) => booleans.iter().max().copied().map(Value::Bool),
+        _ => None,
+    }
+}
+
+/// Selects the last of time-ordered points as `(value, timestamp)`
+///
+/// With a staleness threshold (`last_over_time(value, 5m)`), a last sample more
+/// than the threshold before `end` yields `None`, so a counter that stopped
+/// reporting shows as a gap rather than its final value.
+fn last_over_time(
+    function: &FunctionCall,
+    points: &[&DataPoint],
+    end: i64,
+) -> Result<Option<(Value, i64)>, AggregationError> {
+    let staleness = match function.args.get(1) {
+        None => None,
+        Some(FunctionArg::Duration(threshold)) => Some(*threshold),
+        Some(_) => {
+            return Err(AggregationError::InvalidArgument(
+                function.name.clone(),
+                "staleness threshold must be a duration".to_string(),
+            ))
+        }
+    };
+    let Some(last) = points.last() else {
+        return Ok(None);
+    };
+    if staleness.is_some_and(|threshold| end.saturating_sub(last.timestamp()) > threshold) {
+        return Ok(None);
+    }
+    Ok(Some((last.typed_value(), last.timestamp())))
+}
+
+/// Computes the `p`th percentile (0-100) with linear interpolation between ranks
+fn percentile(mut values: Vec<f64>, p: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.sort_by(f64::total_cmp);
+
+    let rank = p / 100.0 * (values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    values[lower] + (values[upper] - values[lower]) * (rank - lower as f64)
+}
+
+/// Computes the sample standard deviation
+fn stddev(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return f64::NAN;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    variance.sqrt()
+}
+
+/// Computes the per-second change between the first and last of time-ordered points
+fn rate(points: &[&DataPoint]) -> f64 {
+    match (points.first(), points.last()) {
+        (Some(first), Some(last)) if last.timestamp() > first.timestamp() => {
+            let seconds = (last.timestamp() - first.timestamp()) as f64 / 1_000_000_000.0;
+            (last.value() - first.value()) / seconds
+        }
+        _ => f64::NAN,
+    }
+}
+
+/// Orders aggregate values with NaN after every number
+fn compare_values(a: Option<f64>, b: Option<f64>) -> Ordering {
+    let a = a.unwrap_or(f64::NAN);
+    let b = b.unwrap_or(f64::NAN);
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.total_cmp(&b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use crate::query::executor::{ExecutionConfig, QueryExecutor};
+    use crate::query::parser::ast::TimeRange;
+    use crate::query::parser::{Lexer, Parser};
+    use crate::storage::data::TimeSeries;
+    use crate::storage::lsm::memtable::MemTable;
+
+    fn parse(input: &str) -> crate::query::parser::ast::Query {
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let mut query = Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 1_000 });
+        query
+    }
+
+    #[tokio::test]
+    async fn test_group_ordering_by_key_and_aggregate() {
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+        {
+            let series = TimeSeries::new("cpu".to_string()).unwrap();
+            let guard = memtable.write().await;
+            let samples = [("b", 50.0), ("a", 10.0), ("c", 30.0), ("b", 70.0), ("a", 20.0), ("c", 40.0)];
+            for (i, (host, value)) in samples.iter().enumerate() {
+                let mut tags = HashMap::new();
+                tags.insert("host".to_string(), host.to_string());
+                guard.insert(&series, &DataPoint::new(i as i64, *value, tags)).await.unwrap();
+            }
+        }
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+
+        let summary = |groups: Vec<Group>| -> Vec<(String, f64)> {
+            groups
+                .into_iter()
+                .map(|g| (g.key[0].1.clone(), g.value("avg_val").unwrap()))
+                .collect()
+        };
+
+        // Key order is the default
+        let by_key = executor
+            .execute_grouped(&parse("SELECT avg(value) AS avg_val FROM cpu GROUP BY host"))
+            .await
+            .unwrap();
+        assert_eq!(
+            summary(by_key),
+            vec![("a".to_string(), 15.0), ("b".to_string(), 60.0), ("c".to_string(), 35.0)]
+        );
+
+        // Top-n style ordering by the aggregate
+        let by_value = executor
+            .execute_grouped(&parse(
+                "SELECT avg(value) AS avg_val FROM cpu GROUP BY host ORDER GROUPS BY avg_val DESC",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            summary(by_value),
+            vec![("b".to_string(), 60.0), ("c".to_string(), 35.0), ("a".to_string(), 15.0)]
+        );
+    }
+
+    #[test]
+    fn test_percentile_stddev_and_rate() {
+        // One point per second, values 2, 4, 4, 4, 5, 5, 7, 9
+        let points: Vec<DataPoint> = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| DataPoint::new(i as i64 * 1_000_000_000, v, HashMap::new()))
+            .collect();
+        let query = parse("SELECT percentile(value, 50) AS p50, percentile(value, 95) AS p95, stddev(value) AS sd, rate(value) AS r FROM cpu");
+
+        let groups = aggregate(&points, &query, (0, 0)).unwrap();
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0];
+        assert_eq!(group.value("p50"), Some(4.5));
+        // rank 0.95 * 7 = 6.65, between 7 and 9
+        assert!((group.value("p95").unwrap() - 8.3).abs() < 1e-9);
+        // Sample variance is 32 / 7
+        assert!((group.value("sd").unwrap() - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+        // (9 - 2) over 7 seconds
+        assert_eq!(group.value("r"), Some(1.0));
+
+        // Out-of-range percentiles are rejected at evaluation too
+        let query = parse("SELECT percentile(value, 101) FROM cpu");
+        assert!(matches!(
+            aggregate(&points, &query, (0, 0)),
+            Err(AggregationError::InvalidArgument(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_time_buckets_with_tag_grouping() {
+        let second = 1_000_000_000;
+        let points: Vec<DataPoint> = [("a", 0, 1.0), ("a", 30, 3.0), ("b", 70, 5.0), ("a", 150, 7.0)]
+            .iter()
+            .map(|&(host, seconds, value)| {
+                let mut tags = HashMap::new();
+                tags.insert("host".to_string(), host.to_string());
+                DataPoint::new(seconds * second, value, tags)
+            })
+            .collect();
+        let range = (0, 179 * second);
+        let summary = |groups: Vec<Group>| -> Vec<(String, i64, Option<f64>)> {
+            groups
+                .into_iter()
+                .map(|g| (g.key[0].1.clone(), g.bucket.unwrap() / (60 * second), g.value("avg(value)")))
+                .collect()
+        };
+
+        // Empty buckets are skipped by default
+        let query = parse("SELECT avg(value) FROM cpu GROUP BY time(1m), host");
+        assert_eq!(query.time_bucket.map(|b| b.width), Some(60 * second));
+        assert_eq!(
+            summary(aggregate(&points, &query, range).unwrap()),
+            vec![
+                ("a".to_string(), 0, Some(2.0)),
+                ("a".to_string(), 2, Some(7.0)),
+                ("b".to_string(), 1, Some(5.0)),
+            ]
+        );
+
+        // FILL(null) emits every bucket in the range for every host
+        let query = parse("SELECT avg(value) FROM cpu GROUP BY host, time(1m) FILL(null)");
+        assert_eq!(
+            summary(aggregate(&points, &query, range).unwrap()),
+            vec![
+                ("a".to_string(), 0, Some(2.0)),
+                ("a".to_string(), 1, None),
+                ("a".to_string(), 2, Some(7.0)),
+                ("b".to_string(), 0, None),
+                ("b".to_string(), 1, Some(5.0)),
+                ("b".to_string(), 2, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_last_over_time_with_staleness() {
+        let second = 1_000_000_000;
+        let points: Vec<DataPoint> = [(5, 10.0), (50, 12.0), (65, 15.0), (70, 18.0), (125, 20.0)]
+            .iter()
+            .map(|&(seconds, value)| DataPoint::new(seconds * second, value, HashMap::new()))
+            .collect();
+        let range = (0, 179 * second);
+        let summary = |groups: Vec<Group>| -> Vec<(i64, Option<f64>, Option<i64>)> {
+            groups
+                .into_iter()
+                .map(|g| {
+                    (
+                        g.bucket.unwrap() / (60 * second),
+                        g.value("last"),
+                        g.timestamp("last").map(|ts| ts / second),
+                    )
+                })
+                .collect()
+        };
+
+        // The final sample of each bucket, with its timestamp
+        let query = parse("SELECT last_over_time(value) AS last FROM cpu GROUP BY time(1m)");
+        assert_eq!(
+            summary(aggregate(&points, &query, range).unwrap()),
+            vec![(0, Some(12.0), Some(50)), (1, Some(18.0), Some(70)), (2, Some(20.0), Some(125))]
+        );
+
+        // Bucket 1 ends at 120s but its last sample is 50s old
+        let query = parse("SELECT last_over_time(value, 30s) AS last FROM cpu GROUP BY time(1m)");
+        assert_eq!(
+            summary(aggregate(&points, &query, range).unwrap()),
+            vec![(0, Some(12.0), Some(50)), (1, None, None), (2, None, None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integer_and_boolean_aggregates_are_exact() {
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+        {
+            let guard = memtable.write().await;
+            let bytes = TimeSeries::new("bytes".to_string()).unwrap();
+            // Each step of 1 is lost when summing around 2^53 as f64
+            for (i, value) in [1i64 << 53, 1, 1].iter().enumerate() {
+                let point = DataPoint::with_value(i as i64, Value::I64(*value), HashMap::new());
+                guard.insert(&bytes, &point).await.unwrap();
+            }
+            let up = TimeSeries::new("up".to_string()).unwrap();
+            for (i, value) in [true, false, true].iter().enumerate() {
+                let point = DataPoint::with_value(i as i64, Value::Bool(*value), HashMap::new());
+                guard.insert(&up, &point).await.unwrap();
+            }
+        }
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+
+        let groups = executor
+            .execute_grouped(&parse("SELECT sum(value) AS s, max(value) AS m, count(value) AS n FROM bytes"))
+            .await
+            .unwrap();
+        assert_eq!(groups[0].typed_value("s"), Some(Value::I64((1 << 53) + 2)));
+        assert_eq!(groups[0].typed_value("m"), Some(Value::I64(1 << 53)));
+        assert_eq!(groups[0].typed_value("n"), Some(Value::I64(3)));
+
+        let groups = executor
+            .execute_grouped(&parse("SELECT count(value) AS n, sum(value) AS up, min(value) AS all_up FROM up"))
+            .await
+            .unwrap();
+        assert_eq!(groups[0].typed_value("n"), Some(Value::I64(3)));
+        assert_eq!(groups[0].typed_value("up"), Some(Value::I64(2)));
+        assert_eq!(groups[0].typed_value("all_up"), Some(Value::Bool(false)));
+    }
+
+    #[tokio::test]
+    async fn test_cumulative_sum_across_memtable_and_sstable() {
+        use crate::storage::lsm::sstable::{DataBlock, SSTable};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let series = TimeSeries::new("requests".to_string()).unwrap();
+            let guard = memtable.write().await;
+            guard.insert(&series, &DataPoint::new(300, 3.0, HashMap::new())).await.unwrap();
+        }
+        // The older points live in an SSTable and are merged in after the MemTable's
+        let sstable = SSTable::new(temp_dir.path().join("1.sst")).unwrap();
+        let older = [DataPoint::new(100, 1.0, HashMap::new()), DataPoint::new(200, 2.0, HashMap::new())];
+        sstable.write_block(DataBlock::from_points("requests", &older)).await.unwrap();
+        let sstables = Arc::new(RwLock::new(vec![Arc::new(sstable)]));
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+
+        let query = parse("SELECT cumulative_sum(value) FROM requests");
+        let points = executor.execute(&query).await.unwrap().points;
+        let totals: Vec<(i64, f64)> = points.iter().map(|p| (p.timestamp(), p.value())).collect();
+        assert_eq!(totals, vec![(100, 1.0), (200, 3.0), (300, 6.0)]);
+
+        assert!(matches!(
+            executor.execute_grouped(&query).await,
+            Err(crate::query::executor::ExecutionError::Aggregation(AggregationError::RawSelect(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_custom_aggregate_dispatch() {
+        use crate::query::parser::validator::{ArgKind, FunctionSignature, QueryValidator, Schema};
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let series = TimeSeries::new("latency".to_string()).unwrap();
+            let guard = memtable.write().await;
+            for (ts, value, weight) in [(1, 10.0, "1"), (2, 20.0, "3"), (3, 40.0, "0")] {
+                let mut tags = HashMap::new();
+                tags.insert("weight".to_string(), weight.to_string());
+                guard.insert(&series, &DataPoint::new(ts, value, tags)).await.unwrap();
+            }
+        }
+
+        // A mean weighted by each point's `weight` tag, scaled by a literal argument
+        let mut executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+        executor.register_function(
+            "weighted_mean",
+            Arc::new(|points, args| {
+                let weight = |p: &DataPoint| p.tags()["weight"].parse::<f64>().unwrap();
+                let total: f64 = points.iter().map(|p| weight(p)).sum();
+                let scale = match args.get(1) {
+                    Some(FunctionArg::NumberLiteral(scale)) => *scale,
+                    _ => 1.0,
+                };
+                scale * points.iter().map(|p| p.value() * weight(p)).sum::<f64>() / total
+            }),
+        );
+
+        let mut schema = Schema::new();
+        schema.add_value_field("value".to_string());
+        let mut validator = QueryValidator::new().with_schema(schema);
+        let query = parse("SELECT weighted_mean(value, 2) AS wm FROM latency");
+        assert!(validator.validate(&query).is_err());
+        validator.register_function(
+            "weighted_mean",
+            FunctionSignature::new(vec![ArgKind::Column]).with_optional(ArgKind::Number),
+        );
+        assert!(validator.validate(&query).is_ok());
+
+        let groups = executor.execute_grouped(&query).await.unwrap();
+        // (10 * 1 + 20 * 3 + 40 * 0) / 4 = 17.5, doubled
+        assert_eq!(groups[0].value("wm"), Some(35.0));
+    }
+
+    #[test]
+    fn test_having_filters_groups() {
+        let points: Vec<DataPoint> = [("a", 90.0), ("a", 70.0), ("b", 60.0), ("c", 95.0), ("c", 85.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, (host, value))| {
+                let mut tags = HashMap::new();
+                tags.insert("host".to_string(), host.to_string());
+                DataPoint::new(i as i64, *value, tags)
+            })
+            .collect();
+
+        let query = parse("SELECT avg(value) AS a FROM cpu GROUP BY host HAVING a > 75 ORDER GROUPS BY a DESC");
+        let groups = aggregate(&points, &query, (0, 1_000)).unwrap();
+        let hosts: Vec<(&str, Option<f64>)> = groups.iter().map(|g| (g.key[0].1.as_str(), g.value("a"))).collect();
+        assert_eq!(hosts, vec![("c", Some(90.0)), ("a", Some(80.0))]);
+
+        let query = parse("SELECT avg(value) FROM cpu GROUP BY host HAVING avg(value) <= 60");
+        let groups = aggregate(&points, &query, (0, 1_000)).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].key[0].1, "b");
+
+        let query = parse("SELECT avg(value) AS a FROM cpu GROUP BY host HAVING b > 1");
+        assert!(matches!(aggregate(&points, &query, (0, 1_000)), Err(AggregationError::UnknownColumn(c)) if c == "b"));
+    }
+}
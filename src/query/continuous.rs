@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, warn};
+
+use crate::storage::data::{DataError, DataPoint, TimeSeries};
+use crate::storage::lsm::memtable::{MemTable, MemTableError};
+
+/// Error type for continuous query operations
+#[derive(Debug, thiserror::Error)]
+pub enum ContinuousQueryError {
+    #[error("Invalid continuous query: {0}")]
+    InvalidDefinition(String),
+    #[error("Continuous query already registered: {0}")]
+    AlreadyRegistered(String),
+    #[error("Failed to write rollup: {0}")]
+    MemTable(#[from] MemTableError),
+    #[error("Invalid destination series: {0}")]
+    Data(#[from] DataError),
+}
+
+/// Aggregate computed over each interval of a continuous query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollupFunction {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+}
+
+/// Definition of a rollup maintained as data arrives
+#[derive(Debug, Clone)]
+pub struct ContinuousQuery {
+    /// Unique name of the continuous query
+    pub name: String,
+    /// Series the raw points are read from
+    pub source: String,
+    /// Aggregate applied to each interval
+    pub function: RollupFunction,
+    /// Interval length in nanoseconds
+    pub interval: i64,
+    /// Series the rollup points are written to
+    pub destination: String,
+}
+
+/// Running aggregate for one interval
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: i64,
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Window {
+    fn new(start: i64) -> Self {
+        Self {
+            start,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn result(&self, function: RollupFunction) -> f64 {
+        match function {
+            RollupFunction::Avg => self.sum / self.count as f64,
+            RollupFunction::Sum => self.sum,
+            RollupFunction::Min => self.min,
+            RollupFunction::Max => self.max,
+            RollupFunction::Count => self.count as f64,
+        }
+    }
+}
+
+/// A registered continuous query and its open interval
+struct RegisteredQuery {
+    definition: ContinuousQuery,
+    destination: TimeSeries,
+    open_window: Option<Window>,
+    /// End of the last interval written; earlier intervals are closed
+    written_until: Option<i64>,
+}
+
+/// Evaluates continuous queries incrementally and writes their rollups to the MemTable
+///
+/// Each observed point is folded into the open interval of every query reading its
+/// series. An interval is closed and written once a point from a later interval
+/// arrives, or when [`ContinuousQueryManager::flush`] is called. An interval is
+/// written at most once: points arriving for an interval that has already been
+/// written, including one written early by a flush, are ignored.
+pub struct ContinuousQueryManager {
+    /// MemTable the rollup points are written to
+    memtable: Arc<RwLock<MemTable>>,
+    /// Registered queries
+    queries: Mutex<Vec<RegisteredQuery>>,
+}
+
+impl ContinuousQueryManager {
+    /// Creates a new manager writing into the given MemTable
+    pub fn new(memtable: Arc<RwLock<MemTable>>) -> Self {
+        Self {
+            memtable,
+            queries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Registers a continuous query
+    pub async fn register(&self, query: ContinuousQuery) -> Result<(), ContinuousQueryError> {
+        if query.interval <= 0 {
+            return Err(ContinuousQueryError::InvalidDefinition(format!(
+                "interval must be positive, got {}",
+                query.interval
+            )));
+        }
+        if query.source == query.destination {
+            return Err(ContinuousQueryError::InvalidDefinition(
+                "destination must differ from source".to_string(),
+            ));
+        }
+
+        let mut queries = self.queries.lock().await;
+        if queries.iter().any(|q| q.definition.name == query.name) {
+            return Err(ContinuousQueryError::AlreadyRegistered(query.name));
+        }
+
+        let destination = TimeSeries::new(query.destination.clone())?;
+        queries.push(RegisteredQuery {
+            definition: query,
+            destination,
+            open_window: None,
+            written_until: None,
+        });
+        Ok(())
+    }
+
+    /// Removes a continuous query, returning whether it was registered
+    pub async fn unregister(&self, name: &str) -> bool {
+        let mut queries = self.queries.lock().await;
+        let before = queries.len();
+        queries.retain(|q| q.definition.name != name);
+        queries.len() != before
+    }
+
+    /// Folds a newly ingested point into the matching continuous queries
+    ///
+    /// Returns the number of rollup points written.
+    pub async fn observe(
+        &self,
+        series_name: &str,
+        point: &DataPoint,
+    ) -> Result<usize, ContinuousQueryError> {
+        let mut queries = self.queries.lock().await;
+        let mut written = 0;
+
+        for query in queries.iter_mut().filter(|q| q.definition.source == series_name) {
+            let interval = query.definition.interval;
+            let start = point.timestamp() - point.timestamp().rem_euclid(interval);
+            let closed = query.written_until.is_some_and(|written_until| start < written_until);
+
+            match query.open_window {
+                _ if closed => {
+                    warn!(
+                        "Continuous query {} ignoring point at {} for a closed interval",
+                        query.definition.name,
+                        point.timestamp()
+                    );
+                    continue;
+                }
+                Some(window) if start < window.start => {
+                    warn!(
+                        "Continuous query {} ignoring point at {} for a closed interval",
+                        query.definition.name,
+                        point.timestamp()
+                    );
+                    continue;
+                }
+                Some(window) if start > window.start => {
+                    self.write_window(query, window).await?;
+                    written += 1;
+                    query.open_window = Some(Window::new(start));
+                }
+                Some(_) => {}
+                None => query.open_window = Some(Window::new(start)),
+            }
+
+            if let Some(window) = query.open_window.as_mut() {
+                window.add(point.value());
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Writes the open interval of every query, e.g. before a MemTable flush
+    ///
+    /// Returns the number of rollup points written.
+    pub async fn flush(&self) -> Result<usize, ContinuousQueryError> {
+        let mut queries = self.queries.lock().await;
+        let mut written = 0;
+
+        for query in queries.iter_mut() {
+            if let Some(window) = query.open_window.take() {
+                self.write_window(query, window).await?;
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Writes the rollup of a closed interval to the destination series
+    async fn write_window(
+        &self,
+        query: &mut RegisteredQuery,
+        window: Window,
+    ) -> Result<(), ContinuousQueryError> {
+        let mut tags = HashMap::new();
+        tags.insert("series".to_string(), query.definition.destination.clone());
+        let rollup = DataPoint::new(window.start, window.result(query.definition.function), tags);
+
+        self.memtable
+            .read()
+            .await
+            .insert(&query.destination, &rollup)
+            .await?;
+
+        query.written_until = Some(window.start.saturating_add(query.definition.interval));
+
+        debug!(
+            "Continuous query {} wrote {} at {}",
+            query.definition.name,
+            rollup.value(),
+            window.start
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::executor::{ExecutionConfig, QueryExecutor};
+    use crate::query::parser::ast::{Query, TimeRange};
+
+    #[tokio::test]
+    async fn test_continuous_average_rollup() {
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+        let manager = ContinuousQueryManager::new(memtable.clone());
+
+        let minute = 60_000_000_000;
+        manager
+            .register(ContinuousQuery {
+                name: "cpu_1m".to_string(),
+                source: "cpu".to_string(),
+                function: RollupFunction::Avg,
+                interval: minute,
+                destination: "cpu_1m_avg".to_string(),
+            })
+            .await
+            .unwrap();
+
+        // Raw points spread over three one-minute intervals
+        let cpu = TimeSeries::new("cpu".to_string()).unwrap();
+        let raw = [
+            (10, 1.0),
+            (40, 3.0),
+            (70, 10.0),
+            (80, 20.0),
+            (110, 30.0),
+            (130, 7.0),
+        ];
+        let mut written = 0;
+        for (seconds, value) in raw {
+            let point = DataPoint::new(seconds * 1_000_000_000, value, HashMap::new());
+            memtable.read().await.insert(&cpu, &point).await.unwrap();
+            written += manager.observe("cpu", &point).await.unwrap();
+        }
+        assert_eq!(written, 2);
+        assert_eq!(manager.flush().await.unwrap(), 1);
+
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+        let mut query = Query::new();
+        query.from = "cpu_1m_avg".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 10 * minute });
+        let rollups: Vec<(i64, f64)> = executor
+            .execute(&query)
+            .await
+            .unwrap()
+            .points
+            .iter()
+            .map(|p| (p.timestamp(), p.value()))
+            .collect();
+
+        assert_eq!(rollups, vec![(0, 2.0), (minute, 20.0), (2 * minute, 7.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_flushed_interval_is_not_written_again() {
+        let memtable = Arc::new(RwLock::new(
+            MemTable::new(1000).with_duplicate_policy(crate::storage::lsm::memtable::DuplicatePolicy::Error),
+        ));
+        let manager = ContinuousQueryManager::new(memtable.clone());
+        manager
+            .register(ContinuousQuery {
+                name: "cpu_sum".to_string(),
+                source: "cpu".to_string(),
+                function: RollupFunction::Sum,
+                interval: 100,
+                destination: "cpu_sum".to_string(),
+            })
+            .await
+            .unwrap();
+
+        manager.observe("cpu", &DataPoint::new(10, 1.0, HashMap::new())).await.unwrap();
+        assert_eq!(manager.flush().await.unwrap(), 1);
+        // A late point for the flushed interval is dropped rather than
+        // colliding with the rollup already written
+        assert_eq!(manager.observe("cpu", &DataPoint::new(20, 2.0, HashMap::new())).await.unwrap(), 0);
+        assert_eq!(manager.flush().await.unwrap(), 0);
+
+        manager.observe("cpu", &DataPoint::new(110, 4.0, HashMap::new())).await.unwrap();
+        assert_eq!(manager.flush().await.unwrap(), 1);
+        let rollups = memtable.read().await.get_series_range("cpu_sum", 0, 1000).await;
+        let rollups: Vec<(i64, f64)> = rollups.iter().map(|p| (p.timestamp(), p.value())).collect();
+        assert_eq!(rollups, vec![(0, 1.0), (100, 4.0)]);
+    }
+}
@@ -1,13 +1,25 @@
+use std::fmt;
 use std::sync::Arc;
-use tokio::sync::{RwLock, Mutex};
+use tokio::sync::{mpsc, RwLock, Mutex, Semaphore};
 use tokio::task::JoinHandle;
-use std::collections::HashSet;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tracing::warn;
 
-use crate::storage::data::DataPoint;
+use crate::metrics;
+use crate::storage::data::{self, DataPoint};
+use crate::storage::last_value::LastValueCache;
 use crate::storage::lsm::memtable::MemTable;
-use crate::storage::lsm::sstable::{SSTable, DataBlock};
-use crate::query::parser::ast::{Query, TimeRange};
+use crate::storage::lsm::merge::MergeIterator;
+use crate::storage::lsm::sstable::{SSTable, SSTableError, DataBlock};
+use crate::storage::lsm::tombstone::{self, TombstoneSet};
+use crate::query::aggregation::{self, AggregateEvaluator, AggregationError, CustomAggregates, Group};
+use crate::query::output::OutputError;
+use crate::query::parser::ast::{FilterExpr, Query, SelectExpr, SelectItem, TimeRange};
+use crate::query::parser::validator::column_name;
+use crate::query::planner::{PlanExplanation, QueryPlan};
+use crate::query::result::ResultSet;
 
 /// Error type for execution operations
 #[derive(Debug, thiserror::Error)]
@@ -18,11 +30,235 @@ pub enum ExecutionError {
     Cancelled,
     #[error("Memory limit exceeded")]
     MemoryLimitExceeded,
+    #[error("Aggregation failed: {0}")]
+    Aggregation(#[from] AggregationError),
+    #[error("Scan of SSTable {0} failed: {1}")]
+    ScanFailed(String, SSTableError),
+    #[error("Encoding results failed: {0}")]
+    Encoding(#[from] OutputError),
 }
 
 /// Result type for execution operations
 pub type ExecutionResult<T> = Result<T, ExecutionError>;
 
+/// Points returned by a query
+#[derive(Debug, Clone, Default)]
+pub struct QueryResult {
+    /// Returned points ordered by timestamp
+    pub points: Vec<DataPoint>,
+    /// SSTables left out after their scans failed under `ScanErrorPolicy::SkipAndWarn`
+    pub skipped_tables: Vec<String>,
+    /// Time range each point was returned for, parallel to `points`; empty
+    /// unless the query has extra time ranges
+    pub range_indices: Vec<usize>,
+}
+
+/// Points of one window of a query with several time ranges
+#[derive(Debug, Clone, Default)]
+pub struct RangeResult {
+    /// Position of the window: 0 for `time_range`, then the extra ranges in order
+    pub range_index: usize,
+    /// Inclusive bounds the window resolved to, in nanoseconds
+    pub start: i64,
+    pub end: i64,
+    /// Points of the window ordered by timestamp
+    pub points: Vec<DataPoint>,
+    /// SSTables left out of the window after their scans failed
+    pub skipped_tables: Vec<String>,
+}
+
+impl QueryResult {
+    /// Creates a result from a list of points
+    pub fn new(points: Vec<DataPoint>) -> Self {
+        Self {
+            points,
+            skipped_tables: Vec::new(),
+            range_indices: Vec::new(),
+        }
+    }
+
+    /// Returns whether points may be missing because SSTables were skipped
+    pub fn is_incomplete(&self) -> bool {
+        !self.skipped_tables.is_empty()
+    }
+
+    /// Groups the points by series identity into time-ordered `(timestamp, value)` vectors
+    ///
+    /// A series is identified by its full tag set, rendered as `series,key=value,...`
+    /// with the remaining tags in [`data::canonical_series_key`] form.
+    pub fn into_series_matrix(self) -> BTreeMap<String, Vec<(i64, f64)>> {
+        let mut matrix: BTreeMap<String, Vec<(i64, f64)>> = BTreeMap::new();
+        for point in self.points {
+            matrix
+                .entry(series_key(&point))
+                .or_default()
+                .push((point.timestamp(), point.value()));
+        }
+        for samples in matrix.values_mut() {
+            samples.sort_by_key(|(timestamp, _)| *timestamp);
+        }
+        matrix
+    }
+}
+
+/// Output of a query run through [`QueryExecutor::execute_plan`]
+#[derive(Debug, Clone)]
+pub enum PlannedResult {
+    /// The plan of an `EXPLAIN` query, which is not executed
+    Explained(PlanExplanation),
+    /// The points of any other query
+    Executed(QueryResult),
+}
+
+/// Latest value of each matching series as of one instant
+#[derive(Debug, Clone, Default)]
+pub struct InstantResult {
+    /// The instant every series was evaluated at, in nanoseconds
+    pub now: i64,
+    /// Latest point of each matching series, ordered by series name
+    pub series: Vec<(String, DataPoint)>,
+}
+
+impl From<Vec<DataPoint>> for QueryResult {
+    fn from(points: Vec<DataPoint>) -> Self {
+        Self::new(points)
+    }
+}
+
+/// Renders the identity of the series a point belongs to
+fn series_key(point: &DataPoint) -> String {
+    let mut tags = point.tags().clone();
+    let series_name = tags.remove("series").unwrap_or_default();
+    data::series_key(&series_name, &tags)
+}
+
+/// A stage of query execution reported by `explain_analyze`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryStage {
+    /// Reading points from the MemTable and SSTable blocks
+    Scan,
+    /// Applying the time range, value filters, tombstones and deduplication
+    Filter,
+    /// Evaluating the select list per group
+    Aggregate,
+    /// Ordering points by timestamp
+    Sort,
+}
+
+impl fmt::Display for QueryStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            QueryStage::Scan => "Scan",
+            QueryStage::Filter => "Filter",
+            QueryStage::Aggregate => "Aggregate",
+            QueryStage::Sort => "Sort",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Measured output of one execution stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageStats {
+    pub stage: QueryStage,
+    /// Rows the stage produced
+    pub rows: usize,
+    /// Time spent in the stage; for parallel SSTable scans this is summed across tasks
+    pub elapsed: Duration,
+}
+
+/// A query plan annotated with statistics from actually running it
+#[derive(Debug, Clone)]
+pub struct AnalyzedPlan {
+    /// Stages in execution order
+    pub stages: Vec<StageStats>,
+    /// SSTables scanned
+    pub sstables_scanned: usize,
+    /// SSTable blocks read from disk, after pruning
+    pub blocks_read: usize,
+    /// Wall-clock time of the whole query
+    pub total: Duration,
+}
+
+impl AnalyzedPlan {
+    /// Returns the statistics of a stage, if the query ran it
+    pub fn stage(&self, stage: QueryStage) -> Option<&StageStats> {
+        self.stages.iter().find(|s| s.stage == stage)
+    }
+}
+
+impl fmt::Display for AnalyzedPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stats in &self.stages {
+            writeln!(f, "{:<10} rows={:<8} time={:?}", stats.stage, stats.rows, stats.elapsed)?;
+        }
+        write!(
+            f,
+            "sstables={} blocks={} total={:?}",
+            self.sstables_scanned, self.blocks_read, self.total
+        )
+    }
+}
+
+/// Counters filled in while a query runs, shared with its scan tasks
+#[derive(Debug, Default)]
+struct ScanStats {
+    sstables_scanned: AtomicUsize,
+    blocks_read: AtomicUsize,
+    points_scanned: AtomicUsize,
+    points_retained: AtomicUsize,
+    scan_nanos: AtomicU64,
+    filter_nanos: AtomicU64,
+}
+
+impl ScanStats {
+    fn record(counter: &AtomicU64, started: Instant) {
+        counter.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// How samples of one series are matched to the timestamps of another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// Use the sample closest in time, preferring the earlier one on ties
+    Nearest,
+    /// Use the most recent sample at or before the timestamp
+    Previous,
+    /// Linearly interpolate between the surrounding samples
+    Interpolate,
+}
+
+/// Arithmetic operation combining two aligned series
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    /// Applies the operation to a pair of values
+    pub fn apply(&self, left: f64, right: f64) -> f64 {
+        match self {
+            BinaryOp::Add => left + right,
+            BinaryOp::Sub => left - right,
+            BinaryOp::Mul => left * right,
+            BinaryOp::Div => left / right,
+        }
+    }
+}
+
+/// What a query does when scanning one of its SSTables fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanErrorPolicy {
+    /// Fail the whole query
+    #[default]
+    Fail,
+    /// Log and count the failure, and return the other tables' points as an incomplete result
+    SkipAndWarn,
+}
+
 /// Configuration for query execution
 #[derive(Debug, Clone)]
 pub struct ExecutionConfig {
@@ -32,6 +268,18 @@ pub struct ExecutionConfig {
     pub memory_limit: usize,
     /// Timeout for query execution
     pub timeout: Duration,
+    /// Slack added to the upper bound of `Last`/`Relative` ranges so points
+    /// stamped by clients slightly ahead of the server clock are not excluded
+    pub clock_skew_tolerance: Duration,
+    /// Handling of SSTables that cannot be read
+    pub on_scan_error: ScanErrorPolicy,
+    /// Filter and deliver each SSTable block as soon as it is read, rather
+    /// than reading the whole table first
+    ///
+    /// Bounds a scan's memory to a few blocks, but a table that fails partway
+    /// may already have delivered some of its points, even when it is skipped
+    /// under `ScanErrorPolicy::SkipAndWarn`.
+    pub stream_blocks: bool,
 }
 
 impl Default for ExecutionConfig {
@@ -40,6 +288,9 @@ impl Default for ExecutionConfig {
             max_concurrent_tasks: 4,
             memory_limit: 1024 * 1024 * 1024, // 1GB
             timeout: Duration::from_secs(30),
+            clock_skew_tolerance: Duration::ZERO,
+            on_scan_error: ScanErrorPolicy::default(),
+            stream_blocks: false,
         }
     }
 }
@@ -57,6 +308,17 @@ pub struct QueryExecutor {
     memory_usage: Arc<Mutex<usize>>,
     /// Cancellation flag
     cancelled: Arc<Mutex<bool>>,
+    /// Source of the current time in nanoseconds, used to resolve relative ranges
+    clock: Arc<dyn Fn() -> i64 + Send + Sync>,
+    /// Deletions applied to points as they are read
+    tombstones: Arc<TombstoneSet>,
+    /// Latest point per series, used to answer instant queries
+    last_values: Option<LastValueCache>,
+    /// Aggregate functions registered in addition to the built-in ones
+    custom_aggregates: CustomAggregates,
+    /// Number of SSTable scans currently running and the most seen at once
+    #[cfg(test)]
+    scan_counts: Arc<(AtomicUsize, AtomicUsize)>,
 }
 
 impl QueryExecutor {
@@ -72,128 +334,767 @@ impl QueryExecutor {
             config,
             memory_usage: Arc::new(Mutex::new(0)),
             cancelled: Arc::new(Mutex::new(false)),
+            clock: Arc::new(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()),
+            tombstones: Arc::new(TombstoneSet::new()),
+            last_values: None,
+            custom_aggregates: CustomAggregates::new(),
+            #[cfg(test)]
+            scan_counts: Arc::new((AtomicUsize::new(0), AtomicUsize::new(0))),
+        }
+    }
+
+    /// Replaces the wall clock used to resolve `Last`/`Relative` ranges
+    pub fn with_clock(mut self, clock: Arc<dyn Fn() -> i64 + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Registers a custom aggregate function for grouped queries
+    ///
+    /// Queries checked by a [`crate::query::parser::QueryValidator`] also need the
+    /// function's signature registered there.
+    pub fn register_function(&mut self, name: &str, evaluator: AggregateEvaluator) {
+        self.custom_aggregates.register(name, evaluator);
+    }
+
+    /// Sets the tombstones used to hide deleted points
+    pub fn with_tombstones(mut self, tombstones: Arc<TombstoneSet>) -> Self {
+        self.tombstones = tombstones;
+        self
+    }
+
+    /// Sets the last-value cache used to answer instant queries
+    pub fn with_last_value_cache(mut self, cache: LastValueCache) -> Self {
+        self.last_values = Some(cache);
+        self
+    }
+
+    /// Resolves a query time range to inclusive `(start, end)` bounds in nanoseconds
+    ///
+    /// Relative ranges are anchored at the executor's clock, and their upper bound
+    /// is widened by the configured clock skew tolerance.
+    fn resolve_time_range(&self, time_range: &TimeRange) -> (i64, i64) {
+        let tolerance = i64::try_from(self.config.clock_skew_tolerance.as_nanos()).unwrap_or(i64::MAX);
+        match time_range {
+            TimeRange::Absolute { start, end } => (*start, *end),
+            TimeRange::Last { duration } => {
+                let now = (self.clock)();
+                (now.saturating_sub(*duration), now.saturating_add(tolerance))
+            }
+            TimeRange::Relative { offset, duration } => {
+                let start = (self.clock)().saturating_sub(*offset);
+                (start, start.saturating_add(*duration).saturating_add(tolerance))
+            }
+        }
+    }
+
+    /// Executes a query and returns its output as named columns
+    ///
+    /// Queries without a select list, or selecting `*`, return the points of
+    /// [`QueryExecutor::execute`] with a column per tag key. Bare columns and
+    /// transforms return `timestamp` and one column per select expression.
+    /// Aggregate queries return a row per group, as laid out by
+    /// [`ResultSet::from_groups`]. Select aliases name their columns. Queries
+    /// with several time ranges lead with a `range` column holding the index
+    /// of the range each row belongs to.
+    ///
+    /// SSTables skipped under `ScanErrorPolicy::SkipAndWarn` are not reported;
+    /// use [`QueryExecutor::execute`] to learn whether the result is complete.
+    pub async fn execute_query(&self, query: &Query) -> ExecutionResult<ResultSet> {
+        let wildcard = query.select.iter().all(|expr| matches!(expr.item, SelectItem::Wildcard));
+        if wildcard {
+            let result = self.execute(query).await?;
+            Ok(ResultSet::from_points(&result.points).with_range_indices(&result.range_indices))
+        } else if query.is_raw() {
+            let result = self.execute(query).await?;
+            let names = query.select.iter().map(column_name).collect();
+            Ok(ResultSet::from_projection(names, &result.points).with_range_indices(&result.range_indices))
+        } else {
+            let groups = self.execute_grouped(query).await?;
+            Ok(ResultSet::from_groups(query, &groups))
+        }
+    }
+
+    /// Collects all matching points, ordered by timestamp
+    ///
+    /// Queries with extra time ranges return the points of every window, each
+    /// with the index of its window in `range_indices`.
+    async fn collect(&self, query: &Query) -> ExecutionResult<QueryResult> {
+        if query.extra_time_ranges.is_empty() {
+            return self.collect_with_stats(query, &Arc::default()).await.map(|(result, _)| result);
+        }
+
+        let mut skipped_tables = Vec::new();
+        let mut windows = Vec::new();
+        for window in self.execute_ranges(query).await? {
+            for table in window.skipped_tables {
+                if !skipped_tables.contains(&table) {
+                    skipped_tables.push(table);
+                }
+            }
+            windows.extend(window.points.into_iter().map(|point| (window.range_index, point)));
+        }
+        // Each window is ordered and the sort is stable, so only the windows interleave
+        windows.sort_by_key(|(_, point)| point.timestamp());
+        let (range_indices, points) = windows.into_iter().unzip();
+        Ok(QueryResult {
+            points,
+            skipped_tables,
+            range_indices,
+        })
+    }
+
+    /// Executes a query once per time range, returning each window's points
+    ///
+    /// Windows are resolved against one reading of the clock. A point lying in
+    /// several overlapping windows is returned only with the first of them, so
+    /// the windows together hold each point once.
+    pub async fn execute_ranges(&self, query: &Query) -> ExecutionResult<Vec<RangeResult>> {
+        let bounds: Vec<(i64, i64)> = query.time_ranges().map(|range| self.resolve_time_range(range)).collect();
+        if bounds.is_empty() {
+            return Err(ExecutionError::ExecutionFailed("Time range is required".to_string()));
+        }
+
+        let mut results = Vec::with_capacity(bounds.len());
+        for (range_index, &(start, end)) in bounds.iter().enumerate() {
+            let window = Query {
+                time_range: Some(TimeRange::Absolute { start, end }),
+                extra_time_ranges: Vec::new(),
+                ..query.clone()
+            };
+            let (result, _) = self.collect_with_stats(&window, &Arc::default()).await?;
+            let earlier = &bounds[..range_index];
+            let points = result
+                .points
+                .into_iter()
+                .filter(|point| {
+                    !earlier
+                        .iter()
+                        .any(|&(start, end)| (start..=end).contains(&point.timestamp()))
+                })
+                .collect();
+            results.push(RangeResult {
+                range_index,
+                start,
+                end,
+                points,
+                skipped_tables: result.skipped_tables,
+            });
+        }
+        Ok(results)
+    }
+
+    /// Collects all matching points, recording scan statistics and returning the sort time
+    async fn collect_with_stats(
+        &self,
+        query: &Query,
+        stats: &Arc<ScanStats>,
+    ) -> ExecutionResult<(QueryResult, Duration)> {
+        let mut batches = Vec::new();
+        let skipped_tables = self
+            .execute_with_limits(query, false, stats, |batch| {
+                batches.push(batch);
+                Ok(())
+            })
+            .await?;
+
+        // The MemTable's points and each block's arrive in order, so merge them
+        let started = Instant::now();
+        let results: Vec<DataPoint> = MergeIterator::new(batches.into_iter().map(Vec::into_iter)).collect();
+        let sort_time = started.elapsed();
+        Ok((
+            QueryResult {
+                points: results,
+                skipped_tables,
+                range_indices: Vec::new(),
+            },
+            sort_time,
+        ))
+    }
+
+    /// Returns the latest value of every series matching `filter` as of now
+    ///
+    /// `now` is read from the executor's clock once and every series is
+    /// evaluated against it from one snapshot of the last-value cache, so
+    /// series are aligned to the same instant. A series matches when its latest
+    /// point's tags and value satisfy the filter; regex tag filters cannot be
+    /// evaluated here and do not exclude series.
+    pub async fn execute_instant(&self, filter: Option<&FilterExpr>) -> ExecutionResult<InstantResult> {
+        let cache = self.last_values.as_ref().ok_or_else(|| {
+            ExecutionError::ExecutionFailed("instant queries need a last-value cache".to_string())
+        })?;
+        let now = (self.clock)();
+        let mut series = cache.snapshot_at(now).await;
+        if let Some(filter) = filter {
+            series.retain(|(_, point)| filter.matches(point.tags(), point.value()) != Some(false));
+        }
+        Ok(InstantResult { now, series })
+    }
+
+    /// Runs a query and reports the rows and time of each stage
+    ///
+    /// Queries with a select list are aggregated as by
+    /// [`QueryExecutor::execute_grouped`], adding an `Aggregate` stage whose rows
+    /// are the groups produced. The query's results are discarded. Queries with
+    /// several time ranges are rejected; analyze each range separately.
+    pub async fn explain_analyze(&self, query: &Query) -> ExecutionResult<AnalyzedPlan> {
+        let started = Instant::now();
+        let stats = Arc::new(ScanStats::default());
+        let (result, sort_time) = self.collect_with_stats(query, &stats).await?;
+
+        let nanos = |counter: &AtomicU64| Duration::from_nanos(counter.load(Ordering::Relaxed));
+        let mut stages = vec![
+            StageStats {
+                stage: QueryStage::Scan,
+                rows: stats.points_scanned.load(Ordering::Relaxed),
+                elapsed: nanos(&stats.scan_nanos),
+            },
+            StageStats {
+                stage: QueryStage::Filter,
+                rows: stats.points_retained.load(Ordering::Relaxed),
+                elapsed: nanos(&stats.filter_nanos),
+            },
+            StageStats {
+                stage: QueryStage::Sort,
+                rows: result.points.len(),
+                elapsed: sort_time,
+            },
+        ];
+
+        if !query.select.is_empty() && !query.is_raw() {
+            let range = query
+                .time_range
+                .as_ref()
+                .map(|time_range| self.resolve_time_range(time_range))
+                .unwrap_or_default();
+            let aggregate_started = Instant::now();
+            let groups = aggregation::aggregate_with(&result.points, query, range, &self.custom_aggregates)?;
+            stages.push(StageStats {
+                stage: QueryStage::Aggregate,
+                rows: groups.len(),
+                elapsed: aggregate_started.elapsed(),
+            });
         }
+
+        Ok(AnalyzedPlan {
+            stages,
+            sstables_scanned: stats.sstables_scanned.load(Ordering::Relaxed),
+            blocks_read: stats.blocks_read.load(Ordering::Relaxed),
+            total: started.elapsed(),
+        })
     }
 
-    /// Executes a query with parallel processing
-    pub async fn execute_query(&self, query: &Query) -> ExecutionResult<Vec<DataPoint>> {
+    /// Executes a query, handing each matching point to `on_point` as it is read
+    ///
+    /// Points arrive in scan order rather than timestamp order: MemTable points
+    /// first, then SSTable blocks as the parallel scans produce them. Memory
+    /// charged for a block is released once its points have been delivered, so
+    /// wide ranges can be folded incrementally within the memory limit.
+    /// Returning an error from `on_point` stops the query with that error.
+    /// Queries with several time ranges are rejected.
+    ///
+    /// Returns the SSTables skipped under `ScanErrorPolicy::SkipAndWarn`.
+    pub async fn execute_query_with<F>(&self, query: &Query, mut on_point: F) -> ExecutionResult<Vec<String>>
+    where
+        F: FnMut(DataPoint) -> ExecutionResult<()>,
+    {
+        self.execute_with_limits(query, true, &Arc::default(), |batch| {
+            batch.into_iter().try_for_each(&mut on_point)
+        })
+        .await
+    }
+
+    /// Runs a query under the configured timeout and cancellation checks
+    ///
+    /// Every run, including failed ones, is recorded in the query metrics.
+    /// Queries with extra time ranges are rejected, since the scan covers one
+    /// range; they are run a range at a time by [`QueryExecutor::execute_ranges`].
+    async fn execute_with_limits<F>(
+        &self,
+        query: &Query,
+        release_delivered: bool,
+        stats: &Arc<ScanStats>,
+        on_batch: F,
+    ) -> ExecutionResult<Vec<String>>
+    where
+        F: FnMut(Vec<DataPoint>) -> ExecutionResult<()>,
+    {
+        if query.explain {
+            return Err(ExecutionError::ExecutionFailed(
+                "EXPLAIN queries are planned, not executed; see QueryExecutor::execute_plan".to_string(),
+            ));
+        }
+        if !query.extra_time_ranges.is_empty() {
+            return Err(ExecutionError::ExecutionFailed(
+                "Queries with several time ranges are run per range; see QueryExecutor::execute_ranges".to_string(),
+            ));
+        }
+
         // Reset cancellation flag
         *self.cancelled.lock().await = false;
         *self.memory_usage.lock().await = 0;
 
+        let started = Instant::now();
+        let mut points_returned = 0;
+        let mut on_batch = on_batch;
+        let counted = |batch: Vec<DataPoint>| {
+            points_returned += batch.len();
+            on_batch(batch)
+        };
+
         // Create a timeout future
         let timeout = tokio::time::sleep(self.config.timeout);
         tokio::pin!(timeout);
 
         // Execute query with timeout
-        let result = tokio::select! {
-            result = self.execute_query_internal(query) => result,
+        let mut result = tokio::select! {
+            result = self.execute_query_internal(query, release_delivered, stats, counted) => result,
             _ = timeout.as_mut() => Err(ExecutionError::ExecutionFailed("Query timeout".to_string())),
         };
 
         // Check if query was cancelled
         if *self.cancelled.lock().await {
-            return Err(ExecutionError::Cancelled);
+            result = Err(ExecutionError::Cancelled);
+        }
+
+        metrics::record_query_execution(
+            started.elapsed(),
+            stats.sstables_scanned.load(Ordering::Relaxed),
+            stats.blocks_read.load(Ordering::Relaxed),
+            points_returned,
+        );
+        match &result {
+            Err(ExecutionError::Cancelled) => metrics::record_query_cancelled(),
+            Err(ExecutionError::MemoryLimitExceeded) => metrics::record_query_memory_limit_exceeded(),
+            _ => {}
         }
 
         result
     }
 
+    /// Executes a query and wraps the returned points in a QueryResult
+    ///
+    /// Points keep all of their tags and are not grouped, which is what a
+    /// `SELECT *` query asks for. Queries selecting bare columns get only the
+    /// timestamp and value of each point, and a transform such as
+    /// `cumulative_sum(value)` is applied to the time-ordered points.
+    pub async fn execute(&self, query: &Query) -> ExecutionResult<QueryResult> {
+        let project = projects_columns(query)?;
+        let mut result = self.collect(query).await?;
+        if let [SelectExpr { item: SelectItem::Function(call), .. }] = query.select.as_slice() {
+            if aggregation::is_transform(&call.name) {
+                result.points = aggregation::transform(call, result.points)?;
+            }
+        }
+        if project {
+            result.points = result
+                .points
+                .into_iter()
+                .map(|point| DataPoint::with_value(point.timestamp(), point.typed_value(), HashMap::new()))
+                .collect();
+        }
+        Ok(result)
+    }
+
+    /// Executes a query planned by [`crate::query::QueryPlanner`]
+    ///
+    /// `EXPLAIN` queries are not executed; the plan is returned as a
+    /// [`PlanExplanation`] instead.
+    ///
+    /// Every SSTable block overlapping the time range that block metadata cannot
+    /// rule out is read whatever the plan, so a full-scan plan runs like any
+    /// other; it is logged and counted so slow queries can be traced to a
+    /// missing index.
+    pub async fn execute_plan(&self, query: &Query, plan: &QueryPlan) -> ExecutionResult<PlannedResult> {
+        if query.explain {
+            return Ok(PlannedResult::Explained(plan.explain()));
+        }
+        if plan.requires_full_scan() {
+            warn!("Executing a full scan of {}: no index narrows the query", query.from);
+            metrics::record_full_scan_query();
+        }
+        Ok(PlannedResult::Executed(self.execute(query).await?))
+    }
+
+    /// Executes a query and encodes its output as requested by its `FORMAT` clause
+    ///
+    /// The columns and rows of [`QueryExecutor::execute_query`] are encoded, so
+    /// aggregates, projections and aliases appear as they would there. Queries
+    /// without the clause are encoded as JSON. Prometheus samples are named
+    /// after the queried series.
+    pub async fn execute_formatted(&self, query: &Query) -> ExecutionResult<Vec<u8>> {
+        let result = self.execute_query(query).await?;
+        Ok(result.encode(query.format.unwrap_or_default(), &query.from)?)
+    }
+
+    /// Executes a query and aggregates its points per GROUP BY tag combination
+    ///
+    /// Points are also split into `GROUP BY time()` buckets when requested. Groups
+    /// are ordered as requested by the query's `ORDER GROUPS BY` clause, by group
+    /// key when it has none. `SELECT *` queries are rejected, since they select
+    /// raw points; run them with [`QueryExecutor::execute`] instead.
+    ///
+    /// Queries with several time ranges are aggregated per range, each
+    /// range's groups following the previous range's and carrying its index.
+    pub async fn execute_grouped(&self, query: &Query) -> ExecutionResult<Vec<Group>> {
+        if query.extra_time_ranges.is_empty() {
+            let range = query
+                .time_range
+                .as_ref()
+                .map(|time_range| self.resolve_time_range(time_range))
+                .unwrap_or_default();
+            let points = self.collect(query).await?.points;
+            return Ok(aggregation::aggregate_with(&points, query, range, &self.custom_aggregates)?);
+        }
+
+        let mut groups = Vec::new();
+        for window in self.execute_ranges(query).await? {
+            let window_query = Query {
+                time_range: Some(TimeRange::Absolute { start: window.start, end: window.end }),
+                extra_time_ranges: Vec::new(),
+                ..query.clone()
+            };
+            let range = (window.start, window.end);
+            let window_groups = aggregation::aggregate_with(&window.points, &window_query, range, &self.custom_aggregates)?;
+            groups.extend(window_groups.into_iter().map(|group| Group {
+                range_index: Some(window.range_index),
+                ..group
+            }));
+        }
+        Ok(groups)
+    }
+
+    /// Executes two queries and combines their results with a binary operation
+    ///
+    /// The right-hand series is aligned onto the timestamps of the left-hand
+    /// series first; left timestamps with no aligned right value are dropped.
+    pub async fn execute_binary(
+        &self,
+        left: &Query,
+        right: &Query,
+        op: BinaryOp,
+        alignment: Alignment,
+    ) -> ExecutionResult<Vec<DataPoint>> {
+        let left_points = self.collect(left).await?.points;
+        let right_points = self.collect(right).await?.points;
+
+        Ok(align_series(&left_points, &right_points, alignment)
+            .into_iter()
+            .map(|(timestamp, l, r)| {
+                DataPoint::new(timestamp, op.apply(l, r), std::collections::HashMap::new())
+            })
+            .collect())
+    }
+
     /// Internal query execution with parallel processing
-    async fn execute_query_internal(&self, query: &Query) -> ExecutionResult<Vec<DataPoint>> {
-        let mut results = Vec::new();
+    ///
+    /// SSTable scans send their matches back one block at a time. Only points
+    /// that survive filtering and deduplication are charged against the memory
+    /// limit, and the charge is taken here rather than in the scan tasks so the
+    /// limit trips at the same point regardless of scheduling. The MemTable's
+    /// points and then each block's are passed to `on_batch` as one batch,
+    /// ordered by timestamp. When `release_delivered` is set, a batch's charge
+    /// is dropped once it has been passed to `on_batch`.
+    ///
+    /// Returns the paths of SSTables whose scans failed and were skipped.
+    async fn execute_query_internal<F>(
+        &self,
+        query: &Query,
+        release_delivered: bool,
+        stats: &Arc<ScanStats>,
+        mut on_batch: F,
+    ) -> ExecutionResult<Vec<String>>
+    where
+        F: FnMut(Vec<DataPoint>) -> ExecutionResult<()>,
+    {
         let mut seen_timestamps = HashSet::new();
-        let mut tasks = Vec::new();
 
         // First, check MemTable for more recent data
         let memtable = self.memtable.read().await;
         let time_range = query.time_range.as_ref().ok_or_else(|| {
             ExecutionError::ExecutionFailed("Time range is required".to_string())
         })?;
-        let (start, end) = time_range_start_end(time_range)
-            .ok_or_else(|| ExecutionError::ExecutionFailed("Only absolute time ranges are supported in executor".to_string()))?;
+        let (start, end) = self.resolve_time_range(time_range);
 
+        let scan_started = Instant::now();
         let memtable_points = memtable.get_series_range(&query.from, start, end).await;
+        ScanStats::record(&stats.scan_nanos, scan_started);
+        stats.points_scanned.fetch_add(memtable_points.len(), Ordering::Relaxed);
+
+        let tombstones = Arc::new(self.tombstones.snapshot().await);
 
         // Add MemTable points first
+        let filter_started = Instant::now();
+        let mut retained = Vec::with_capacity(memtable_points.len());
         for point in memtable_points {
-            if time_range_contains(time_range, point.timestamp()) {
-                seen_timestamps.insert(point.timestamp());
-                results.push(point);
+            seen_timestamps.insert(point.timestamp());
+            if value_filter_admits(query.filter.as_ref(), point.value())
+                && !tombstone::is_covered(&tombstones, point.timestamp(), point.value(), point.tags()) {
+                retained.push(point);
             }
         }
+        ScanStats::record(&stats.filter_nanos, filter_started);
+        stats.points_retained.fetch_add(retained.len(), Ordering::Relaxed);
+        let charged = self.reserve(retained.len()).await?;
+        on_batch(retained)?;
+        if release_delivered {
+            self.release(charged).await;
+        }
+
+        // Then process SSTables in parallel, bounding how many scans run at once
+        // and how many scanned blocks can wait to be delivered. Tables are
+        // ranked newest first; see the delivery loop below.
+        let mut sstables: Vec<Arc<SSTable>> = self.sstables.read().await.clone();
+        sstables.sort_by_key(|sstable| std::cmp::Reverse(sstable.sequence));
+        let mut delivered_timestamps = seen_timestamps.clone();
+
+        // A block the value filter would skip still shadows older copies of
+        // its points, so it may only be skipped where no older table holds
+        // the series. Collect, for each table, the spans older tables cover.
+        let mut older_spans: Vec<Arc<Vec<(i64, i64)>>> = Vec::with_capacity(sstables.len());
+        let mut spans = Vec::new();
+        for sstable in sstables.iter().rev() {
+            older_spans.push(Arc::new(spans.clone()));
+            let metadata = sstable.metadata.read().await;
+            spans.extend(
+                metadata
+                    .blocks
+                    .iter()
+                    .filter(|block| block.series_names.iter().any(|series_name| series_name == &query.from))
+                    .map(|block| (block.start_timestamp, block.max_timestamp)),
+            );
+        }
+        older_spans.reverse();
+        let max_concurrent_tasks = self.config.max_concurrent_tasks.max(1);
+        let (sender, mut receiver) = mpsc::channel(max_concurrent_tasks);
+        let semaphore = Arc::new(Semaphore::new(max_concurrent_tasks));
+        let cancelled = Arc::clone(&self.cancelled);
+        let from = query.from.clone();
+        let filter = query.filter.clone();
+        let on_scan_error = self.config.on_scan_error;
+        let stream_blocks = self.config.stream_blocks;
+        let scan_stats = Arc::clone(stats);
+        #[cfg(test)]
+        let scan_counts = Arc::clone(&self.scan_counts);
 
-        // Then process SSTables in parallel
-        let sstables = self.sstables.read().await;
-        let memory_limit = self.config.memory_limit;
-        for sstable in sstables.iter() {
-            let sstable: Arc<SSTable> = Arc::clone(sstable);
-            let time_range = time_range.clone();
-            let seen_timestamps = Arc::new(RwLock::new(seen_timestamps.clone()));
-            let memory_usage = Arc::clone(&self.memory_usage);
-            let cancelled = Arc::clone(&self.cancelled);
-            let from = query.from.clone();
-
-            let task = tokio::spawn(async move {
-                let mut sstable_results = Vec::new();
-                let (start, end) = time_range_start_end(&time_range)
-                    .ok_or_else(|| ExecutionError::ExecutionFailed("Only absolute time ranges are supported in executor".to_string()))?;
-                for block in sstable.scan_blocks().await {
-                    // Add artificial delay for cancellation test
+        // Scans are spawned from a separate task so that waiting for a permit
+        // never blocks the delivery loop below, which the running scans need
+        let dispatcher: JoinHandle<ExecutionResult<Vec<String>>> = tokio::spawn(async move {
+            let mut tasks = Vec::new();
+            for ((rank, sstable), older_spans) in sstables.into_iter().enumerate().zip(older_spans) {
+                let permit = Arc::clone(&semaphore)
+                    .acquire_owned()
+                    .await
+                    .map_err(|e| ExecutionError::ExecutionFailed(e.to_string()))?;
+                let seen_timestamps = Arc::new(RwLock::new(seen_timestamps.clone()));
+                let cancelled = Arc::clone(&cancelled);
+                let from = from.clone();
+                let filter = filter.clone();
+                let tombstones = Arc::clone(&tombstones);
+                let sender = sender.clone();
+                let stats = Arc::clone(&scan_stats);
+                #[cfg(test)]
+                let scan_counts = Arc::clone(&scan_counts);
+
+                let task = tokio::spawn(async move {
+                    let _permit = permit;
                     #[cfg(test)]
-                    if std::thread::current().name() == Some("tokio-runtime-worker") {
-                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-                    }
-                    // Check cancellation
-                    if *cancelled.lock().await {
-                        return Err(ExecutionError::Cancelled);
-                    }
+                    let _scan = ScanGuard::enter(scan_counts);
 
-                    // Check memory limit
-                    let mut usage = memory_usage.lock().await;
-                    if *usage > memory_limit {
-                        return Err(ExecutionError::MemoryLimitExceeded);
+                    // Skip blocks lying entirely outside the time range, and blocks
+                    // whose value range cannot satisfy the filter unless they
+                    // overlap an older table, whose points they may shadow.
+                    // Unless blocks are streamed, the whole table is read before
+                    // anything is sent, so a failed scan delivers none of its points.
+                    let scan_started = Instant::now();
+                    let scan_failed = |e| ExecutionError::ScanFailed(sstable.path.display().to_string(), e);
+                    let mut stream = sstable
+                        .block_stream_where(|block| {
+                            block.start_timestamp <= end
+                                && block.max_timestamp >= start
+                                && (filter.as_ref().is_none_or(|filter| {
+                                    filter.may_match_value_range(block.min_value, block.max_value)
+                                }) || older_spans.iter().any(|&(older_start, older_end)| {
+                                    older_start <= block.max_timestamp && older_end >= block.start_timestamp
+                                }))
+                        })
+                        .await;
+                    let blocks_read = stream.blocks_remaining();
+                    let mut prefetched = Vec::new();
+                    if !stream_blocks {
+                        while let Some(block) = stream.next().await {
+                            prefetched.push(block.map_err(scan_failed)?);
+                        }
                     }
-                    *usage += block.timestamp_deltas.len() * std::mem::size_of::<DataPoint>();
-
-                    if block.start_timestamp <= end {
-                        let mut current_timestamp = block.start_timestamp;
-                        let mut filtered_points = Vec::new();
-                        
-                        for ((&delta, &value), series_name) in block.timestamp_deltas.iter()
-                            .zip(block.values.iter())
-                            .zip(block.series_names.iter()) {
-                            current_timestamp += delta;
-                            if time_range_contains(&time_range, current_timestamp)
-                                && series_name == &from {
-                                let mut seen = seen_timestamps.write().await;
-                                if !seen.contains(&current_timestamp) {
-                                    seen.insert(current_timestamp);
-                                    filtered_points.push(DataPoint::new(current_timestamp, value, std::collections::HashMap::new()));
+                    let mut prefetched = prefetched.into_iter();
+                    let deletions = sstable.tombstones().await;
+                    ScanStats::record(&stats.scan_nanos, scan_started);
+                    stats.sstables_scanned.fetch_add(1, Ordering::Relaxed);
+                    stats.blocks_read.fetch_add(blocks_read, Ordering::Relaxed);
+                    loop {
+                        let block = if stream_blocks {
+                            let read_started = Instant::now();
+                            let block = stream.next().await.transpose().map_err(scan_failed)?;
+                            ScanStats::record(&stats.scan_nanos, read_started);
+                            block
+                        } else {
+                            prefetched.next()
+                        };
+                        let Some(block) = block else { break };
+                        // Add artificial delay for cancellation test
+                        #[cfg(test)]
+                        if std::thread::current().name() == Some("tokio-runtime-worker") {
+                            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        }
+                        // Check cancellation
+                        if *cancelled.lock().await {
+                            return Err(ExecutionError::Cancelled);
+                        }
+
+                        stats.points_scanned.fetch_add(block.values.len(), Ordering::Relaxed);
+                        if block.start_timestamp <= end {
+                            let filter_started = Instant::now();
+                            let mut filtered_points = Vec::new();
+                            let mut shadowed = Vec::new();
+                            // Only the points within the time range are visited
+                            let range = block.search_range(start, end);
+                            let timestamps = block.timestamps();
+                            for (((&current_timestamp, &value), series_name), tags) in timestamps[range.clone()].iter()
+                                .zip(&block.values[range.clone()])
+                                .zip(&block.series_names[range.clone()])
+                                .zip(&block.tags[range]) {
+                                if current_timestamp >= start && current_timestamp <= end && series_name == &from {
+                                    // The newest copy of a point decides, even when the
+                                    // filter rejects it
+                                    if !seen_timestamps.write().await.insert(current_timestamp) {
+                                        continue;
+                                    }
+                                    shadowed.push(current_timestamp);
+                                    if value_filter_admits(filter.as_ref(), value.as_f64())
+                                        && !deletions.iter().any(|t| t.covers(series_name, current_timestamp))
+                                        && !tombstone::is_covered(&tombstones, current_timestamp, value.as_f64(), tags) {
+                                        filtered_points.push(DataPoint::with_value(current_timestamp, value, tags.clone()));
+                                    }
                                 }
                             }
+                            ScanStats::record(&stats.filter_nanos, filter_started);
+                            stats.points_retained.fetch_add(filtered_points.len(), Ordering::Relaxed);
+                            // The receiver is gone once the query has stopped
+                            if sender.send((rank, Some((filtered_points, shadowed)))).await.is_err() {
+                                return Ok(());
+                            }
                         }
-                        sstable_results.extend(filtered_points);
                     }
+                    let _ = sender.send((rank, None)).await;
+                    Ok(())
+                });
+
+                tasks.push(task);
+            }
+            drop(sender);
+
+            // Wait for all tasks to complete
+            let mut skipped_tables = Vec::new();
+            for task in tasks {
+                match task.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(ExecutionError::ScanFailed(path, e)))
+                        if on_scan_error == ScanErrorPolicy::SkipAndWarn =>
+                    {
+                        warn!("Skipping SSTable {} after scan failure: {}", path, e);
+                        metrics::record_skipped_sstable_scan();
+                        skipped_tables.push(path);
+                    }
+                    Ok(Err(e)) => return Err(e),
+                    Err(e) => return Err(ExecutionError::ExecutionFailed(e.to_string())),
                 }
-                Ok(sstable_results)
-            });
+            }
+            Ok(skipped_tables)
+        });
 
-            tasks.push(task);
-        }
+        // Deliver blocks as the scans produce them, one table at a time from
+        // the newest so a timestamp held by several tables is taken from the
+        // newest. Each block also reports every timestamp it holds in range,
+        // so an older table's copy is dropped even when the newer copy was
+        // filtered out. Blocks of older tables are held until the newer ones
+        // finish; tables whose scan was skipped never finish, and are
+        // delivered last.
+        let mut next_rank = 0;
+        // Points of a block, the timestamps it shadows, and the memory charged for it
+        type ScannedBlock = (Vec<DataPoint>, Vec<i64>, usize);
+        let mut pending: BTreeMap<usize, Vec<ScannedBlock>> = BTreeMap::new();
+        let mut finished = HashSet::new();
+        let mut closed = false;
+        while !closed {
+            match receiver.recv().await {
+                Some((rank, Some((points, shadowed)))) => {
+                    let charged = match self.reserve(points.len()).await {
+                        Ok(charged) => charged,
+                        Err(e) => {
+                            dispatcher.abort();
+                            return Err(e);
+                        }
+                    };
+                    pending.entry(rank).or_default().push((points, shadowed, charged));
+                }
+                Some((rank, None)) => {
+                    finished.insert(rank);
+                }
+                None => closed = true,
+            }
 
-        // Wait for all tasks to complete
-        for task in tasks {
-            match task.await {
-                Ok(Ok(mut points)) => results.extend(points),
-                Ok(Err(e)) => return Err(e),
-                Err(e) => return Err(ExecutionError::ExecutionFailed(e.to_string())),
+            let mut ready = Vec::new();
+            loop {
+                ready.extend(pending.remove(&next_rank).unwrap_or_default());
+                if !finished.remove(&next_rank) {
+                    break;
+                }
+                next_rank += 1;
+            }
+            if closed {
+                ready.extend(std::mem::take(&mut pending).into_values().flatten());
+            }
+
+            for (points, shadowed, charged) in ready {
+                let delivered = on_batch(
+                    points
+                        .into_iter()
+                        .filter(|point| !delivered_timestamps.contains(&point.timestamp()))
+                        .collect(),
+                );
+                delivered_timestamps.extend(shadowed);
+                match delivered {
+                    Ok(()) if release_delivered => self.release(charged).await,
+                    Ok(()) => {}
+                    Err(e) => {
+                        // Running scans stop once the receiver is dropped
+                        dispatcher.abort();
+                        return Err(e);
+                    }
+                }
             }
         }
 
-        // Sort results by timestamp
-        results.sort_by_key(|point| point.timestamp());
-        Ok(results)
+        dispatcher
+            .await
+            .map_err(|e| ExecutionError::ExecutionFailed(e.to_string()))?
+    }
+
+    /// Charges `points` retained points against the memory limit, returning the bytes charged
+    async fn reserve(&self, points: usize) -> ExecutionResult<usize> {
+        let bytes = points * std::mem::size_of::<DataPoint>();
+        let mut usage = self.memory_usage.lock().await;
+        if *usage + bytes > self.config.memory_limit {
+            return Err(ExecutionError::MemoryLimitExceeded);
+        }
+        *usage += bytes;
+        Ok(bytes)
+    }
+
+    /// Returns bytes previously charged by `reserve`
+    async fn release(&self, bytes: usize) {
+        let mut usage = self.memory_usage.lock().await;
+        *usage = usage.saturating_sub(bytes);
     }
 
     /// Cancels the current query execution
@@ -207,25 +1108,102 @@ impl QueryExecutor {
     }
 }
 
-fn time_range_contains(time_range: &TimeRange, ts: i64) -> bool {
-    match time_range {
-        TimeRange::Absolute { start, end } => ts >= *start && ts <= *end,
-        TimeRange::Last { duration } => {
-            // For Last, assume [now-duration, now], but we don't have 'now' here, so always true
-            true
-        }
-        TimeRange::Relative { offset, duration } => {
-            // For Relative, assume [now-offset, now-offset+duration], but we don't have 'now' here, so always true
-            true
+/// Tracks a running SSTable scan for the concurrency tests
+#[cfg(test)]
+struct ScanGuard(Arc<(AtomicUsize, AtomicUsize)>);
+
+#[cfg(test)]
+impl ScanGuard {
+    fn enter(counts: Arc<(AtomicUsize, AtomicUsize)>) -> Self {
+        let active = counts.0.fetch_add(1, Ordering::SeqCst) + 1;
+        counts.1.fetch_max(active, Ordering::SeqCst);
+        Self(counts)
+    }
+}
+
+#[cfg(test)]
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Returns whether a query selects bare columns, rejecting unknown ones
+///
+/// Points hold a single value, so `value` is the only column there is.
+fn projects_columns(query: &Query) -> ExecutionResult<bool> {
+    let mut projects = false;
+    for expr in &query.select {
+        if let SelectItem::Column(name) = &expr.item {
+            if name != "value" {
+                return Err(ExecutionError::ExecutionFailed(format!("Unknown column: {}", name)));
+            }
+            projects = true;
         }
     }
+    Ok(projects)
 }
 
-fn time_range_start_end(time_range: &TimeRange) -> Option<(i64, i64)> {
-    match time_range {
-        TimeRange::Absolute { start, end } => Some((*start, *end)),
-        _ => None,
+/// Checks a value against the value filters of a query
+///
+/// Tag filters are not evaluated here, so a value is only rejected when the
+/// value filters alone rule it out.
+fn value_filter_admits(filter: Option<&FilterExpr>, value: f64) -> bool {
+    filter.and_then(|filter| filter.matches_value(value)) != Some(false)
+}
+
+/// Pairs each left-hand sample with a right-hand value aligned to its timestamp
+///
+/// Both inputs must be sorted by timestamp. Returns `(timestamp, left, right)`
+/// for every left sample the alignment can produce a right value for.
+pub fn align_series(
+    left: &[DataPoint],
+    right: &[DataPoint],
+    alignment: Alignment,
+) -> Vec<(i64, f64, f64)> {
+    let mut aligned = Vec::with_capacity(left.len());
+    if right.is_empty() {
+        return aligned;
+    }
+
+    for point in left {
+        let ts = point.timestamp();
+        // Index of the first right sample strictly after ts
+        let after = right.partition_point(|p| p.timestamp() <= ts);
+        let previous = after.checked_sub(1).map(|i| &right[i]);
+        let next = right.get(after);
+
+        let value = match alignment {
+            Alignment::Previous => previous.map(|p| p.value()),
+            Alignment::Nearest => match (previous, next) {
+                (Some(p), Some(n)) => {
+                    if ts - p.timestamp() <= n.timestamp() - ts {
+                        Some(p.value())
+                    } else {
+                        Some(n.value())
+                    }
+                }
+                (Some(p), None) => Some(p.value()),
+                (None, Some(n)) => Some(n.value()),
+                (None, None) => None,
+            },
+            Alignment::Interpolate => match (previous, next) {
+                (Some(p), _) if p.timestamp() == ts => Some(p.value()),
+                (Some(p), Some(n)) => {
+                    let fraction = (ts - p.timestamp()) as f64
+                        / (n.timestamp() - p.timestamp()) as f64;
+                    Some(p.value() + (n.value() - p.value()) * fraction)
+                }
+                _ => None,
+            },
+        };
+
+        if let Some(value) = value {
+            aligned.push((ts, point.value(), value));
+        }
     }
+
+    aligned
 }
 
 #[cfg(test)]
@@ -253,13 +1231,13 @@ mod tests {
         // Create SSTable with data
         let sstable_path = temp_dir.path().join("test.sst");
         let sstable = SSTable::new(&sstable_path).unwrap();
-        let block = DataBlock {
-            start_timestamp: 500,
-            timestamp_deltas: vec![0, 100],
-            values: vec![41.0, 42.0],
-            series_names: vec!["test_series".to_string(), "test_series".to_string()],
-            tags: vec![HashMap::new(), HashMap::new()],
-        };
+        let block = DataBlock::new(
+            500,
+            vec![0, 100],
+            vec![41.0.into(), 42.0.into()],
+            vec!["test_series".to_string(), "test_series".to_string()],
+            vec![HashMap::new(), HashMap::new()],
+        );
         sstable.write_block(block).await.unwrap();
         sstables.write().await.push(Arc::new(sstable));
 
@@ -268,6 +1246,7 @@ mod tests {
             max_concurrent_tasks: 2,
             memory_limit: 1024 * 1024, // 1MB
             timeout: Duration::from_secs(5),
+            ..Default::default()
         };
         let executor = QueryExecutor::new(memtable, sstables, config);
 
@@ -275,7 +1254,7 @@ mod tests {
         let mut query = Query::new();
         query.from = "test_series".to_string();
         query.time_range = Some(TimeRange::Absolute { start: 400, end: 1100 });
-        let results = executor.execute_query(&query).await.unwrap();
+        let results = executor.execute(&query).await.unwrap().points;
 
         // Verify results
         assert_eq!(results.len(), 3);
@@ -302,18 +1281,18 @@ mod tests {
         for i in 0..20_000 {
             let delta = if i == 0 { 0 } else { 1 };
             timestamp_deltas.push(delta);
-            values.push(i as f64);
+            values.push((i as f64).into());
             series_names.push("test_series".to_string());
             tags.push(std::collections::HashMap::new());
             last_ts += delta;
         }
-        let block = DataBlock {
-            start_timestamp: 0,
+        let block = DataBlock::new(
+            0,
             timestamp_deltas,
             values,
             series_names,
             tags,
-        };
+        );
         sstable.write_block(block).await.unwrap();
         sstables.write().await.push(Arc::new(sstable));
 
@@ -322,6 +1301,7 @@ mod tests {
             max_concurrent_tasks: 2,
             memory_limit: 1024 * 1024,
             timeout: Duration::from_secs(5),
+            ..Default::default()
         };
         let executor = QueryExecutor::new(memtable, sstables, config);
 
@@ -342,4 +1322,814 @@ mod tests {
         let result = handle.await.unwrap();
         assert!(matches!(result, Err(ExecutionError::Cancelled)));
     }
+
+    #[test]
+    fn test_into_series_matrix() {
+        let tags = |host: &str| {
+            let mut tags = HashMap::new();
+            tags.insert("series".to_string(), "cpu".to_string());
+            tags.insert("host".to_string(), host.to_string());
+            tags
+        };
+
+        // Two series interleaved, not in time order
+        let result = QueryResult::from(vec![
+            DataPoint::new(300, 3.0, tags("a")),
+            DataPoint::new(100, 10.0, tags("b")),
+            DataPoint::new(100, 1.0, tags("a")),
+            DataPoint::new(200, 20.0, tags("b")),
+            DataPoint::new(200, 2.0, tags("a")),
+        ]);
+
+        let matrix = result.into_series_matrix();
+        assert_eq!(matrix.len(), 2);
+        assert_eq!(matrix["cpu,host=a"], vec![(100, 1.0), (200, 2.0), (300, 3.0)]);
+        assert_eq!(matrix["cpu,host=b"], vec![(100, 10.0), (200, 20.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_binary_op_with_previous_alignment() {
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        // Denominator samples land slightly before the numerator samples
+        {
+            let memtable_guard = memtable.write().await;
+            let a = TimeSeries::new("a".to_string()).unwrap();
+            let b = TimeSeries::new("b".to_string()).unwrap();
+            for (ts, value) in [(1000, 10.0), (2000, 20.0), (3000, 30.0)] {
+                memtable_guard.insert(&a, &DataPoint::new(ts, value, HashMap::new())).await.unwrap();
+            }
+            for (ts, value) in [(990, 2.0), (1990, 4.0), (2990, 5.0)] {
+                memtable_guard.insert(&b, &DataPoint::new(ts, value, HashMap::new())).await.unwrap();
+            }
+        }
+
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+        let query = |from: &str| {
+            let mut query = Query::new();
+            query.from = from.to_string();
+            query.time_range = Some(TimeRange::Absolute { start: 0, end: 4000 });
+            query
+        };
+
+        let results = executor
+            .execute_binary(&query("a"), &query("b"), BinaryOp::Div, Alignment::Previous)
+            .await
+            .unwrap();
+        let combined: Vec<(i64, f64)> = results.iter().map(|p| (p.timestamp(), p.value())).collect();
+        assert_eq!(combined, vec![(1000, 5.0), (2000, 5.0), (3000, 6.0)]);
+
+        // Without alignment none of the timestamps coincide; interpolation needs
+        // samples on both sides
+        let left = executor.execute(&query("a")).await.unwrap().points;
+        let right = executor.execute(&query("b")).await.unwrap().points;
+        let interpolated = align_series(&left, &right, Alignment::Interpolate);
+        assert_eq!(interpolated.len(), 2);
+        assert!((interpolated[0].2 - 2.02).abs() < 1e-9);
+        assert_eq!(align_series(&left, &right, Alignment::Nearest)[2], (3000, 30.0, 5.0));
+    }
+
+    #[tokio::test]
+    async fn test_last_range_clock_skew_tolerance() {
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+        let now = 100_000_000_000;
+
+        // A point stamped half a second ahead of the server clock
+        {
+            let series = TimeSeries::new("test_series".to_string()).unwrap();
+            let guard = memtable.write().await;
+            guard.insert(&series, &DataPoint::new(now - 1_000_000_000, 1.0, HashMap::new())).await.unwrap();
+            guard.insert(&series, &DataPoint::new(now + 500_000_000, 2.0, HashMap::new())).await.unwrap();
+        }
+
+        let mut query = Query::new();
+        query.from = "test_series".to_string();
+        query.time_range = Some(TimeRange::Last { duration: 5_000_000_000 });
+
+        let executor_with_tolerance = |tolerance: Duration| {
+            let config = ExecutionConfig {
+                clock_skew_tolerance: tolerance,
+                ..Default::default()
+            };
+            QueryExecutor::new(memtable.clone(), sstables.clone(), config).with_clock(Arc::new(move || now))
+        };
+
+        let within = executor_with_tolerance(Duration::from_secs(1));
+        assert_eq!(within.execute_query(&query).await.unwrap().len(), 2);
+
+        let beyond = executor_with_tolerance(Duration::from_millis(100));
+        let results = beyond.execute(&query).await.unwrap().points;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].value(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_stays_within_memory_limit() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        // Ten blocks of 100 points each
+        let sstable = SSTable::new(temp_dir.path().join("test.sst")).unwrap();
+        for b in 0..10 {
+            let points: Vec<DataPoint> = (0..100)
+                .map(|i| DataPoint::new(b * 100 + i, 1.0, std::collections::HashMap::new()))
+                .collect();
+            sstable.write_block(DataBlock::from_points("test_series", &points)).await.unwrap();
+        }
+        sstables.write().await.push(Arc::new(sstable));
+
+        // Room for three blocks in flight, far less than the whole range
+        let config = ExecutionConfig {
+            max_concurrent_tasks: 1,
+            memory_limit: 3 * 100 * std::mem::size_of::<DataPoint>(),
+            ..Default::default()
+        };
+        let executor = QueryExecutor::new(memtable, sstables, config);
+
+        let mut query = Query::new();
+        query.from = "test_series".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 1_000 });
+
+        let (mut count, mut sum) = (0, 0.0);
+        executor
+            .execute_query_with(&query, |point| {
+                count += 1;
+                sum += point.value();
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 1_000);
+        assert_eq!(sum / count as f64, 1.0);
+
+        // Collecting the same range keeps every block charged
+        assert!(matches!(
+            executor.execute_query(&query).await,
+            Err(ExecutionError::MemoryLimitExceeded)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_value_filter_prunes_blocks() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        // One block entirely below the threshold, one block with matches
+        let sstable = Arc::new(SSTable::new(temp_dir.path().join("test.sst")).unwrap());
+        let block = |start: i64, values: &[f64]| {
+            let points: Vec<DataPoint> = values
+                .iter()
+                .enumerate()
+                .map(|(i, &v)| DataPoint::new(start + i as i64, v, std::collections::HashMap::new()))
+                .collect();
+            DataBlock::from_points("test_series", &points)
+        };
+        sstable.write_block(block(0, &[10.0, 500.0, 999.0])).await.unwrap();
+        sstable.write_block(block(100, &[20.0, 1500.0, 2000.0])).await.unwrap();
+        sstables.write().await.push(Arc::clone(&sstable));
+
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+        let tokens = crate::query::parser::Lexer::new("SELECT avg(value) FROM test_series WHERE value > 1000")
+            .tokenize()
+            .unwrap();
+        let mut query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 1_000 });
+
+        let values: Vec<f64> = executor
+            .execute(&query)
+            .await
+            .unwrap()
+            .points
+            .iter()
+            .map(|p| p.value())
+            .collect();
+        assert_eq!(values, vec![1500.0, 2000.0]);
+        assert_eq!(sstable.blocks_read.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_filtered_out_points_still_shadow_older_copies() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let write_table = |name: &str, value: f64| {
+            let sstable = SSTable::new(temp_dir.path().join(name)).unwrap();
+            async move {
+                let point = DataPoint::new(1000, value, HashMap::new());
+                sstable.write_block(DataBlock::from_points("test_series", &[point])).await.unwrap();
+                Arc::new(sstable)
+            }
+        };
+        let older = write_table("older.sst", 1500.0).await;
+        let newer = write_table("newer.sst", 20.0).await;
+        let sstables = Arc::new(RwLock::new(vec![older, newer]));
+
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+        let tokens = crate::query::parser::Lexer::new("SELECT value FROM test_series WHERE value > 1000")
+            .tokenize()
+            .unwrap();
+        let mut query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 2_000 });
+
+        // The stale copy must not stand in for the newer one the filter rejects
+        assert!(executor.execute_query(&query).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_time_range_prunes_blocks() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        // Twenty blocks of ten points; block b spans b * 1000 ..= b * 1000 + 900
+        let sstable = Arc::new(SSTable::new(temp_dir.path().join("test.sst")).unwrap());
+        for b in 0..20i64 {
+            let points: Vec<DataPoint> = (0..10)
+                .map(|i| DataPoint::new(b * 1_000 + i * 100, (b * 10 + i) as f64, HashMap::new()))
+                .collect();
+            sstable.write_block(DataBlock::from_points("test_series", &points)).await.unwrap();
+        }
+        sstables.write().await.push(Arc::clone(&sstable));
+
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+        let mut query = Query::new();
+        query.from = "test_series".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 7_150, end: 7_450 });
+
+        let values: Vec<f64> = executor
+            .execute(&query)
+            .await
+            .unwrap()
+            .points
+            .iter()
+            .map(|p| p.value())
+            .collect();
+        assert_eq!(values, vec![72.0, 73.0, 74.0]);
+        // Blocks starting before the range but ending before it are not decoded
+        assert_eq!(sstable.blocks_read.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit_counts_retained_points() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+        let point_size = std::mem::size_of::<DataPoint>();
+
+        // Two points in the MemTable, and a large block of which only two points match
+        {
+            let series = TimeSeries::new("test_series".to_string()).unwrap();
+            let guard = memtable.write().await;
+            guard.insert(&series, &DataPoint::new(10_000, 1.0, HashMap::new())).await.unwrap();
+            guard.insert(&series, &DataPoint::new(10_001, 2.0, HashMap::new())).await.unwrap();
+        }
+        let sstable = SSTable::new(temp_dir.path().join("test.sst")).unwrap();
+        let points: Vec<DataPoint> = (0..1_000).map(|i| DataPoint::new(i, 0.0, HashMap::new())).collect();
+        sstable.write_block(DataBlock::from_points("test_series", &points)).await.unwrap();
+        sstables.write().await.push(Arc::new(sstable));
+
+        let mut query = Query::new();
+        query.from = "test_series".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 998, end: 20_000 });
+
+        // Four retained points fit exactly; the 998 discarded points are not charged
+        let config = ExecutionConfig {
+            memory_limit: 4 * point_size,
+            ..Default::default()
+        };
+        let executor = QueryExecutor::new(memtable.clone(), sstables.clone(), config);
+        for _ in 0..5 {
+            assert_eq!(executor.execute_query(&query).await.unwrap().len(), 4);
+            assert_eq!(executor.memory_usage().await, 4 * point_size);
+        }
+
+        // One point less and the limit trips every time, MemTable points included
+        let config = ExecutionConfig {
+            memory_limit: 3 * point_size,
+            ..Default::default()
+        };
+        let executor = QueryExecutor::new(memtable, sstables, config);
+        for _ in 0..5 {
+            assert!(matches!(
+                executor.execute_query(&query).await,
+                Err(ExecutionError::MemoryLimitExceeded)
+            ));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_concurrent_tasks_bounds_scans() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        for i in 0..8 {
+            let sstable = SSTable::new(temp_dir.path().join(format!("{}.sst", i))).unwrap();
+            let point = DataPoint::new(i, i as f64, HashMap::new());
+            sstable.write_block(DataBlock::from_points("test_series", &[point])).await.unwrap();
+            sstables.write().await.push(Arc::new(sstable));
+        }
+
+        let config = ExecutionConfig {
+            max_concurrent_tasks: 2,
+            ..Default::default()
+        };
+        let executor = QueryExecutor::new(memtable, sstables, config);
+
+        let mut query = Query::new();
+        query.from = "test_series".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 100 });
+
+        assert_eq!(executor.execute_query(&query).await.unwrap().len(), 8);
+        let peak = executor.scan_counts.1.load(Ordering::SeqCst);
+        assert!((1..=2).contains(&peak), "peak concurrent scans was {}", peak);
+        assert_eq!(executor.scan_counts.0.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_skip_corrupt_sstable_on_scan_error() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        for (i, name) in ["healthy.sst", "corrupt.sst"].iter().enumerate() {
+            let sstable = SSTable::new(temp_dir.path().join(name)).unwrap();
+            let points: Vec<DataPoint> = (0..3)
+                .map(|j| DataPoint::new(i as i64 * 10 + j, j as f64, HashMap::new()))
+                .collect();
+            sstable.write_block(DataBlock::from_points("test_series", &points)).await.unwrap();
+            sstables.write().await.push(Arc::new(sstable));
+        }
+        // Cut the second table off partway through its only block
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(temp_dir.path().join("corrupt.sst"))
+            .unwrap()
+            .set_len(20)
+            .unwrap();
+
+        let mut query = Query::new();
+        query.from = "test_series".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 100 });
+
+        let executor = QueryExecutor::new(memtable.clone(), sstables.clone(), ExecutionConfig::default());
+        assert!(matches!(
+            executor.execute(&query).await,
+            Err(ExecutionError::ScanFailed(path, _)) if path.ends_with("corrupt.sst")
+        ));
+
+        let config = ExecutionConfig {
+            on_scan_error: ScanErrorPolicy::SkipAndWarn,
+            ..Default::default()
+        };
+        let executor = QueryExecutor::new(memtable, sstables, config);
+        let result = executor.execute(&query).await.unwrap();
+        assert!(result.is_incomplete());
+        assert_eq!(result.skipped_tables.len(), 1);
+        assert!(result.skipped_tables[0].ends_with("corrupt.sst"));
+        let timestamps: Vec<i64> = result.points.iter().map(|p| p.timestamp()).collect();
+        assert_eq!(timestamps, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_explain_analyze_reports_actual_stats() {
+        use crate::query::parser::{Lexer, Parser};
+
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        // Three blocks with values 0..10, 10..20 and 20..30, alternating hosts
+        let sstable = SSTable::new(temp_dir.path().join("test.sst")).unwrap();
+        for b in 0..3 {
+            let points: Vec<DataPoint> = (0..10)
+                .map(|i| {
+                    let mut tags = HashMap::new();
+                    tags.insert("host".to_string(), if i % 2 == 0 { "a" } else { "b" }.to_string());
+                    DataPoint::new(b * 10 + i, (b * 10 + i) as f64, tags)
+                })
+                .collect();
+            sstable.write_block(DataBlock::from_points("cpu", &points)).await.unwrap();
+        }
+        sstables.write().await.push(Arc::new(sstable));
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+
+        let tokens = Lexer::new("SELECT avg(value) FROM cpu WHERE value >= 15 GROUP BY host").tokenize().unwrap();
+        let mut query = Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 100 });
+
+        let plan = executor.explain_analyze(&query).await.unwrap();
+        // The first block cannot hold a value >= 15 and is never read
+        assert_eq!(plan.sstables_scanned, 1);
+        assert_eq!(plan.blocks_read, 2);
+        assert_eq!(plan.stage(QueryStage::Scan).unwrap().rows, 20);
+        assert_eq!(plan.stage(QueryStage::Filter).unwrap().rows, 15);
+        assert_eq!(plan.stage(QueryStage::Sort).unwrap().rows, 15);
+        assert_eq!(plan.stage(QueryStage::Aggregate).unwrap().rows, 2);
+        assert!(plan.to_string().contains("blocks=2"));
+    }
+
+    #[tokio::test]
+    async fn test_instant_query_aligns_series_to_one_now() {
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+        let cache = LastValueCache::new();
+        let region = |region: &str| {
+            let mut tags = HashMap::new();
+            tags.insert("region".to_string(), region.to_string());
+            tags
+        };
+        for (series, timestamp, value, tags) in [
+            ("cpu", 1_000, 1.0, region("us-west")),
+            ("cpu", 3_000, 3.0, region("us-west")),
+            ("mem", 2_000, 2.0, region("us-west")),
+            ("disk", 2_500, 5.0, region("eu")),
+            // Stamped after the instant the query resolves
+            ("net", 9_000, 9.0, region("us-west")),
+        ] {
+            cache.update(series, &DataPoint::new(timestamp, value, tags)).await;
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let clock_calls = calls.clone();
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default())
+            .with_clock(Arc::new(move || 5_000 + clock_calls.fetch_add(1, Ordering::SeqCst) as i64))
+            .with_last_value_cache(cache);
+
+        let filter = FilterExpr::TagFilter(crate::query::parser::ast::TagFilter {
+            key: "region".to_string(),
+            op: crate::query::parser::ast::TagFilterOp::Eq,
+            value: "us-west".to_string(),
+        });
+        let result = executor.execute_instant(Some(&filter)).await.unwrap();
+        // The clock is read once for the whole query
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(result.now, 5_000);
+        let latest: Vec<(&str, i64, f64)> = result
+            .series
+            .iter()
+            .map(|(name, point)| (name.as_str(), point.timestamp(), point.value()))
+            .collect();
+        assert_eq!(latest, vec![("cpu", 3_000, 3.0), ("mem", 2_000, 2.0), ("net", 5_000, 9.0)]);
+
+        let all = executor.execute_instant(None).await.unwrap();
+        assert_eq!(all.series.len(), 4);
+        assert!(all.now > result.now);
+    }
+
+    #[test]
+    fn test_query_execution_metrics() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let temp_dir = tempdir().unwrap();
+                let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+                {
+                    let series = TimeSeries::new("test_series".to_string()).unwrap();
+                    let point = DataPoint::new(1000, 42.0, HashMap::new());
+                    memtable.write().await.insert(&series, &point).await.unwrap();
+                }
+                let sstable = SSTable::new(temp_dir.path().join("test.sst")).unwrap();
+                let points = vec![
+                    DataPoint::new(500, 41.0, HashMap::new()),
+                    DataPoint::new(600, 42.0, HashMap::new()),
+                ];
+                sstable.write_block(DataBlock::from_points("test_series", &points)).await.unwrap();
+                let sstables = Arc::new(RwLock::new(vec![Arc::new(sstable)]));
+
+                let mut query = Query::new();
+                query.from = "test_series".to_string();
+                query.time_range = Some(TimeRange::Absolute { start: 400, end: 1100 });
+
+                let executor = QueryExecutor::new(memtable.clone(), sstables.clone(), ExecutionConfig::default());
+                assert_eq!(executor.execute_query(&query).await.unwrap().len(), 3);
+
+                // A query stopped by the memory limit is counted as such
+                let config = ExecutionConfig {
+                    memory_limit: 1,
+                    ..Default::default()
+                };
+                let executor = QueryExecutor::new(memtable, sstables, config);
+                assert!(matches!(
+                    executor.execute_query(&query).await,
+                    Err(ExecutionError::MemoryLimitExceeded)
+                ));
+            })
+        });
+
+        let counters: HashMap<String, u64> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| match value {
+                DebugValue::Counter(count) => Some((key.key().name().to_string(), count)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(counters["vctsdb.query.executions"], 2);
+        assert_eq!(counters["vctsdb.query.sstables_scanned"], 1);
+        assert_eq!(counters["vctsdb.query.blocks_decoded"], 1);
+        assert_eq!(counters["vctsdb.query.points_returned"], 3);
+        assert_eq!(counters["vctsdb.query.memory_limit_exceeded"], 1);
+        assert!(!counters.contains_key("vctsdb.query.cancelled"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_formatted_csv() {
+        use crate::query::parser::{Lexer, Parser};
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let series = TimeSeries::new("cpu".to_string()).unwrap();
+            let mut tags = HashMap::new();
+            tags.insert("host".to_string(), "server1".to_string());
+            let guard = memtable.write().await;
+            guard.insert(&series, &DataPoint::new(1000, 42.5, tags.clone())).await.unwrap();
+            guard.insert(&series, &DataPoint::new(2000, 43.0, tags)).await.unwrap();
+        }
+        let executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+
+        let tokens = Lexer::new("SELECT * FROM cpu FORMAT CSV").tokenize().unwrap();
+        let mut query = Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 5000 });
+
+        let bytes = executor.execute_formatted(&query).await.unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "timestamp,value,host\n1000,42.5,server1\n2000,43,server1\n"
+        );
+
+        // Aggregates are evaluated before encoding
+        let tokens = Lexer::new("SELECT max(value) AS peak FROM cpu GROUP BY host FORMAT CSV").tokenize().unwrap();
+        let mut query = Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 5000 });
+        let bytes = executor.execute_formatted(&query).await.unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "host,peak\nserver1,43\n");
+    }
+
+    #[tokio::test]
+    async fn test_select_wildcard_returns_raw_points() {
+        use crate::query::parser::{Lexer, Parser};
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let series = TimeSeries::new("cpu".to_string()).unwrap();
+            let guard = memtable.write().await;
+            for (ts, host, region) in [(1000, "a", "us"), (2000, "b", "eu"), (3000, "a", "eu")] {
+                let mut tags = HashMap::new();
+                tags.insert("host".to_string(), host.to_string());
+                tags.insert("region".to_string(), region.to_string());
+                guard.insert(&series, &DataPoint::new(ts, ts as f64, tags)).await.unwrap();
+            }
+        }
+        let executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+
+        let tokens = Lexer::new("SELECT * FROM cpu WHERE value > 1500").tokenize().unwrap();
+        let mut query = Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 5000 });
+
+        let result = executor.execute(&query).await.unwrap();
+        let rows: Vec<(i64, &str, &str)> = result
+            .points
+            .iter()
+            .map(|p| (p.timestamp(), p.tags()["host"].as_str(), p.tags()["region"].as_str()))
+            .collect();
+        assert_eq!(rows, vec![(2000, "b", "eu"), (3000, "a", "eu")]);
+
+        assert!(matches!(
+            executor.execute_grouped(&query).await,
+            Err(ExecutionError::Aggregation(AggregationError::RawSelect(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_select_bare_column_projects_values() {
+        use crate::query::parser::{Lexer, Parser};
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let series = TimeSeries::new("cpu".to_string()).unwrap();
+            let mut tags = HashMap::new();
+            tags.insert("host".to_string(), "a".to_string());
+            let guard = memtable.write().await;
+            for ts in [1000, 2000, 3000] {
+                guard.insert(&series, &DataPoint::new(ts, ts as f64 / 100.0, tags.clone())).await.unwrap();
+            }
+        }
+        let executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+        let parse = |input: &str| {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            let mut query = Parser::new(&tokens).parse().unwrap();
+            query.time_range = Some(TimeRange::Absolute { start: 0, end: 5000 });
+            query
+        };
+
+        let result = executor.execute(&parse("SELECT value AS v FROM cpu WHERE value >= 20")).await.unwrap();
+        let values: Vec<(i64, f64)> = result.points.iter().map(|p| (p.timestamp(), p.value())).collect();
+        assert_eq!(values, vec![(2000, 20.0), (3000, 30.0)]);
+        assert!(result.points.iter().all(|p| p.tags().is_empty()));
+
+        assert!(matches!(
+            executor.execute(&parse("SELECT bogus FROM cpu")).await,
+            Err(ExecutionError::ExecutionFailed(msg)) if msg.contains("bogus")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_execute_query_names_columns() {
+        use crate::query::parser::{Lexer, Parser};
+        use crate::query::result::Cell;
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let series = TimeSeries::new("cpu".to_string()).unwrap();
+            let guard = memtable.write().await;
+            for (ts, host, value) in [(1000, "a", 10.0), (2000, "b", 30.0), (3000, "a", 20.0)] {
+                let mut tags = HashMap::new();
+                tags.insert("host".to_string(), host.to_string());
+                guard.insert(&series, &DataPoint::new(ts, value, tags)).await.unwrap();
+            }
+        }
+        let executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+        let parse = |sql: &str| {
+            let tokens = Lexer::new(sql).tokenize().unwrap();
+            let mut query = Parser::new(&tokens).parse().unwrap();
+            query.time_range = Some(TimeRange::Absolute { start: 0, end: 5000 });
+            query
+        };
+
+        let query = parse("SELECT avg(value) AS mean, max(value) FROM cpu");
+        let result = executor.execute_query(&query).await.unwrap();
+        assert_eq!(result.columns, vec!["mean", "max(value)"]);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result.cell(0, "mean").and_then(Cell::as_f64), Some(20.0));
+        assert_eq!(result.cell(0, "max(value)").and_then(Cell::as_f64), Some(30.0));
+
+        let query = parse("SELECT value AS v FROM cpu");
+        let result = executor.execute_query(&query).await.unwrap();
+        assert_eq!(result.columns, vec!["timestamp", "v"]);
+        assert_eq!(result.cell(2, "timestamp"), Some(&Cell::Timestamp(3000)));
+        assert_eq!(result.cell(2, "v").and_then(Cell::as_f64), Some(20.0));
+
+        let query = parse("SELECT * FROM cpu");
+        let result = executor.execute_query(&query).await.unwrap();
+        assert_eq!(result.columns, vec!["timestamp", "value", "host"]);
+        assert_eq!(result.cell(1, "host"), Some(&Cell::Tag("b".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_stream_blocks_matches_buffered_scan() {
+        let temp_dir = tempdir().unwrap();
+        let sstable = SSTable::new(temp_dir.path().join("test.sst")).unwrap();
+        for i in 0..10 {
+            let points: Vec<DataPoint> = (0..5)
+                .map(|j| DataPoint::new(i * 100 + j * 10, (i * 5 + j) as f64, HashMap::new()))
+                .collect();
+            sstable.write_block(DataBlock::from_points("cpu", &points)).await.unwrap();
+        }
+        let sstables = Arc::new(RwLock::new(vec![Arc::new(sstable)]));
+
+        let mut query = Query::new();
+        query.from = "cpu".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 150, end: 720 });
+
+        let mut results = Vec::new();
+        for stream_blocks in [false, true] {
+            let config = ExecutionConfig {
+                stream_blocks,
+                ..Default::default()
+            };
+            let executor = QueryExecutor::new(Arc::new(RwLock::new(MemTable::new(100))), Arc::clone(&sstables), config);
+            let points = executor.execute(&query).await.unwrap().points;
+            results.push(points.iter().map(|p| (p.timestamp(), p.value())).collect::<Vec<_>>());
+        }
+        assert_eq!(results[0].len(), 28);
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[tokio::test]
+    async fn test_newest_sstable_wins_regardless_of_scan_order() {
+        let temp_dir = tempdir().unwrap();
+        let mut tables = Vec::new();
+        for (name, value) in [("older.sst", 1.0), ("newer.sst", 2.0)] {
+            let sstable = SSTable::new(temp_dir.path().join(name)).unwrap();
+            let points: Vec<DataPoint> = (0..3)
+                .map(|i| DataPoint::new(1000 + i, value, std::collections::HashMap::new()))
+                .collect();
+            sstable.write_block(DataBlock::from_points("cpu", &points)).await.unwrap();
+            tables.push(Arc::new(sstable));
+        }
+
+        let tokens = crate::query::parser::Lexer::new("SELECT * FROM cpu").tokenize().unwrap();
+        let mut query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 2_000 });
+        for _ in 0..2 {
+            let executor = QueryExecutor::new(
+                Arc::new(RwLock::new(MemTable::new(1000))),
+                Arc::new(RwLock::new(tables.clone())),
+                ExecutionConfig::default(),
+            );
+            let values: Vec<f64> = executor.execute(&query).await.unwrap().points.iter().map(|p| p.value()).collect();
+            assert_eq!(values, vec![2.0, 2.0, 2.0]);
+            tables.reverse();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_full_scan_plan() {
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let series = crate::storage::data::TimeSeries::new("cpu".to_string()).unwrap();
+        let point = DataPoint::new(1000, 1.5, std::collections::HashMap::new());
+        memtable.read().await.insert(&series, &point).await.unwrap();
+        let executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+
+        let tokens = crate::query::parser::Lexer::new("SELECT * FROM cpu").tokenize().unwrap();
+        let mut query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 2_000 });
+        let plan = crate::query::QueryPlanner::new().plan_query(&query).unwrap();
+        assert!(plan.requires_full_scan());
+
+        let Ok(PlannedResult::Executed(result)) = executor.execute_plan(&query, &plan).await else {
+            panic!("the query should have been executed");
+        };
+        assert_eq!(result.points.len(), 1);
+
+        // EXPLAIN returns the plan without reading anything
+        let tokens = crate::query::parser::Lexer::new("EXPLAIN SELECT * FROM cpu").tokenize().unwrap();
+        let mut explain = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        explain.time_range = query.time_range.clone();
+        let Ok(PlannedResult::Explained(explanation)) = executor.execute_plan(&explain, &plan).await else {
+            panic!("the query should have been explained");
+        };
+        assert_eq!(explanation, plan.explain());
+        assert!(explanation.full_scan);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_time_ranges() {
+        use crate::query::result::Cell;
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let series = crate::storage::data::TimeSeries::new("cpu".to_string()).unwrap();
+        for timestamp in [100, 140, 150, 200, 300] {
+            let point = DataPoint::new(timestamp, timestamp as f64, std::collections::HashMap::new());
+            memtable.read().await.insert(&series, &point).await.unwrap();
+        }
+        let executor = QueryExecutor::new(memtable, Arc::new(RwLock::new(Vec::new())), ExecutionConfig::default());
+
+        let tokens = crate::query::parser::Lexer::new("SELECT * FROM cpu").tokenize().unwrap();
+        let mut query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        query.time_range = Some(TimeRange::Absolute { start: 100, end: 150 });
+        query.extra_time_ranges = vec![
+            TimeRange::Absolute { start: 290, end: 310 },
+            TimeRange::Absolute { start: 140, end: 200 },
+        ];
+
+        let windows: Vec<(usize, Vec<i64>)> = executor
+            .execute_ranges(&query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|window| (window.range_index, window.points.iter().map(|p| p.timestamp()).collect()))
+            .collect();
+        // 140 and 150 belong to the first window only
+        assert_eq!(windows, vec![(0, vec![100, 140, 150]), (1, vec![300]), (2, vec![200])]);
+
+        let result = executor.execute(&query).await.unwrap();
+        let timestamps: Vec<i64> = result.points.iter().map(|p| p.timestamp()).collect();
+        assert_eq!(timestamps, vec![100, 140, 150, 200, 300]);
+        assert_eq!(result.range_indices, vec![0, 0, 0, 2, 1]);
+
+        let result = executor.execute_query(&query).await.unwrap();
+        assert_eq!(result.columns[0], crate::query::result::RANGE_COLUMN);
+        assert_eq!(result.cell(3, "range").and_then(Cell::as_f64), Some(2.0));
+
+        // Aggregates are computed per range
+        let tokens = crate::query::parser::Lexer::new("SELECT count(value) FROM cpu").tokenize().unwrap();
+        let counted = Query {
+            time_range: query.time_range.clone(),
+            extra_time_ranges: query.extra_time_ranges.clone(),
+            ..crate::query::parser::Parser::new(&tokens).parse().unwrap()
+        };
+        let counts: Vec<(Option<usize>, Option<f64>)> = executor
+            .execute_grouped(&counted)
+            .await
+            .unwrap()
+            .iter()
+            .map(|group| (group.range_index, group.value("count(value)")))
+            .collect();
+        assert_eq!(counts, vec![(Some(0), Some(3.0)), (Some(1), Some(1.0)), (Some(2), Some(1.0))]);
+
+        // Paths that scan a single range reject the query
+        assert!(executor.execute_query_with(&query, |_| Ok(())).await.is_err());
+        assert!(executor.explain_analyze(&query).await.is_err());
+    }
 }
@@ -1,12 +1,21 @@
 //! Query module for VCTSDB
 //! Handles query parsing, planning, and execution.
 
+pub mod aggregation;
+pub mod continuous;
 pub mod executor;
+pub mod output;
 pub mod parser;
 pub mod planner;
+pub mod result;
 
-pub use parser::ast::{Query, TimeRange, FilterExpr, TagFilter, TagFilterOp, FunctionCall, SelectExpr};
-pub use executor::{QueryExecutor, ExecutionConfig, ExecutionError, ExecutionResult};
+pub use parser::ast::{Query, TimeRange, FilterExpr, GroupOrder, HavingExpr, TagFilter, TagFilterOp, ValueFilter, ValueFilterOp, FunctionCall, SelectExpr, SelectItem};
+pub use aggregation::{AggregateEvaluator, AggregationError, CustomAggregates, Group};
+pub use continuous::{ContinuousQuery, ContinuousQueryError, ContinuousQueryManager, RollupFunction};
+pub use output::{OutputError, OutputFormat};
+pub use planner::{IndexExplanation, PlanExplanation, QueryPlan, QueryPlanner};
+pub use result::{Cell, ResultSet, Row};
+pub use executor::{QueryExecutor, QueryResult, PlannedResult, RangeResult, InstantResult, Alignment, BinaryOp, ExecutionConfig, ExecutionError, ExecutionResult};
 
 #[cfg(test)]
 mod tests {
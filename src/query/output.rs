@@ -0,0 +1,212 @@
+//! Encodings for query results
+//!
+//! Every encoding lays points out as rows of `timestamp`, `value` and one
+//! column per tag key, with tag keys in sorted order.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, TimestampNanosecondArray};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::metrics::push_sample;
+use crate::storage::data::{DataPoint, Value};
+
+/// Error type for result encoding
+#[derive(Debug, Error)]
+pub enum OutputError {
+    #[error("JSON encoding failed: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("CSV encoding failed: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Arrow encoding failed: {0}")]
+    Arrow(#[from] ArrowError),
+}
+
+/// Result type for result encoding
+pub type OutputResult<T> = Result<T, OutputError>;
+
+/// Encoding requested by a query's `FORMAT` clause
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// An array of `{"timestamp", "value", "tags"}` objects
+    #[default]
+    Json,
+    /// A header row followed by one row per point
+    Csv,
+    /// An Arrow IPC stream holding a single record batch
+    Arrow,
+    /// Prometheus text exposition, one sample per point
+    Prometheus,
+}
+
+impl OutputFormat {
+    /// Parses a format name, ignoring case
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            "arrow" => Some(OutputFormat::Arrow),
+            "prometheus" => Some(OutputFormat::Prometheus),
+            _ => None,
+        }
+    }
+
+    /// Returns the MIME type of the encoding
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "application/json",
+            OutputFormat::Csv => "text/csv",
+            OutputFormat::Arrow => "application/vnd.apache.arrow.stream",
+            OutputFormat::Prometheus => "text/plain; version=0.0.4",
+        }
+    }
+}
+
+/// Encodes points in the given format; `metric_name` names Prometheus samples
+pub fn encode(format: OutputFormat, points: &[DataPoint], metric_name: &str) -> OutputResult<Vec<u8>> {
+    match format {
+        OutputFormat::Json => to_json(points),
+        OutputFormat::Csv => to_csv(points),
+        OutputFormat::Arrow => to_arrow(points),
+        OutputFormat::Prometheus => Ok(to_prometheus(points, metric_name).into_bytes()),
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    timestamp: i64,
+    value: Value,
+    tags: BTreeMap<&'a str, &'a str>,
+}
+
+/// Encodes points as a JSON array, keeping integer and boolean values as such
+pub fn to_json(points: &[DataPoint]) -> OutputResult<Vec<u8>> {
+    let rows: Vec<JsonRow> = points
+        .iter()
+        .map(|point| JsonRow {
+            timestamp: point.timestamp(),
+            value: point.typed_value(),
+            tags: point.tags().iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(),
+        })
+        .collect();
+    Ok(serde_json::to_vec(&rows)?)
+}
+
+/// Encodes points as CSV; points without a tag leave its column empty
+pub fn to_csv(points: &[DataPoint]) -> OutputResult<Vec<u8>> {
+    let keys = tag_keys(points);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    let mut header = vec!["timestamp", "value"];
+    header.extend(keys.iter().map(String::as_str));
+    writer.write_record(&header)?;
+
+    for point in points {
+        let mut record = vec![point.timestamp().to_string(), point.typed_value().to_string()];
+        record.extend(keys.iter().map(|key| point.tags().get(key).cloned().unwrap_or_default()));
+        writer.write_record(&record)?;
+    }
+    writer.into_inner().map_err(|e| OutputError::Csv(e.into_error().into()))
+}
+
+/// Encodes points as an Arrow IPC stream
+///
+/// Timestamps are a nanosecond timestamp column, values are widened to
+/// `Float64`, and each tag key is a nullable `Utf8` column.
+pub fn to_arrow(points: &[DataPoint]) -> OutputResult<Vec<u8>> {
+    let keys = tag_keys(points);
+
+    let mut fields = vec![
+        Field::new("timestamp", DataType::Timestamp(TimeUnit::Nanosecond, None), false),
+        Field::new("value", DataType::Float64, false),
+    ];
+    fields.extend(keys.iter().map(|key| Field::new(key, DataType::Utf8, true)));
+    let schema = Arc::new(Schema::new(fields));
+
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(TimestampNanosecondArray::from_iter_values(points.iter().map(DataPoint::timestamp))),
+        Arc::new(Float64Array::from_iter_values(points.iter().map(DataPoint::value))),
+    ];
+    for key in &keys {
+        let values: StringArray = points.iter().map(|point| point.tags().get(key)).collect();
+        columns.push(Arc::new(values));
+    }
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let mut writer = StreamWriter::try_new(Vec::new(), &schema)?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(writer.into_inner()?)
+}
+
+/// Encodes points as Prometheus samples named `metric_name`
+///
+/// Tags become labels, except `series`, which already names the metric.
+pub fn to_prometheus(points: &[DataPoint], metric_name: &str) -> String {
+    let mut output = String::new();
+    for point in points {
+        push_sample(&mut output, metric_name, point);
+    }
+    output
+}
+
+/// Returns the tag keys used by any point, in sorted order
+fn tag_keys(points: &[DataPoint]) -> Vec<String> {
+    let keys: BTreeSet<&String> = points.iter().flat_map(|point| point.tags().keys()).collect();
+    keys.into_iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_ipc::reader::StreamReader;
+    use std::collections::HashMap;
+
+    fn points() -> Vec<DataPoint> {
+        let mut tags = HashMap::new();
+        tags.insert("host".to_string(), "a,b".to_string());
+        vec![
+            DataPoint::with_value(1_000_000, Value::I64(3), tags),
+            DataPoint::new(2_000_000, 1.5, HashMap::new()),
+        ]
+    }
+
+    #[test]
+    fn test_encodings() {
+        let points = points();
+
+        let json: serde_json::Value = serde_json::from_slice(&to_json(&points).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"timestamp": 1_000_000, "value": 3, "tags": {"host": "a,b"}},
+                {"timestamp": 2_000_000, "value": 1.5, "tags": {}},
+            ])
+        );
+
+        let csv = String::from_utf8(to_csv(&points).unwrap()).unwrap();
+        assert_eq!(csv, "timestamp,value,host\n1000000,3,\"a,b\"\n2000000,1.5,\n");
+
+        let prometheus = to_prometheus(&points, "cpu.usage");
+        assert_eq!(prometheus, "cpu_usage{host=\"a,b\"} 3 1\ncpu_usage 1.5 2\n");
+
+        let arrow = to_arrow(&points).unwrap();
+        let batches: Vec<RecordBatch> = StreamReader::try_new(arrow.as_slice(), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        let names: Vec<&String> = batch.schema_ref().fields().iter().map(|f| f.name()).collect();
+        assert_eq!(names, vec!["timestamp", "value", "host"]);
+        let hosts = batch.column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(hosts.value(0), "a,b");
+        assert!(hosts.is_null(1));
+    }
+}
@@ -1,5 +1,10 @@
+use std::collections::HashMap;
 use thiserror::Error;
 
+use crate::query::aggregation::is_transform;
+use crate::query::output::OutputFormat;
+use crate::query::parser::lexer::Span;
+
 #[derive(Debug, Error)]
 pub enum AstError {
     #[error("Invalid time range expression: {0}")]
@@ -8,6 +13,10 @@ pub enum AstError {
     InvalidTagFilter(String),
     #[error("Invalid function call: {0}")]
     InvalidFunctionCall(String),
+    #[error("Unknown output format: {0}")]
+    UnknownFormat(String),
+    #[error("{0} at {1}")]
+    At(Box<AstError>, Span),
 }
 
 #[derive(Debug, Clone)]
@@ -25,7 +34,7 @@ pub enum TimeRange {
     },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TagFilterOp {
     Eq,
     Neq,
@@ -40,19 +49,134 @@ pub struct TagFilter {
     pub value: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueFilterOp {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+/// Comparison of a point's value against a constant, e.g. `value > 1000`
+#[derive(Debug, Clone)]
+pub struct ValueFilter {
+    pub op: ValueFilterOp,
+    pub value: f64,
+}
+
+impl ValueFilter {
+    /// Checks whether a single value satisfies the filter
+    pub fn matches(&self, value: f64) -> bool {
+        match self.op {
+            ValueFilterOp::Eq => value == self.value,
+            ValueFilterOp::Neq => value != self.value,
+            ValueFilterOp::Gt => value > self.value,
+            ValueFilterOp::Gte => value >= self.value,
+            ValueFilterOp::Lt => value < self.value,
+            ValueFilterOp::Lte => value <= self.value,
+        }
+    }
+
+    /// Checks whether any value within `[min, max]` could satisfy the filter
+    pub fn may_match_range(&self, min: f64, max: f64) -> bool {
+        match self.op {
+            ValueFilterOp::Eq => min <= self.value && self.value <= max,
+            // NaN values are not reflected in the range but always differ
+            ValueFilterOp::Neq => true,
+            ValueFilterOp::Gt => max > self.value,
+            ValueFilterOp::Gte => max >= self.value,
+            ValueFilterOp::Lt => min < self.value,
+            ValueFilterOp::Lte => min <= self.value,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum FilterExpr {
     TagFilter(TagFilter),
+    ValueFilter(ValueFilter),
     And(Box<FilterExpr>, Box<FilterExpr>),
     Or(Box<FilterExpr>, Box<FilterExpr>),
     Not(Box<FilterExpr>),
 }
 
+impl FilterExpr {
+    /// Evaluates the value filters in this expression against a point's value
+    ///
+    /// Returns `None` when the outcome also depends on tag filters.
+    pub fn matches_value(&self, value: f64) -> Option<bool> {
+        match self {
+            FilterExpr::TagFilter(_) => None,
+            FilterExpr::ValueFilter(filter) => Some(filter.matches(value)),
+            FilterExpr::And(left, right) => match (left.matches_value(value), right.matches_value(value)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            FilterExpr::Or(left, right) => match (left.matches_value(value), right.matches_value(value)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            FilterExpr::Not(expr) => expr.matches_value(value).map(|matched| !matched),
+        }
+    }
+
+    /// Evaluates the expression against a point's tags and value
+    ///
+    /// Regex tag filters are not supported here and make the result `None`
+    /// unless the rest of the expression decides it.
+    pub fn matches(&self, tags: &HashMap<String, String>, value: f64) -> Option<bool> {
+        match self {
+            FilterExpr::TagFilter(filter) => {
+                let actual = tags.get(&filter.key);
+                match filter.op {
+                    TagFilterOp::Eq => Some(actual == Some(&filter.value)),
+                    TagFilterOp::Neq => Some(actual != Some(&filter.value)),
+                    TagFilterOp::Regex | TagFilterOp::NotRegex => None,
+                }
+            }
+            FilterExpr::ValueFilter(filter) => Some(filter.matches(value)),
+            FilterExpr::And(left, right) => match (left.matches(tags, value), right.matches(tags, value)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            FilterExpr::Or(left, right) => match (left.matches(tags, value), right.matches(tags, value)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            FilterExpr::Not(expr) => expr.matches(tags, value).map(|matched| !matched),
+        }
+    }
+
+    /// Checks whether a block whose values span `[min, max]` could hold a match
+    ///
+    /// This is conservative: only value filters combined through AND/OR are used
+    /// to rule a block out.
+    pub fn may_match_value_range(&self, min: f64, max: f64) -> bool {
+        match self {
+            FilterExpr::ValueFilter(filter) => filter.may_match_range(min, max),
+            FilterExpr::And(left, right) => {
+                left.may_match_value_range(min, max) && right.may_match_value_range(min, max)
+            }
+            FilterExpr::Or(left, right) => {
+                left.may_match_value_range(min, max) || right.may_match_value_range(min, max)
+            }
+            FilterExpr::TagFilter(_) | FilterExpr::Not(_) => true,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum FunctionArg {
     Identifier(String),
     NumberLiteral(f64),
     StringLiteral(String),
+    Duration(i64), // nanoseconds
     FunctionCall(Box<FunctionCall>),
 }
 
@@ -62,12 +186,96 @@ pub struct FunctionCall {
     pub args: Vec<FunctionArg>,
 }
 
+/// What a select expression produces
+#[derive(Debug, Clone)]
+pub enum SelectItem {
+    /// `*`: the raw points, with all of their tags
+    Wildcard,
+    /// A bare value column, e.g. `value`, projected from the raw points
+    Column(String),
+    /// An aggregate over each group of points, e.g. `avg(value)`
+    Function(FunctionCall),
+}
+
 #[derive(Debug, Clone)]
 pub struct SelectExpr {
-    pub function: FunctionCall,
+    pub item: SelectItem,
     pub alias: Option<String>,
 }
 
+/// How grouped results are ordered, independent of per-point ORDER BY
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupOrder {
+    /// Order groups by their group-by tag values
+    Key { descending: bool },
+    /// Order groups by the value of an output column, e.g. for top-n queries
+    Aggregate { column: String, descending: bool },
+}
+
+/// Condition on the aggregated output of a group, from a HAVING clause
+#[derive(Debug, Clone)]
+pub enum HavingExpr {
+    /// Compares an output column, named by alias or as written (`avg(value)`),
+    /// against a constant
+    Condition { column: String, filter: ValueFilter },
+    And(Box<HavingExpr>, Box<HavingExpr>),
+    Or(Box<HavingExpr>, Box<HavingExpr>),
+    Not(Box<HavingExpr>),
+}
+
+impl HavingExpr {
+    /// Returns every output column the expression refers to
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            HavingExpr::Condition { column, .. } => vec![column.as_str()],
+            HavingExpr::And(left, right) | HavingExpr::Or(left, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            HavingExpr::Not(expr) => expr.columns(),
+        }
+    }
+
+    /// Evaluates the expression given the value of each output column
+    ///
+    /// A column whose value is missing or null fails its condition.
+    pub fn matches(&self, value_of: &impl Fn(&str) -> Option<f64>) -> bool {
+        match self {
+            HavingExpr::Condition { column, filter } => value_of(column).is_some_and(|v| filter.matches(v)),
+            HavingExpr::And(left, right) => left.matches(value_of) && right.matches(value_of),
+            HavingExpr::Or(left, right) => left.matches(value_of) || right.matches(value_of),
+            HavingExpr::Not(expr) => !expr.matches(value_of),
+        }
+    }
+}
+
+/// What to emit for time buckets that contain no points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BucketFill {
+    /// Leave empty buckets out of the results
+    #[default]
+    None,
+    /// Emit empty buckets with null aggregate values
+    Null,
+}
+
+/// Fixed-width time buckets from `GROUP BY time(<duration>)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeBucket {
+    /// Bucket width in nanoseconds
+    pub width: i64,
+    /// Handling of buckets with no points
+    pub fill: BucketFill,
+}
+
+impl TimeBucket {
+    /// Returns the start of the bucket containing `timestamp`
+    pub fn floor(&self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.width)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Query {
     pub select: Vec<SelectExpr>,
@@ -75,9 +283,20 @@ pub struct Query {
     pub time_range: Option<TimeRange>,
     pub filter: Option<FilterExpr>,
     pub group_by: Vec<String>,
+    pub time_bucket: Option<TimeBucket>,
+    /// Condition groups must meet after aggregation
+    pub having: Option<HavingExpr>,
+    pub group_order: Option<GroupOrder>,
     pub order_by: Vec<(String, bool)>,  // (field, descending)
     pub limit: Option<usize>,
     pub offset: Option<usize>,
+    /// Encoding requested by a trailing `FORMAT` clause
+    pub format: Option<OutputFormat>,
+    /// Set by a leading `EXPLAIN`: the query is planned, not executed
+    pub explain: bool,
+    /// Further windows read alongside `time_range`, e.g. the same hours a week
+    /// earlier for a week-over-week comparison
+    pub extra_time_ranges: Vec<TimeRange>,
 }
 
 impl Query {
@@ -88,11 +307,34 @@ impl Query {
             time_range: None,
             filter: None,
             group_by: Vec::new(),
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
+            group_order: None,
             order_by: Vec::new(),
             limit: None,
             offset: None,
+            format: None,
         }
     }
+
+    /// Returns `time_range` followed by the extra time ranges
+    pub fn time_ranges(&self) -> impl Iterator<Item = &TimeRange> {
+        self.time_range.iter().chain(&self.extra_time_ranges)
+    }
+
+    /// Returns true if the query selects points rather than aggregates
+    ///
+    /// That is raw points, bare columns, or a transform such as `cumulative_sum`
+    /// that maps each point to an output point.
+    pub fn is_raw(&self) -> bool {
+        !self.select.is_empty()
+            && self.select.iter().all(|expr| match &expr.item {
+                SelectItem::Function(call) => is_transform(&call.name),
+                SelectItem::Wildcard | SelectItem::Column(_) => true,
+            })
+    }
 }
 
 #[cfg(test)]
@@ -104,10 +346,10 @@ mod tests {
         let query = Query {
             select: vec![
                 SelectExpr {
-                    function: FunctionCall {
+                    item: SelectItem::Function(FunctionCall {
                         name: "avg".to_string(),
                         args: vec![FunctionArg::Identifier("value".to_string())],
-                    },
+                    }),
                     alias: Some("avg_value".to_string()),
                 }
             ],
@@ -124,6 +366,12 @@ mod tests {
             order_by: vec![("avg_value".to_string(), true)],
             limit: Some(10),
             offset: None,
+            format: None,
+            group_order: None,
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
         };
 
         // Verify the query structure
@@ -1,26 +1,44 @@
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum LexerError {
-    #[error("Unexpected character: {0}")]
-    UnexpectedChar(char),
-    #[error("Invalid number format: {0}")]
-    InvalidNumber(String),
-    #[error("Unterminated string literal")]
-    UnterminatedString,
-    #[error("Invalid identifier: {0}")]
-    InvalidIdentifier(String),
+    #[error("Unexpected character {0:?} at {1}")]
+    UnexpectedChar(char, Span),
+    #[error("Invalid number format {0} at {1}")]
+    InvalidNumber(String, Span),
+    #[error("Unterminated string literal starting at {0}")]
+    UnterminatedString(Span),
+    #[error("Unterminated block comment starting at {0}")]
+    UnterminatedComment(Span),
+    #[error("Invalid identifier {0} at {1}")]
+    InvalidIdentifier(String, Span),
+}
+
+/// Position of a token in the query text; lines and columns start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.column)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Keywords
+    Explain,
     Select,
     From,
     Where,
     GroupBy,
+    Having,
     OrderBy,
     Limit,
     Offset,
@@ -58,6 +76,7 @@ pub enum Token {
     Identifier(String),
     StringLiteral(String),
     NumberLiteral(f64),
+    DurationLiteral(i64), // nanoseconds, e.g. 5m
     
     // Special
     EOF,
@@ -65,111 +84,136 @@ pub enum Token {
 
 pub struct Lexer<'a> {
     input: Peekable<Chars<'a>>,
-    current_pos: usize,
+    /// Position of the next character
+    position: Span,
+    /// Whether the last token ends an operand, making a following `-` a
+    /// subtraction rather than the sign of a number
+    after_operand: bool,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Self {
             input: input.chars().peekable(),
-            current_pos: 0,
+            position: Span { line: 1, column: 1 },
+            after_operand: false,
         }
     }
     
     pub fn tokenize(&mut self) -> Result<Vec<Token>, LexerError> {
+        Ok(self.tokenize_spanned()?.into_iter().map(|(token, _)| token).collect())
+    }
+
+    /// Tokenizes the input, pairing each token with where it starts
+    pub fn tokenize_spanned(&mut self) -> Result<Vec<(Token, Span)>, LexerError> {
         let mut tokens = Vec::new();
         
         while let Some(token) = self.next_token()? {
             tokens.push(token);
         }
         
-        tokens.push(Token::EOF);
+        tokens.push((Token::EOF, self.position));
         Ok(tokens)
     }
+
+    /// Consumes the next character, keeping track of its position
+    fn advance(&mut self) -> Option<char> {
+        let c = self.input.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
     
-    fn next_token(&mut self) -> Result<Option<Token>, LexerError> {
-        self.skip_whitespace();
+    fn next_token(&mut self) -> Result<Option<(Token, Span)>, LexerError> {
+        self.skip_trivia()?;
+        let start = self.position;
         
         if let Some(&c) = self.input.peek() {
             let token = match c {
                 // Single character tokens
                 '=' => {
-                    self.input.next();
+                    self.advance();
                     Token::Eq
                 }
                 '!' => {
-                    self.input.next();
+                    self.advance();
                     if let Some('=') = self.input.peek() {
-                        self.input.next();
+                        self.advance();
                         Token::Neq
                     } else {
-                        return Err(LexerError::UnexpectedChar('!'));
+                        return Err(LexerError::UnexpectedChar('!', start));
                     }
                 }
                 '>' => {
-                    self.input.next();
+                    self.advance();
                     if let Some('=') = self.input.peek() {
-                        self.input.next();
+                        self.advance();
                         Token::Gte
                     } else {
                         Token::Gt
                     }
                 }
                 '<' => {
-                    self.input.next();
+                    self.advance();
                     if let Some('=') = self.input.peek() {
-                        self.input.next();
+                        self.advance();
                         Token::Lte
                     } else {
                         Token::Lt
                     }
                 }
+                // A sign directly before a digit, where no operand precedes it
+                '+' | '-' if !self.after_operand && self.sign_starts_number() => self.parse_number()?,
                 '+' => {
-                    self.input.next();
+                    self.advance();
                     Token::Plus
                 }
                 '-' => {
-                    self.input.next();
+                    self.advance();
                     Token::Minus
                 }
                 '*' => {
-                    self.input.next();
+                    self.advance();
                     Token::Star
                 }
                 '/' => {
-                    self.input.next();
+                    self.advance();
                     Token::Slash
                 }
                 '%' => {
-                    self.input.next();
+                    self.advance();
                     Token::Percent
                 }
                 ',' => {
-                    self.input.next();
+                    self.advance();
                     Token::Comma
                 }
                 '.' => {
-                    self.input.next();
+                    self.advance();
                     Token::Dot
                 }
                 '(' => {
-                    self.input.next();
+                    self.advance();
                     Token::LParen
                 }
                 ')' => {
-                    self.input.next();
+                    self.advance();
                     Token::RParen
                 }
                 '[' => {
-                    self.input.next();
+                    self.advance();
                     Token::LBracket
                 }
                 ']' => {
-                    self.input.next();
+                    self.advance();
                     Token::RBracket
                 }
                 ';' => {
-                    self.input.next();
+                    self.advance();
                     Token::Semicolon
                 }
                 
@@ -181,10 +225,19 @@ impl<'a> Lexer<'a> {
                 c if c.is_ascii_alphabetic() || c == '_' => self.parse_identifier()?,
                 
                 // Unexpected character
-                c => return Err(LexerError::UnexpectedChar(c)),
+                c => return Err(LexerError::UnexpectedChar(c, start)),
             };
             
-            Ok(Some(token))
+            self.after_operand = matches!(
+                token,
+                Token::Identifier(_)
+                    | Token::StringLiteral(_)
+                    | Token::NumberLiteral(_)
+                    | Token::DurationLiteral(_)
+                    | Token::RParen
+                    | Token::RBracket
+            );
+            Ok(Some((token, start)))
         } else {
             Ok(None)
         }
@@ -193,51 +246,135 @@ impl<'a> Lexer<'a> {
     fn skip_whitespace(&mut self) {
         while let Some(&c) = self.input.peek() {
             if c.is_whitespace() {
-                self.input.next();
-                self.current_pos += 1;
+                self.advance();
             } else {
                 break;
             }
         }
     }
     
+    /// Skips whitespace and comments
+    ///
+    /// `--` starts a comment running to the end of the line, and `/*` one
+    /// running to the next `*/`. Block comments do not nest: the first `*/`
+    /// ends the comment however many `/*` it contains.
+    fn skip_trivia(&mut self) -> Result<(), LexerError> {
+        loop {
+            self.skip_whitespace();
+            let start = self.position;
+            let mut ahead = self.input.clone();
+            match (ahead.next(), ahead.next()) {
+                (Some('-'), Some('-')) => {
+                    while self.input.peek().is_some_and(|&c| c != '\n') {
+                        self.advance();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    self.consume_chars(2);
+                    let mut previous = None;
+                    loop {
+                        match self.advance() {
+                            Some('/') if previous == Some('*') => break,
+                            Some(c) => previous = Some(c),
+                            None => return Err(LexerError::UnterminatedComment(start)),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+    
     fn parse_string(&mut self) -> Result<Token, LexerError> {
-        let quote = self.input.next().unwrap();
+        let start = self.position;
+        let quote = self.advance().unwrap();
         let mut string = String::new();
         
         while let Some(&c) = self.input.peek() {
             if c == quote {
-                self.input.next();
+                self.advance();
                 return Ok(Token::StringLiteral(string));
             }
-            string.push(self.input.next().unwrap());
+            string.push(self.advance().unwrap());
         }
         
-        Err(LexerError::UnterminatedString)
+        Err(LexerError::UnterminatedString(start))
     }
     
+    /// Checks whether the sign at the cursor is directly followed by a digit
+    fn sign_starts_number(&self) -> bool {
+        let mut ahead = self.input.clone();
+        ahead.next();
+        ahead.next().is_some_and(|c| c.is_ascii_digit())
+    }
+
+    /// Parses a number with an optional sign, fraction and exponent, e.g.
+    /// `-3.2` or `2.5e-3`
     fn parse_number(&mut self) -> Result<Token, LexerError> {
+        let start = self.position;
         let mut number = String::new();
         let mut has_decimal = false;
+        let mut has_exponent = false;
+
+        if let Some(&sign @ ('+' | '-')) = self.input.peek() {
+            self.advance();
+            if sign == '-' {
+                number.push(sign);
+            }
+        }
         
         while let Some(&c) = self.input.peek() {
             match c {
                 '0'..='9' => {
-                    number.push(self.input.next().unwrap());
+                    number.push(self.advance().unwrap());
                 }
-                '.' if !has_decimal => {
+                '.' if !has_decimal && !has_exponent => {
                     has_decimal = true;
-                    number.push(self.input.next().unwrap());
+                    number.push(self.advance().unwrap());
+                }
+                'e' | 'E' if !has_exponent && self.exponent_follows() => {
+                    has_exponent = true;
+                    number.push(self.advance().unwrap());
+                    if let Some(&sign @ ('+' | '-')) = self.input.peek() {
+                        self.advance();
+                        number.push(sign);
+                    }
+                }
+                // A second `.` or an exponent without digits, as in `1.2.3` or `1e`
+                '.' | 'e' | 'E' => {
+                    while self.input.peek().is_some_and(|c| c.is_ascii_alphanumeric() || *c == '.') {
+                        number.push(self.advance().unwrap());
+                    }
+                    return Err(LexerError::InvalidNumber(number, start));
                 }
                 _ => break,
             }
         }
         
-        number.parse::<f64>()
-            .map(Token::NumberLiteral)
-            .map_err(|_| LexerError::InvalidNumber(number))
+        let value = number.parse::<f64>()
+            .map_err(|_| LexerError::InvalidNumber(number.clone(), start))?;
+
+        // A unit directly after the number makes it a duration
+        let unit = self.peek_word();
+        if let Some(unit_nanos) = duration_unit_nanos(&unit) {
+            self.consume_chars(unit.len());
+            return Ok(Token::DurationLiteral((value * unit_nanos as f64) as i64));
+        }
+
+        Ok(Token::NumberLiteral(value))
     }
     
+    /// Checks whether the `e` at the cursor starts an exponent with digits
+    fn exponent_follows(&self) -> bool {
+        let mut ahead = self.input.clone();
+        ahead.next();
+        match ahead.next() {
+            Some('+' | '-') => ahead.next().is_some_and(|c| c.is_ascii_digit()),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
     fn peek_word(&mut self) -> String {
         let mut word = String::new();
         let mut chars = self.input.clone();
@@ -255,7 +392,7 @@ impl<'a> Lexer<'a> {
     
     fn consume_chars(&mut self, count: usize) {
         for _ in 0..count {
-            self.input.next();
+            self.advance();
         }
     }
     
@@ -264,7 +401,7 @@ impl<'a> Lexer<'a> {
         
         while let Some(&c) = self.input.peek() {
             if c.is_ascii_alphanumeric() || c == '_' {
-                identifier.push(self.input.next().unwrap());
+                identifier.push(self.advance().unwrap());
             } else {
                 break;
             }
@@ -293,6 +430,8 @@ impl<'a> Lexer<'a> {
                     Token::Identifier(identifier)
                 }
             }
+            "having" => Token::Having,
+            "explain" => Token::Explain,
             "limit" => Token::Limit,
             "offset" => Token::Offset,
             "and" => Token::And,
@@ -309,6 +448,21 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// Returns the length in nanoseconds of a duration unit suffix
+fn duration_unit_nanos(unit: &str) -> Option<i64> {
+    match unit {
+        "ns" => Some(1),
+        "us" => Some(1_000),
+        "ms" => Some(1_000_000),
+        "s" => Some(1_000_000_000),
+        "m" => Some(60_000_000_000),
+        "h" => Some(3_600_000_000_000),
+        "d" => Some(86_400_000_000_000),
+        "w" => Some(604_800_000_000_000),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -392,6 +546,70 @@ mod tests {
         let mut lexer = Lexer::new(input);
         let result = lexer.tokenize();
         
-        assert!(matches!(result, Err(LexerError::UnexpectedChar('@'))));
+        assert!(matches!(
+            result,
+            Err(LexerError::UnexpectedChar('@', Span { line: 1, column: 37 }))
+        ));
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "SELECT avg(value)\nFROM cpu\n  WHERE host = 'a'";
+        let tokens = Lexer::new(input).tokenize_spanned().unwrap();
+        let span = |token: &Token| tokens.iter().find(|(t, _)| t == token).unwrap().1;
+
+        assert_eq!(span(&Token::Select), Span { line: 1, column: 1 });
+        assert_eq!(span(&Token::LParen), Span { line: 1, column: 11 });
+        assert_eq!(span(&Token::From), Span { line: 2, column: 1 });
+        assert_eq!(span(&Token::Where), Span { line: 3, column: 3 });
+        assert_eq!(span(&Token::StringLiteral("a".to_string())), Span { line: 3, column: 16 });
+        assert_eq!(tokens.last().unwrap(), &(Token::EOF, Span { line: 3, column: 19 }));
+
+        let err = Lexer::new("SELECT avg(value)\nFROM cpu WHERE host = 'a").tokenize().unwrap_err();
+        assert_eq!(err.to_string(), "Unterminated string literal starting at line 2, col 23");
+    }
+
+    #[test]
+    fn test_comments_are_skipped() {
+        let input = "-- CPU by host\n\
+                     SELECT avg(value) /* mean */ AS a -- trailing\n\
+                     FROM cpu /* multi\n line, /* not nested */\n\
+                     WHERE value > 5 - 1 --\n\
+                     LIMIT 10 /**/";
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let expected = Lexer::new("SELECT avg(value) AS a FROM cpu WHERE value > 5 - 1 LIMIT 10").tokenize().unwrap();
+        assert_eq!(tokens, expected);
+        assert!(tokens.contains(&Token::Minus));
+
+        // Comments still count toward positions
+        let spanned = Lexer::new("/* a */ SELECT\n-- b\n*").tokenize_spanned().unwrap();
+        assert_eq!(spanned[0], (Token::Select, Span { line: 1, column: 9 }));
+        assert_eq!(spanned[1], (Token::Star, Span { line: 3, column: 1 }));
+
+        assert!(matches!(
+            Lexer::new("SELECT * /* never closed").tokenize(),
+            Err(LexerError::UnterminatedComment(Span { line: 1, column: 10 }))
+        ));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_signed_and_scientific_numbers() {
+        let number = |input: &str| Lexer::new(input).tokenize().map(|tokens| tokens[0].clone());
+        assert_eq!(number("1e9").unwrap(), Token::NumberLiteral(1e9));
+        assert_eq!(number("4.5e1").unwrap(), Token::NumberLiteral(45.0));
+        assert_eq!(number("2.5E-3").unwrap(), Token::NumberLiteral(0.0025));
+        assert_eq!(number("-3.2").unwrap(), Token::NumberLiteral(-3.2));
+        assert_eq!(number("+7").unwrap(), Token::NumberLiteral(7.0));
+        assert_eq!(number("1e3ms").unwrap(), Token::DurationLiteral(1_000_000_000));
+
+        assert!(matches!(number("1e"), Err(LexerError::InvalidNumber(n, _)) if n == "1e"));
+        assert!(matches!(number("1e+"), Err(LexerError::InvalidNumber(_, _))));
+        assert!(matches!(number("1.2.3"), Err(LexerError::InvalidNumber(n, _)) if n == "1.2.3"));
+
+        // After an operand a sign is an operator
+        let tokens = Lexer::new("value > -5 AND x-1 < (2)-3").tokenize().unwrap();
+        assert_eq!(tokens[2], Token::NumberLiteral(-5.0));
+        assert_eq!(&tokens[4..7], &[Token::Identifier("x".to_string()), Token::Minus, Token::NumberLiteral(1.0)]);
+        assert_eq!(&tokens[10..12], &[Token::RParen, Token::Minus]);
+    }
+}
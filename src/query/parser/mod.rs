@@ -2,16 +2,22 @@ pub mod lexer;
 pub mod ast;
 pub mod validator;
 
-pub use lexer::{Lexer, Token, LexerError};
-pub use ast::{AstError, Query, TimeRange, FilterExpr, TagFilter, TagFilterOp, FunctionCall, SelectExpr};
-pub use validator::{ValidationError, QueryValidator, Schema};
+pub use lexer::{Lexer, Token, LexerError, Span};
+pub use ast::{AstError, Query, TimeRange, FilterExpr, GroupOrder, HavingExpr, TimeBucket, BucketFill, TagFilter, TagFilterOp, ValueFilter, ValueFilterOp, FunctionCall, SelectExpr, SelectItem};
+use validator::render_select_item;
+pub use validator::{ValidationError, QueryValidator, Schema, TagValueConstraint, ArgKind, FunctionRegistry, FunctionSignature};
 
+use crate::query::output::OutputFormat;
 use std::iter::Peekable;
 use std::slice::Iter;
 
 pub struct Parser<'a> {
     tokens: Peekable<Iter<'a, Token>>,
     validator: Option<QueryValidator>,
+    /// Positions of the tokens, when known, to locate syntax errors
+    spans: Option<&'a [Span]>,
+    /// Number of tokens consumed so far
+    consumed: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -19,6 +25,8 @@ impl<'a> Parser<'a> {
         Self {
             tokens: tokens.iter().peekable(),
             validator: None,
+            spans: None,
+            consumed: 0,
         }
     }
 
@@ -27,9 +35,48 @@ impl<'a> Parser<'a> {
         self
     }
 
+    /// Sets the positions of the tokens, as from [`Lexer::tokenize_spanned`]
+    ///
+    /// Syntax errors are then wrapped in `AstError::At` with the position of
+    /// the token that caused them.
+    pub fn with_spans(mut self, spans: &'a [Span]) -> Self {
+        self.spans = Some(spans);
+        self
+    }
+
     pub fn parse(&mut self) -> Result<Query, AstError> {
+        let query = match self.parse_clauses() {
+            Ok(query) => query,
+            Err(e) => return Err(self.locate(e)),
+        };
+
+        // Validate the query if a validator is provided
+        if let Some(validator) = &self.validator {
+            validator.validate(&query).map_err(|e| {
+                AstError::InvalidFunctionCall(format!("Validation error: {}", e))
+            })?;
+        }
+
+        Ok(query)
+    }
+
+    /// Attaches the position of the last consumed token to a syntax error
+    fn locate(&self, error: AstError) -> AstError {
+        match self.spans.and_then(|spans| spans.get(self.consumed.saturating_sub(1))) {
+            Some(span) => AstError::At(Box::new(error), *span),
+            None => error,
+        }
+    }
+
+    fn parse_clauses(&mut self) -> Result<Query, AstError> {
         let mut query = Query::new();
 
+        // Parse EXPLAIN prefix (optional)
+        if self.peek_token() == Some(&&Token::Explain) {
+            self.next_token()?;
+            query.explain = true;
+        }
+
         // Parse SELECT clause
         self.expect_token(Token::Select)?;
         query.select = self.parse_select_list()?;
@@ -51,7 +98,20 @@ impl<'a> Parser<'a> {
         // Parse GROUP BY clause (optional)
         if self.peek_token() == Some(&&Token::GroupBy) {
             self.next_token()?;
-            query.group_by = self.parse_identifier_list()?;
+            let (group_by, time_bucket) = self.parse_group_by()?;
+            query.group_by = group_by;
+            query.time_bucket = time_bucket;
+        }
+
+        // Parse HAVING clause (optional)
+        if self.peek_token() == Some(&&Token::Having) {
+            self.next_token()?;
+            query.having = Some(self.parse_having()?);
+        }
+
+        // Parse ORDER GROUPS BY clause (optional)
+        if self.peek_keyword("order") {
+            query.group_order = Some(self.parse_group_order()?);
         }
 
         // Parse ORDER BY clause (optional)
@@ -80,11 +140,15 @@ impl<'a> Parser<'a> {
             }
         }
 
-        // Validate the query if a validator is provided
-        if let Some(validator) = &self.validator {
-            validator.validate(&query).map_err(|e| {
-                AstError::InvalidFunctionCall(format!("Validation error: {}", e))
-            })?;
+        // Parse FORMAT clause (optional)
+        if self.peek_keyword("format") {
+            self.next_token()?;
+            query.format = Some(match self.next_token()? {
+                Token::Identifier(name) => {
+                    OutputFormat::parse(name).ok_or_else(|| AstError::UnknownFormat(name.clone()))?
+                }
+                other => return Err(AstError::UnknownFormat(format!("{:?}", other))),
+            });
         }
 
         Ok(query)
@@ -108,7 +172,12 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_select_expr(&mut self) -> Result<SelectExpr, AstError> {
-        let function = self.parse_function_call()?;
+        let item = if self.peek_token() == Some(&&Token::Star) {
+            self.next_token()?;
+            SelectItem::Wildcard
+        } else {
+            self.parse_select_item()?
+        };
         let alias = if self.peek_token() == Some(&&Token::As) {
             self.next_token()?;
             if let Token::Identifier(name) = self.next_token()?.clone() {
@@ -120,21 +189,29 @@ impl<'a> Parser<'a> {
             None
         };
 
-        Ok(SelectExpr { function, alias })
+        if alias.is_some() && matches!(item, SelectItem::Wildcard) {
+            return Err(AstError::InvalidFunctionCall("* cannot be aliased".to_string()));
+        }
+
+        Ok(SelectExpr { item, alias })
     }
 
-    fn parse_function_call(&mut self) -> Result<FunctionCall, AstError> {
+    /// Parses a function call, or a bare column name when no `(` follows it
+    fn parse_select_item(&mut self) -> Result<SelectItem, AstError> {
         let name = if let Token::Identifier(name) = self.next_token()?.clone() {
             name
         } else {
-            return Err(AstError::InvalidFunctionCall("Expected function name".to_string()));
+            return Err(AstError::InvalidFunctionCall("Expected function or column name".to_string()));
         };
 
+        if self.peek_token() != Some(&&Token::LParen) {
+            return Ok(SelectItem::Column(name));
+        }
         self.expect_token(Token::LParen)?;
         let args = self.parse_function_args()?;
         self.expect_token(Token::RParen)?;
 
-        Ok(FunctionCall { name, args })
+        Ok(SelectItem::Function(FunctionCall { name, args }))
     }
 
     fn parse_function_args(&mut self) -> Result<Vec<ast::FunctionArg>, AstError> {
@@ -175,6 +252,13 @@ impl<'a> Parser<'a> {
                         unreachable!()
                     }
                 }
+                Some(&&Token::DurationLiteral(_)) => {
+                    if let Token::DurationLiteral(value) = self.next_token()?.clone() {
+                        ast::FunctionArg::Duration(value)
+                    } else {
+                        unreachable!()
+                    }
+                }
                 _ => return Err(AstError::InvalidFunctionCall("Invalid function argument".to_string())),
             };
             args.push(arg);
@@ -231,6 +315,10 @@ impl<'a> Parser<'a> {
             return Err(AstError::InvalidTagFilter("Expected tag key".to_string()));
         };
 
+        if key == "value" {
+            return self.parse_value_filter();
+        }
+
         let op = match self.next_token()? {
             Token::Eq => TagFilterOp::Eq,
             Token::Neq => TagFilterOp::Neq,
@@ -246,14 +334,98 @@ impl<'a> Parser<'a> {
         Ok(FilterExpr::TagFilter(TagFilter { key, op, value }))
     }
 
-    fn parse_identifier_list(&mut self) -> Result<Vec<String>, AstError> {
-        let mut identifiers = Vec::new();
-        
+    fn parse_value_filter(&mut self) -> Result<FilterExpr, AstError> {
+        let op = match self.next_token()? {
+            Token::Eq => ValueFilterOp::Eq,
+            Token::Neq => ValueFilterOp::Neq,
+            Token::Gt => ValueFilterOp::Gt,
+            Token::Gte => ValueFilterOp::Gte,
+            Token::Lt => ValueFilterOp::Lt,
+            Token::Lte => ValueFilterOp::Lte,
+            _ => return Err(AstError::InvalidTagFilter("Expected comparison operator".to_string())),
+        };
+
+        let value = match self.next_token()? {
+            Token::NumberLiteral(value) => *value,
+            _ => return Err(AstError::InvalidTagFilter("Expected number".to_string())),
+        };
+
+        Ok(FilterExpr::ValueFilter(ValueFilter { op, value }))
+    }
+
+    /// Parses a HAVING condition, with the same precedence as WHERE
+    fn parse_having(&mut self) -> Result<HavingExpr, AstError> {
+        let mut expr = self.parse_having_term()?;
+
+        while let Some(token) = self.peek_token() {
+            match token {
+                Token::And => {
+                    self.next_token()?;
+                    let right = self.parse_having_term()?;
+                    expr = HavingExpr::And(Box::new(expr), Box::new(right));
+                }
+                Token::Or => {
+                    self.next_token()?;
+                    let right = self.parse_having_term()?;
+                    expr = HavingExpr::Or(Box::new(expr), Box::new(right));
+                }
+                _ => break,
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_having_term(&mut self) -> Result<HavingExpr, AstError> {
+        if self.peek_token() == Some(&&Token::Not) {
+            self.next_token()?;
+            let expr = self.parse_having_term()?;
+            return Ok(HavingExpr::Not(Box::new(expr)));
+        }
+
+        if self.peek_token() == Some(&&Token::LParen) {
+            self.next_token()?;
+            let expr = self.parse_having()?;
+            self.expect_token(Token::RParen)?;
+            return Ok(expr);
+        }
+
+        // An alias, or an aggregate written out as in the select list
+        let column = match self.parse_select_item()? {
+            SelectItem::Column(name) => name,
+            item => render_select_item(&item),
+        };
+        match self.parse_value_filter()? {
+            FilterExpr::ValueFilter(filter) => Ok(HavingExpr::Condition { column, filter }),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Parses a GROUP BY list of tag keys and at most one `time(<duration>)`,
+    /// followed by an optional `FILL(null | none)`
+    fn parse_group_by(&mut self) -> Result<(Vec<String>, Option<TimeBucket>), AstError> {
+        let mut tags = Vec::new();
+        let mut width = None;
+
         loop {
-            if let Token::Identifier(name) = self.next_token()?.clone() {
-                identifiers.push(name);
+            let name = if let Token::Identifier(name) = self.next_token()?.clone() {
+                name
             } else {
                 return Err(AstError::InvalidFunctionCall("Expected identifier".to_string()));
+            };
+
+            if name.eq_ignore_ascii_case("time") && self.peek_token() == Some(&&Token::LParen) {
+                self.next_token()?;
+                let duration = match self.next_token()? {
+                    Token::DurationLiteral(duration) if *duration > 0 => *duration,
+                    _ => return Err(AstError::InvalidTimeRange("Expected a positive duration in time()".to_string())),
+                };
+                self.expect_token(Token::RParen)?;
+                if width.replace(duration).is_some() {
+                    return Err(AstError::InvalidTimeRange("Only one time() grouping is allowed".to_string()));
+                }
+            } else {
+                tags.push(name);
             }
 
             if self.peek_token() == Some(&&Token::Comma) {
@@ -263,7 +435,22 @@ impl<'a> Parser<'a> {
             }
         }
 
-        Ok(identifiers)
+        let mut fill = BucketFill::None;
+        if self.peek_keyword("fill") {
+            self.next_token()?;
+            self.expect_token(Token::LParen)?;
+            fill = match self.next_token()? {
+                Token::Identifier(word) if word.eq_ignore_ascii_case("null") => BucketFill::Null,
+                Token::Identifier(word) if word.eq_ignore_ascii_case("none") => BucketFill::None,
+                _ => return Err(AstError::InvalidFunctionCall("Expected null or none in FILL()".to_string())),
+            };
+            self.expect_token(Token::RParen)?;
+            if width.is_none() {
+                return Err(AstError::InvalidFunctionCall("FILL requires GROUP BY time()".to_string()));
+            }
+        }
+
+        Ok((tags, width.map(|width| TimeBucket { width, fill })))
     }
 
     fn parse_order_by(&mut self) -> Result<Vec<(String, bool)>, AstError> {
@@ -295,10 +482,51 @@ impl<'a> Parser<'a> {
         Ok(order_by)
     }
 
+    /// Parses `ORDER GROUPS BY (KEY | <column>) [ASC | DESC]`
+    fn parse_group_order(&mut self) -> Result<GroupOrder, AstError> {
+        self.next_token()?;
+        match self.next_token()? {
+            Token::Identifier(word) if word.eq_ignore_ascii_case("groups") => {}
+            _ => return Err(AstError::InvalidFunctionCall("Expected GROUPS after ORDER".to_string())),
+        }
+        self.expect_token(Token::By)?;
+
+        let column = if let Token::Identifier(name) = self.next_token()?.clone() {
+            name
+        } else {
+            return Err(AstError::InvalidFunctionCall("Expected KEY or column in ORDER GROUPS BY".to_string()));
+        };
+
+        let descending = match self.peek_token() {
+            Some(&&Token::Desc) => {
+                self.next_token()?;
+                true
+            }
+            Some(&&Token::Asc) => {
+                self.next_token()?;
+                false
+            }
+            _ => false,
+        };
+
+        if column.eq_ignore_ascii_case("key") {
+            Ok(GroupOrder::Key { descending })
+        } else {
+            Ok(GroupOrder::Aggregate { column, descending })
+        }
+    }
+
+    /// Checks whether the next token is the given (case-insensitive) bare word
+    fn peek_keyword(&mut self, keyword: &str) -> bool {
+        matches!(self.peek_token(), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
     fn next_token(&mut self) -> Result<&Token, AstError> {
-        self.tokens.next().ok_or_else(|| {
+        let token = self.tokens.next().ok_or_else(|| {
             AstError::InvalidFunctionCall("Unexpected end of input".to_string())
-        })
+        })?;
+        self.consumed += 1;
+        Ok(token)
     }
 
     fn peek_token(&mut self) -> Option<&&Token> {
@@ -416,4 +644,93 @@ mod tests {
         let query = parser.parse().unwrap();
         assert!(matches!(query.filter, Some(FilterExpr::Not(_))));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_parse_format_clause() {
+        let parse = |input: &str| {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            Parser::new(&tokens).parse()
+        };
+
+        for (name, format) in [
+            ("JSON", OutputFormat::Json),
+            ("csv", OutputFormat::Csv),
+            ("Arrow", OutputFormat::Arrow),
+            ("PROMETHEUS", OutputFormat::Prometheus),
+        ] {
+            let query = parse(&format!("SELECT avg(value) FROM metrics LIMIT 10 FORMAT {}", name)).unwrap();
+            assert_eq!(query.format, Some(format));
+            assert_eq!(query.limit, Some(10));
+        }
+
+        assert_eq!(parse("SELECT avg(value) FROM metrics").unwrap().format, None);
+        assert!(matches!(
+            parse("SELECT avg(value) FROM metrics FORMAT XML"),
+            Err(AstError::UnknownFormat(name)) if name == "XML"
+        ));
+        assert!(parse("SELECT avg(value) FROM metrics FORMAT").is_err());
+    }
+
+    #[test]
+    fn test_parse_select_wildcard() {
+        let parse = |input: &str| {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            Parser::new(&tokens).parse()
+        };
+
+        let query = parse("SELECT * FROM cpu WHERE host = 'a' LIMIT 5").unwrap();
+        assert!(matches!(query.select.as_slice(), [SelectExpr { item: SelectItem::Wildcard, alias: None }]));
+        assert!(query.is_raw());
+        assert_eq!(query.limit, Some(5));
+
+        assert!(!parse("SELECT avg(value) FROM cpu").unwrap().is_raw());
+        assert!(parse("SELECT * AS everything FROM cpu").is_err());
+    }
+
+    #[test]
+    fn test_parse_bare_column() {
+        let tokens = Lexer::new("SELECT value AS v FROM cpu WHERE value > 10").tokenize().unwrap();
+        let query = Parser::new(&tokens).parse().unwrap();
+        match query.select.as_slice() {
+            [SelectExpr { item: SelectItem::Column(column), alias }] => {
+                assert_eq!(column, "value");
+                assert_eq!(alias.as_deref(), Some("v"));
+            }
+            other => panic!("Expected a single column, got {:?}", other),
+        }
+        assert!(query.is_raw());
+        assert!(matches!(query.filter, Some(FilterExpr::ValueFilter(_))));
+    }
+
+    #[test]
+    fn test_parse_errors_report_position() {
+        let input = "SELECT avg(value)\nFROM cpu\nWHERE host = 'a' LIMIT x";
+        let (tokens, spans): (Vec<Token>, Vec<Span>) = Lexer::new(input).tokenize_spanned().unwrap().into_iter().unzip();
+
+        let err = Parser::new(&tokens).with_spans(&spans).parse().unwrap_err();
+        assert!(matches!(&err, AstError::At(inner, Span { line: 3, column: 24 })
+            if matches!(inner.as_ref(), AstError::InvalidFunctionCall(_))));
+        assert_eq!(err.to_string(), "Invalid function call: Expected number after LIMIT at line 3, col 24");
+
+        // Without spans the error is reported as before
+        assert!(matches!(Parser::new(&tokens).parse(), Err(AstError::InvalidFunctionCall(_))));
+    }
+
+    #[test]
+    fn test_parse_having() {
+        let tokens = Lexer::new(
+            "SELECT avg(value) AS a, max(value) FROM cpu GROUP BY host HAVING a > 80 AND NOT max(value) >= 99 LIMIT 5",
+        )
+        .tokenize()
+        .unwrap();
+        let query = Parser::new(&tokens).parse().unwrap();
+        let having = query.having.expect("HAVING clause");
+        assert_eq!(having.columns(), vec!["a", "max(value)"]);
+        assert_eq!(query.limit, Some(5));
+
+        let values = |a: f64, max: f64| move |column: &str| if column == "a" { Some(a) } else { Some(max) };
+        assert!(having.matches(&values(90.0, 95.0)));
+        assert!(!having.matches(&values(90.0, 99.0)));
+        assert!(!having.matches(&values(70.0, 95.0)));
+    }
+}
@@ -1,7 +1,9 @@
 use thiserror::Error;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use regex::Regex;
 
-use super::ast::{Query, FunctionCall, FunctionArg, FilterExpr, TagFilter, AstError};
+use crate::query::aggregation::is_transform;
+use super::ast::{Query, FunctionCall, FunctionArg, FilterExpr, TagFilter, TagFilterOp, AstError, SelectExpr, SelectItem};
 
 #[derive(Debug, Error)]
 pub enum ValidationError {
@@ -19,71 +21,199 @@ pub enum ValidationError {
     InvalidOrderByField(String),
     #[error("Invalid group by field: {0}")]
     InvalidGroupByField(String),
+    #[error("Duplicate output column name '{0}': {1}")]
+    DuplicateColumnName(String, String),
+    #[error("Invalid select list: {0}")]
+    InvalidSelect(String),
+    #[error("HAVING refers to {0}, which is not a selected column")]
+    InvalidHavingField(String),
+    #[error("Unknown series: {0}")]
+    UnknownSeries(String),
+}
+
+/// Kind of argument a function accepts at a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A value column, e.g. `value`
+    Column,
+    /// A value column or a nested aggregate, e.g. `value` or `sum(value)`
+    Series,
+    /// A numeric literal
+    Number,
+    /// A numeric literal between 0 and 100
+    Percentage,
+    /// A duration literal, e.g. `5m`
+    Duration,
+    /// A string literal
+    String,
+}
+
+impl ArgKind {
+    /// Checks whether an argument is of this kind
+    fn accepts(&self, arg: &FunctionArg) -> bool {
+        match (self, arg) {
+            (ArgKind::Column | ArgKind::Series, FunctionArg::Identifier(_)) => true,
+            (ArgKind::Series, FunctionArg::FunctionCall(_)) => true,
+            (ArgKind::Number, FunctionArg::NumberLiteral(_)) => true,
+            (ArgKind::Percentage, FunctionArg::NumberLiteral(p)) => (0.0..=100.0).contains(p),
+            (ArgKind::Duration, FunctionArg::Duration(_)) => true,
+            (ArgKind::String, FunctionArg::StringLiteral(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            ArgKind::Column => "a column",
+            ArgKind::Series => "a column or aggregate",
+            ArgKind::Number => "a number",
+            ArgKind::Percentage => "a number between 0 and 100",
+            ArgKind::Duration => "a duration",
+            ArgKind::String => "a string",
+        }
+    }
+}
+
+/// Arguments a function accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSignature {
+    /// Kinds of the required arguments, in order
+    required: Vec<ArgKind>,
+    /// Kinds of the optional arguments that may follow them, in order
+    optional: Vec<ArgKind>,
+    /// Kind of any number of further arguments, for variadic functions
+    variadic: Option<ArgKind>,
+}
+
+impl FunctionSignature {
+    /// Creates a signature taking exactly the given arguments
+    pub fn new(required: Vec<ArgKind>) -> Self {
+        Self {
+            required,
+            optional: Vec::new(),
+            variadic: None,
+        }
+    }
+
+    /// Adds an optional argument after the required ones
+    pub fn with_optional(mut self, kind: ArgKind) -> Self {
+        self.optional.push(kind);
+        self
+    }
+
+    /// Accepts any number of further arguments of the given kind
+    pub fn with_variadic(mut self, kind: ArgKind) -> Self {
+        self.variadic = Some(kind);
+        self
+    }
+
+    /// Returns the kind expected at an argument position, if any
+    fn kind_at(&self, position: usize) -> Option<ArgKind> {
+        self.required
+            .iter()
+            .chain(&self.optional)
+            .nth(position)
+            .copied()
+            .or(self.variadic)
+    }
 }
 
 /// Registry of known functions and their signatures
 pub struct FunctionRegistry {
-    functions: HashSet<String>,
-    // TODO: Add function signatures with argument types
+    functions: HashMap<String, FunctionSignature>,
 }
 
 impl FunctionRegistry {
     pub fn new() -> Self {
-        let mut functions = HashSet::new();
+        let mut registry = Self { functions: HashMap::new() };
         // Add built-in functions
-        functions.insert("avg".to_string());
-        functions.insert("sum".to_string());
-        functions.insert("min".to_string());
-        functions.insert("max".to_string());
-        functions.insert("count".to_string());
-        functions.insert("rate".to_string());
-        functions.insert("stddev".to_string());
-        functions.insert("percentile".to_string());
-        
-        Self { functions }
+        for name in ["avg", "sum", "min", "max", "count", "rate", "stddev"] {
+            registry.register(name, FunctionSignature::new(vec![ArgKind::Series]));
+        }
+        registry.register("percentile", FunctionSignature::new(vec![ArgKind::Series, ArgKind::Percentage]));
+        registry.register(
+            "last_over_time",
+            FunctionSignature::new(vec![ArgKind::Series]).with_optional(ArgKind::Duration),
+        );
+        registry.register("cumulative_sum", FunctionSignature::new(vec![ArgKind::Column]));
+
+        registry
+    }
+
+    /// Adds a function, replacing the signature of any function of the same name
+    pub fn register(&mut self, name: &str, signature: FunctionSignature) {
+        self.functions.insert(name.to_string(), signature);
+    }
+
+    /// Returns the signature of a function
+    pub fn signature(&self, name: &str) -> Option<&FunctionSignature> {
+        self.functions.get(name)
     }
 
     pub fn validate_function(&self, name: &str) -> Result<(), ValidationError> {
-        if !self.functions.contains(name) {
+        if !self.functions.contains_key(name) {
             return Err(ValidationError::UnknownFunction(name.to_string()));
         }
         Ok(())
     }
 
+    /// Checks a call's argument count and the kind of each argument against its signature
     pub fn validate_arguments(&self, call: &FunctionCall) -> Result<(), ValidationError> {
         self.validate_function(&call.name)?;
+        let signature = &self.functions[&call.name];
 
-        // Basic argument count validation
-        match call.name.as_str() {
-            "avg" | "sum" | "min" | "max" | "count" | "rate" => {
-                if call.args.len() != 1 {
-                    return Err(ValidationError::InvalidArgumentCount(
-                        call.name.clone(),
-                        1,
-                        call.args.len(),
-                    ));
-                }
-                Ok(())
-            }
-            "percentile" => {
-                if call.args.len() != 2 {
-                    return Err(ValidationError::InvalidArgumentCount(
-                        call.name.clone(),
-                        2,
-                        call.args.len(),
-                    ));
-                }
-                // Validate second argument is a number
-                if let FunctionArg::NumberLiteral(_) = &call.args[1] {
-                    Ok(())
-                } else {
-                    Err(ValidationError::InvalidArgumentType(
-                        call.name.clone(),
-                        "Second argument must be a number".to_string(),
-                    ))
-                }
+        let count = call.args.len();
+        let max = signature.required.len() + signature.optional.len();
+        if count < signature.required.len() {
+            return Err(ValidationError::InvalidArgumentCount(call.name.clone(), signature.required.len(), count));
+        }
+        if count > max && signature.variadic.is_none() {
+            return Err(ValidationError::InvalidArgumentCount(call.name.clone(), max, count));
+        }
+
+        for (position, arg) in call.args.iter().enumerate() {
+            let Some(kind) = signature.kind_at(position) else { continue };
+            if !kind.accepts(arg) {
+                return Err(ValidationError::InvalidArgumentType(
+                    call.name.clone(),
+                    format!(
+                        "argument {} must be {}, got {}",
+                        position + 1,
+                        kind.describe(),
+                        render_function_arg(arg)
+                    ),
+                ));
             }
-            _ => Ok(()),
+        }
+        Ok(())
+    }
+}
+
+/// Values a constrained tag key may take
+#[derive(Debug, Clone)]
+pub enum TagValueConstraint {
+    /// One of a known set of values
+    OneOf(HashSet<String>),
+    /// Any value matching a regular expression in full
+    Pattern(Regex),
+}
+
+impl TagValueConstraint {
+    /// Admits exactly the given values
+    pub fn one_of<I: IntoIterator<Item = S>, S: Into<String>>(values: I) -> Self {
+        Self::OneOf(values.into_iter().map(Into::into).collect())
+    }
+
+    /// Admits values matching `pattern`, which is anchored at both ends
+    pub fn pattern(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self::Pattern(Regex::new(&format!("^(?:{})$", pattern))?))
+    }
+
+    /// Checks whether a tag value is admitted
+    pub fn admits(&self, value: &str) -> bool {
+        match self {
+            Self::OneOf(values) => values.contains(value),
+            Self::Pattern(regex) => regex.is_match(value),
         }
     }
 }
@@ -92,6 +222,10 @@ impl FunctionRegistry {
 pub struct Schema {
     pub tag_keys: HashSet<String>,
     pub value_fields: HashSet<String>,
+    /// Series queries may read from; empty admits any series
+    pub series: HashSet<String>,
+    /// Values allowed for tag keys; keys without an entry take any value
+    pub tag_values: HashMap<String, TagValueConstraint>,
 }
 
 impl Schema {
@@ -99,6 +233,8 @@ impl Schema {
         Self {
             tag_keys: HashSet::new(),
             value_fields: HashSet::new(),
+            series: HashSet::new(),
+            tag_values: HashMap::new(),
         }
     }
 
@@ -110,6 +246,18 @@ impl Schema {
         self.value_fields.insert(field);
     }
 
+    pub fn add_series(&mut self, name: String) {
+        self.series.insert(name);
+    }
+
+    /// Checks a query's `FROM` against the known series, if any are registered
+    pub fn validate_series(&self, name: &str) -> Result<(), ValidationError> {
+        if !self.series.is_empty() && !self.series.contains(name) {
+            return Err(ValidationError::UnknownSeries(name.to_string()));
+        }
+        Ok(())
+    }
+
     pub fn validate_tag_key(&self, key: &str) -> Result<(), ValidationError> {
         if !self.tag_keys.contains(key) {
             return Err(ValidationError::UnknownTagKey(key.to_string()));
@@ -117,6 +265,33 @@ impl Schema {
         Ok(())
     }
 
+    /// Restricts the values filters may compare a tag key against
+    pub fn constrain_tag_values(&mut self, key: String, constraint: TagValueConstraint) {
+        self.tag_keys.insert(key.clone());
+        self.tag_values.insert(key, constraint);
+    }
+
+    /// Checks that a tag filter compares against a value its key can take
+    ///
+    /// Equality filters must name an admitted value; regex filters must be a
+    /// valid regular expression. Unconstrained keys accept any value.
+    pub fn validate_tag_value(&self, filter: &TagFilter) -> Result<(), ValidationError> {
+        match filter.op {
+            TagFilterOp::Eq | TagFilterOp::Neq => match self.tag_values.get(&filter.key) {
+                Some(constraint) if !constraint.admits(&filter.value) => Err(ValidationError::InvalidTagValueType(
+                    format!("'{}' is not a valid value for tag '{}'", filter.value, filter.key),
+                )),
+                _ => Ok(()),
+            },
+            TagFilterOp::Regex | TagFilterOp::NotRegex => match Regex::new(&filter.value) {
+                Err(e) if self.tag_values.contains_key(&filter.key) => {
+                    Err(ValidationError::InvalidTagValueType(format!("invalid pattern for tag '{}': {}", filter.key, e)))
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
     pub fn validate_value_field(&self, field: &str) -> Result<(), ValidationError> {
         if !self.value_fields.contains(field) {
             return Err(ValidationError::InvalidOrderByField(field.to_string()));
@@ -143,7 +318,17 @@ impl QueryValidator {
         self
     }
 
+    /// Accepts calls to a custom function matching `signature`
+    ///
+    /// The executor evaluating the queries needs the function's evaluator, see
+    /// [`crate::query::QueryExecutor::register_function`].
+    pub fn register_function(&mut self, name: &str, signature: FunctionSignature) {
+        self.function_registry.register(name, signature);
+    }
+
     pub fn validate(&self, query: &Query) -> Result<(), ValidationError> {
+        self.schema.validate_series(&query.from)?;
+
         // Collect select aliases
         let mut select_aliases = std::collections::HashSet::new();
         for expr in &query.select {
@@ -152,16 +337,51 @@ impl QueryValidator {
             }
         }
 
-        // Validate SELECT expressions
+        // Validate SELECT expressions; `*` returns raw points and stands alone,
+        // and bare columns are projected from raw points so cannot mix with aggregates
+        let has_function = query.select.iter().any(|expr| matches!(expr.item, SelectItem::Function(_)));
+        let has_transform = query.select.iter().any(|expr| {
+            matches!(&expr.item, SelectItem::Function(call) if is_transform(&call.name))
+        });
+        if has_transform && query.select.len() > 1 {
+            return Err(ValidationError::InvalidSelect(
+                "transforms cannot be combined with other expressions".to_string(),
+            ));
+        }
         for expr in &query.select {
-            self.validate_function_call(&expr.function)?;
+            match &expr.item {
+                SelectItem::Wildcard if query.select.len() > 1 => {
+                    return Err(ValidationError::InvalidSelect(
+                        "* cannot be combined with other expressions".to_string(),
+                    ));
+                }
+                SelectItem::Wildcard => {}
+                SelectItem::Column(_) if has_function => {
+                    return Err(ValidationError::InvalidSelect(
+                        "bare columns cannot be combined with aggregates".to_string(),
+                    ));
+                }
+                SelectItem::Column(name) => self.schema.validate_value_field(name)?,
+                SelectItem::Function(call) => self.validate_function_call(call)?,
+            }
         }
 
+        self.validate_output_columns(query)?;
+
         // Validate WHERE clause
         if let Some(filter) = &query.filter {
             self.validate_filter(filter)?;
         }
 
+        // HAVING may only refer to output columns
+        if let Some(having) = &query.having {
+            for column in having.columns() {
+                if !query.select.iter().any(|expr| column_name(expr) == column) {
+                    return Err(ValidationError::InvalidHavingField(column.to_string()));
+                }
+            }
+        }
+
         // Validate GROUP BY fields
         for field in &query.group_by {
             if !self.schema.value_fields.contains(field) && !select_aliases.contains(field) {
@@ -179,6 +399,32 @@ impl QueryValidator {
         Ok(())
     }
 
+    /// Ensures every output column has a distinct name
+    ///
+    /// Aliases may also not shadow a tag key, since tags are returned alongside
+    /// the selected values.
+    fn validate_output_columns(&self, query: &Query) -> Result<(), ValidationError> {
+        let mut columns: HashMap<String, String> = HashMap::new();
+        for expr in &query.select {
+            let name = column_name(expr);
+            let rendered = render_select_item(&expr.item);
+            if let Some(previous) = columns.get(&name) {
+                return Err(ValidationError::DuplicateColumnName(
+                    name,
+                    format!("produced by both {} and {}", previous, rendered),
+                ));
+            }
+            if expr.alias.is_some() && self.schema.tag_keys.contains(&name) {
+                return Err(ValidationError::DuplicateColumnName(
+                    name,
+                    format!("alias of {} collides with a tag key", rendered),
+                ));
+            }
+            columns.insert(name, rendered);
+        }
+        Ok(())
+    }
+
     fn validate_function_call(&self, call: &FunctionCall) -> Result<(), ValidationError> {
         self.function_registry.validate_arguments(call)?;
 
@@ -202,8 +448,9 @@ impl QueryValidator {
         match filter {
             FilterExpr::TagFilter(tag_filter) => {
                 self.schema.validate_tag_key(&tag_filter.key)?;
-                // TODO: Add tag value type validation
+                self.schema.validate_tag_value(tag_filter)?;
             }
+            FilterExpr::ValueFilter(_) => {}
             FilterExpr::And(left, right) => {
                 self.validate_filter(left)?;
                 self.validate_filter(right)?;
@@ -220,10 +467,47 @@ impl QueryValidator {
     }
 }
 
+/// Returns the name of the output column produced by a select expression
+pub(crate) fn column_name(expr: &SelectExpr) -> String {
+    expr.alias
+        .clone()
+        .unwrap_or_else(|| render_select_item(&expr.item))
+}
+
+/// Renders a select item the way it would be written in a query
+pub(crate) fn render_select_item(item: &SelectItem) -> String {
+    match item {
+        SelectItem::Wildcard => "*".to_string(),
+        SelectItem::Column(name) => name.clone(),
+        SelectItem::Function(call) => render_function_call(call),
+    }
+}
+
+/// Renders a function call the way it would be written in a query
+fn render_function_call(call: &FunctionCall) -> String {
+    let args: Vec<String> = call
+        .args
+        .iter()
+        .map(render_function_arg)
+        .collect();
+    format!("{}({})", call.name, args.join(", "))
+}
+
+/// Renders a function argument the way it would be written in a query
+fn render_function_arg(arg: &FunctionArg) -> String {
+    match arg {
+        FunctionArg::Identifier(name) => name.clone(),
+        FunctionArg::NumberLiteral(n) => n.to_string(),
+        FunctionArg::StringLiteral(s) => format!("'{}'", s),
+        FunctionArg::Duration(ns) => format!("{}ns", ns),
+        FunctionArg::FunctionCall(nested) => render_function_call(nested),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::query::parser::ast::{Query, SelectExpr, FunctionCall, FunctionArg, FilterExpr, TagFilter, TagFilterOp};
+    use crate::query::parser::ast::{Query, SelectExpr, SelectItem, FunctionCall, FunctionArg, FilterExpr, TagFilter, TagFilterOp};
 
     fn create_test_schema() -> Schema {
         let mut schema = Schema::new();
@@ -242,10 +526,10 @@ mod tests {
         let query = Query {
             select: vec![
                 SelectExpr {
-                    function: FunctionCall {
+                    item: SelectItem::Function(FunctionCall {
                         name: "avg".to_string(),
                         args: vec![FunctionArg::Identifier("value".to_string())],
-                    },
+                    }),
                     alias: Some("avg_value".to_string()),
                 }
             ],
@@ -260,6 +544,12 @@ mod tests {
             order_by: vec![("avg_value".to_string(), true)],
             limit: Some(10),
             offset: None,
+            format: None,
+            group_order: None,
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
         };
 
         assert!(validator.validate(&query).is_ok());
@@ -273,10 +563,10 @@ mod tests {
         let query = Query {
             select: vec![
                 SelectExpr {
-                    function: FunctionCall {
+                    item: SelectItem::Function(FunctionCall {
                         name: "unknown_func".to_string(),
                         args: vec![FunctionArg::Identifier("value".to_string())],
-                    },
+                    }),
                     alias: None,
                 }
             ],
@@ -287,6 +577,12 @@ mod tests {
             order_by: vec![],
             limit: None,
             offset: None,
+            format: None,
+            group_order: None,
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
         };
 
         assert!(matches!(
@@ -303,10 +599,10 @@ mod tests {
         let query = Query {
             select: vec![
                 SelectExpr {
-                    function: FunctionCall {
+                    item: SelectItem::Function(FunctionCall {
                         name: "avg".to_string(),
                         args: vec![FunctionArg::Identifier("value".to_string())],
-                    },
+                    }),
                     alias: None,
                 }
             ],
@@ -321,6 +617,12 @@ mod tests {
             order_by: vec![],
             limit: None,
             offset: None,
+            format: None,
+            group_order: None,
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
         };
 
         assert!(matches!(
@@ -337,13 +639,13 @@ mod tests {
         let query = Query {
             select: vec![
                 SelectExpr {
-                    function: FunctionCall {
+                    item: SelectItem::Function(FunctionCall {
                         name: "avg".to_string(),
                         args: vec![
                             FunctionArg::Identifier("value".to_string()),
                             FunctionArg::Identifier("count".to_string()),
                         ],
-                    },
+                    }),
                     alias: None,
                 }
             ],
@@ -354,6 +656,12 @@ mod tests {
             order_by: vec![],
             limit: None,
             offset: None,
+            format: None,
+            group_order: None,
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
         };
 
         assert!(matches!(
@@ -361,4 +669,190 @@ mod tests {
             Err(ValidationError::InvalidArgumentCount(_, _, _))
         ));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_duplicate_output_columns() {
+        use crate::query::parser::{Lexer, Parser};
+
+        let parse = |input: &str| {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            Parser::new(&tokens).parse().unwrap()
+        };
+        let validator = QueryValidator::new().with_schema(create_test_schema());
+
+        let query = parse("SELECT avg(value) AS x, sum(value) AS x FROM metrics");
+        match validator.validate(&query) {
+            Err(ValidationError::DuplicateColumnName(name, detail)) => {
+                assert_eq!(name, "x");
+                assert_eq!(detail, "produced by both avg(value) and sum(value)");
+            }
+            other => panic!("Expected DuplicateColumnName, got {:?}", other),
+        }
+
+        // The same unaliased expression twice collides on its rendered name
+        let query = parse("SELECT avg(value), avg(value) FROM metrics");
+        assert!(matches!(
+            validator.validate(&query),
+            Err(ValidationError::DuplicateColumnName(name, _)) if name == "avg(value)"
+        ));
+
+        // An alias may not shadow a tag key
+        let query = parse("SELECT avg(value) AS region FROM metrics");
+        assert!(matches!(
+            validator.validate(&query),
+            Err(ValidationError::DuplicateColumnName(name, _)) if name == "region"
+        ));
+
+        let query = parse("SELECT avg(value) AS x, sum(value) AS y FROM metrics");
+        assert!(validator.validate(&query).is_ok());
+
+        // `*` is valid on its own but not mixed with aggregates
+        assert!(validator.validate(&parse("SELECT * FROM metrics")).is_ok());
+        assert!(matches!(
+            validator.validate(&parse("SELECT *, avg(value) FROM metrics")),
+            Err(ValidationError::InvalidSelect(_))
+        ));
+    }
+
+    #[test]
+    fn test_bare_columns() {
+        use crate::query::parser::{Lexer, Parser};
+
+        let parse = |input: &str| {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            Parser::new(&tokens).parse().unwrap()
+        };
+        let validator = QueryValidator::new().with_schema(create_test_schema());
+
+        assert!(validator.validate(&parse("SELECT value, count FROM metrics")).is_ok());
+        assert!(validator.validate(&parse("SELECT value AS v FROM metrics ORDER BY v")).is_ok());
+        assert!(validator.validate(&parse("SELECT bogus FROM metrics")).is_err());
+        assert!(matches!(
+            validator.validate(&parse("SELECT value, avg(value) FROM metrics")),
+            Err(ValidationError::InvalidSelect(_))
+        ));
+
+        // Aliases of bare columns are checked like any other output column
+        assert!(matches!(
+            validator.validate(&parse("SELECT value AS count, count FROM metrics")),
+            Err(ValidationError::DuplicateColumnName(name, _)) if name == "count"
+        ));
+    }
+
+    #[test]
+    fn test_percentile_argument_range() {
+        let validator = QueryValidator::new().with_schema(create_test_schema());
+        let percentile = |p: f64| {
+            let mut query = Query::new();
+            query.from = "metrics".to_string();
+            query.select = vec![SelectExpr {
+                item: SelectItem::Function(FunctionCall {
+                    name: "percentile".to_string(),
+                    args: vec![
+                        FunctionArg::Identifier("value".to_string()),
+                        FunctionArg::NumberLiteral(p),
+                    ],
+                }),
+                alias: None,
+            }];
+            query
+        };
+
+        assert!(validator.validate(&percentile(95.0)).is_ok());
+        assert!(validator.validate(&percentile(0.0)).is_ok());
+        assert!(matches!(
+            validator.validate(&percentile(150.0)),
+            Err(ValidationError::InvalidArgumentType(_, msg)) if msg.contains("150")
+        ));
+    }
+
+    #[test]
+    fn test_signature_argument_checks() {
+        use crate::query::parser::{Lexer, Parser};
+
+        let parse = |input: &str| {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            Parser::new(&tokens).parse().unwrap()
+        };
+        let mut validator = QueryValidator::new().with_schema(create_test_schema());
+        let validate = |validator: &QueryValidator, input: &str| validator.validate(&parse(input));
+
+        assert!(validate(&validator, "SELECT last_over_time(value, 5m) FROM metrics").is_ok());
+        assert!(validate(&validator, "SELECT avg(sum(value)) FROM metrics").is_ok());
+        assert!(matches!(
+            validate(&validator, "SELECT last_over_time(value, 5) FROM metrics"),
+            Err(ValidationError::InvalidArgumentType(name, msg))
+                if name == "last_over_time" && msg == "argument 2 must be a duration, got 5"
+        ));
+        assert!(matches!(
+            validate(&validator, "SELECT percentile(value, 'p99') FROM metrics"),
+            Err(ValidationError::InvalidArgumentType(_, msg)) if msg.contains("between 0 and 100")
+        ));
+        assert!(matches!(
+            validate(&validator, "SELECT cumulative_sum(sum(value)) FROM metrics"),
+            Err(ValidationError::InvalidArgumentType(_, msg)) if msg.contains("must be a column")
+        ));
+        assert!(matches!(
+            validate(&validator, "SELECT percentile(value) FROM metrics"),
+            Err(ValidationError::InvalidArgumentCount(_, 2, 1))
+        ));
+
+        // Variadic signatures accept any number of trailing arguments
+        validator.function_registry.register(
+            "weighted",
+            FunctionSignature::new(vec![ArgKind::Column]).with_variadic(ArgKind::Number),
+        );
+        assert!(validate(&validator, "SELECT weighted(value) FROM metrics").is_ok());
+        assert!(validate(&validator, "SELECT weighted(value, 1, 2, 3) FROM metrics").is_ok());
+        assert!(matches!(
+            validate(&validator, "SELECT weighted(value, 1, 5m) FROM metrics"),
+            Err(ValidationError::InvalidArgumentType(_, msg)) if msg.starts_with("argument 3")
+        ));
+    }
+
+    #[test]
+    fn test_unknown_series() {
+        let parse = |input: &str| {
+            let tokens = crate::query::parser::Lexer::new(input).tokenize().unwrap();
+            crate::query::parser::Parser::new(&tokens).parse().unwrap()
+        };
+
+        // Without registered series any FROM is accepted
+        let validator = QueryValidator::new().with_schema(create_test_schema());
+        assert!(validator.validate(&parse("SELECT avg(value) FROM cpu_typo")).is_ok());
+
+        let mut schema = create_test_schema();
+        schema.add_series("cpu".to_string());
+        let validator = QueryValidator::new().with_schema(schema);
+        assert!(validator.validate(&parse("SELECT avg(value) FROM cpu")).is_ok());
+        assert!(matches!(
+            validator.validate(&parse("SELECT avg(value) FROM cpu_typo")),
+            Err(ValidationError::UnknownSeries(name)) if name == "cpu_typo"
+        ));
+    }
+
+    #[test]
+    fn test_tag_value_constraints() {
+        let parse = |input: &str| {
+            let tokens = crate::query::parser::Lexer::new(input).tokenize().unwrap();
+            crate::query::parser::Parser::new(&tokens).parse().unwrap()
+        };
+        let mut schema = create_test_schema();
+        schema.constrain_tag_values("region".to_string(), TagValueConstraint::one_of(["us-west", "us-east"]));
+        schema.constrain_tag_values("host".to_string(), TagValueConstraint::pattern("web-[0-9]+").unwrap());
+        let validator = QueryValidator::new().with_schema(schema);
+
+        assert!(validator.validate(&parse("SELECT avg(value) FROM cpu WHERE region = 'us-west'")).is_ok());
+        assert!(validator.validate(&parse("SELECT avg(value) FROM cpu WHERE host != 'web-12'")).is_ok());
+        // Unconstrained keys take any value
+        assert!(validator.validate(&parse("SELECT avg(value) FROM cpu WHERE env = 'anything'")).is_ok());
+
+        for input in [
+            "SELECT avg(value) FROM cpu WHERE region = 'mars'",
+            "SELECT avg(value) FROM cpu WHERE host = 'web-1x'",
+            "SELECT avg(value) FROM cpu WHERE env = 'prod' AND NOT region = 'mars'",
+        ] {
+            assert!(matches!(validator.validate(&parse(input)), Err(ValidationError::InvalidTagValueType(_))), "{}", input);
+        }
+    }
+}
@@ -1,25 +1,52 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use serde::Serialize;
 use thiserror::Error;
 
+use crate::query::output::OutputResult;
 use crate::query::parser::ast::{Query, FilterExpr, TagFilter, TimeRange};
 use crate::storage::index::IndexInfo;
+use crate::storage::tag_index::BlockRef;
 
 #[derive(Debug, Error)]
 pub enum PlanningError {
-    #[error("No suitable index found for query: {0}")]
-    NoSuitableIndex(String),
     #[error("Invalid time range: {0}")]
     InvalidTimeRange(String),
     #[error("Invalid filter expression: {0}")]
     InvalidFilter(String),
 }
 
+/// Index name of the selection planned when no index can answer a query
+pub const FULL_SCAN: &str = "full_scan";
+
 #[derive(Debug, Clone)]
 pub struct IndexSelection {
     pub index_name: String,
     pub time_range: TimeRange,
     pub filter: Option<FilterExpr>,
     pub estimated_rows: usize,
+    /// Estimated fraction of the index's rows in range that pass the filter
+    pub filter_selectivity: f64,
+    /// Blocks that may match the filter, when the index's tag index can tell
+    pub candidate_blocks: Option<BTreeSet<BlockRef>>,
+    /// Set on the fallback selection that reads every block in the time range
+    pub full_scan: bool,
+}
+
+impl IndexSelection {
+    /// A selection reading every SSTable block in the time range
+    ///
+    /// Nothing is known about the data, so every row is assumed to match.
+    pub fn full_scan(time_range: TimeRange, filter: Option<FilterExpr>) -> Self {
+        Self {
+            index_name: FULL_SCAN.to_string(),
+            time_range,
+            filter,
+            estimated_rows: usize::MAX,
+            filter_selectivity: 1.0,
+            candidate_blocks: None,
+            full_scan: true,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -31,6 +58,70 @@ pub struct QueryPlan {
     pub offset: Option<usize>,
 }
 
+impl QueryPlan {
+    /// Returns true if no index was found or the chosen index cannot narrow
+    /// the scan to candidate blocks, so every block in the time range is read
+    pub fn requires_full_scan(&self) -> bool {
+        self.index_selections
+            .first()
+            .is_none_or(|selection| selection.full_scan || selection.candidate_blocks.is_none())
+    }
+
+    /// Summarizes the plan as reported by `EXPLAIN`
+    pub fn explain(&self) -> PlanExplanation {
+        PlanExplanation {
+            indexes: self
+                .index_selections
+                .iter()
+                .map(|selection| IndexExplanation {
+                    index_name: selection.index_name.clone(),
+                    estimated_rows: selection.estimated_rows,
+                    filter_selectivity: selection.filter_selectivity,
+                    candidate_blocks: selection.candidate_blocks.as_ref().map(BTreeSet::len),
+                })
+                .collect(),
+            estimated_rows: self.index_selections.first().map_or(0, |selection| selection.estimated_rows),
+            full_scan: self.requires_full_scan(),
+            group_by: self.group_by.clone(),
+            order_by: self.order_by.clone(),
+            limit: self.limit,
+            offset: self.offset,
+        }
+    }
+}
+
+/// The result of an `EXPLAIN` query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanExplanation {
+    /// Indexes able to answer the query, the chosen one first
+    pub indexes: Vec<IndexExplanation>,
+    /// Rows the chosen index is expected to produce
+    pub estimated_rows: usize,
+    /// Whether every block in the time range is read
+    pub full_scan: bool,
+    pub group_by: Vec<String>,
+    pub order_by: Vec<(String, bool)>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl PlanExplanation {
+    /// Encodes the explanation as JSON
+    pub fn to_json(&self) -> OutputResult<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+}
+
+/// How one candidate index would answer a query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndexExplanation {
+    pub index_name: String,
+    pub estimated_rows: usize,
+    pub filter_selectivity: f64,
+    /// Number of blocks the tag index narrowed the scan to, if it could
+    pub candidate_blocks: Option<usize>,
+}
+
 pub struct QueryPlanner {
     available_indexes: HashMap<String, IndexInfo>,
 }
@@ -60,6 +151,11 @@ impl QueryPlanner {
         })
     }
 
+    /// Plans a query without executing it, as `EXPLAIN <query>` asks
+    pub fn explain(&self, query: &Query) -> Result<PlanExplanation, PlanningError> {
+        Ok(self.plan_query(query)?.explain())
+    }
+
     fn select_indexes(&self, query: &Query) -> Result<Vec<IndexSelection>, PlanningError> {
         let mut selections = Vec::new();
 
@@ -68,24 +164,36 @@ impl QueryPlanner {
             PlanningError::InvalidTimeRange("Query must specify a time range".to_string())
         })?;
 
-        // Find indexes that can satisfy the query's time range and filters
+        // Find indexes that can satisfy every time range and the filters
         for (name, info) in &self.available_indexes {
-            if self.can_satisfy_query(name, info, &time_range, &query.filter) {
-                let estimated_rows = self.estimate_rows(info, &time_range, &query.filter);
-                
+            if query.time_ranges().all(|range| self.can_satisfy_query(name, info, range, &query.filter)) {
+                let estimated_rows = query
+                    .time_ranges()
+                    .map(|range| self.estimate_rows(info, range, &query.filter))
+                    .sum();
+                let candidate_blocks = match (&info.tag_index, &query.filter) {
+                    (Some(tag_index), Some(filter)) => tag_index.lookup(filter),
+                    _ => None,
+                };
+
                 selections.push(IndexSelection {
                     index_name: name.clone(),
                     time_range: time_range.clone(),
                     filter: query.filter.clone(),
                     estimated_rows,
+                    filter_selectivity: query
+                        .filter
+                        .as_ref()
+                        .map_or(1.0, |filter| info.estimate_filter_selectivity(filter)),
+                    candidate_blocks,
+                    full_scan: false,
                 });
             }
         }
 
+        // Without a usable index the query is still answered, by reading everything
         if selections.is_empty() {
-            return Err(PlanningError::NoSuitableIndex(
-                "No index can satisfy the query requirements".to_string(),
-            ));
+            selections.push(IndexSelection::full_scan(time_range, query.filter.clone()));
         }
 
         // Sort selections by estimated row count to prefer more selective indexes
@@ -149,6 +257,7 @@ mod tests {
             },
             tag_keys: vec!["region".to_string(), "env".to_string()],
             estimated_rows: 1000,
+            tag_index: None,
         }
     }
 
@@ -173,6 +282,12 @@ mod tests {
             order_by: vec![("value".to_string(), true)],
             limit: Some(10),
             offset: None,
+            format: None,
+            group_order: None,
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
         };
 
         let plan = planner.plan_query(&query).unwrap();
@@ -181,7 +296,7 @@ mod tests {
     }
 
     #[test]
-    fn test_no_suitable_index() {
+    fn test_full_scan_without_indexes() {
         let planner = QueryPlanner::new();
         let query = Query {
             select: vec![],
@@ -194,11 +309,46 @@ mod tests {
             order_by: vec![],
             limit: None,
             offset: None,
+            format: None,
+            group_order: None,
+            time_bucket: None,
+            having: None,
+            explain: false,
+            extra_time_ranges: Vec::new(),
         };
 
-        assert!(matches!(
-            planner.plan_query(&query),
-            Err(PlanningError::NoSuitableIndex(_))
-        ));
+        let plan = planner.plan_query(&query).unwrap();
+        assert_eq!(plan.index_selections.len(), 1);
+        assert_eq!(plan.index_selections[0].index_name, FULL_SCAN);
+        assert!(plan.requires_full_scan());
+
+        // Planning still fails for invalid queries
+        let query = Query { time_range: None, ..query };
+        assert!(matches!(planner.plan_query(&query), Err(PlanningError::InvalidTimeRange(_))));
+    }
+
+    #[test]
+    fn test_explain_reports_plan() {
+        let mut planner = QueryPlanner::new();
+        planner.register_index("test_index".to_string(), create_test_index());
+
+        let tokens = crate::query::parser::Lexer::new(
+            "EXPLAIN SELECT avg(value) FROM metrics WHERE region = 'us-west' LIMIT 5",
+        )
+        .tokenize()
+        .unwrap();
+        let mut query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        assert!(query.explain);
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 1000000000000 });
+
+        let explanation = planner.explain(&query).unwrap();
+        assert_eq!(
+            String::from_utf8(explanation.to_json().unwrap()).unwrap(),
+            concat!(
+                r#"{"indexes":[{"index_name":"test_index","estimated_rows":100,"filter_selectivity":0.1,"#,
+                r#""candidate_blocks":null}],"estimated_rows":100,"full_scan":true,"group_by":[],"#,
+                r#""order_by":[],"limit":5,"offset":null}"#
+            )
+        );
     }
 }
@@ -0,0 +1,408 @@
+//! Tabular query results
+//!
+//! A [`ResultSet`] names its columns and holds one typed [`Row`] per output
+//! point or group, so a row can carry several aggregates and its group key.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, TimestampNanosecondArray};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use serde::{Serialize, Serializer};
+
+use crate::metrics::push_sample;
+use crate::query::aggregation::Group;
+use crate::query::output::{OutputError, OutputFormat, OutputResult};
+use crate::query::parser::ast::Query;
+use crate::query::parser::validator::column_name;
+use crate::storage::data::{DataPoint, Value};
+
+/// Column holding the time range of each row, for queries with several
+pub const RANGE_COLUMN: &str = "range";
+
+/// A single value in a result row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    /// A timestamp or bucket start, in nanoseconds
+    Timestamp(i64),
+    /// A tag value, including group-by key values
+    Tag(String),
+    /// A point value or aggregate output
+    Value(Value),
+    /// A missing tag or an aggregate over no points
+    Null,
+}
+
+impl Cell {
+    /// Returns the cell as a float, when it holds a value
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Cell::Value(value) => Some(value.as_f64()),
+            _ => None,
+        }
+    }
+}
+
+/// Cells serialize as bare JSON values
+///
+/// Whole floats are written as integers so `2.0` reads as `2`; timestamps stay
+/// integers and nulls are `null`.
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Timestamp(timestamp) => serializer.serialize_i64(*timestamp),
+            Cell::Tag(value) => serializer.serialize_str(value),
+            Cell::Value(Value::F64(v)) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => {
+                serializer.serialize_i64(*v as i64)
+            }
+            Cell::Value(value) => value.serialize(serializer),
+            Cell::Null => serializer.serialize_none(),
+        }
+    }
+}
+
+impl From<Option<Value>> for Cell {
+    fn from(value: Option<Value>) -> Self {
+        value.map_or(Cell::Null, Cell::Value)
+    }
+}
+
+/// One row of a result, with a cell per column
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+}
+
+impl Row {
+    /// Returns the cell at a column index
+    pub fn get(&self, index: usize) -> Option<&Cell> {
+        self.cells.get(index)
+    }
+}
+
+/// Named columns and the rows under them
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+impl ResultSet {
+    /// Lays out points as `timestamp`, `value` and one column per tag key,
+    /// with tag keys in sorted order
+    pub fn from_points(points: &[DataPoint]) -> Self {
+        let keys: BTreeSet<&String> = points.iter().flat_map(|point| point.tags().keys()).collect();
+
+        let mut columns = vec!["timestamp".to_string(), "value".to_string()];
+        columns.extend(keys.iter().map(|key| key.to_string()));
+
+        let rows = points
+            .iter()
+            .map(|point| {
+                let mut cells = vec![Cell::Timestamp(point.timestamp()), Cell::Value(point.typed_value())];
+                cells.extend(keys.iter().map(|key| {
+                    point.tags().get(*key).map_or(Cell::Null, |value| Cell::Tag(value.clone()))
+                }));
+                Row { cells }
+            })
+            .collect();
+        ResultSet { columns, rows }
+    }
+
+    /// Lays out projected points as `timestamp` followed by the named columns,
+    /// each holding the point's value
+    pub fn from_projection(names: Vec<String>, points: &[DataPoint]) -> Self {
+        let mut columns = vec!["timestamp".to_string()];
+        columns.extend(names);
+
+        let rows = points
+            .iter()
+            .map(|point| {
+                let mut cells = vec![Cell::Timestamp(point.timestamp())];
+                cells.resize(columns.len(), Cell::Value(point.typed_value()));
+                Row { cells }
+            })
+            .collect();
+        ResultSet { columns, rows }
+    }
+
+    /// Lays out aggregated groups as `time` when grouping by `time()`, then
+    /// the GROUP BY tags, then the aggregate columns named as selected
+    ///
+    /// Queries with several time ranges lead with a `range` column.
+    pub fn from_groups(query: &Query, groups: &[Group]) -> Self {
+        let ranged = !query.extra_time_ranges.is_empty();
+        let mut columns = Vec::new();
+        if ranged {
+            columns.push(RANGE_COLUMN.to_string());
+        }
+        if query.time_bucket.is_some() {
+            columns.push("time".to_string());
+        }
+        columns.extend(query.group_by.iter().cloned());
+        columns.extend(query.select.iter().map(column_name));
+
+        let rows = groups
+            .iter()
+            .map(|group| {
+                let mut cells = Vec::with_capacity(columns.len());
+                if ranged {
+                    cells.push(group.range_index.map_or(Cell::Null, range_cell));
+                }
+                if query.time_bucket.is_some() {
+                    cells.push(group.bucket.map_or(Cell::Null, Cell::Timestamp));
+                }
+                cells.extend(group.key.iter().map(|(_, value)| Cell::Tag(value.clone())));
+                cells.extend(group.columns.iter().map(|(_, value)| Cell::from(*value)));
+                Row { cells }
+            })
+            .collect();
+        ResultSet { columns, rows }
+    }
+
+    /// Prepends a `range` column holding the time range of each row
+    ///
+    /// `range_indices` runs parallel to the rows; an empty slice, as returned
+    /// for single-range queries, leaves the result unchanged.
+    pub fn with_range_indices(mut self, range_indices: &[usize]) -> Self {
+        if range_indices.is_empty() {
+            return self;
+        }
+        self.columns.insert(0, RANGE_COLUMN.to_string());
+        for (row, &range_index) in self.rows.iter_mut().zip(range_indices) {
+            row.cells.insert(0, range_cell(range_index));
+        }
+        self
+    }
+
+    /// Encodes the result in the given format; `metric_name` names Prometheus samples
+    pub fn encode(&self, format: OutputFormat, metric_name: &str) -> OutputResult<Vec<u8>> {
+        match format {
+            OutputFormat::Json => self.to_json(),
+            OutputFormat::Csv => self.to_csv(),
+            OutputFormat::Arrow => self.to_arrow(),
+            OutputFormat::Prometheus => Ok(self.to_prometheus(metric_name).into_bytes()),
+        }
+    }
+
+    /// Encodes the result as `{"columns": [...], "rows": [[...], ...]}`
+    pub fn to_json(&self) -> OutputResult<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// Encodes the result as CSV under a header of the column names; nulls are empty
+    pub fn to_csv(&self) -> OutputResult<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(&self.columns)?;
+        for row in &self.rows {
+            writer.write_record(row.cells.iter().map(|cell| match cell {
+                Cell::Timestamp(timestamp) => timestamp.to_string(),
+                Cell::Tag(value) => value.clone(),
+                Cell::Value(value) => value.to_string(),
+                Cell::Null => String::new(),
+            }))?;
+        }
+        writer.into_inner().map_err(|e| OutputError::Csv(e.into_error().into()))
+    }
+
+    /// Encodes the result as an Arrow IPC stream holding one record batch
+    ///
+    /// Columns of timestamps become nanosecond timestamp columns, columns of
+    /// values are widened to `Float64` and every other column is `Utf8`. All
+    /// columns are nullable.
+    pub fn to_arrow(&self) -> OutputResult<Vec<u8>> {
+        let mut fields = Vec::with_capacity(self.columns.len());
+        let mut arrays: Vec<ArrayRef> = Vec::with_capacity(self.columns.len());
+        for (index, name) in self.columns.iter().enumerate() {
+            let cells = || self.rows.iter().map(move |row| row.get(index).unwrap_or(&Cell::Null));
+            if cells().any(|cell| matches!(cell, Cell::Tag(_))) || cells().all(|cell| *cell == Cell::Null) {
+                fields.push(Field::new(name, DataType::Utf8, true));
+                let values: StringArray = cells()
+                    .map(|cell| match cell {
+                        Cell::Tag(value) => Some(value.clone()),
+                        Cell::Null => None,
+                        Cell::Timestamp(timestamp) => Some(timestamp.to_string()),
+                        Cell::Value(value) => Some(value.to_string()),
+                    })
+                    .collect();
+                arrays.push(Arc::new(values));
+            } else if cells().any(|cell| matches!(cell, Cell::Value(_))) {
+                fields.push(Field::new(name, DataType::Float64, true));
+                let values: Float64Array = cells().map(Cell::as_f64).collect();
+                arrays.push(Arc::new(values));
+            } else {
+                fields.push(Field::new(name, DataType::Timestamp(TimeUnit::Nanosecond, None), true));
+                let values: TimestampNanosecondArray = cells()
+                    .map(|cell| match cell {
+                        Cell::Timestamp(timestamp) => Some(*timestamp),
+                        _ => None,
+                    })
+                    .collect();
+                arrays.push(Arc::new(values));
+            }
+        }
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+        let mut writer = StreamWriter::try_new(Vec::new(), &schema)?;
+        writer.write(&batch)?;
+        writer.finish()?;
+        Ok(writer.into_inner()?)
+    }
+
+    /// Encodes the result as Prometheus samples
+    ///
+    /// Each non-null value of a row is a sample labelled with the row's tags
+    /// and range, stamped with its first timestamp cell or 0 when it has none.
+    /// The `value` column is named `metric_name`; other value columns, such as
+    /// aggregates, are named `metric_name` followed by the column name.
+    pub fn to_prometheus(&self, metric_name: &str) -> String {
+        let mut output = String::new();
+        for row in &self.rows {
+            let mut labels = HashMap::new();
+            let mut timestamp = None;
+            for (name, cell) in self.columns.iter().zip(&row.cells) {
+                match cell {
+                    Cell::Tag(value) => {
+                        labels.insert(name.clone(), value.clone());
+                    }
+                    Cell::Value(value) if name == RANGE_COLUMN => {
+                        labels.insert(name.clone(), value.to_string());
+                    }
+                    Cell::Timestamp(value) => {
+                        timestamp.get_or_insert(*value);
+                    }
+                    _ => {}
+                }
+            }
+            for (name, cell) in self.columns.iter().zip(&row.cells) {
+                let Cell::Value(value) = cell else { continue };
+                if name == RANGE_COLUMN {
+                    continue;
+                }
+                let sample_name = if name == "value" {
+                    metric_name.to_string()
+                } else {
+                    format!("{}_{}", metric_name, name)
+                };
+                let point = DataPoint::with_value(timestamp.unwrap_or_default(), *value, labels.clone());
+                push_sample(&mut output, &sample_name, &point);
+            }
+        }
+        output
+    }
+
+    /// Returns the index of a column by name
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column == name)
+    }
+
+    /// Returns the cell of a row under the named column
+    pub fn cell(&self, row: usize, column: &str) -> Option<&Cell> {
+        let index = self.column_index(column)?;
+        self.rows.get(row)?.get(index)
+    }
+
+    /// Returns the number of rows
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns true if the result has no rows
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+fn range_cell(range_index: usize) -> Cell {
+    Cell::Value(Value::I64(range_index as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_from_points_fills_missing_tags() {
+        let mut tags = HashMap::new();
+        tags.insert("host".to_string(), "a".to_string());
+        let points = vec![
+            DataPoint::with_value(1, Value::I64(3), tags),
+            DataPoint::new(2, 1.5, HashMap::new()),
+        ];
+
+        let result = ResultSet::from_points(&points);
+        assert_eq!(result.columns, vec!["timestamp", "value", "host"]);
+        assert_eq!(result.cell(0, "value"), Some(&Cell::Value(Value::I64(3))));
+        assert_eq!(result.cell(0, "host"), Some(&Cell::Tag("a".to_string())));
+        assert_eq!(result.cell(1, "host"), Some(&Cell::Null));
+    }
+
+    #[test]
+    fn test_grouped_json_schema() {
+        let tokens = crate::query::parser::Lexer::new(
+            "SELECT avg(value) AS mean, count(value) FROM cpu GROUP BY host, time(10ns)",
+        )
+        .tokenize()
+        .unwrap();
+        let query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        let groups = vec![
+            Group {
+                key: vec![("host".to_string(), "a".to_string())],
+                bucket: Some(0),
+                columns: vec![
+                    ("mean".to_string(), Some(Value::F64(2.5))),
+                    ("count(value)".to_string(), Some(Value::F64(2.0))),
+                ],
+                timestamps: vec![None, None],
+                range_index: None,
+            },
+            Group {
+                key: vec![("host".to_string(), "b".to_string())],
+                bucket: Some(10),
+                columns: vec![("mean".to_string(), None), ("count(value)".to_string(), Some(Value::I64(0)))],
+                timestamps: vec![None, None],
+                range_index: None,
+            },
+        ];
+
+        let json = ResultSet::from_groups(&query, &groups).to_json().unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#"{"columns":["time","host","mean","count(value)"],"rows":[[0,"a",2.5,2],[10,"b",null,0]]}"#
+        );
+    }
+
+    #[test]
+    fn test_encodes_grouped_rows() {
+        use arrow_ipc::reader::StreamReader;
+
+        let tokens = crate::query::parser::Lexer::new("SELECT max(value) AS peak FROM cpu GROUP BY host, time(10ns)")
+            .tokenize()
+            .unwrap();
+        let query = crate::query::parser::Parser::new(&tokens).parse().unwrap();
+        let groups = vec![Group {
+            key: vec![("host".to_string(), "a".to_string())],
+            bucket: Some(2_000_000),
+            columns: vec![("peak".to_string(), Some(Value::F64(2.5)))],
+            timestamps: vec![None],
+            range_index: None,
+        }];
+        let result = ResultSet::from_groups(&query, &groups);
+
+        let csv = String::from_utf8(result.to_csv().unwrap()).unwrap();
+        assert_eq!(csv, "time,host,peak\n2000000,a,2.5\n");
+        assert_eq!(result.to_prometheus("cpu"), "cpu_peak{host=\"a\"} 2.5 2\n");
+
+        let arrow = result.to_arrow().unwrap();
+        let batch = StreamReader::try_new(arrow.as_slice(), None).unwrap().next().unwrap().unwrap();
+        let types: Vec<&DataType> = batch.schema_ref().fields().iter().map(|f| f.data_type()).collect();
+        assert_eq!(
+            types,
+            vec![&DataType::Timestamp(TimeUnit::Nanosecond, None), &DataType::Utf8, &DataType::Float64]
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::RwLock;
@@ -15,6 +17,192 @@ pub enum DataError {
     InvalidTagValue(String),
     #[error("Timestamp not strictly increasing")]
     NonIncreasingTimestamp,
+    #[error("Timestamp {0} is already in the series")]
+    DuplicateTimestamp(i64),
+}
+
+/// Characters accepted in series names and tag keys and values
+///
+/// Control characters are rejected under either setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CharacterSet {
+    /// Any valid UTF-8, e.g. `région=île-de-france`
+    #[default]
+    Utf8,
+    /// ASCII only, for deployments whose downstream tooling requires it
+    Ascii,
+}
+
+impl CharacterSet {
+    /// Checks whether every character of `text` is allowed
+    pub fn allows(&self, text: &str) -> bool {
+        !text.chars().any(char::is_control) && (*self == CharacterSet::Utf8 || text.is_ascii())
+    }
+}
+
+/// The type of a point's value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    F64,
+    I64,
+    Bool,
+}
+
+impl ValueType {
+    /// Returns the byte the type is encoded as on disk
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ValueType::F64 => 0,
+            ValueType::I64 => 1,
+            ValueType::Bool => 2,
+        }
+    }
+
+    /// Decodes a type byte written by [`ValueType::to_byte`]
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ValueType::F64),
+            1 => Some(ValueType::I64),
+            2 => Some(ValueType::Bool),
+            _ => None,
+        }
+    }
+}
+
+/// A point's value
+///
+/// Serializes as a bare JSON number or boolean; integers without a fractional
+/// part deserialize as `I64`, so `42` and `42.0` keep their distinct types.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    I64(i64),
+    F64(f64),
+}
+
+impl Value {
+    /// Returns the value's type
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::F64(_) => ValueType::F64,
+            Value::I64(_) => ValueType::I64,
+            Value::Bool(_) => ValueType::Bool,
+        }
+    }
+
+    /// Returns the value as a float; booleans are `1.0` or `0.0`
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Value::F64(v) => v,
+            Value::I64(v) => v as f64,
+            Value::Bool(v) => if v { 1.0 } else { 0.0 },
+        }
+    }
+
+    /// Converts the value to `value_type`, going through `f64` where needed
+    pub fn cast(&self, value_type: ValueType) -> Value {
+        match (value_type, *self) {
+            (ValueType::I64, Value::Bool(v)) => Value::I64(v as i64),
+            (ValueType::I64, Value::F64(v)) => Value::I64(v as i64),
+            (ValueType::Bool, Value::I64(v)) => Value::Bool(v != 0),
+            (ValueType::Bool, Value::F64(v)) => Value::Bool(v != 0.0),
+            (ValueType::F64, v) => Value::F64(v.as_f64()),
+            (_, v) => v,
+        }
+    }
+
+    /// Encodes the value as 8 little-endian bytes
+    pub fn to_le_bytes(self) -> [u8; 8] {
+        match self {
+            Value::F64(v) => v.to_le_bytes(),
+            Value::I64(v) => v.to_le_bytes(),
+            Value::Bool(v) => (v as u64).to_le_bytes(),
+        }
+    }
+
+    /// Decodes 8 bytes written by [`Value::to_le_bytes`] for a value of `value_type`
+    pub fn from_le_bytes(value_type: ValueType, bytes: [u8; 8]) -> Value {
+        match value_type {
+            ValueType::F64 => Value::F64(f64::from_le_bytes(bytes)),
+            ValueType::I64 => Value::I64(i64::from_le_bytes(bytes)),
+            ValueType::Bool => Value::Bool(u64::from_le_bytes(bytes) != 0),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::F64(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::I64(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::F64(v) => write!(f, "{}", v),
+            Value::I64(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Renders a tag set as a canonical series key
+///
+/// Tags are sorted by key in byte order and rendered as `key=value` pairs
+/// joined by `,`, so tag sets that differ only in insertion order produce the
+/// same key. `\`, `,` and `=` inside keys and values are escaped with a
+/// backslash, so distinct tag sets never collide. The MemTable, the SSTable
+/// tag dictionary, the tag index and query output all identify series through
+/// it, see [`series_key`].
+pub fn canonical_series_key(tags: &HashMap<String, String>) -> String {
+    let mut sorted: Vec<(&String, &String)> = tags.iter().collect();
+    sorted.sort_unstable();
+
+    let mut key = String::new();
+    for (i, (name, value)) in sorted.into_iter().enumerate() {
+        if i > 0 {
+            key.push(',');
+        }
+        push_escaped(&mut key, name);
+        key.push('=');
+        push_escaped(&mut key, value);
+    }
+    key
+}
+
+/// Renders the identity of a series: its escaped name, then its tags in
+/// [`canonical_series_key`] form, e.g. `cpu,host=a,region=us`
+pub fn series_key(series_name: &str, tags: &HashMap<String, String>) -> String {
+    let mut key = String::new();
+    push_escaped(&mut key, series_name);
+    if !tags.is_empty() {
+        key.push(',');
+        key.push_str(&canonical_series_key(tags));
+    }
+    key
+}
+
+/// Appends `text`, escaping the delimiters used by [`canonical_series_key`]
+fn push_escaped(output: &mut String, text: &str) {
+    for c in text.chars() {
+        if matches!(c, '\\' | ',' | '=') {
+            output.push('\\');
+        }
+        output.push(c);
+    }
 }
 
 /// Represents a single data point in a time series
@@ -23,7 +211,7 @@ pub struct DataPoint {
     /// Timestamp in nanoseconds since epoch
     timestamp: i64,
     /// The actual value
-    value: f64,
+    value: Value,
     /// Key-value pairs of tags
     tags: HashMap<String, String>,
 }
@@ -31,6 +219,11 @@ pub struct DataPoint {
 impl DataPoint {
     /// Creates a new DataPoint with the given timestamp, value, and tags
     pub fn new(timestamp: i64, value: f64, tags: HashMap<String, String>) -> Self {
+        Self::with_value(timestamp, Value::F64(value), tags)
+    }
+
+    /// Creates a new DataPoint carrying a typed value
+    pub fn with_value(timestamp: i64, value: Value, tags: HashMap<String, String>) -> Self {
         Self {
             timestamp,
             value,
@@ -43,8 +236,13 @@ impl DataPoint {
         self.timestamp
     }
 
-    /// Returns the value
+    /// Returns the value as a float, see [`Value::as_f64`]
     pub fn value(&self) -> f64 {
+        self.value.as_f64()
+    }
+
+    /// Returns the value with its original type
+    pub fn typed_value(&self) -> Value {
         self.value
     }
 
@@ -53,8 +251,26 @@ impl DataPoint {
         &self.tags
     }
 
-    /// Validates the data point
+    /// Estimates the memory footprint of the point in bytes
+    ///
+    /// Counts the timestamp, the value and the bytes of every tag key and value.
+    pub fn estimated_size(&self) -> usize {
+        std::mem::size_of::<i64>()
+            + std::mem::size_of::<f64>()
+            + self
+                .tags
+                .iter()
+                .map(|(key, value)| key.len() + value.len())
+                .sum::<usize>()
+    }
+
+    /// Validates the data point, accepting UTF-8 tags
     pub fn validate(&self) -> Result<(), DataError> {
+        self.validate_with(CharacterSet::Utf8)
+    }
+
+    /// Validates the data point, restricting tags to the given character set
+    pub fn validate_with(&self, charset: CharacterSet) -> Result<(), DataError> {
         // Validate timestamp is positive
         if self.timestamp < 0 {
             return Err(DataError::InvalidTimestamp(format!(
@@ -65,10 +281,10 @@ impl DataPoint {
 
         // Validate tags
         for (key, value) in &self.tags {
-            if !key.chars().all(|c| c.is_ascii()) {
+            if key.is_empty() || !charset.allows(key) {
                 return Err(DataError::InvalidTagKey(key.clone()));
             }
-            if !value.chars().all(|c| c.is_ascii()) {
+            if !charset.allows(value) {
                 return Err(DataError::InvalidTagValue(value.clone()));
             }
         }
@@ -89,18 +305,24 @@ pub struct TimeSeries {
 }
 
 impl TimeSeries {
-    /// Creates a new TimeSeries with the given name
+    /// Creates a new TimeSeries with the given name, which may be any UTF-8
     pub fn new(name: String) -> Result<Self, DataError> {
+        Self::with_character_set(name, CharacterSet::Utf8)
+    }
+
+    /// Creates a new TimeSeries whose name is restricted to the given character set
+    pub fn with_character_set(name: String, charset: CharacterSet) -> Result<Self, DataError> {
         // Validate series name
         if name.is_empty() {
             return Err(DataError::InvalidSeriesName(
                 "Series name cannot be empty".to_string(),
             ));
         }
-        if !name.chars().all(|c| c.is_ascii()) {
-            return Err(DataError::InvalidSeriesName(
-                "Series name must be ASCII-only".to_string(),
-            ));
+        if !charset.allows(&name) {
+            return Err(DataError::InvalidSeriesName(format!(
+                "Series name must not contain control characters{}",
+                if charset == CharacterSet::Ascii { " and must be ASCII-only" } else { "" }
+            )));
         }
 
         Ok(Self {
@@ -136,6 +358,59 @@ impl TimeSeries {
         Ok(())
     }
 
+    /// Adds a batch of data points, keeping the series ordered by timestamp
+    ///
+    /// Unlike [`TimeSeries::add_point`], the batch may be older than points
+    /// already in the series; it is merged into place. With `sort_batch` the
+    /// batch may arrive in any order, otherwise it must be strictly increasing.
+    /// The batch is all-or-nothing: if any point is invalid, shares a
+    /// timestamp with another point of the batch or of the series, or is out
+    /// of order, nothing is added. `last_timestamp` becomes the newest
+    /// timestamp in the series.
+    pub async fn add_points(&self, mut batch: Vec<DataPoint>, sort_batch: bool) -> Result<(), DataError> {
+        for point in &batch {
+            point.validate()?;
+        }
+        if sort_batch {
+            batch.sort_by_key(|point| point.timestamp);
+        }
+        for pair in batch.windows(2) {
+            match pair[0].timestamp.cmp(&pair[1].timestamp) {
+                std::cmp::Ordering::Less => {}
+                std::cmp::Ordering::Equal => return Err(DataError::DuplicateTimestamp(pair[1].timestamp)),
+                std::cmp::Ordering::Greater => return Err(DataError::NonIncreasingTimestamp),
+            }
+        }
+        let Some(batch_max) = batch.last().map(|point| point.timestamp) else {
+            return Ok(());
+        };
+
+        let mut points = self.points.write().await;
+        let mut last_timestamp = self.last_timestamp.write().await;
+        if batch[0].timestamp > *last_timestamp {
+            points.extend(batch);
+        } else {
+            if let Some(point) = batch
+                .iter()
+                .find(|point| points.binary_search_by_key(&point.timestamp, |p| p.timestamp).is_ok())
+            {
+                return Err(DataError::DuplicateTimestamp(point.timestamp));
+            }
+            let existing = std::mem::take(&mut *points);
+            let mut existing = existing.into_iter().peekable();
+            let mut batch = batch.into_iter().peekable();
+            while let (Some(a), Some(b)) = (existing.peek(), batch.peek()) {
+                let next = if a.timestamp < b.timestamp { existing.next() } else { batch.next() };
+                points.extend(next);
+            }
+            points.extend(existing);
+            points.extend(batch);
+        }
+        *last_timestamp = (*last_timestamp).max(batch_max);
+
+        Ok(())
+    }
+
     /// Returns all data points in the time series
     pub async fn points(&self) -> Vec<DataPoint> {
         self.points.read().await.clone()
@@ -168,11 +443,29 @@ mod tests {
             Err(DataError::InvalidTimestamp(_))
         ));
 
-        // Invalid tag key (non-ASCII)
+        // Non-ASCII tags are valid UTF-8
+        let mut utf8_tags = HashMap::new();
+        utf8_tags.insert("région".to_string(), "île-de-france".to_string());
+        let point = DataPoint::new(1000, 42.0, utf8_tags);
+        assert!(point.validate().is_ok());
+
+        // ...unless strict ASCII is requested
+        assert!(matches!(
+            point.validate_with(CharacterSet::Ascii),
+            Err(DataError::InvalidTagKey(_))
+        ));
+
+        // Invalid tag key (empty)
         let mut invalid_tags = HashMap::new();
-        invalid_tags.insert("høst".to_string(), "server1".to_string());
+        invalid_tags.insert("".to_string(), "server1".to_string());
         let point = DataPoint::new(1000, 42.0, invalid_tags);
         assert!(matches!(point.validate(), Err(DataError::InvalidTagKey(_))));
+
+        // Invalid tag value (control character)
+        let mut invalid_tags = HashMap::new();
+        invalid_tags.insert("host".to_string(), "server\n1".to_string());
+        let point = DataPoint::new(1000, 42.0, invalid_tags);
+        assert!(matches!(point.validate(), Err(DataError::InvalidTagValue(_))));
     }
 
     #[test]
@@ -186,9 +479,18 @@ mod tests {
             Err(DataError::InvalidSeriesName(_))
         ));
 
-        // Invalid series name (non-ASCII)
+        // Non-ASCII series names are valid UTF-8
+        assert!(TimeSeries::new("série".to_string()).is_ok());
+
+        // ...unless strict ASCII is requested
+        assert!(matches!(
+            TimeSeries::with_character_set("série".to_string(), CharacterSet::Ascii),
+            Err(DataError::InvalidSeriesName(_))
+        ));
+
+        // Invalid series name (control character)
         assert!(matches!(
-            TimeSeries::new("série".to_string()),
+            TimeSeries::new("cpu\tusage".to_string()),
             Err(DataError::InvalidSeriesName(_))
         ));
     }
@@ -222,4 +524,67 @@ mod tests {
             Err(DataError::NonIncreasingTimestamp)
         ));
     }
+
+    #[test]
+    async fn test_time_series_add_points() {
+        let batch = |timestamps: &[i64]| -> Vec<DataPoint> {
+            timestamps.iter().map(|ts| DataPoint::new(*ts, *ts as f64, HashMap::new())).collect()
+        };
+        let timestamps = |points: Vec<DataPoint>| -> Vec<i64> { points.iter().map(|p| p.timestamp()).collect() };
+
+        // Sorted batches append
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+        series.add_points(batch(&[1000, 2000, 3000]), false).await.unwrap();
+        assert_eq!(timestamps(series.points().await), vec![1000, 2000, 3000]);
+        assert_eq!(series.last_timestamp().await, 3000);
+
+        // Reverse-sorted batches are rejected unless sorting is requested
+        assert!(matches!(
+            series.add_points(batch(&[6000, 5000, 4000]), false).await,
+            Err(DataError::NonIncreasingTimestamp)
+        ));
+        series.add_points(batch(&[6000, 5000, 4000]), true).await.unwrap();
+        assert_eq!(timestamps(series.points().await), vec![1000, 2000, 3000, 4000, 5000, 6000]);
+        assert_eq!(series.last_timestamp().await, 6000);
+
+        // Overlapping batches merge into place, older than the newest point
+        series.add_points(batch(&[1500, 2500, 7000]), false).await.unwrap();
+        assert_eq!(
+            timestamps(series.points().await),
+            vec![1000, 1500, 2000, 2500, 3000, 4000, 5000, 6000, 7000]
+        );
+        assert_eq!(series.last_timestamp().await, 7000);
+
+        // A batch colliding with a stored timestamp is rejected whole
+        assert!(matches!(
+            series.add_points(batch(&[500, 2000]), true).await,
+            Err(DataError::DuplicateTimestamp(2000))
+        ));
+        assert!(matches!(
+            series.add_points(batch(&[8000, 8000]), true).await,
+            Err(DataError::DuplicateTimestamp(8000))
+        ));
+        assert_eq!(series.points().await.len(), 9);
+    }
+
+    #[test]
+    async fn test_canonical_series_key() {
+        let mut forward = HashMap::new();
+        forward.insert("a".to_string(), "1".to_string());
+        forward.insert("b".to_string(), "2".to_string());
+        let mut reverse = HashMap::new();
+        reverse.insert("b".to_string(), "2".to_string());
+        reverse.insert("a".to_string(), "1".to_string());
+        assert_eq!(canonical_series_key(&forward), "a=1,b=2");
+        assert_eq!(canonical_series_key(&forward), canonical_series_key(&reverse));
+
+        // Delimiters inside values are escaped, so these two tag sets stay distinct
+        let mut embedded = HashMap::new();
+        embedded.insert("a".to_string(), "1,b=2".to_string());
+        assert_eq!(canonical_series_key(&embedded), r"a=1\,b\=2");
+        assert_ne!(canonical_series_key(&embedded), canonical_series_key(&forward));
+        let mut backslash = HashMap::new();
+        backslash.insert("path".to_string(), r"c:\tmp".to_string());
+        assert_eq!(canonical_series_key(&backslash), r"path=c:\\tmp");
+    }
 }
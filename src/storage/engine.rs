@@ -0,0 +1,925 @@
+//! Storage engine tying the write path together
+//!
+//! [`StorageEngine::ingest`] logs a point to the WAL, inserts it into the
+//! MemTable and flushes the MemTable to an SSTable once it fills, so callers no
+//! longer coordinate the WAL, MemTable, flush manager and catalog themselves.
+//! [`StorageEngine::shutdown`] makes everything written so far durable before
+//! the process exits.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+
+use crate::query::continuous::{ContinuousQueryError, ContinuousQueryManager};
+use crate::storage::data::{DataPoint, TimeSeries};
+use crate::storage::last_value::LastValueCache;
+use crate::storage::lsm::cache::{BlockCache, BlockCacheConfig};
+use crate::storage::lsm::catalog::SSTableCatalog;
+use crate::storage::lsm::compaction::Compactor;
+use crate::storage::lsm::flush::{FlushError, FlushManager};
+use crate::storage::lsm::memtable::{MemTable, MemTableError};
+use crate::storage::lsm::query::{Query as RouterQuery, QueryRouter};
+use crate::storage::lsm::recovery::{recover_from_wal, RecoveryError};
+use crate::storage::lsm::sstable::{SSTable, SSTableError};
+use crate::storage::lsm::tombstone;
+use crate::storage::recent_events::RecentEvents;
+use crate::storage::tag_index::{TagIndex, TagIndexError};
+use crate::storage::wal::{WalError, WriteAheadLog};
+
+/// Error type for storage engine operations
+#[derive(Debug, thiserror::Error)]
+pub enum EngineError {
+    #[error("WAL error: {0}")]
+    Wal(#[from] WalError),
+    #[error("MemTable error: {0}")]
+    MemTable(#[from] MemTableError),
+    #[error("Flush error: {0}")]
+    Flush(#[from] FlushError),
+    #[error("Catalog error: {0}")]
+    Catalog(#[from] SSTableError),
+    #[error("Recovery error: {0}")]
+    Recovery(#[from] RecoveryError),
+    #[error("Tag index error: {0}")]
+    TagIndex(#[from] TagIndexError),
+    #[error("Continuous query error: {0}")]
+    ContinuousQuery(#[from] ContinuousQueryError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Storage engine is shutting down")]
+    ShuttingDown,
+    #[error("Shutdown timed out after {0:?}")]
+    ShutdownTimeout(Duration),
+}
+
+/// Storage engine configuration
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// Whether SSTables are read through a memory map once sealed, see
+    /// [`SSTable::with_mmap_reads`]
+    pub mmap_reads: bool,
+    /// Cache of decoded blocks shared by every SSTable the engine opens,
+    /// flushes or compacts
+    pub block_cache: BlockCacheConfig,
+    /// Points kept per series for [`StorageEngine::recent_events`], or `None`
+    /// to keep no recent events
+    pub recent_events: Option<usize>,
+    /// Whether the latest point of each series is cached for
+    /// [`StorageEngine::last_values`]
+    pub last_values: bool,
+}
+
+/// Owns the WAL, MemTable, flush manager and catalog of one database
+pub struct StorageEngine {
+    wal: Arc<WriteAheadLog>,
+    memtable: Arc<RwLock<MemTable>>,
+    /// Flushed SSTables, shared with query executors and routers
+    sstables: Arc<RwLock<Vec<Arc<SSTable>>>>,
+    flush_manager: Mutex<FlushManager>,
+    catalog: SSTableCatalog,
+    /// Directory the SSTables live in
+    sstable_dir: PathBuf,
+    config: EngineConfig,
+    /// Block cache attached to the engine's SSTables, if the config enables one
+    block_cache: Option<Arc<BlockCache>>,
+    /// Tag postings of the SSTables' blocks, kept current by flushes and the
+    /// engine's compactor and saved alongside the catalog
+    tag_index: Arc<RwLock<TagIndex>>,
+    /// Continuous queries fed every stored point
+    continuous_queries: Option<Arc<ContinuousQueryManager>>,
+    /// Newest points of each series, if the config enables them
+    recent_events: Option<RecentEvents>,
+    /// Latest point of each series, if the config enables it
+    last_values: Option<LastValueCache>,
+    /// Whether writes are accepted; ingests hold it shared for the WAL write
+    /// and MemTable insert, flushes hold it exclusively while sealing the WAL
+    /// segment and freezing the MemTable, so no point is split between the two
+    write_gate: RwLock<bool>,
+}
+
+impl StorageEngine {
+    /// Creates an engine from its parts
+    ///
+    /// `sstables` should hold the tables already in the catalog, so queries
+    /// through [`StorageEngine::sstables`] see them.
+    pub fn new(
+        wal: Arc<WriteAheadLog>,
+        memtable: Arc<RwLock<MemTable>>,
+        sstables: Arc<RwLock<Vec<Arc<SSTable>>>>,
+        flush_manager: FlushManager,
+        catalog: SSTableCatalog,
+    ) -> Self {
+        let (flush_manager, tag_index) = match flush_manager.tag_index() {
+            Some(tag_index) => (flush_manager, tag_index),
+            None => {
+                let tag_index = Arc::new(RwLock::new(TagIndex::new()));
+                (flush_manager.with_tag_index(Arc::clone(&tag_index)), tag_index)
+            }
+        };
+        Self {
+            wal,
+            memtable,
+            sstables,
+            sstable_dir: flush_manager.sstable_dir().to_path_buf(),
+            flush_manager: Mutex::new(flush_manager),
+            catalog,
+            config: EngineConfig::default(),
+            block_cache: None,
+            tag_index,
+            continuous_queries: None,
+            recent_events: None,
+            last_values: None,
+            write_gate: RwLock::new(true),
+        }
+    }
+
+    /// Opens the database in `data_dir`, recovering what a previous run left
+    ///
+    /// The WAL lives in `data_dir/wal` and SSTables in `data_dir/sstables`.
+    /// Every SSTable in the catalog is opened, then the WAL segments not yet
+    /// checkpointed are replayed into `memtable`. The replayed points are
+    /// flushed and the catalog manifest saved before the replayed segments are
+    /// checkpointed, so a crash after `open` never replays them a second time.
+    pub async fn open<P: AsRef<Path>>(data_dir: P, memtable: MemTable) -> Result<Self, EngineError> {
+        Self::open_with_config(data_dir, memtable, EngineConfig::default()).await
+    }
+
+    /// Opens the database in `data_dir` like [`StorageEngine::open`], with a custom configuration
+    pub async fn open_with_config<P: AsRef<Path>>(
+        data_dir: P,
+        memtable: MemTable,
+        config: EngineConfig,
+    ) -> Result<Self, EngineError> {
+        let sstable_dir = data_dir.as_ref().join("sstables");
+        std::fs::create_dir_all(&sstable_dir)?;
+        let catalog = SSTableCatalog::load(&sstable_dir).await?;
+        let block_cache = BlockCache::from_config(&config.block_cache);
+        let mut sstables = Vec::new();
+        for info in catalog.get_all_tables().await {
+            let mut sstable = SSTable::open(&info.path)?.with_mmap_reads(config.mmap_reads);
+            if let Some(cache) = &block_cache {
+                sstable = sstable.with_block_cache(Arc::clone(cache));
+            }
+            sstables.push(Arc::new(sstable));
+        }
+
+        // Bring the saved tag index up to date with the catalog
+        let mut tag_index = TagIndex::load(&sstable_dir)?;
+        tag_index.retain_sstables(&sstables);
+        let mut builder = TagIndex::builder().with_index(tag_index);
+        for sstable in &sstables {
+            builder = builder.add_sstable(sstable).await?;
+        }
+        let tag_index = builder.build();
+        tag_index.save(&sstable_dir)?;
+        let tag_index = Arc::new(RwLock::new(tag_index));
+
+        let wal = WriteAheadLog::new(data_dir.as_ref().join("wal"))?;
+        let replayed = wal.segments()?.last().map(|segment| segment.id);
+        let memtable = Arc::new(RwLock::new(memtable));
+        let mut flush_manager = FlushManager::new(sstable_dir)
+            .with_mmap_reads(config.mmap_reads)
+            .with_tag_index(Arc::clone(&tag_index));
+        if let Some(cache) = &block_cache {
+            flush_manager = flush_manager.with_block_cache(Arc::clone(cache));
+        }
+        let outcome = recover_from_wal(&wal, Arc::clone(&memtable), &mut flush_manager, &catalog).await?;
+        // Normally recorded when the delete ran; redone in case a crash lost
+        // the SSTable a delete during a flush was meant to land on
+        for deleted in &outcome.deletes {
+            tombstone::add_to_overlapping(&sstables, deleted).await?;
+        }
+        sstables.extend(outcome.sstables);
+
+        let mut engine = Self::new(
+            Arc::new(wal),
+            memtable,
+            Arc::new(RwLock::new(sstables)),
+            flush_manager,
+            catalog,
+        );
+        engine.block_cache = block_cache;
+        if let Some(segment) = replayed {
+            engine.flush().await?;
+            engine.catalog.save().await?;
+            engine.wal.checkpoint(segment).await?;
+        }
+        if let Some(capacity) = config.recent_events {
+            let recent_events = RecentEvents::new(capacity);
+            if let Some(newest) = engine.sstables.read().await.last() {
+                recent_events.load_sstable(newest).await;
+            }
+            engine.recent_events = Some(recent_events);
+        }
+        if config.last_values {
+            let last_values = LastValueCache::new();
+            if let Some(newest) = engine.sstables.read().await.last() {
+                last_values.load_sstable(newest).await;
+            }
+            engine.last_values = Some(last_values);
+        }
+        engine.config = config;
+        Ok(engine)
+    }
+
+    /// Feeds every point stored from now on to the given continuous queries
+    ///
+    /// Their open intervals are written before the MemTable is flushed on
+    /// [`StorageEngine::shutdown`].
+    pub fn with_continuous_queries(mut self, manager: Arc<ContinuousQueryManager>) -> Self {
+        self.continuous_queries = Some(manager);
+        self
+    }
+
+    /// Returns the MemTable, for building query executors
+    ///
+    /// Writes must go through [`StorageEngine::ingest`] or
+    /// [`StorageEngine::ingest_batch`], which log them and flush a full MemTable.
+    pub fn memtable(&self) -> Arc<RwLock<MemTable>> {
+        Arc::clone(&self.memtable)
+    }
+
+    /// Returns the flushed SSTables, for building query executors
+    pub fn sstables(&self) -> Arc<RwLock<Vec<Arc<SSTable>>>> {
+        Arc::clone(&self.sstables)
+    }
+
+    /// Returns the newest points of each series, if [`EngineConfig::recent_events`] is set
+    ///
+    /// Every stored point is recorded; on open the buffer is rebuilt from the
+    /// newest SSTable.
+    pub fn recent_events(&self) -> Option<RecentEvents> {
+        self.recent_events.clone()
+    }
+
+    /// Returns the latest point of each series, if [`EngineConfig::last_values`] is set
+    ///
+    /// Every stored point updates it, a delete covering a series' latest point
+    /// falls back to the newest remaining one, and on open it is reloaded from
+    /// the newest SSTable.
+    pub fn last_values(&self) -> Option<LastValueCache> {
+        self.last_values.clone()
+    }
+
+    /// Returns the block cache shared by the engine's SSTables, if enabled
+    pub fn block_cache(&self) -> Option<Arc<BlockCache>> {
+        self.block_cache.clone()
+    }
+
+    /// Returns the tag index over the engine's SSTables
+    pub fn tag_index(&self) -> Arc<RwLock<TagIndex>> {
+        Arc::clone(&self.tag_index)
+    }
+
+    /// Returns a compactor writing to the engine's SSTable directory
+    ///
+    /// Merged tables share the engine's block cache and mmap setting, and
+    /// compactions keep the engine's tag index current.
+    pub fn compactor(&self) -> Compactor {
+        let mut compactor = Compactor::new(self.sstable_dir.clone())
+            .with_mmap_reads(self.config.mmap_reads)
+            .with_tag_index(Arc::clone(&self.tag_index));
+        if let Some(cache) = &self.block_cache {
+            compactor = compactor.with_block_cache(Arc::clone(cache));
+        }
+        compactor
+    }
+
+    /// Returns the catalog of flushed SSTables
+    pub fn catalog(&self) -> &SSTableCatalog {
+        &self.catalog
+    }
+
+    /// Durably stores a point
+    ///
+    /// The point is written to the WAL before it is inserted into the MemTable,
+    /// so it survives a crash once this returns. A point the MemTable would
+    /// reject is refused before it is logged. A MemTable filled by the insert
+    /// is flushed before returning.
+    pub async fn ingest(&self, series: &TimeSeries, point: &DataPoint) -> Result<(), EngineError> {
+        let accepting = self.write_gate.read().await;
+        if !*accepting {
+            return Err(EngineError::ShuttingDown);
+        }
+        let memtable = self.memtable.read().await;
+        memtable.check_batch(&[(series, point)]).await?;
+        self.wal.write(series, point).await?;
+        let needs_flush = memtable.insert(series, point).await?;
+        drop(memtable);
+        self.observe(&[(series, point)]).await;
+        drop(accepting);
+
+        if needs_flush {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Durably deletes the points of `series` in `start..=end`
+    ///
+    /// The deletion is logged to the WAL first, so a restart does not replay
+    /// the deleted points back. Returns the number of points removed from the
+    /// MemTable; flushed points are hidden by tombstones, see
+    /// [`tombstone::delete_range`].
+    pub async fn delete_range(&self, series: &str, start: i64, end: i64) -> Result<usize, EngineError> {
+        let accepting = self.write_gate.read().await;
+        if !*accepting {
+            return Err(EngineError::ShuttingDown);
+        }
+        self.wal.write_delete(series, start, end).await?;
+        // Held across the MemTable delete, so a flush publishing its SSTable
+        // meanwhile either is in the list or receives the deletion on release
+        let sstables = self.sstables.read().await;
+        let removed = tombstone::delete_range(&*self.memtable.read().await, &sstables, series, start, end).await?;
+        drop(sstables);
+        if let Some(last_values) = &self.last_values {
+            if last_values.invalidate_range(series, start, end).await {
+                self.reload_last_value(last_values, series).await;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Caches the newest point of `series` left in the MemTable and SSTables
+    ///
+    /// Scans the whole series; the points are already deleted, so a scan that
+    /// fails is logged and leaves the series uncached.
+    async fn reload_last_value(&self, last_values: &LastValueCache, series: &str) {
+        let router = QueryRouter::new(self.memtable(), self.sstables());
+        let query = RouterQuery::with_series(i64::MIN, i64::MAX, series.to_string());
+        match router.route_query(&query).await {
+            Ok(points) => {
+                if let Some(latest) = points.iter().max_by_key(|point| point.timestamp()) {
+                    last_values.update(series, latest).await;
+                }
+            }
+            Err(e) => warn!("Failed to reload the last value of {}: {}", series, e),
+        }
+    }
+
+    /// Durably stores a batch of points with a single WAL append
+    ///
+    /// Like [`StorageEngine::ingest`], the points are in the WAL before they
+    /// are inserted into the MemTable, and a MemTable filled by the batch is
+    /// flushed before returning. If the MemTable would reject any point, see
+    /// [`MemTable::check_batch`], nothing is logged or inserted.
+    pub async fn ingest_batch(&self, entries: &[(&TimeSeries, &DataPoint)]) -> Result<(), EngineError> {
+        let accepting = self.write_gate.read().await;
+        if !*accepting {
+            return Err(EngineError::ShuttingDown);
+        }
+        let memtable = self.memtable.read().await;
+        memtable.check_batch(entries).await?;
+        self.wal.write_batch(entries).await?;
+        let mut needs_flush = false;
+        for (series, point) in entries {
+            needs_flush |= memtable.insert(series, point).await?;
+        }
+        drop(memtable);
+        self.observe(entries).await;
+        drop(accepting);
+
+        if needs_flush {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Hands stored points to the last values, recent events and continuous queries
+    ///
+    /// The points are already durable, so a rollup that cannot be written is
+    /// logged rather than failing the write.
+    async fn observe(&self, entries: &[(&TimeSeries, &DataPoint)]) {
+        if let Some(last_values) = &self.last_values {
+            for (series, point) in entries {
+                last_values.update(series.name(), point).await;
+            }
+        }
+        if let Some(recent_events) = &self.recent_events {
+            for (series, point) in entries {
+                recent_events.record(series.name(), point).await;
+            }
+        }
+        let Some(manager) = &self.continuous_queries else { return };
+        for (series, point) in entries {
+            if let Err(e) = manager.observe(series.name(), point).await {
+                warn!("Continuous queries failed to observe a point of {}: {}", series.name(), e);
+            }
+        }
+    }
+
+    /// Flushes the MemTable to a new SSTable and records it in the catalog
+    ///
+    /// The WAL segment being written is sealed first, and deleted once the
+    /// SSTable and the catalog manifest are on disk. Returns the new SSTable,
+    /// or `None` if the MemTable was empty.
+    pub async fn flush(&self) -> Result<Option<Arc<SSTable>>, EngineError> {
+        let mut flush_manager = self.flush_manager.lock().await;
+        {
+            let memtable = self.memtable.read().await;
+            if memtable.is_empty().await && !memtable.is_flushing().await {
+                return Ok(None);
+            }
+        }
+
+        let sealed = {
+            let _writes = self.write_gate.write().await;
+            let sealed = self.wal.rotate().await?;
+            flush_manager
+                .start_flush(Arc::clone(&self.memtable))
+                .await?;
+            sealed
+        };
+        let Some(sstable) = flush_manager.wait_for_flush().await? else {
+            return Ok(None);
+        };
+
+        self.catalog.add_table(&sstable).await?;
+        self.catalog.save().await?;
+        self.tag_index.read().await.save(&self.sstable_dir)?;
+        self.sstables.write().await.push(Arc::clone(&sstable));
+        self.memtable.read().await.release_flushed(&sstable).await?;
+        if let Some(segment) = sealed {
+            self.wal.checkpoint(segment).await?;
+        }
+
+        info!("Flushed MemTable to {}", sstable.path.display());
+        Ok(Some(sstable))
+    }
+
+    /// Removes the SSTables and WAL segments that have outlived the catalog's retention
+    ///
+    /// Does nothing unless a retention was set with
+    /// [`SSTableCatalog::set_retention`]. Returns the number of SSTables removed.
+    pub async fn enforce_retention(&self) -> Result<usize, EngineError> {
+        let now = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        let Some(cutoff) = self.catalog.retention_cutoff(now) else {
+            return Ok(0);
+        };
+
+        let expired = self.catalog.apply_retention(now).await?;
+        self.sstables.write().await.retain(|table| {
+            let keep = !expired.iter().any(|info| info.path == table.path);
+            if !keep {
+                table.evict_cached_blocks();
+                table.unmap();
+            }
+            keep
+        });
+        self.wal.expire_before(cutoff).await?;
+        Ok(expired.len())
+    }
+
+    /// Enforces the retention every `interval` until the returned task is aborted
+    pub fn spawn_retention(self: &Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let engine = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = engine.enforce_retention().await {
+                    warn!("Failed to enforce retention: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Stops accepting writes and makes everything written so far durable
+    ///
+    /// Waits for in-flight ingests, writes the open intervals of continuous
+    /// queries, then syncs the WAL, flushes the MemTable and saves the catalog
+    /// manifest. Gives up with
+    /// [`EngineError::ShutdownTimeout`] if that takes longer than `timeout`;
+    /// whatever was not flushed is still in the WAL. Ingests fail with
+    /// [`EngineError::ShuttingDown`] once this is called.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), EngineError> {
+        let durable = async {
+            *self.write_gate.write().await = false;
+            if let Some(manager) = &self.continuous_queries {
+                manager.flush().await?;
+            }
+            // Synced before the flush checkpoints the segments it covers
+            self.wal.sync().await?;
+            self.flush().await?;
+            self.catalog.save().await?;
+            Ok::<(), EngineError>(())
+        };
+        tokio::time::timeout(timeout, durable)
+            .await
+            .map_err(|_| EngineError::ShutdownTimeout(timeout))??;
+        info!("Storage engine shut down");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::executor::{ExecutionConfig, QueryExecutor};
+    use crate::query::parser::ast::{Query, TimeRange};
+    use crate::storage::lsm::sstable::DataBlock;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_ingest_flushes_full_memtable() {
+        let dir = tempdir().unwrap();
+        let sstable_dir = dir.path().join("sstables");
+        std::fs::create_dir_all(&sstable_dir).unwrap();
+        let engine = StorageEngine::new(
+            Arc::new(WriteAheadLog::new(dir.path().join("wal")).unwrap()),
+            Arc::new(RwLock::new(MemTable::new(3))),
+            Arc::new(RwLock::new(Vec::new())),
+            FlushManager::new(sstable_dir.clone()),
+            SSTableCatalog::new(&sstable_dir),
+        );
+
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        for timestamp in 1..=4 {
+            let point = DataPoint::new(timestamp * 1000, timestamp as f64, HashMap::new());
+            engine.ingest(&series, &point).await.unwrap();
+        }
+
+        // The third point filled the MemTable, the fourth is still in memory
+        assert_eq!(engine.sstables().read().await.len(), 1);
+        assert_eq!(engine.memtable().read().await.size().await, 1);
+        assert_eq!(engine.catalog().total_points().await, 3);
+        assert_eq!(
+            SSTableCatalog::load(&sstable_dir)
+                .await
+                .unwrap()
+                .total_points()
+                .await,
+            3
+        );
+
+        let executor = QueryExecutor::new(
+            engine.memtable(),
+            engine.sstables(),
+            ExecutionConfig::default(),
+        );
+        let mut query = Query::new();
+        query.from = "cpu".to_string();
+        query.time_range = Some(TimeRange::Absolute {
+            start: 0,
+            end: 10_000,
+        });
+        let values: Vec<f64> = executor
+            .execute(&query)
+            .await
+            .unwrap()
+            .points
+            .iter()
+            .map(|p| p.value())
+            .collect();
+        assert_eq!(values, vec![1.0, 2.0, 3.0, 4.0]);
+
+        // Only the points written since the flush remain in the WAL
+        let mut replayed = Vec::new();
+        WriteAheadLog::new(dir.path().join("wal"))
+            .unwrap()
+            .replay(|_, point| {
+                replayed.push(point.timestamp());
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(replayed, vec![4000]);
+    }
+
+    #[tokio::test]
+    async fn test_deletes_survive_restart() {
+        let dir = tempdir().unwrap();
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        {
+            let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+            for timestamp in 1..=4 {
+                let point = DataPoint::new(timestamp * 1000, timestamp as f64, HashMap::new());
+                engine.ingest(&series, &point).await.unwrap();
+            }
+            assert_eq!(engine.delete_range("cpu", 2000, 3000).await.unwrap(), 2);
+            assert!(engine.wal.verify().unwrap());
+        }
+
+        // Reopened without a shutdown, so everything comes back from the WAL
+        let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+        let timestamps: Vec<i64> = engine.sstables().read().await[0]
+            .scan_blocks()
+            .await
+            .iter()
+            .flat_map(|block| block.to_points())
+            .map(|(_, p)| p.timestamp())
+            .collect();
+        assert_eq!(timestamps, vec![1000, 4000]);
+    }
+
+    #[tokio::test]
+    async fn test_mmap_reads_serve_flushed_and_reopened_tables() {
+        let dir = tempdir().unwrap();
+        let config = EngineConfig {
+            mmap_reads: true,
+            ..Default::default()
+        };
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        let scan = |sstables: Vec<Arc<SSTable>>| async move {
+            let mut timestamps = Vec::new();
+            for sstable in sstables {
+                for block in sstable.try_scan_blocks_where(|_| true).await.unwrap() {
+                    timestamps.extend(block.to_points().into_iter().map(|(_, p)| p.timestamp()));
+                }
+            }
+            timestamps.sort_unstable();
+            timestamps
+        };
+        {
+            let engine = StorageEngine::open_with_config(dir.path(), MemTable::new(2), config.clone())
+                .await
+                .unwrap();
+            for timestamp in 1..=4 {
+                let point = DataPoint::new(timestamp * 1000, timestamp as f64, HashMap::new());
+                engine.ingest(&series, &point).await.unwrap();
+            }
+            let flushed = engine.sstables().read().await.clone();
+            assert!(matches!(
+                flushed[0].write_block(DataBlock::from_points("cpu", &[])).await,
+                Err(SSTableError::Sealed)
+            ));
+            assert_eq!(scan(flushed).await, vec![1000, 2000, 3000, 4000]);
+        }
+
+        let engine = StorageEngine::open_with_config(dir.path(), MemTable::new(2), config).await.unwrap();
+        let reopened = engine.sstables().read().await.clone();
+        assert_eq!(scan(reopened).await, vec![1000, 2000, 3000, 4000]);
+    }
+
+    #[tokio::test]
+    async fn test_block_cache_serves_opened_flushed_and_compacted_tables() {
+        let dir = tempdir().unwrap();
+        let config = EngineConfig {
+            block_cache: BlockCacheConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        // Once every block has been read, reading them again only hits the cache
+        let read_twice = |engine: &StorageEngine, sstables: Vec<Arc<SSTable>>| {
+            let cache = engine.block_cache().unwrap();
+            async move {
+                for sstable in &sstables {
+                    sstable.try_scan_blocks_where(|_| true).await.unwrap();
+                }
+                let (hits, misses) = (cache.hits(), cache.misses());
+                let mut blocks = 0;
+                for sstable in &sstables {
+                    sstable.try_scan_blocks_where(|_| true).await.unwrap();
+                    blocks += sstable.metadata.read().await.blocks.len() as u64;
+                }
+                assert_eq!(cache.misses(), misses);
+                assert_eq!(cache.hits() - hits, blocks);
+            }
+        };
+        {
+            let engine = StorageEngine::open_with_config(dir.path(), MemTable::new(2), config.clone())
+                .await
+                .unwrap();
+            for timestamp in 1..=4 {
+                let point = DataPoint::new(timestamp * 1000, timestamp as f64, HashMap::new());
+                engine.ingest(&series, &point).await.unwrap();
+            }
+            let flushed = engine.sstables().read().await.clone();
+            read_twice(&engine, flushed.clone()).await;
+
+            let compacted = engine.compactor().compact(&flushed, &flushed).await.unwrap();
+            read_twice(&engine, vec![compacted]).await;
+        }
+
+        let engine = StorageEngine::open_with_config(dir.path(), MemTable::new(2), config).await.unwrap();
+        let reopened = engine.sstables().read().await.clone();
+        read_twice(&engine, reopened).await;
+    }
+
+    #[tokio::test]
+    async fn test_tag_index_follows_flushes_compactions_and_reopens() {
+        use crate::query::parser::ast::{FilterExpr, TagFilter, TagFilterOp};
+
+        let dir = tempdir().unwrap();
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        let west = FilterExpr::TagFilter(TagFilter {
+            key: "region".to_string(),
+            op: TagFilterOp::Eq,
+            value: "us-west".to_string(),
+        });
+        let tables_matching = |engine: &StorageEngine| {
+            let tag_index = engine.tag_index();
+            let west = west.clone();
+            async move {
+                let blocks = tag_index.read().await.lookup(&west).unwrap();
+                blocks.into_iter().map(|block| block.table).collect::<std::collections::BTreeSet<_>>()
+            }
+        };
+        let file_name = |sstable: &SSTable| sstable.path.file_name().unwrap().to_string_lossy().into_owned();
+        {
+            let engine = StorageEngine::open(dir.path(), MemTable::new(2)).await.unwrap();
+            for timestamp in 1..=4 {
+                let tags = HashMap::from([("region".to_string(), "us-west".to_string())]);
+                engine.ingest(&series, &DataPoint::new(timestamp * 1000, 1.0, tags)).await.unwrap();
+            }
+            let flushed = engine.sstables().read().await.clone();
+            assert_eq!(tables_matching(&engine).await, flushed.iter().map(|t| file_name(t)).collect());
+
+            let compacted = engine.compactor().compact(&flushed, &flushed).await.unwrap();
+            assert_eq!(tables_matching(&engine).await, [file_name(&compacted)].into());
+        }
+
+        // The saved index names the compacted table, which the catalog does
+        // not know about; reopening drops it and indexes the catalog's tables
+        let engine = StorageEngine::open(dir.path(), MemTable::new(2)).await.unwrap();
+        let reopened = engine.sstables().read().await.clone();
+        assert_eq!(tables_matching(&engine).await, reopened.iter().map(|t| file_name(t)).collect());
+    }
+
+    #[tokio::test]
+    async fn test_recent_events_survive_reopen() {
+        let dir = tempdir().unwrap();
+        let config = EngineConfig {
+            recent_events: Some(2),
+            ..Default::default()
+        };
+        let series = TimeSeries::new("deploys".to_string()).unwrap();
+        let latest = |events: Vec<DataPoint>| events.iter().map(|p| p.timestamp()).collect::<Vec<_>>();
+        {
+            let engine = StorageEngine::open_with_config(dir.path(), MemTable::new(100), config.clone())
+                .await
+                .unwrap();
+            for timestamp in [1000, 2000, 3000] {
+                engine
+                    .ingest(&series, &DataPoint::new(timestamp, 1.0, HashMap::new()))
+                    .await
+                    .unwrap();
+            }
+            let events = engine.recent_events().unwrap();
+            assert_eq!(latest(events.latest_events("deploys", 5).await), vec![3000, 2000]);
+            engine.shutdown(Duration::from_secs(5)).await.unwrap();
+        }
+
+        let engine = StorageEngine::open_with_config(dir.path(), MemTable::new(100), config)
+            .await
+            .unwrap();
+        let events = engine.recent_events().unwrap();
+        assert_eq!(latest(events.latest_events("deploys", 5).await), vec![3000, 2000]);
+
+        let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+        assert!(engine.recent_events().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_last_values_follow_ingest_and_delete() {
+        let dir = tempdir().unwrap();
+        let config = EngineConfig {
+            last_values: true,
+            ..Default::default()
+        };
+        let engine = StorageEngine::open_with_config(dir.path(), MemTable::new(100), config)
+            .await
+            .unwrap();
+        let cpu = TimeSeries::new("cpu".to_string()).unwrap();
+        let mem = TimeSeries::new("mem".to_string()).unwrap();
+        let points = [DataPoint::new(1000, 1.0, HashMap::new()), DataPoint::new(2000, 2.0, HashMap::new())];
+        engine.ingest_batch(&[(&cpu, &points[0]), (&mem, &points[1])]).await.unwrap();
+        // The older point is only left in an SSTable
+        engine.flush().await.unwrap();
+        engine.ingest(&cpu, &points[1]).await.unwrap();
+
+        let last_values = engine.last_values().unwrap();
+        assert_eq!(last_values.get("cpu").await.unwrap().timestamp(), 2000);
+
+        // Deleting the newest point falls back to the one before it
+        engine.delete_range("cpu", 1500, 2500).await.unwrap();
+        assert_eq!(last_values.get("cpu").await.unwrap().timestamp(), 1000);
+        assert_eq!(last_values.get("mem").await.unwrap().value(), 2.0);
+
+        engine.delete_range("cpu", 0, 2500).await.unwrap();
+        assert!(last_values.get("cpu").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tag_order_does_not_split_series() {
+        let dir = tempdir().unwrap();
+        let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        let tags = |pairs: &[(&str, &str)]| {
+            let mut tags = HashMap::with_capacity(pairs.len());
+            for (key, value) in pairs {
+                tags.insert(key.to_string(), value.to_string());
+            }
+            tags
+        };
+        let points = [
+            DataPoint::new(1000, 1.0, tags(&[("host", "a"), ("region", "us")])),
+            DataPoint::new(2000, 2.0, tags(&[("region", "us"), ("host", "a")])),
+            DataPoint::new(3000, 3.0, tags(&[("region", "eu"), ("host", "a")])),
+        ];
+        for point in &points {
+            engine.ingest(&series, point).await.unwrap();
+        }
+
+        let memtable = engine.memtable();
+        let keys: Vec<String> = memtable.read().await.series_keys().await.into_iter().collect();
+        assert_eq!(keys, vec!["cpu,host=a,region=eu", "cpu,host=a,region=us"]);
+
+        engine.flush().await.unwrap();
+        assert_eq!(engine.tag_index().read().await.series_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_feeds_continuous_queries() {
+        use crate::query::continuous::{ContinuousQuery, RollupFunction};
+
+        let dir = tempdir().unwrap();
+        let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+        let manager = Arc::new(ContinuousQueryManager::new(engine.memtable()));
+        manager
+            .register(ContinuousQuery {
+                name: "cpu_sum".to_string(),
+                source: "cpu".to_string(),
+                function: RollupFunction::Sum,
+                interval: 1000,
+                destination: "cpu_sum".to_string(),
+            })
+            .await
+            .unwrap();
+        let engine = engine.with_continuous_queries(manager);
+
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        let points: Vec<DataPoint> = [100, 200, 1100]
+            .into_iter()
+            .map(|timestamp| DataPoint::new(timestamp, 1.0, HashMap::new()))
+            .collect();
+        engine.ingest_batch(&[(&series, &points[0]), (&series, &points[1])]).await.unwrap();
+        engine.ingest(&series, &points[2]).await.unwrap();
+        let memtable = engine.memtable();
+        let rollups = memtable.read().await.get_series_range("cpu_sum", 0, 10_000).await;
+        let rollups: Vec<(i64, f64)> = rollups.iter().map(|p| (p.timestamp(), p.value())).collect();
+        assert_eq!(rollups, vec![(0, 2.0)]);
+
+        // Shutdown writes the open interval before the final flush
+        engine.shutdown(Duration::from_secs(5)).await.unwrap();
+        let mut flushed = Vec::new();
+        for sstable in engine.sstables().read().await.iter() {
+            for block in sstable.scan_blocks().await {
+                flushed.extend(
+                    block
+                        .to_points()
+                        .into_iter()
+                        .filter(|(series_name, _)| series_name == "cpu_sum")
+                        .map(|(_, p)| (p.timestamp(), p.value())),
+                );
+            }
+        }
+        flushed.sort_by_key(|(timestamp, _)| *timestamp);
+        assert_eq!(flushed, vec![(0, 2.0), (1000, 1.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_open_checkpoints_replayed_segments() {
+        let dir = tempdir().unwrap();
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        {
+            let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+            for timestamp in 1..=3 {
+                let point = DataPoint::new(timestamp * 1000, timestamp as f64, HashMap::new());
+                engine.ingest(&series, &point).await.unwrap();
+            }
+        }
+
+        // The replayed points are flushed and recorded, and the WAL emptied
+        let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+        assert!(engine.wal.segments().unwrap().is_empty());
+        assert!(engine.memtable().read().await.is_empty().await);
+        drop(engine);
+
+        // So a second restart finds the same single table and nothing to replay
+        let engine = StorageEngine::open(dir.path(), MemTable::new(100)).await.unwrap();
+        assert_eq!(engine.sstables().read().await.len(), 1);
+        assert_eq!(engine.catalog().total_points().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_batch_flushes_full_memtable() {
+        let dir = tempdir().unwrap();
+        let engine = StorageEngine::open(dir.path(), MemTable::new(3)).await.unwrap();
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        let points: Vec<DataPoint> = (1..=4).map(|i| DataPoint::new(i * 1000, i as f64, HashMap::new())).collect();
+        let batch: Vec<(&TimeSeries, &DataPoint)> = points.iter().map(|point| (&series, point)).collect();
+        engine.ingest_batch(&batch).await.unwrap();
+
+        assert_eq!(engine.sstables().read().await.len(), 1);
+        assert!(engine.memtable().read().await.is_empty().await);
+        assert!(!engine.memtable().read().await.is_flushing().await);
+        assert_eq!(engine.catalog().total_points().await, 4);
+    }
+}
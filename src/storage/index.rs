@@ -1,6 +1,8 @@
-use crate::query::parser::ast::{TimeRange, FilterExpr, TagFilter, TagFilterOp};
+use crate::query::parser::ast::{TimeRange, FilterExpr, TagFilter, TagFilterOp, ValueFilterOp};
 use std::collections::HashMap;
+use std::sync::Arc;
 use crate::storage::data::DataPoint;
+use crate::storage::tag_index::TagIndex;
 
 /// Information about an index for a time series
 #[derive(Debug, Clone)]
@@ -13,6 +15,8 @@ pub struct IndexInfo {
     pub tag_keys: Vec<String>,
     /// The estimated number of rows in the index
     pub estimated_rows: usize,
+    /// Inverted tag index used for block lookups and selectivity estimates
+    pub tag_index: Option<Arc<TagIndex>>,
 }
 
 impl IndexInfo {
@@ -23,9 +27,16 @@ impl IndexInfo {
             time_range,
             tag_keys,
             estimated_rows,
+            tag_index: None,
         }
     }
 
+    /// Attaches an inverted tag index
+    pub fn with_tag_index(mut self, tag_index: Arc<TagIndex>) -> Self {
+        self.tag_index = Some(tag_index);
+        self
+    }
+
     /// Updates the index info with a new data point
     pub fn update(&mut self, point: &DataPoint) {
         // Implementation of update method
@@ -66,6 +77,7 @@ impl IndexInfo {
             FilterExpr::TagFilter(tag_filter) => {
                 self.tag_keys.contains(&tag_filter.key)
             }
+            FilterExpr::ValueFilter(_) => true,
             FilterExpr::And(left, right) => {
                 self.can_satisfy_filter(left) && self.can_satisfy_filter(right)
             }
@@ -102,13 +114,25 @@ impl IndexInfo {
     pub fn estimate_filter_selectivity(&self, filter: &FilterExpr) -> f64 {
         match filter {
             FilterExpr::TagFilter(tag_filter) => {
+                // Prefer observed value frequencies over fixed guesses
+                let observed = self
+                    .tag_index
+                    .as_ref()
+                    .and_then(|index| index.selectivity(&tag_filter.key, &tag_filter.value));
                 match tag_filter.op {
-                    TagFilterOp::Eq => 0.1,
-                    TagFilterOp::Neq => 0.9,
+                    TagFilterOp::Eq => observed.unwrap_or(0.1),
+                    TagFilterOp::Neq => observed.map_or(0.9, |s| 1.0 - s),
                     TagFilterOp::Regex => 0.3,
                     TagFilterOp::NotRegex => 0.7,
                 }
             }
+            FilterExpr::ValueFilter(value_filter) => {
+                match value_filter.op {
+                    ValueFilterOp::Eq => 0.1,
+                    ValueFilterOp::Neq => 0.9,
+                    _ => 0.5,
+                }
+            }
             FilterExpr::And(left, right) => {
                 self.estimate_filter_selectivity(left) * self.estimate_filter_selectivity(right)
             }
@@ -137,6 +161,7 @@ mod tests {
             },
             tag_keys: vec!["region".to_string(), "env".to_string()],
             estimated_rows: 1000,
+            tag_index: None,
         }
     }
 
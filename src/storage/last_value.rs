@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::storage::data::DataPoint;
+use crate::storage::lsm::sstable::SSTable;
+
+/// Caches the most recent data point seen for each series
+///
+/// A [`crate::storage::StorageEngine`] opened with
+/// [`crate::storage::EngineConfig::last_values`] updates it with every point
+/// it stores, replaces deleted points with the newest remaining ones and
+/// reloads it from its newest SSTable on open.
+#[derive(Debug, Clone, Default)]
+pub struct LastValueCache {
+    /// Latest point per series name
+    entries: Arc<RwLock<HashMap<String, DataPoint>>>,
+}
+
+impl LastValueCache {
+    /// Creates a new, empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a point for a series, keeping it only if it is at least as new as the cached one
+    pub async fn update(&self, series_name: &str, point: &DataPoint) {
+        let mut entries = self.entries.write().await;
+        match entries.get(series_name) {
+            Some(existing) if existing.timestamp() > point.timestamp() => {}
+            _ => {
+                entries.insert(series_name.to_string(), point.clone());
+            }
+        }
+    }
+
+    /// Forgets the cached point of a series if it lies in `start..=end`
+    ///
+    /// Returns whether it was forgotten. Older points are not cached, so the
+    /// caller should [`LastValueCache::update`] the series with its newest
+    /// remaining point, if any.
+    pub async fn invalidate_range(&self, series_name: &str, start: i64, end: i64) -> bool {
+        let mut entries = self.entries.write().await;
+        let covered = entries
+            .get(series_name)
+            .is_some_and(|point| (start..=end).contains(&point.timestamp()));
+        if covered {
+            entries.remove(series_name);
+        }
+        covered
+    }
+
+    /// Records every point of an SSTable, typically the newest one on startup
+    pub async fn load_sstable(&self, sstable: &SSTable) {
+        for block in sstable.scan_blocks().await {
+            for (series_name, point) in block.to_points() {
+                self.update(&series_name, &point).await;
+            }
+        }
+    }
+
+    /// Returns the latest point for a series, if any
+    pub async fn get(&self, series_name: &str) -> Option<DataPoint> {
+        self.entries.read().await.get(series_name).cloned()
+    }
+
+    /// Returns the latest point of every series, ordered by series name
+    pub async fn snapshot(&self) -> Vec<(String, DataPoint)> {
+        let entries = self.entries.read().await;
+        let mut snapshot: Vec<(String, DataPoint)> = entries
+            .iter()
+            .map(|(name, point)| (name.clone(), point.clone()))
+            .collect();
+        snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot
+    }
+
+    /// Returns the latest point of every series as of `now`, ordered by series name
+    ///
+    /// All entries are read under one lock, so the result is a consistent
+    /// snapshot. A latest point newer than `now`, e.g. from a clock running
+    /// ahead, is reported at `now`.
+    pub async fn snapshot_at(&self, now: i64) -> Vec<(String, DataPoint)> {
+        let mut snapshot = self.snapshot().await;
+        for (_, point) in &mut snapshot {
+            if point.timestamp() > now {
+                *point = DataPoint::with_value(now, point.typed_value(), point.tags().clone());
+            }
+        }
+        snapshot
+    }
+
+    /// Returns the number of cached series
+    pub async fn len(&self) -> usize {
+        self.entries.read().await.len()
+    }
+
+    /// Returns true if no series have been cached
+    pub async fn is_empty(&self) -> bool {
+        self.entries.read().await.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_last_value_cache_keeps_newest() {
+        let cache = LastValueCache::new();
+        cache.update("cpu", &DataPoint::new(2000, 2.0, HashMap::new())).await;
+        cache.update("cpu", &DataPoint::new(1000, 1.0, HashMap::new())).await;
+        cache.update("mem", &DataPoint::new(1500, 3.0, HashMap::new())).await;
+
+        assert_eq!(cache.get("cpu").await.unwrap().value(), 2.0);
+        assert_eq!(cache.len().await, 2);
+
+        let snapshot = cache.snapshot().await;
+        assert_eq!(snapshot[0].0, "cpu");
+        assert_eq!(snapshot[1].0, "mem");
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_at_clamps_future_points() {
+        let cache = LastValueCache::new();
+        cache.update("cpu", &DataPoint::new(1000, 1.0, HashMap::new())).await;
+        cache.update("mem", &DataPoint::new(9000, 2.0, HashMap::new())).await;
+
+        let snapshot: Vec<(String, i64, f64)> = cache
+            .snapshot_at(5000)
+            .await
+            .into_iter()
+            .map(|(name, point)| (name, point.timestamp(), point.value()))
+            .collect();
+        assert_eq!(
+            snapshot,
+            vec![("cpu".to_string(), 1000, 1.0), ("mem".to_string(), 5000, 2.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_range_forgets_deleted_point() {
+        let cache = LastValueCache::new();
+        cache.update("cpu", &DataPoint::new(2000, 2.0, HashMap::new())).await;
+
+        assert!(!cache.invalidate_range("cpu", 0, 1000).await);
+        assert!(cache.get("cpu").await.is_some());
+        assert!(cache.invalidate_range("cpu", 1500, 2500).await);
+        assert!(cache.get("cpu").await.is_none());
+    }
+}
@@ -0,0 +1,220 @@
+//! LRU cache of decoded SSTable blocks
+//!
+//! Blocks are keyed by table path and block index and evicted least recently
+//! used first once their estimated decoded size exceeds the byte budget.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::metrics;
+use crate::storage::lsm::sstable::DataBlock;
+
+/// Block cache configuration
+#[derive(Debug, Clone)]
+pub struct BlockCacheConfig {
+    /// Whether decoded blocks are cached at all
+    pub enabled: bool,
+    /// Upper bound on the estimated size of the cached blocks
+    pub capacity_bytes: usize,
+}
+
+impl Default for BlockCacheConfig {
+    /// The cache is off unless a deployment opts in
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity_bytes: 64 * 1024 * 1024, // 64MB
+        }
+    }
+}
+
+/// Identifies a block within a table
+type BlockKey = (PathBuf, usize);
+
+/// A cached block and its bookkeeping
+struct Entry {
+    block: DataBlock,
+    size: usize,
+    /// Tick of the most recent access, the entry's key in `Inner::recency`
+    last_used: u64,
+}
+
+struct Inner {
+    entries: HashMap<BlockKey, Entry>,
+    /// Keys ordered from least to most recently used
+    recency: BTreeMap<u64, BlockKey>,
+    used_bytes: usize,
+    tick: u64,
+}
+
+impl Inner {
+    fn touch(&mut self, key: &BlockKey) -> Option<&Entry> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = tick;
+        self.recency.insert(tick, key.clone());
+        Some(entry)
+    }
+
+    fn remove(&mut self, key: &BlockKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.used_bytes -= entry.size;
+        }
+    }
+}
+
+/// Decoded blocks shared by every SSTable the cache is attached to
+pub struct BlockCache {
+    capacity_bytes: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BlockCache {
+    /// Creates a cache holding at most `capacity_bytes` of decoded blocks
+    pub fn new(capacity_bytes: usize) -> Self {
+        Self {
+            capacity_bytes,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                used_bytes: 0,
+                tick: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates a cache if the configuration enables one
+    pub fn from_config(config: &BlockCacheConfig) -> Option<Arc<Self>> {
+        config.enabled.then(|| Arc::new(Self::new(config.capacity_bytes)))
+    }
+
+    /// Returns a copy of a cached block, marking it most recently used
+    pub fn get(&self, path: &Path, block_index: usize) -> Option<DataBlock> {
+        let key = (path.to_path_buf(), block_index);
+        let block = self.lock().touch(&key).map(|entry| entry.block.clone());
+        if block.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            metrics::record_block_cache_hit();
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            metrics::record_block_cache_miss();
+        }
+        block
+    }
+
+    /// Caches a block, evicting the least recently used blocks to make room
+    ///
+    /// Blocks larger than the whole budget are not cached.
+    pub fn insert(&self, path: &Path, block_index: usize, block: DataBlock) {
+        let size = estimated_size(&block);
+        if size > self.capacity_bytes {
+            return;
+        }
+
+        let key = (path.to_path_buf(), block_index);
+        let mut inner = self.lock();
+        inner.remove(&key);
+        while inner.used_bytes + size > self.capacity_bytes {
+            let Some((_, oldest)) = inner.recency.pop_first() else { break };
+            if let Some(entry) = inner.entries.remove(&oldest) {
+                inner.used_bytes -= entry.size;
+            }
+        }
+
+        inner.tick += 1;
+        let last_used = inner.tick;
+        inner.recency.insert(last_used, key.clone());
+        inner.entries.insert(key, Entry { block, size, last_used });
+        inner.used_bytes += size;
+    }
+
+    /// Drops every block cached for the table at `path`
+    ///
+    /// Called when a table is removed or replaced, so a new table reusing the
+    /// path never sees the old table's blocks.
+    pub fn invalidate(&self, path: &Path) {
+        let mut inner = self.lock();
+        let keys: Vec<BlockKey> = inner.entries.keys().filter(|(p, _)| p == path).cloned().collect();
+        for key in &keys {
+            inner.remove(key);
+        }
+    }
+
+    /// Returns the estimated size of the cached blocks
+    pub fn used_bytes(&self) -> usize {
+        self.lock().used_bytes
+    }
+
+    /// Returns the number of lookups answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of lookups that had to read from disk
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // The cache holds no invariants a panicking reader could break halfway
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Estimates the memory held by a decoded block
+fn estimated_size(block: &DataBlock) -> usize {
+    let fixed = std::mem::size_of::<DataBlock>()
+        + block.timestamp_deltas.len() * std::mem::size_of::<i64>()
+        + block.values.len() * 16;
+    let names: usize = block.series_names.iter().map(String::len).sum();
+    let tags: usize = block
+        .tags
+        .iter()
+        .flat_map(|tags| tags.iter())
+        .map(|(key, value)| key.len() + value.len())
+        .sum();
+    fixed + names + tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::data::DataPoint;
+    use std::collections::HashMap;
+
+    fn block(points: usize) -> DataBlock {
+        let points: Vec<DataPoint> = (0..points as i64).map(|i| DataPoint::new(i, 1.0, HashMap::new())).collect();
+        DataBlock::from_points("cpu", &points)
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let size = estimated_size(&block(10));
+        let cache = BlockCache::new(size * 2);
+        let path = Path::new("a.sst");
+
+        cache.insert(path, 0, block(10));
+        cache.insert(path, 1, block(10));
+        assert!(cache.get(path, 0).is_some());
+        cache.insert(path, 2, block(10));
+
+        assert!(cache.get(path, 1).is_none());
+        assert!(cache.get(path, 0).is_some());
+        assert!(cache.get(path, 2).is_some());
+        assert_eq!(cache.used_bytes(), size * 2);
+        assert_eq!((cache.hits(), cache.misses()), (3, 1));
+
+        cache.invalidate(path);
+        assert_eq!(cache.used_bytes(), 0);
+        assert!(cache.get(path, 0).is_none());
+    }
+}
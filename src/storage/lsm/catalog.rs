@@ -1,13 +1,18 @@
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::storage::lsm::sstable::{SSTable, SSTableError, DataBlock};
+use crate::storage::lsm::sstable::{tombstone_path, SSTable, SSTableError, DataBlock};
+
+/// Name of the manifest file persisted in the catalog directory
+const MANIFEST_FILE: &str = "MANIFEST.json";
 
 /// Represents metadata about an SSTable in the catalog
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SSTableInfo {
     /// Path to the SSTable file
     pub path: PathBuf,
@@ -24,7 +29,7 @@ pub struct SSTableInfo {
 }
 
 /// Metadata for a single block in an SSTable
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockInfo {
     /// File offset where the block starts
     pub offset: u64,
@@ -34,6 +39,19 @@ pub struct BlockInfo {
     pub start_timestamp: i64,
     /// Series names present in this block
     pub series_names: HashSet<String>,
+    /// Smallest value in the block, if finite
+    #[serde(default)]
+    pub min_value: Option<f64>,
+    /// Largest value in the block, if finite
+    #[serde(default)]
+    pub max_value: Option<f64>,
+}
+
+/// On-disk representation of the catalog
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// SSTable metadata keyed by table id
+    tables: HashMap<String, SSTableInfo>,
 }
 
 /// Manages a collection of SSTables and their metadata
@@ -44,6 +62,8 @@ pub struct SSTableCatalog {
     tables: Arc<RwLock<HashMap<String, SSTableInfo>>>,
     /// Map of series names to SSTable IDs that contain them
     series_index: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    /// How long data is kept; `None` keeps it forever
+    retention: std::sync::Mutex<Option<Duration>>,
 }
 
 impl SSTableCatalog {
@@ -53,7 +73,62 @@ impl SSTableCatalog {
             base_dir: base_dir.as_ref().to_path_buf(),
             tables: Arc::new(RwLock::new(HashMap::new())),
             series_index: Arc::new(RwLock::new(HashMap::new())),
+            retention: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Expires tables once their newest point is older than `retention`
+    ///
+    /// Data is kept forever unless a retention is set. Expired tables are only
+    /// removed when [`SSTableCatalog::apply_retention`] runs.
+    pub fn set_retention(&self, retention: Duration) {
+        *self.retention.lock().unwrap_or_else(|e| e.into_inner()) = Some(retention);
+    }
+
+    /// Returns the retention, if data expires at all
+    pub fn retention(&self) -> Option<Duration> {
+        *self.retention.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the timestamp before which data has expired at `now`, if a retention is set
+    pub fn retention_cutoff(&self, now: i64) -> Option<i64> {
+        let retention = i64::try_from(self.retention()?.as_nanos()).unwrap_or(i64::MAX);
+        Some(now.saturating_sub(retention))
+    }
+
+    /// Removes and deletes every table whose newest point has expired at `now`
+    ///
+    /// The manifest is saved if any table was removed. Returns the removed
+    /// tables, so callers can drop their open handles to them.
+    pub async fn apply_retention(&self, now: i64) -> Result<Vec<SSTableInfo>, SSTableError> {
+        let Some(cutoff) = self.retention_cutoff(now) else {
+            return Ok(Vec::new());
+        };
+        let expired: Vec<(String, SSTableInfo)> = self
+            .tables
+            .read()
+            .await
+            .iter()
+            .filter(|(_, info)| info.max_timestamp < cutoff)
+            .map(|(table_id, info)| (table_id.clone(), info.clone()))
+            .collect();
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        for (table_id, info) in &expired {
+            self.remove_table(table_id).await?;
+            for path in [info.path.clone(), tombstone_path(&info.path)] {
+                match std::fs::remove_file(&path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            info!("Expired SSTable {} (newest point {})", info.path.display(), info.max_timestamp);
         }
+        self.save().await?;
+
+        Ok(expired.into_iter().map(|(_, info)| info).collect())
     }
 
     /// Adds a new SSTable to the catalog
@@ -66,6 +141,9 @@ impl SSTableCatalog {
             point_count: block.point_count,
             start_timestamp: block.start_timestamp,
             series_names: HashSet::new(), // Will be populated during block reads
+            // Non-finite bounds cannot be stored in the manifest; None disables pruning
+            min_value: Some(block.min_value).filter(|v| v.is_finite()),
+            max_value: Some(block.max_value).filter(|v| v.is_finite()),
         }).collect();
 
         // Create SSTableInfo
@@ -79,31 +157,102 @@ impl SSTableCatalog {
         };
 
         let table_id = self.generate_table_id(&info);
-        
-        // Update the main table index
-        let mut tables = self.tables.write().await;
-        tables.insert(table_id.clone(), info.clone());
+        debug!(
+            "Added SSTable to catalog: id={}, path={}, points={}, series={}",
+            table_id,
+            table.path.display(),
+            info.point_count,
+            info.series_names.len()
+        );
+        self.insert_info(table_id, info).await;
 
-        // Update the series index
+        Ok(())
+    }
+
+    /// Records table metadata under the given id in both indexes
+    async fn insert_info(&self, table_id: String, info: SSTableInfo) {
+        let mut tables = self.tables.write().await;
         let mut series_index = self.series_index.write().await;
+
         for series_name in &info.series_names {
             series_index
                 .entry(series_name.clone())
                 .or_insert_with(HashSet::new)
                 .insert(table_id.clone());
         }
+        tables.insert(table_id, info);
+    }
+
+    /// Writes the catalog state to a manifest file in the base directory
+    ///
+    /// The manifest is written to a temporary file and renamed into place, so a
+    /// crash mid-save leaves the previous manifest intact.
+    pub async fn save(&self) -> Result<(), SSTableError> {
+        let manifest = Manifest {
+            tables: self.tables.read().await.clone(),
+        };
+
+        let manifest_path = self.base_dir.join(MANIFEST_FILE);
+        let temp_path = self.base_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(&manifest)?)?;
+        std::fs::rename(&temp_path, &manifest_path)?;
 
         debug!(
-            "Added SSTable to catalog: id={}, path={}, points={}, series={}",
-            table_id,
-            table.path.display(),
-            info.point_count,
-            info.series_names.len()
+            "Saved catalog manifest with {} tables to {}",
+            manifest.tables.len(),
+            manifest_path.display()
         );
-
         Ok(())
     }
 
+    /// Reconstructs a catalog from the manifest in `base_dir`
+    ///
+    /// Tables listed in the manifest whose files no longer exist are skipped.
+    /// SSTable files in the directory that the manifest does not list (for
+    /// example, flushed after the last save) are opened and their metadata is
+    /// rebuilt from the file. A missing manifest yields a catalog built purely
+    /// from the directory contents.
+    pub async fn load<P: AsRef<Path>>(base_dir: P) -> Result<Self, SSTableError> {
+        let catalog = Self::new(base_dir);
+        let manifest_path = catalog.base_dir.join(MANIFEST_FILE);
+
+        let mut known_paths = HashSet::new();
+        if manifest_path.exists() {
+            let manifest: Manifest = serde_json::from_slice(&std::fs::read(&manifest_path)?)?;
+            for (table_id, info) in manifest.tables {
+                if !info.path.exists() {
+                    warn!(
+                        "Skipping SSTable {} listed in manifest: file {} is missing",
+                        table_id,
+                        info.path.display()
+                    );
+                    continue;
+                }
+                known_paths.insert(info.path.clone());
+                catalog.insert_info(table_id, info).await;
+            }
+        }
+
+        for entry in std::fs::read_dir(&catalog.base_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("sst")
+                || known_paths.contains(&path)
+            {
+                continue;
+            }
+
+            match SSTable::open(&path) {
+                Ok(table) => {
+                    info!("Recovered SSTable {} missing from manifest", path.display());
+                    catalog.add_table(&table).await?;
+                }
+                Err(e) => warn!("Skipping unreadable SSTable {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(catalog)
+    }
+
     /// Removes an SSTable from the catalog
     pub async fn remove_table(&self, table_id: &str) -> Result<(), SSTableError> {
         let mut tables = self.tables.write().await;
@@ -195,18 +344,18 @@ mod tests {
 
         for i in 0..point_count {
             timestamp_deltas.push(i as i64);
-            values.push(i as f64);
+            values.push((i as f64).into());
             block_series_names.push(series_names[0].clone());
             tags.push(HashMap::new());
         }
 
-        let block = DataBlock {
-            start_timestamp: start_time,
+        let block = DataBlock::new(
+            start_time,
             timestamp_deltas,
             values,
-            series_names: block_series_names,
+            block_series_names,
             tags,
-        };
+        );
 
         // Write the block
         sstable.write_block(block).await.unwrap();
@@ -334,4 +483,72 @@ mod tests {
         assert_eq!(catalog.total_points().await, 25); // 10 + 15 points
         assert_eq!(catalog.unique_series_count().await, 2); // series1 and series2
     }
-} 
\ No newline at end of file
+
+    #[test]
+    async fn test_catalog_save_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let catalog = SSTableCatalog::new(temp_dir.path());
+
+        let sstable1 = create_test_sstable(
+            &temp_dir.path().join("table1.sst"),
+            vec!["series1".to_string()],
+            1000,
+            10,
+        ).await;
+        let sstable2 = create_test_sstable(
+            &temp_dir.path().join("table2.sst"),
+            vec!["series2".to_string()],
+            2000,
+            15,
+        ).await;
+        catalog.add_table(&sstable1).await.unwrap();
+        catalog.add_table(&sstable2).await.unwrap();
+        catalog.save().await.unwrap();
+
+        // Make the manifest stale: one listed table disappears, one unlisted appears
+        drop(sstable2);
+        std::fs::remove_file(temp_dir.path().join("table2.sst")).unwrap();
+        create_test_sstable(
+            &temp_dir.path().join("table3.sst"),
+            vec!["series3".to_string()],
+            3000,
+            5,
+        ).await;
+
+        let loaded = SSTableCatalog::load(temp_dir.path()).await.unwrap();
+        assert_eq!(loaded.get_all_tables().await.len(), 2);
+        assert_eq!(loaded.total_points().await, 15);
+        assert_eq!(loaded.get_tables_for_series("series1").await.len(), 1);
+        assert!(loaded.get_tables_for_series("series2").await.is_empty());
+
+        let recovered = loaded.get_tables_for_series("series3").await;
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].min_timestamp, 3000);
+        assert_eq!(recovered[0].max_timestamp, 3010);
+    }
+
+    #[test]
+    async fn test_retention_removes_only_expired_tables() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let catalog = SSTableCatalog::new(temp_dir.path());
+        let series = vec!["cpu".to_string()];
+        let old = create_test_sstable(&temp_dir.path().join("old.sst"), series.clone(), 1000, 10).await;
+        let new = create_test_sstable(&temp_dir.path().join("new.sst"), series, 90_000, 10).await;
+        catalog.add_table(&old).await.unwrap();
+        catalog.add_table(&new).await.unwrap();
+
+        // Without a retention nothing ever expires
+        assert!(catalog.apply_retention(i64::MAX).await.unwrap().is_empty());
+
+        catalog.set_retention(Duration::from_nanos(50_000));
+        let expired = catalog.apply_retention(100_000).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].path, old.path);
+        assert!(!old.path.exists());
+        assert!(new.path.exists());
+
+        let remaining = SSTableCatalog::load(temp_dir.path()).await.unwrap().get_all_tables().await;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].path, new.path);
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::storage::data::DataPoint;
+use crate::storage::lsm::cache::BlockCache;
+use crate::storage::lsm::flush::{split_into_blocks, FlushConfig};
+use crate::storage::lsm::memtable::DuplicatePolicy;
+use crate::storage::lsm::sstable::{tombstone_path, DataBlock, SSTable, SSTableError};
+use crate::storage::lsm::tombstone::{self, TombstoneSet};
+use crate::storage::tag_index::{BlockRef, TagIndex, TagIndexError};
+
+/// Error type for compaction operations
+#[derive(Debug, thiserror::Error)]
+pub enum CompactionError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("SSTable error: {0}")]
+    SSTable(#[from] SSTableError),
+    #[error("Tag index error: {0}")]
+    TagIndex(#[from] TagIndexError),
+    #[error("Duplicate point for series {series} at timestamp {timestamp}")]
+    DuplicateTimestamp { series: String, timestamp: i64 },
+    #[error("No SSTables to compact")]
+    NoInput,
+    #[error("SSTable {table} with sequence {sequence} lies between the compaction inputs")]
+    NotContiguous { table: String, sequence: u64 },
+}
+
+/// Name of the subdirectory compacted-away SSTables are moved to
+pub const TRASH_DIR: &str = "trash";
+
+/// Merges several SSTables into one
+pub struct Compactor {
+    /// Directory the merged SSTable is written to
+    output_dir: PathBuf,
+    /// How points sharing a series and timestamp are resolved
+    duplicate_policy: DuplicatePolicy,
+    /// Block layout of the merged SSTable
+    config: FlushConfig,
+    /// Deletions whose points are dropped from the merged SSTable
+    tombstones: Arc<TombstoneSet>,
+    /// How long retired inputs stay in the trash; `None` deletes them outright
+    trash_grace_period: Option<Duration>,
+    /// Source of the current time in nanoseconds, used to age the trash
+    clock: Arc<dyn Fn() -> i64 + Send + Sync>,
+    /// Block cache attached to the merged SSTable
+    block_cache: Option<Arc<BlockCache>>,
+    /// Whether the merged SSTable is read through a memory map
+    mmap_reads: bool,
+    /// Tag index moved from the inputs' blocks to the merged SSTable's
+    tag_index: Option<Arc<RwLock<TagIndex>>>,
+}
+
+impl Compactor {
+    /// Creates a compactor that keeps the newest of any duplicate points
+    pub fn new(output_dir: PathBuf) -> Self {
+        Self {
+            output_dir,
+            duplicate_policy: DuplicatePolicy::KeepLast,
+            config: FlushConfig::default(),
+            tombstones: Arc::new(TombstoneSet::new()),
+            trash_grace_period: None,
+            clock: Arc::new(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()),
+            block_cache: None,
+            mmap_reads: false,
+            tag_index: None,
+        }
+    }
+
+    /// Sets how points sharing a series and timestamp are resolved
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Sets the block layout of the merged SSTable
+    pub fn with_flush_config(mut self, config: FlushConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the tombstones whose points are dropped while merging
+    pub fn with_tombstones(mut self, tombstones: Arc<TombstoneSet>) -> Self {
+        self.tombstones = tombstones;
+        self
+    }
+
+    /// Keeps retired inputs in the `trash` subdirectory for `grace_period`
+    /// before deleting them, so a botched compaction can be recovered from
+    pub fn with_trash_grace_period(mut self, grace_period: Duration) -> Self {
+        self.trash_grace_period = Some(grace_period);
+        self
+    }
+
+    /// Replaces the wall clock used to age trashed SSTables
+    pub fn with_clock(mut self, clock: Arc<dyn Fn() -> i64 + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Attaches the given block cache to the merged SSTable
+    pub fn with_block_cache(mut self, cache: Arc<BlockCache>) -> Self {
+        self.block_cache = Some(cache);
+        self
+    }
+
+    /// Reads the merged SSTable through a memory map, see [`SSTable::with_mmap_reads`]
+    pub fn with_mmap_reads(mut self, enabled: bool) -> Self {
+        self.mmap_reads = enabled;
+        self
+    }
+
+    /// Keeps the given tag index in step with compaction
+    ///
+    /// The inputs' postings are replaced by the merged SSTable's and the index
+    /// is saved to the output directory. Tables the index does not cover are
+    /// scanned in full, so dropping the inputs' postings before they are
+    /// retired never hides their points.
+    pub fn with_tag_index(mut self, tag_index: Arc<RwLock<TagIndex>>) -> Self {
+        self.tag_index = Some(tag_index);
+        self
+    }
+
+    /// Directory retired SSTables are moved to while their grace period runs
+    pub fn trash_dir(&self) -> PathBuf {
+        self.output_dir.join(TRASH_DIR)
+    }
+
+    /// Merges the given SSTables into a new SSTable
+    ///
+    /// Tables are applied oldest first, ordered by sequence number with ties
+    /// broken by table id (the file name), so the duplicate policy sees the same
+    /// "earlier" and "later" point regardless of the order tables are passed in.
+    ///
+    /// The merged table takes the newest input's sequence, so the inputs must
+    /// be a contiguous run of the sequences in `live_tables`, the tables
+    /// currently serving reads: a table outside the inputs whose sequence lies
+    /// between theirs would otherwise lose its points to older merged ones.
+    pub async fn compact(
+        &self,
+        tables: &[Arc<SSTable>],
+        live_tables: &[Arc<SSTable>],
+    ) -> Result<Arc<SSTable>, CompactionError> {
+        if tables.is_empty() {
+            return Err(CompactionError::NoInput);
+        }
+
+        let mut ordered: Vec<(u64, String, Arc<SSTable>)> = tables
+            .iter()
+            .map(|table| (table.sequence, table_id(table), Arc::clone(table)))
+            .collect();
+        ordered.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        let oldest = ordered.first().map(|(sequence, _, _)| *sequence).unwrap_or_default();
+        let sequence = ordered.last().map(|(sequence, _, _)| *sequence).unwrap_or_default();
+        if let Some(between) = live_tables.iter().find(|live| {
+            (oldest..=sequence).contains(&live.sequence) && !tables.iter().any(|table| table.path == live.path)
+        }) {
+            return Err(CompactionError::NotContiguous {
+                table: table_id(between),
+                sequence: between.sequence,
+            });
+        }
+
+        let tombstones = self.tombstones.snapshot().await;
+
+        // Merge oldest to newest, resolving duplicates as they are encountered
+        let mut merged: BTreeMap<String, BTreeMap<i64, DataPoint>> = BTreeMap::new();
+        for (_, _, table) in &ordered {
+            // Points deleted from this table are purged rather than carried over
+            let deletions = table.tombstones().await;
+            // A block that cannot be read fails the compaction, so the inputs
+            // are never retired with points missing from the merged table
+            for block in table.try_scan_blocks_where(|_| true).await? {
+                for (series_name, point) in block.to_points() {
+                    if deletions.iter().any(|t| t.covers(&series_name, point.timestamp())) {
+                        continue;
+                    }
+                    let series = merged.entry(series_name.clone()).or_default();
+                    let resolved = match series.get(&point.timestamp()) {
+                        Some(existing) => self.duplicate_policy.resolve(existing, &point).ok_or(
+                            CompactionError::DuplicateTimestamp {
+                                series: series_name,
+                                timestamp: point.timestamp(),
+                            },
+                        )?,
+                        None => point,
+                    };
+                    series.insert(resolved.timestamp(), resolved);
+                }
+            }
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let output_path = self.output_dir.join(format!("{}.sst", timestamp));
+        let mut output = SSTable::new_with_sequence(&output_path, sequence)?.with_mmap_reads(self.mmap_reads);
+        if let Some(cache) = &self.block_cache {
+            output = output.with_block_cache(Arc::clone(cache));
+        }
+
+        let mut written = Vec::new();
+        for (series_name, points) in merged {
+            let points: Vec<DataPoint> = points
+                .into_values()
+                .filter(|p| !tombstone::is_covered(&tombstones, p.timestamp(), p.value(), p.tags()))
+                .collect();
+            for chunk in split_into_blocks(&points, &self.config) {
+                let block = DataBlock::from_points(&series_name, chunk);
+                if self.tag_index.is_some() {
+                    written.push(block.clone());
+                }
+                output.write_block(block).await?;
+            }
+        }
+        output.seal().await;
+
+        if let Some(tag_index) = &self.tag_index {
+            let mut tag_index = tag_index.write().await;
+            for (_, _, table) in &ordered {
+                tag_index.remove_sstable(table);
+            }
+            for (block_index, block) in written.iter().enumerate() {
+                tag_index.insert_block(BlockRef::new(&output, block_index), block);
+            }
+            tag_index.save(&self.output_dir)?;
+        }
+
+        info!(
+            "Compacted {} SSTables into {}",
+            ordered.len(),
+            output_path.display()
+        );
+        Ok(Arc::new(output))
+    }
+
+    /// Removes compacted-away SSTables, along with their tombstone sidecars
+    ///
+    /// Their blocks are evicted from their block cache.
+    ///
+    /// Without a grace period the files are deleted at once. With one, they are
+    /// moved to [`Compactor::trash_dir`] under a name prefixed with the time they
+    /// were retired, and [`Compactor::purge_trash`] deletes them once the grace
+    /// period has passed. Returns the paths the tables were moved to.
+    pub fn retire_inputs(&self, tables: &[Arc<SSTable>]) -> Result<Vec<PathBuf>, CompactionError> {
+        let mut trashed = Vec::new();
+        if self.trash_grace_period.is_none() {
+            for table in tables {
+                table.evict_cached_blocks();
+                table.unmap();
+                std::fs::remove_file(&table.path)?;
+                remove_if_exists(&tombstone_path(&table.path))?;
+            }
+            return Ok(trashed);
+        }
+
+        let trash_dir = self.trash_dir();
+        std::fs::create_dir_all(&trash_dir)?;
+        let retired_at = (self.clock)();
+        for table in tables {
+            table.evict_cached_blocks();
+            table.unmap();
+            let destination = trash_dir.join(format!("{}-{}", retired_at, table_id(table)));
+            std::fs::rename(&table.path, &destination)?;
+            let sidecar = tombstone_path(&table.path);
+            if sidecar.exists() {
+                std::fs::rename(&sidecar, tombstone_path(&destination))?;
+            }
+            info!("Moved {} to {}", table.path.display(), destination.display());
+            trashed.push(destination);
+        }
+        Ok(trashed)
+    }
+
+    /// Deletes trashed SSTables whose grace period has elapsed
+    ///
+    /// Returns the number of files deleted.
+    pub fn purge_trash(&self) -> Result<usize, CompactionError> {
+        let trash_dir = self.trash_dir();
+        if !trash_dir.exists() {
+            return Ok(0);
+        }
+        let grace = self.trash_grace_period.unwrap_or_default().as_nanos() as i64;
+        let now = (self.clock)();
+
+        let mut purged = 0;
+        for entry in std::fs::read_dir(&trash_dir)? {
+            let path = entry?.path();
+            let retired_at = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split_once('-'))
+                .and_then(|(prefix, _)| prefix.parse::<i64>().ok());
+            match retired_at {
+                Some(retired_at) if now.saturating_sub(retired_at) >= grace => {
+                    std::fs::remove_file(&path)?;
+                    purged += 1;
+                }
+                _ => {}
+            }
+        }
+        if purged > 0 {
+            info!("Purged {} files from {}", purged, trash_dir.display());
+        }
+        Ok(purged)
+    }
+}
+
+/// Deletes a file, treating an already missing file as success
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the id used to order SSTables with identical sequence numbers
+fn table_id(table: &SSTable) -> String {
+    table
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    async fn write_table(path: PathBuf, points: &[(i64, f64)]) -> Arc<SSTable> {
+        let table = SSTable::new(path).unwrap();
+        let points: Vec<DataPoint> = points
+            .iter()
+            .map(|(ts, value)| DataPoint::new(*ts, *value, HashMap::new()))
+            .collect();
+        table.write_block(DataBlock::from_points("cpu", &points)).await.unwrap();
+        Arc::new(table)
+    }
+
+    #[tokio::test]
+    async fn test_compaction_sums_duplicates() {
+        let temp_dir = tempdir().unwrap();
+        let older = write_table(temp_dir.path().join("a.sst"), &[(1000, 1.0), (2000, 2.0)]).await;
+        let newer = write_table(temp_dir.path().join("b.sst"), &[(2000, 5.0), (3000, 3.0)]).await;
+
+        let compactor = Compactor::new(temp_dir.path().to_path_buf())
+            .with_duplicate_policy(DuplicatePolicy::Sum);
+        let merged = compactor.compact(&[newer.clone(), older.clone()], &[]).await.unwrap();
+
+        let points: Vec<(i64, f64)> = merged
+            .scan_blocks()
+            .await
+            .iter()
+            .flat_map(|block| block.to_points())
+            .map(|(_, p)| (p.timestamp(), p.value()))
+            .collect();
+        assert_eq!(points, vec![(1000, 1.0), (2000, 7.0), (3000, 3.0)]);
+
+        // Under Error the collision aborts the compaction
+        let compactor = Compactor::new(temp_dir.path().to_path_buf())
+            .with_duplicate_policy(DuplicatePolicy::Error);
+        assert!(matches!(
+            compactor.compact(&[older, newer], &[]).await,
+            Err(CompactionError::DuplicateTimestamp { timestamp: 2000, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retired_inputs_wait_in_trash_for_grace_period() {
+        let temp_dir = tempdir().unwrap();
+        let older = write_table(temp_dir.path().join("a.sst"), &[(1000, 1.0)]).await;
+        let newer = write_table(temp_dir.path().join("b.sst"), &[(2000, 2.0)]).await;
+
+        let now = Arc::new(std::sync::atomic::AtomicI64::new(1_000));
+        let clock = now.clone();
+        let compactor = Compactor::new(temp_dir.path().to_path_buf())
+            .with_trash_grace_period(Duration::from_nanos(500))
+            .with_clock(Arc::new(move || clock.load(std::sync::atomic::Ordering::SeqCst)));
+        let merged = compactor.compact(&[older.clone(), newer.clone()], &[]).await.unwrap();
+
+        let trashed = compactor.retire_inputs(&[older.clone(), newer.clone()]).unwrap();
+        assert!(!older.path.exists() && !newer.path.exists());
+        assert_eq!(trashed.len(), 2);
+        for path in &trashed {
+            assert!(path.exists());
+            assert_eq!(path.parent().unwrap(), compactor.trash_dir());
+        }
+        // A trashed table can still be opened to recover its points
+        let recovered = SSTable::open(&trashed[0]).unwrap();
+        assert_eq!(recovered.metadata.read().await.point_count, 1);
+        assert!(merged.path.exists());
+
+        // Nothing is purged until the grace period has elapsed
+        now.store(1_499, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(compactor.purge_trash().unwrap(), 0);
+        assert!(trashed.iter().all(|path| path.exists()));
+
+        now.store(1_500, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(compactor.purge_trash().unwrap(), 2);
+        assert!(trashed.iter().all(|path| !path.exists()));
+    }
+
+    #[tokio::test]
+    async fn test_newest_table_wins_regardless_of_name() {
+        let temp_dir = tempdir().unwrap();
+        let older = write_table(temp_dir.path().join("b.sst"), &[(1000, 1.0)]).await;
+        let newer = write_table(temp_dir.path().join("a.sst"), &[(1000, 2.0)]).await;
+        assert!(newer.sequence > older.sequence);
+
+        let compactor = Compactor::new(temp_dir.path().to_path_buf());
+        let merged = compactor.compact(&[newer.clone(), older.clone()], &[]).await.unwrap();
+        assert_eq!(merged.sequence, newer.sequence);
+        assert_eq!(merged.scan_blocks().await[0].values, vec![crate::storage::data::Value::F64(2.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_inputs_must_be_a_contiguous_run() {
+        let temp_dir = tempdir().unwrap();
+        let oldest = write_table(temp_dir.path().join("a.sst"), &[(1000, 1.0)]).await;
+        let middle = write_table(temp_dir.path().join("b.sst"), &[(1000, 2.0)]).await;
+        let newest = write_table(temp_dir.path().join("c.sst"), &[(1000, 3.0)]).await;
+        let live = [oldest.clone(), middle.clone(), newest.clone()];
+
+        // Merging around the middle table would let the oldest point outrank it
+        let compactor = Compactor::new(temp_dir.path().to_path_buf());
+        assert!(matches!(
+            compactor.compact(&[oldest.clone(), newest.clone()], &live).await,
+            Err(CompactionError::NotContiguous { sequence, .. }) if sequence == middle.sequence
+        ));
+
+        let merged = compactor.compact(&[middle.clone(), newest.clone()], &live).await.unwrap();
+        assert_eq!(merged.sequence, newest.sequence);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_block_fails_compaction() {
+        let temp_dir = tempdir().unwrap();
+        let older = write_table(temp_dir.path().join("a.sst"), &[(1000, 1.0)]).await;
+        let newer_path = temp_dir.path().join("b.sst");
+        write_table(newer_path.clone(), &[(2000, 2.0), (3000, 3.0)]).await;
+        // Flip the last byte of the block body, which its checksum covers
+        let mut bytes = std::fs::read(&newer_path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&newer_path, bytes).unwrap();
+        let newer = Arc::new(SSTable::open(&newer_path).unwrap());
+
+        let compactor = Compactor::new(temp_dir.path().to_path_buf());
+        assert!(matches!(
+            compactor.compact(&[older.clone(), newer.clone()], &[]).await,
+            Err(CompactionError::SSTable(_))
+        ));
+        assert!(older.path.exists() && newer.path.exists());
+    }
+}
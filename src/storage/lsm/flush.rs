@@ -1,12 +1,17 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tracing::{info};
 
 
+use crate::storage::data::DataPoint;
+use crate::storage::lsm::cache::BlockCache;
 use crate::storage::lsm::memtable::MemTable;
 use crate::storage::lsm::sstable::{SSTable, SSTableError, DataBlock};
+use crate::storage::tag_index::{BlockRef, TagIndex};
 
 /// Error type for flush operations
 #[derive(Debug, thiserror::Error)]
@@ -19,26 +24,116 @@ pub enum FlushError {
     FlushInProgress,
     #[error("Flush failed: {0}")]
     FlushFailed(String),
+    #[error("Flush timed out after {0:?}")]
+    Timeout(Duration),
+}
+
+/// Order in which a flush writes the blocks of different series
+///
+/// Every block holds a single series either way, and readers accept both
+/// layouts; the choice only changes which blocks sit next to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockOrdering {
+    /// All blocks of a series together, series in name order; suits queries
+    /// that read one series over a long range
+    #[default]
+    BySeries,
+    /// Blocks of all series interleaved by start timestamp (ties by series
+    /// name); suits queries that read many series over a narrow range
+    ByTime,
+}
+
+/// Configuration for how MemTable data is laid out into SSTable blocks
+#[derive(Debug, Clone)]
+pub struct FlushConfig {
+    /// Maximum number of points in a single block
+    pub max_points_per_block: usize,
+    /// Maximum time span (in nanoseconds) covered by a single block
+    pub max_block_span: i64,
+    /// Order in which blocks of different series are written
+    pub block_ordering: BlockOrdering,
+}
+
+impl Default for FlushConfig {
+    fn default() -> Self {
+        Self {
+            max_points_per_block: 4096,
+            max_block_span: 3_600_000_000_000, // 1 hour
+            block_ordering: BlockOrdering::default(),
+        }
+    }
 }
 
 /// Manages the process of flushing MemTables to SSTables
 pub struct FlushManager {
     /// Path where SSTables are stored
     sstable_dir: PathBuf,
+    /// Block layout configuration
+    config: FlushConfig,
     /// Current flush task if one is running
-    flush_task: Option<JoinHandle<Result<(), FlushError>>>,
+    flush_task: Option<JoinHandle<Result<Arc<SSTable>, FlushError>>>,
+    /// Tag index updated with every flushed block
+    tag_index: Option<Arc<RwLock<TagIndex>>>,
+    /// Block cache attached to every flushed SSTable
+    block_cache: Option<Arc<BlockCache>>,
+    /// Whether flushed SSTables are read through a memory map
+    mmap_reads: bool,
 }
 
 impl FlushManager {
     /// Creates a new FlushManager
     pub fn new(sstable_dir: PathBuf) -> Self {
+        Self::with_config(sstable_dir, FlushConfig::default())
+    }
+
+    /// Creates a new FlushManager with a custom block layout configuration
+    pub fn with_config(sstable_dir: PathBuf, config: FlushConfig) -> Self {
         Self {
             sstable_dir,
+            config,
             flush_task: None,
+            tag_index: None,
+            block_cache: None,
+            mmap_reads: false,
         }
     }
 
+    /// Indexes the tags of every flushed block in the given tag index
+    pub fn with_tag_index(mut self, tag_index: Arc<RwLock<TagIndex>>) -> Self {
+        self.tag_index = Some(tag_index);
+        self
+    }
+
+    /// Attaches the given block cache to every flushed SSTable
+    pub fn with_block_cache(mut self, cache: Arc<BlockCache>) -> Self {
+        self.block_cache = Some(cache);
+        self
+    }
+
+    /// Reads flushed SSTables through a memory map once they are sealed,
+    /// see [`SSTable::with_mmap_reads`]
+    pub fn with_mmap_reads(mut self, enabled: bool) -> Self {
+        self.mmap_reads = enabled;
+        self
+    }
+
+    /// Returns the tag index flushed blocks are indexed in, if any
+    pub fn tag_index(&self) -> Option<Arc<RwLock<TagIndex>>> {
+        self.tag_index.clone()
+    }
+
+    /// Directory flushed SSTables are written to
+    pub fn sstable_dir(&self) -> &Path {
+        &self.sstable_dir
+    }
+
     /// Starts a background flush of the given MemTable to an SSTable
+    ///
+    /// The MemTable's points are frozen into its flushing buffer before this
+    /// returns, so writes made while the flush runs land in the emptied
+    /// MemTable and are left for the next flush. The buffer stays readable
+    /// until the caller has published the SSTable and passed it to
+    /// [`MemTable::release_flushed`].
     pub async fn start_flush(
         &mut self,
         memtable: Arc<RwLock<MemTable>>,
@@ -51,53 +146,36 @@ impl FlushManager {
         // Create a new SSTable for this flush
         let timestamp = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
         let sstable_path = self.sstable_dir.join(format!("{}.sst", timestamp));
-        let sstable = SSTable::new(&sstable_path)?;
+        let mut sstable = SSTable::new(&sstable_path)?.with_mmap_reads(self.mmap_reads);
+        if let Some(cache) = &self.block_cache {
+            sstable = sstable.with_block_cache(Arc::clone(cache));
+        }
+        let config = self.config.clone();
+        let tag_index = self.tag_index.clone();
+        let data = memtable.read().await.freeze().await;
 
         // Start the flush task
         let task = tokio::spawn(async move {
-            // Take a read lock on the MemTable
-            let memtable_guard = memtable.read().await;
-            let data = memtable_guard.get_data().await;
-            
-            // Create a new empty MemTable for atomic swap
-            let new_memtable = MemTable::new(memtable_guard.capacity());
-            
-            // Write all data points to the SSTable
-            for (series_name, points) in data {
-                let mut start_timestamp = i64::MAX;
-                let mut timestamp_deltas = Vec::new();
-                let mut values = Vec::new();
-                let mut tags = Vec::new();
-
-
-                // Process points to create a DataBlock
-                for point in &points {
-                    if start_timestamp == i64::MAX {
-                        start_timestamp = point.timestamp();
-                    } else {
-                        timestamp_deltas.push(point.timestamp() - start_timestamp);
-                    }
-                    values.push(point.value());
-                    tags.push(point.tags().clone());
+            // Write each series as one or more blocks bounded by size and time span.
+            // Blocks are indexed once the whole table is written, so a failed
+            // flush leaves no postings for blocks that never landed.
+            let mut written = Vec::new();
+            for block in order_blocks(data, &config) {
+                if tag_index.is_some() {
+                    written.push(block.clone());
                 }
-
-                let block = DataBlock {
-                    start_timestamp,
-                    timestamp_deltas,
-                    values,
-                    series_names: vec![series_name],
-                    tags,
-                };
                 sstable.write_block(block).await?;
             }
-
-            // Atomically swap the MemTables
-            drop(memtable_guard);
-            let mut memtable_guard = memtable.write().await;
-            *memtable_guard = new_memtable;
+            sstable.seal().await;
+            if let Some(tag_index) = &tag_index {
+                let mut tag_index = tag_index.write().await;
+                for (block_index, block) in written.iter().enumerate() {
+                    tag_index.insert_block(BlockRef::new(&sstable, block_index), block);
+                }
+            }
 
             info!("Successfully flushed MemTable to {}", sstable_path.display());
-            Ok(())
+            Ok(Arc::new(sstable))
         });
 
         self.flush_task = Some(task);
@@ -109,14 +187,94 @@ impl FlushManager {
         self.flush_task.is_some()
     }
 
-    /// Waits for the current flush to complete and returns the result
-    pub async fn wait_for_flush(&mut self) -> Result<(), FlushError> {
+    /// Waits for the current flush to complete and returns the written SSTable, if any
+    pub async fn wait_for_flush(&mut self) -> Result<Option<Arc<SSTable>>, FlushError> {
         if let Some(task) = self.flush_task.take() {
-            task.await.map_err(|e| FlushError::FlushFailed(e.to_string()))?
+            let sstable = task.await.map_err(|e| FlushError::FlushFailed(e.to_string()))??;
+            Ok(Some(sstable))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Waits for the current flush to complete, abandoning it if it exceeds `timeout`
+    ///
+    /// An abandoned flush leaves the MemTable's flushing buffer in place, so the
+    /// unflushed data stays readable and the next flush retries it.
+    pub async fn wait_for_flush_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Arc<SSTable>>, FlushError> {
+        if let Some(mut task) = self.flush_task.take() {
+            match tokio::time::timeout(timeout, &mut task).await {
+                Ok(joined) => {
+                    let sstable = joined.map_err(|e| FlushError::FlushFailed(e.to_string()))??;
+                    Ok(Some(sstable))
+                }
+                Err(_) => {
+                    task.abort();
+                    Err(FlushError::Timeout(timeout))
+                }
+            }
         } else {
-            Ok(())
+            Ok(None)
         }
     }
+
+    /// Cancels the current flush, if any, returning whether one was running
+    pub fn cancel_flush(&mut self) -> bool {
+        match self.flush_task.take() {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Splits time-ordered points into block-sized chunks
+///
+/// A new block is started when the current one reaches `max_points_per_block`
+/// or when the next point would stretch it beyond `max_block_span`, so dense
+/// series are bounded by count and sparse series by time.
+pub(crate) fn split_into_blocks<'a>(points: &'a [DataPoint], config: &FlushConfig) -> Vec<&'a [DataPoint]> {
+    let max_points = config.max_points_per_block.max(1);
+    let mut chunks = Vec::new();
+    let mut block_start = 0;
+
+    for i in 1..points.len() {
+        let span = points[i].timestamp() - points[block_start].timestamp();
+        if i - block_start >= max_points || span > config.max_block_span {
+            chunks.push(&points[block_start..i]);
+            block_start = i;
+        }
+    }
+    if block_start < points.len() {
+        chunks.push(&points[block_start..]);
+    }
+
+    chunks
+}
+
+/// Splits every series into blocks and arranges them per `config.block_ordering`
+fn order_blocks(data: HashMap<String, Vec<DataPoint>>, config: &FlushConfig) -> Vec<DataBlock> {
+    let mut series: Vec<(String, Vec<DataPoint>)> = data.into_iter().collect();
+    series.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut blocks: Vec<DataBlock> = series
+        .iter()
+        .flat_map(|(series_name, points)| {
+            split_into_blocks(points, config)
+                .into_iter()
+                .map(|chunk| DataBlock::from_points(series_name, chunk))
+        })
+        .collect();
+    if config.block_ordering == BlockOrdering::ByTime {
+        // Stable, so equal start timestamps keep series name order
+        blocks.sort_by_key(|block| block.start_timestamp);
+    }
+    blocks
 }
 
 #[cfg(test)]
@@ -168,4 +326,148 @@ mod tests {
         let result = flush_manager.start_flush(memtable.clone()).await;
         assert!(matches!(result, Err(FlushError::FlushInProgress)));
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_flush_splits_blocks_by_count_and_span() {
+        let temp_dir = tempdir().unwrap();
+        let config = FlushConfig {
+            max_points_per_block: 4,
+            max_block_span: 3_600_000_000_000,
+            ..FlushConfig::default()
+        };
+        let mut flush_manager = FlushManager::with_config(temp_dir.path().to_path_buf(), config);
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+
+        {
+            let memtable_guard = memtable.write().await;
+
+            // Dense series: 10 points one second apart, bounded by point count
+            let dense = TimeSeries::new("dense".to_string()).unwrap();
+            for i in 0..10 {
+                let point = DataPoint::new(1_000_000_000 * (i + 1), i as f64, HashMap::new());
+                memtable_guard.insert(&dense, &point).await.unwrap();
+            }
+
+            // Sparse series: 3 points two hours apart, bounded by time span
+            let sparse = TimeSeries::new("sparse".to_string()).unwrap();
+            for i in 0..3 {
+                let point = DataPoint::new(7_200_000_000_000 * (i + 1), i as f64, HashMap::new());
+                memtable_guard.insert(&sparse, &point).await.unwrap();
+            }
+        }
+
+        flush_manager.start_flush(memtable.clone()).await.unwrap();
+        let sstable = flush_manager.wait_for_flush().await.unwrap().unwrap();
+
+        let mut dense_counts = Vec::new();
+        let mut sparse_counts = Vec::new();
+        for block in sstable.scan_blocks().await {
+            let count = block.timestamp_deltas.len();
+            assert_eq!(block.values.len(), count);
+            assert_eq!(block.series_names.len(), count);
+            if block.series_names[0] == "dense" {
+                dense_counts.push(count);
+            } else {
+                sparse_counts.push(count);
+            }
+        }
+        dense_counts.sort();
+        assert_eq!(dense_counts, vec![2, 4, 4]);
+        assert_eq!(sparse_counts, vec![1, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_flush_block_ordering_layouts_are_queryable() {
+        use crate::storage::lsm::query::{Query, QueryRouter};
+
+        for ordering in [BlockOrdering::BySeries, BlockOrdering::ByTime] {
+            let temp_dir = tempdir().unwrap();
+            let config = FlushConfig {
+                max_points_per_block: 2,
+                block_ordering: ordering,
+                ..FlushConfig::default()
+            };
+            let mut flush_manager = FlushManager::with_config(temp_dir.path().to_path_buf(), config);
+            let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+
+            {
+                let memtable_guard = memtable.write().await;
+                for name in ["cpu", "mem"] {
+                    let series = TimeSeries::new(name.to_string()).unwrap();
+                    for i in 0..4 {
+                        let point = DataPoint::new(1000 * (i + 1), i as f64, HashMap::new());
+                        memtable_guard.insert(&series, &point).await.unwrap();
+                    }
+                }
+            }
+
+            flush_manager.start_flush(memtable.clone()).await.unwrap();
+            let sstable = flush_manager.wait_for_flush().await.unwrap().unwrap();
+            memtable.read().await.release_flushed(&sstable).await.unwrap();
+
+            let layout: Vec<(String, i64)> = sstable
+                .scan_blocks()
+                .await
+                .into_iter()
+                .map(|block| (block.series_names[0].clone(), block.start_timestamp))
+                .collect();
+            let expected = match ordering {
+                BlockOrdering::BySeries => [("cpu", 1000), ("cpu", 3000), ("mem", 1000), ("mem", 3000)],
+                BlockOrdering::ByTime => [("cpu", 1000), ("mem", 1000), ("cpu", 3000), ("mem", 3000)],
+            };
+            let expected: Vec<(String, i64)> = expected.iter().map(|(s, t)| (s.to_string(), *t)).collect();
+            assert_eq!(layout, expected, "{:?}", ordering);
+
+            let router = QueryRouter::new(memtable.clone(), Arc::new(RwLock::new(vec![sstable])));
+            for name in ["cpu", "mem"] {
+                let points = router.route_query(&Query::with_series(0, 10_000, name.to_string())).await.unwrap();
+                let timestamps: Vec<i64> = points.iter().map(|p| p.timestamp()).collect();
+                assert_eq!(timestamps, vec![1000, 2000, 3000, 4000], "{:?} {}", ordering, name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_during_flush_are_not_lost() {
+        use crate::storage::lsm::query::{Query, QueryRouter};
+
+        let temp_dir = tempdir().unwrap();
+        let mut flush_manager = FlushManager::new(temp_dir.path().to_path_buf());
+        let memtable = Arc::new(RwLock::new(MemTable::new(100_000)));
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        for i in 0..1000 {
+            let point = DataPoint::new(i, i as f64, HashMap::new());
+            memtable.read().await.insert(&series, &point).await.unwrap();
+        }
+
+        flush_manager.start_flush(memtable.clone()).await.unwrap();
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let memtable = memtable.clone();
+                tokio::spawn(async move {
+                    let series = TimeSeries::new(format!("writer_{}", writer)).unwrap();
+                    for i in 0..250 {
+                        let point = DataPoint::new(i, i as f64, HashMap::new());
+                        memtable.read().await.insert(&series, &point).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let sstable = flush_manager.wait_for_flush().await.unwrap().unwrap();
+
+        assert_eq!(sstable.metadata.read().await.point_count, 1000);
+        assert_eq!(memtable.read().await.size().await, 1000);
+
+        // Until the caller publishes the SSTable, the flushed points are still read from the MemTable
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+        let router = QueryRouter::new(memtable.clone(), sstables.clone());
+        assert_eq!(router.route_query(&Query::new(0, 1000)).await.unwrap().len(), 2000);
+        sstables.write().await.push(Arc::clone(&sstable));
+        memtable.read().await.release_flushed(&sstable).await.unwrap();
+        assert!(!memtable.read().await.is_flushing().await);
+        assert_eq!(router.route_query(&Query::new(0, 1000)).await.unwrap().len(), 2000);
+    }
+}
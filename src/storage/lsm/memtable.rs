@@ -1,10 +1,14 @@
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tracing::{debug};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-use crate::storage::data::{DataPoint, TimeSeries};
+use crate::storage::data::{series_key, DataPoint, TimeSeries, Value};
+use crate::storage::lsm::sstable::{SSTable, SSTableError};
+use crate::storage::lsm::tombstone::RangeTombstone;
 
 /// Represents a single entry in the MemTable
 #[derive(Debug, Clone)]
@@ -13,34 +17,292 @@ struct MemTableEntry {
     point: DataPoint,
 }
 
+/// How to resolve two points of a series that share a timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Keep the most recently written point
+    KeepLast,
+    /// Keep the first written point
+    KeepFirst,
+    /// Add the values together, keeping the most recent tags; two integers
+    /// stay an integer unless their sum overflows
+    Sum,
+    /// Reject the duplicate
+    #[default]
+    Error,
+}
+
+impl DuplicatePolicy {
+    /// Resolves `newer` written after `existing`, returning the point to keep
+    ///
+    /// Returns `None` under [`DuplicatePolicy::Error`].
+    pub fn resolve(&self, existing: &DataPoint, newer: &DataPoint) -> Option<DataPoint> {
+        match self {
+            DuplicatePolicy::KeepLast => Some(newer.clone()),
+            DuplicatePolicy::KeepFirst => Some(existing.clone()),
+            DuplicatePolicy::Sum => {
+                let sum = match (existing.typed_value(), newer.typed_value()) {
+                    (Value::I64(a), Value::I64(b)) => {
+                        a.checked_add(b).map_or(Value::F64(a as f64 + b as f64), Value::I64)
+                    }
+                    (a, b) => Value::F64(a.as_f64() + b.as_f64()),
+                };
+                Some(DataPoint::with_value(newer.timestamp(), sum, newer.tags().clone()))
+            }
+            DuplicatePolicy::Error => None,
+        }
+    }
+}
+
+/// Returns the shard a series is stored in, out of `num_shards`
+///
+/// The mapping is `fnv1a(series) % num_shards`. FNV-1a is used rather than the
+/// standard library hasher so the mapping is stable across processes and Rust
+/// versions; it only changes if the number of shards does.
+pub fn shard_for(series: &str, num_shards: usize) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in series.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % num_shards.max(1) as u64) as usize
+}
+
+/// Points of several series, keyed by series name
+type SeriesPoints = HashMap<String, Vec<DataPoint>>;
+
+/// Points of the series in one shard
+type Shard = RwLock<SeriesPoints>;
+
+/// Returns a series' flushing and active points merged in timestamp order
+///
+/// A timestamp present in both buffers was rewritten during the flush, so only
+/// the active copy is returned.
+fn merge_buffers(flushing: Option<&Vec<DataPoint>>, active: Option<&Vec<DataPoint>>) -> Vec<DataPoint> {
+    let mut points: Vec<DataPoint> = flushing.into_iter().chain(active).flatten().cloned().collect();
+    if flushing.is_some_and(|points| !points.is_empty()) && active.is_some_and(|points| !points.is_empty()) {
+        // Stable, so a timestamp present in both buffers reads the newer write last
+        points.sort_by_key(|p| p.timestamp());
+        keep_newest(&mut points);
+    }
+    points
+}
+
+/// Keeps only the last of each run of points sharing a timestamp
+fn keep_newest(points: &mut Vec<DataPoint>) {
+    points.dedup_by(|newer, older| {
+        let duplicate = newer.timestamp() == older.timestamp();
+        if duplicate {
+            std::mem::swap(newer, older);
+        }
+        duplicate
+    });
+}
+
 /// The in-memory table that stores recent writes before they are flushed to disk
 pub struct MemTable {
-    /// The data stored in the MemTable, organized by series name
-    data: Arc<RwLock<HashMap<String, Vec<DataPoint>>>>,
+    /// The data stored in the MemTable, organized by series name and split into
+    /// independently locked shards by [`shard_for`]
+    shards: Arc<Vec<Shard>>,
     /// Maximum number of points allowed in the MemTable
     capacity: usize,
-    /// Current number of points in the MemTable
-    size: Arc<RwLock<usize>>,
+    /// Current number of points in the MemTable, updated under the shard lock
+    /// only, so writers to different shards never wait on each other
+    size: Arc<AtomicUsize>,
+    /// Maximum estimated size in bytes, when sized by memory rather than point count
+    byte_capacity: Option<usize>,
+    /// Estimated size in bytes of the stored points
+    bytes: Arc<AtomicUsize>,
+    /// How far behind a series' newest point a late point may arrive
+    out_of_order_window: Duration,
+    /// How points with an already stored timestamp are handled
+    duplicate_policy: DuplicatePolicy,
+    /// Points moved out of the shards by [`MemTable::freeze`] whose flush has
+    /// not completed yet
+    flushing: Arc<RwLock<Option<SeriesPoints>>>,
+    /// Deletes that removed flushing points, which the running flush still
+    /// writes out; changed only while holding the `flushing` write lock
+    flushing_deletes: Arc<RwLock<Vec<RangeTombstone>>>,
+    /// Number of accesses to each shard, used to verify shard targeting in tests
+    #[cfg(test)]
+    pub(crate) shard_accesses: Arc<Vec<AtomicUsize>>,
 }
 
 impl MemTable {
     /// Creates a new MemTable with the given capacity
     pub fn new(capacity: usize) -> Self {
+        Self::with_shards(capacity, 1)
+    }
+
+    /// Creates a new MemTable whose series are spread over `num_shards` shards
+    ///
+    /// Single-series reads and writes only lock the series' shard.
+    pub fn with_shards(capacity: usize, num_shards: usize) -> Self {
+        let num_shards = num_shards.max(1);
         Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
+            shards: Arc::new((0..num_shards).map(|_| RwLock::new(HashMap::new())).collect()),
             capacity,
-            size: Arc::new(RwLock::new(0)),
+            size: Arc::new(AtomicUsize::new(0)),
+            byte_capacity: None,
+            bytes: Arc::new(AtomicUsize::new(0)),
+            out_of_order_window: Duration::ZERO,
+            duplicate_policy: DuplicatePolicy::default(),
+            flushing: Arc::new(RwLock::new(None)),
+            flushing_deletes: Arc::new(RwLock::new(Vec::new())),
+            #[cfg(test)]
+            shard_accesses: Arc::new((0..num_shards).map(|_| AtomicUsize::new(0)).collect()),
+        }
+    }
+
+    /// Creates a new MemTable that needs flushing once its estimated size reaches `max_bytes`
+    ///
+    /// See [`DataPoint::estimated_size`] for how points are measured.
+    pub fn with_byte_capacity(max_bytes: usize) -> Self {
+        Self {
+            byte_capacity: Some(max_bytes),
+            ..Self::new(usize::MAX)
+        }
+    }
+
+    /// Creates an empty MemTable with the same capacity, shards and insert settings
+    pub fn empty_like(&self) -> Self {
+        Self {
+            byte_capacity: self.byte_capacity,
+            out_of_order_window: self.out_of_order_window,
+            duplicate_policy: self.duplicate_policy,
+            ..Self::with_shards(self.capacity, self.num_shards())
         }
     }
 
+    /// Accepts points up to `window` older than the newest point of their series
+    ///
+    /// Late points are inserted in timestamp order; points older than the window
+    /// are still rejected. The default window of zero requires strictly
+    /// increasing timestamps.
+    pub fn with_out_of_order_window(mut self, window: Duration) -> Self {
+        self.out_of_order_window = window;
+        self
+    }
+
+    /// Sets how points with an already stored timestamp are handled
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
     /// Returns the capacity of the MemTable
     pub fn capacity(&self) -> usize {
         self.capacity
     }
 
-    /// Returns the current data in the MemTable
+    /// Returns the number of shards
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the shard holding a series
+    fn shard(&self, series_name: &str) -> &Shard {
+        self.shard_at(shard_for(series_name, self.shards.len()))
+    }
+
+    fn shard_at(&self, index: usize) -> &Shard {
+        #[cfg(test)]
+        self.shard_accesses[index].fetch_add(1, Ordering::Relaxed);
+        &self.shards[index]
+    }
+
+    /// Returns the byte capacity, if the MemTable is sized by memory
+    pub fn byte_capacity(&self) -> Option<usize> {
+        self.byte_capacity
+    }
+
+    /// Returns the out-of-order window
+    pub fn out_of_order_window(&self) -> Duration {
+        self.out_of_order_window
+    }
+
+    /// Returns the duplicate timestamp policy
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    /// Returns the current data in the MemTable, including points still being flushed
     pub async fn get_data(&self) -> HashMap<String, Vec<DataPoint>> {
-        self.data.read().await.clone()
+        let flushing = self.flushing.read().await;
+        let mut data = flushing.clone().unwrap_or_default();
+        for shard in self.shards.iter() {
+            for (series_name, points) in shard.read().await.iter() {
+                let merged = merge_buffers(data.get(series_name), Some(points));
+                data.insert(series_name.clone(), merged);
+            }
+        }
+        data
+    }
+
+    /// Returns the identity of every buffered series, including series still being flushed
+    ///
+    /// Points are grouped by name and tag set, see [`series_key`], so tag sets
+    /// differing only in insertion order count as one series.
+    pub async fn series_keys(&self) -> BTreeSet<String> {
+        let flushing = self.flushing.read().await;
+        let mut keys = BTreeSet::new();
+        for (series_name, points) in flushing.iter().flatten() {
+            keys.extend(points.iter().map(|point| series_key(series_name, point.tags())));
+        }
+        for shard in self.shards.iter() {
+            for (series_name, points) in shard.read().await.iter() {
+                keys.extend(points.iter().map(|point| series_key(series_name, point.tags())));
+            }
+        }
+        keys
+    }
+
+    /// Moves every point into the flushing buffer, leaving the MemTable empty for new writes
+    ///
+    /// Returns the points to flush. Writes only wait for the points to be moved,
+    /// not for the flush. Reads keep seeing the moved points until
+    /// [`MemTable::release_flushed`] drops them once they are on disk. Points
+    /// left in the buffer by a flush that did not complete are returned again,
+    /// with points rewritten since replacing their older copies.
+    pub async fn freeze(&self) -> HashMap<String, Vec<DataPoint>> {
+        let mut flushing = self.flushing.write().await;
+        let frozen = flushing.get_or_insert_with(HashMap::new);
+        for shard in self.shards.iter() {
+            for (series_name, points) in shard.write().await.drain() {
+                self.release_counts(&points);
+                let buffered = frozen.entry(series_name).or_default();
+                let retried = !buffered.is_empty();
+                buffered.extend(points);
+                if retried {
+                    buffered.sort_by_key(|p| p.timestamp());
+                    keep_newest(buffered);
+                }
+            }
+        }
+
+        frozen.clone()
+    }
+
+    /// Drops the flushing buffer once its points are readable from `sstable`
+    ///
+    /// Call this only after `sstable` is visible to queries, so the points are
+    /// never missing from both. Deletes that removed flushing points while the
+    /// flush ran are recorded as tombstones on `sstable` first, as the flush
+    /// wrote those points out.
+    pub async fn release_flushed(&self, sstable: &SSTable) -> Result<(), SSTableError> {
+        let mut flushing = self.flushing.write().await;
+        let mut deletes = self.flushing_deletes.write().await;
+        for tombstone in deletes.iter() {
+            sstable.add_tombstone(tombstone.clone()).await?;
+        }
+        deletes.clear();
+        *flushing = None;
+        Ok(())
+    }
+
+    /// Returns true if frozen points are waiting for their flush to complete
+    pub async fn is_flushing(&self) -> bool {
+        self.flushing.read().await.is_some()
     }
 
     /// Inserts a data point into the MemTable
@@ -50,44 +312,158 @@ impl MemTable {
         series: &TimeSeries,
         point: &DataPoint,
     ) -> Result<bool, MemTableError> {
-        let mut size = self.size.write().await;
-        let mut data = self.data.write().await;
-
-        // Check if we need to flush after this insert
-        let needs_flush = (*size + 1) >= self.capacity;
+        // Taken before the shard, like `freeze`, so the series' frozen points
+        // count towards ordering and duplicates
+        let flushing = self.flushing.read().await;
+        let frozen = flushing
+            .as_ref()
+            .and_then(|data| data.get(series.name()))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut data = self.shard(series.name()).write().await;
 
         // Get or create the series vector
         let points = data.entry(series.name().to_string())
             .or_insert_with(Vec::new);
 
-        // Validate timestamp ordering
-        if let Some(last_point) = points.last() {
-            if point.timestamp() <= last_point.timestamp() {
-                return Err(MemTableError::InvalidTimestampOrder);
+        // Validate timestamp ordering, placing late points within the window in order
+        let newest = points.last().into_iter().chain(frozen.last()).map(|p| p.timestamp()).max();
+        let inserted = match newest {
+            Some(newest) if point.timestamp() <= newest => {
+                if newest.saturating_sub(point.timestamp()) > self.window_nanos() {
+                    return Err(MemTableError::InvalidTimestampOrder);
+                }
+                match points.binary_search_by_key(&point.timestamp(), |p| p.timestamp()) {
+                    Ok(position) => {
+                        let resolved = self
+                            .duplicate_policy
+                            .resolve(&points[position], point)
+                            .ok_or_else(|| MemTableError::duplicate(series.name(), point))?;
+                        let replaced = points[position].estimated_size();
+                        let added = resolved.estimated_size();
+                        points[position] = resolved;
+                        let bytes = if added >= replaced {
+                            self.bytes.fetch_add(added - replaced, Ordering::AcqRel) + added - replaced
+                        } else {
+                            self.bytes.fetch_sub(replaced - added, Ordering::AcqRel) - (replaced - added)
+                        };
+                        return Ok(self.is_over_capacity(self.size.load(Ordering::Acquire), bytes));
+                    }
+                    Err(position) => {
+                        // A frozen copy is resolved against, then shadowed by the active one
+                        let resolved = match frozen.binary_search_by_key(&point.timestamp(), |p| p.timestamp()) {
+                            Ok(frozen_position) => self
+                                .duplicate_policy
+                                .resolve(&frozen[frozen_position], point)
+                                .ok_or_else(|| MemTableError::duplicate(series.name(), point))?,
+                            Err(_) => point.clone(),
+                        };
+                        let inserted = resolved.estimated_size();
+                        points.insert(position, resolved);
+                        inserted
+                    }
+                }
             }
-        }
-
-        // Insert the point
-        points.push(point.clone());
-        *size += 1;
+            _ => {
+                points.push(point.clone());
+                point.estimated_size()
+            }
+        };
+        let size = self.size.fetch_add(1, Ordering::AcqRel) + 1;
+        let bytes = self.bytes.fetch_add(inserted, Ordering::AcqRel) + inserted;
 
         debug!(
             "Inserted point into MemTable: series={}, timestamp={}, size={}/{}",
             series.name(),
             point.timestamp(),
-            *size,
+            size,
             self.capacity
         );
 
-        Ok(needs_flush)
+        Ok(self.is_over_capacity(size, bytes))
+    }
+
+    /// Checks that [`MemTable::insert`] would accept every point of a batch, inserting none
+    ///
+    /// Each point is checked against the stored points and the points before
+    /// it in the batch, so a batch that passes inserts cleanly unless a
+    /// concurrent write to the same series gets in between.
+    pub async fn check_batch(&self, entries: &[(&TimeSeries, &DataPoint)]) -> Result<(), MemTableError> {
+        let flushing = self.flushing.read().await;
+        // Newest timestamp and every timestamp of each series in the batch so far
+        let mut batch: HashMap<&str, (Option<i64>, HashSet<i64>)> = HashMap::new();
+        for (series, point) in entries {
+            let timestamp = point.timestamp();
+            let frozen = flushing
+                .as_ref()
+                .and_then(|data| data.get(series.name()))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let shard = self.shard(series.name()).read().await;
+            let active = shard.get(series.name()).map(Vec::as_slice).unwrap_or_default();
+            let (batch_newest, batch_timestamps) = batch.entry(series.name()).or_default();
+
+            let newest = active.last().into_iter().chain(frozen.last()).map(|p| p.timestamp()).chain(*batch_newest).max();
+            if let Some(newest) = newest.filter(|newest| timestamp <= *newest) {
+                if newest.saturating_sub(timestamp) > self.window_nanos() {
+                    return Err(MemTableError::InvalidTimestampOrder);
+                }
+                let stored = |points: &[DataPoint]| points.binary_search_by_key(&timestamp, |p| p.timestamp()).is_ok();
+                if self.duplicate_policy == DuplicatePolicy::Error
+                    && (batch_timestamps.contains(&timestamp) || stored(active) || stored(frozen))
+                {
+                    return Err(MemTableError::duplicate(series.name(), point));
+                }
+            }
+            *batch_newest = Some(batch_newest.map_or(timestamp, |newest| newest.max(timestamp)));
+            batch_timestamps.insert(timestamp);
+        }
+        Ok(())
+    }
+
+    /// Returns the out-of-order window in nanoseconds
+    fn window_nanos(&self) -> i64 {
+        i64::try_from(self.out_of_order_window.as_nanos()).unwrap_or(i64::MAX)
+    }
+
+    /// Subtracts points moved or removed from the shards from the counters
+    fn release_counts(&self, points: &[DataPoint]) {
+        let bytes: usize = points.iter().map(DataPoint::estimated_size).sum();
+        self.size.fetch_sub(points.len(), Ordering::AcqRel);
+        self.bytes.fetch_sub(bytes, Ordering::AcqRel);
+    }
+
+    /// Returns true if the given point count or byte size calls for a flush
+    fn is_over_capacity(&self, size: usize, bytes: usize) -> bool {
+        match self.byte_capacity {
+            Some(max_bytes) => bytes >= max_bytes,
+            None => size >= self.capacity,
+        }
     }
 
     /// Returns all points within a time range
     pub async fn get_range(&self, start: i64, end: i64) -> Vec<(String, DataPoint)> {
-        let data = self.data.read().await;
         let mut result = Vec::new();
 
-        for (series_name, points) in data.iter() {
+        // Held across the shard reads so a concurrent freeze can't move points
+        // between buffers already read and buffers not yet read
+        let flushing = self.flushing.read().await;
+        let mut active_series = std::collections::HashSet::new();
+        for index in 0..self.shards.len() {
+            for (series_name, points) in self.shard_at(index).read().await.iter() {
+                active_series.insert(series_name.clone());
+                let frozen = flushing.as_ref().and_then(|data| data.get(series_name));
+                for point in merge_buffers(frozen, Some(points)) {
+                    if point.timestamp() >= start && point.timestamp() <= end {
+                        result.push((series_name.clone(), point));
+                    }
+                }
+            }
+        }
+        for (series_name, points) in flushing.iter().flatten() {
+            if active_series.contains(series_name) {
+                continue;
+            }
             for point in points {
                 if point.timestamp() >= start && point.timestamp() <= end {
                     result.push((series_name.clone(), point.clone()));
@@ -105,42 +481,75 @@ impl MemTable {
         start: i64,
         end: i64,
     ) -> Vec<DataPoint> {
-        let data = self.data.read().await;
-        if let Some(points) = data.get(series_name) {
-            points
-                .iter()
-                .filter(|p| p.timestamp() >= start && p.timestamp() <= end)
-                .cloned()
-                .collect()
-        } else {
-            Vec::new()
+        let flushing = self.flushing.read().await;
+        let data = self.shard(series_name).read().await;
+        let frozen = flushing.as_ref().and_then(|data| data.get(series_name));
+        let mut points = merge_buffers(frozen, data.get(series_name));
+        points.retain(|p| p.timestamp() >= start && p.timestamp() <= end);
+        points
+    }
+
+    /// Removes the points of a series within `start..=end`, returning how many were removed
+    ///
+    /// Points in the flushing buffer are removed as well. The running flush
+    /// still writes them out, so the deletion is kept as a tombstone that
+    /// [`MemTable::release_flushed`] records on the flushed SSTable.
+    pub async fn delete_range(&self, series_name: &str, start: i64, end: i64) -> usize {
+        let mut flushing = self.flushing.write().await;
+        let mut data = self.shard(series_name).write().await;
+
+        let mut frozen_removed = 0;
+        if let Some(points) = flushing.as_mut().and_then(|data| data.get_mut(series_name)) {
+            let before = points.len();
+            points.retain(|p| p.timestamp() < start || end < p.timestamp());
+            frozen_removed = before - points.len();
+        }
+        if frozen_removed > 0 {
+            self.flushing_deletes
+                .write()
+                .await
+                .push(RangeTombstone::new(series_name, start, end));
         }
+
+        let Some(points) = data.get_mut(series_name) else {
+            return frozen_removed;
+        };
+        let (deleted, kept): (Vec<DataPoint>, Vec<DataPoint>) = std::mem::take(points)
+            .into_iter()
+            .partition(|p| start <= p.timestamp() && p.timestamp() <= end);
+        *points = kept;
+        self.release_counts(&deleted);
+        deleted.len() + frozen_removed
     }
 
     /// Clears the MemTable and returns all entries
     pub async fn clear(&self) -> Vec<(String, DataPoint)> {
-        let mut data = self.data.write().await;
-        let mut size = self.size.write().await;
-
         let mut entries = Vec::new();
-        for (series_name, points) in data.drain() {
-            for point in points {
-                entries.push((series_name.clone(), point));
+        for shard in self.shards.iter() {
+            for (series_name, points) in shard.write().await.drain() {
+                self.release_counts(&points);
+                for point in points {
+                    entries.push((series_name.clone(), point));
+                }
             }
         }
 
-        *size = 0;
         entries
     }
 
-    /// Returns the current number of entries
+    /// Returns the current number of entries, excluding points being flushed
     pub async fn size(&self) -> usize {
-        *self.size.read().await
+        self.size.load(Ordering::Acquire)
+    }
+
+    /// Returns the estimated size in bytes of the stored points
+    pub async fn memory_bytes(&self) -> usize {
+        self.bytes.load(Ordering::Acquire)
     }
 
     /// Returns true if the MemTable is empty
     pub async fn is_empty(&self) -> bool {
-        *self.size.read().await == 0
+        self.size.load(Ordering::Acquire) == 0
     }
 }
 
@@ -150,6 +559,18 @@ pub enum MemTableError {
     Full,
     #[error("Invalid timestamp order")]
     InvalidTimestampOrder,
+    #[error("Duplicate point for series {series} at timestamp {timestamp}")]
+    DuplicateTimestamp { series: String, timestamp: i64 },
+}
+
+impl MemTableError {
+    /// Builds the error rejecting `point` as a duplicate under [`DuplicatePolicy::Error`]
+    fn duplicate(series: &str, point: &DataPoint) -> Self {
+        MemTableError::DuplicateTimestamp {
+            series: series.to_string(),
+            timestamp: point.timestamp(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -231,4 +652,164 @@ mod tests {
         assert_eq!(cleared.len(), 2);
         assert!(memtable.is_empty().await);
     }
+
+    #[test]
+    async fn test_memtable_out_of_order_window() {
+        let memtable = MemTable::new(1000).with_out_of_order_window(Duration::from_secs(10));
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+        let second = 1_000_000_000;
+
+        for ts in [100 * second, 105 * second] {
+            memtable.insert(&series, &DataPoint::new(ts, 1.0, HashMap::new())).await.unwrap();
+        }
+
+        // Within the window: placed in sorted position
+        memtable.insert(&series, &DataPoint::new(97 * second, 2.0, HashMap::new())).await.unwrap();
+        memtable.insert(&series, &DataPoint::new(102 * second, 3.0, HashMap::new())).await.unwrap();
+
+        // Older than the window, or an exact duplicate: rejected
+        assert!(matches!(
+            memtable.insert(&series, &DataPoint::new(94 * second, 4.0, HashMap::new())).await,
+            Err(MemTableError::InvalidTimestampOrder)
+        ));
+        assert!(matches!(
+            memtable.insert(&series, &DataPoint::new(102 * second, 5.0, HashMap::new())).await,
+            Err(MemTableError::DuplicateTimestamp { timestamp, .. }) if timestamp == 102 * second
+        ));
+
+        let timestamps: Vec<i64> = memtable
+            .get_series_range("test_series", 0, 200 * second)
+            .await
+            .iter()
+            .map(|p| p.timestamp() / second)
+            .collect();
+        assert_eq!(timestamps, vec![97, 100, 102, 105]);
+        assert_eq!(memtable.size().await, 4);
+
+        // Without a window, late points are rejected as before
+        let strict = MemTable::new(1000);
+        strict.insert(&series, &DataPoint::new(100, 1.0, HashMap::new())).await.unwrap();
+        assert!(strict.insert(&series, &DataPoint::new(99, 1.0, HashMap::new())).await.is_err());
+    }
+
+    #[test]
+    async fn test_memtable_byte_capacity() {
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+        let mut tags = std::collections::HashMap::new();
+        tags.insert("host".to_string(), "server1".to_string());
+        let point = DataPoint::new(1000, 42.0, tags.clone());
+
+        // 8 bytes timestamp + 8 bytes value + "host" + "server1"
+        assert_eq!(point.estimated_size(), 27);
+
+        let memtable = MemTable::with_byte_capacity(60);
+        assert!(!memtable.insert(&series, &point).await.unwrap());
+        assert!(!memtable.insert(&series, &DataPoint::new(1001, 1.0, tags.clone())).await.unwrap());
+        assert_eq!(memtable.memory_bytes().await, 54);
+
+        // The third point crosses the threshold
+        assert!(memtable.insert(&series, &DataPoint::new(1002, 1.0, tags)).await.unwrap());
+        assert_eq!(memtable.memory_bytes().await, 81);
+
+        memtable.clear().await;
+        assert_eq!(memtable.memory_bytes().await, 0);
+        assert_eq!(memtable.empty_like().byte_capacity(), Some(60));
+    }
+
+    #[tokio::test]
+    async fn test_reads_see_frozen_points_until_released() {
+        let memtable = MemTable::new(100).with_out_of_order_window(Duration::from_nanos(1500));
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        for timestamp in [1000, 3000] {
+            let point = DataPoint::new(timestamp, 1.0, HashMap::new());
+            memtable.insert(&series, &point).await.unwrap();
+        }
+
+        let frozen = memtable.freeze().await;
+        assert_eq!(frozen["cpu"].len(), 2);
+        assert!(memtable.is_empty().await);
+        assert!(memtable.is_flushing().await);
+
+        // New writes go to the active buffer, reads merge both
+        memtable.insert(&series, &DataPoint::new(2000, 2.0, HashMap::new())).await.unwrap();
+        let timestamps = |points: Vec<DataPoint>| -> Vec<i64> { points.iter().map(|p| p.timestamp()).collect() };
+        assert_eq!(timestamps(memtable.get_series_range("cpu", 0, 5000).await), vec![1000, 2000, 3000]);
+        assert_eq!(memtable.get_range(0, 5000).await.len(), 3);
+        assert_eq!(timestamps(memtable.get_data().await.remove("cpu").unwrap()), vec![1000, 2000, 3000]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let sstable = SSTable::new(dir.path().join("1.sst")).unwrap();
+        memtable.release_flushed(&sstable).await.unwrap();
+        assert_eq!(timestamps(memtable.get_series_range("cpu", 0, 5000).await), vec![2000]);
+    }
+
+    #[tokio::test]
+    async fn test_writes_during_flush_resolve_against_frozen_points() {
+        let memtable = MemTable::new(100)
+            .with_out_of_order_window(Duration::from_nanos(1500))
+            .with_duplicate_policy(DuplicatePolicy::KeepLast);
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        for timestamp in [1000, 2000, 3000] {
+            let point = DataPoint::new(timestamp, 1.0, HashMap::new());
+            memtable.insert(&series, &point).await.unwrap();
+        }
+        memtable.freeze().await;
+
+        // A rewrite shadows the frozen copy, a stale point is still rejected
+        memtable.insert(&series, &DataPoint::new(2000, 2.0, HashMap::new())).await.unwrap();
+        assert!(memtable.insert(&series, &DataPoint::new(500, 1.0, HashMap::new())).await.is_err());
+        let samples = |points: Vec<DataPoint>| -> Vec<(i64, f64)> {
+            points.iter().map(|p| (p.timestamp(), p.value())).collect()
+        };
+        let expected = vec![(1000, 1.0), (2000, 2.0), (3000, 1.0)];
+        assert_eq!(samples(memtable.get_series_range("cpu", 0, 5000).await), expected);
+        let routed: Vec<DataPoint> = memtable.get_range(0, 5000).await.into_iter().map(|(_, p)| p).collect();
+        assert_eq!(samples(routed), expected);
+
+        // A delete of frozen points outlives the flush that writes them
+        assert_eq!(memtable.delete_range("cpu", 2500, 3500).await, 1);
+        let dir = tempfile::tempdir().unwrap();
+        let sstable = SSTable::new(dir.path().join("1.sst")).unwrap();
+        memtable.release_flushed(&sstable).await.unwrap();
+        assert_eq!(sstable.tombstones().await, vec![RangeTombstone::new("cpu", 2500, 3500)]);
+        assert!(!memtable.is_flushing().await);
+    }
+
+    #[tokio::test]
+    async fn test_writers_to_other_shards_do_not_wait() {
+        let memtable = MemTable::with_shards(100, 4);
+        let held = TimeSeries::new("a".to_string()).unwrap();
+        let free = ["b", "c", "d", "e"]
+            .iter()
+            .find(|name| shard_for(name, 4) != shard_for("a", 4))
+            .map(|name| TimeSeries::new(name.to_string()).unwrap())
+            .unwrap();
+
+        let _locked = memtable.shards[shard_for(held.name(), 4)].write().await;
+        let point = DataPoint::new(1000, 1.0, HashMap::new());
+        let inserted = tokio::time::timeout(Duration::from_secs(1), memtable.insert(&free, &point)).await;
+        assert!(!inserted.unwrap().unwrap());
+        assert_eq!(memtable.size().await, 1);
+        assert_eq!(memtable.memory_bytes().await, point.estimated_size());
+    }
+
+    #[test]
+    async fn test_sum_keeps_integer_duplicates_integral() {
+        let memtable = MemTable::new(1000).with_duplicate_policy(DuplicatePolicy::Sum);
+        let series = TimeSeries::new("requests".to_string()).unwrap();
+        let large = (1 << 53) + 1;
+        for value in [Value::I64(large), Value::I64(2)] {
+            memtable.insert(&series, &DataPoint::with_value(1000, value, HashMap::new())).await.unwrap();
+        }
+        memtable.insert(&series, &DataPoint::with_value(2000, Value::I64(1), HashMap::new())).await.unwrap();
+        memtable.insert(&series, &DataPoint::new(2000, 0.5, HashMap::new())).await.unwrap();
+
+        let values: Vec<Value> = memtable
+            .get_series_range("requests", 0, 5000)
+            .await
+            .iter()
+            .map(|p| p.typed_value())
+            .collect();
+        assert_eq!(values, vec![Value::I64(large + 2), Value::F64(1.5)]);
+    }
 }
@@ -0,0 +1,102 @@
+//! K-way merge of time-ordered point sources
+//!
+//! MemTable series and SSTable blocks are each sorted by timestamp, so their
+//! union can be produced in order with a heap of source heads instead of
+//! buffering everything and sorting it.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::storage::data::DataPoint;
+
+/// Yields the points of several timestamp-ordered sources in timestamp order
+///
+/// Points sharing a timestamp come out in source order, and in their original
+/// order within a source, so the output matches a stable sort of the sources
+/// concatenated.
+pub struct MergeIterator<I: Iterator<Item = DataPoint>> {
+    sources: Vec<I>,
+    /// The next point of each source not yet exhausted
+    heads: Vec<Option<DataPoint>>,
+    /// `(timestamp, source)` of every head, smallest first
+    heap: BinaryHeap<Reverse<(i64, usize)>>,
+}
+
+impl<I: Iterator<Item = DataPoint>> MergeIterator<I> {
+    /// Merges sources that are each ordered by timestamp
+    pub fn new(sources: impl IntoIterator<Item = I>) -> Self {
+        let mut sources: Vec<I> = sources.into_iter().collect();
+        let mut heads = Vec::with_capacity(sources.len());
+        let mut heap = BinaryHeap::with_capacity(sources.len());
+        for (index, source) in sources.iter_mut().enumerate() {
+            let head = source.next();
+            if let Some(point) = &head {
+                heap.push(Reverse((point.timestamp(), index)));
+            }
+            heads.push(head);
+        }
+        Self { sources, heads, heap }
+    }
+}
+
+impl MergeIterator<std::vec::IntoIter<DataPoint>> {
+    /// Merges points in any order by splitting them into their sorted runs
+    ///
+    /// Input that is already mostly ordered, such as the concatenated results
+    /// of several sorted sources, splits into few runs and merges cheaply.
+    pub fn from_runs(points: Vec<DataPoint>) -> Self {
+        let mut runs: Vec<Vec<DataPoint>> = Vec::new();
+        for point in points {
+            match runs.last_mut() {
+                Some(run) if run.last().is_some_and(|last| last.timestamp() <= point.timestamp()) => run.push(point),
+                _ => runs.push(vec![point]),
+            }
+        }
+        Self::new(runs.into_iter().map(Vec::into_iter))
+    }
+}
+
+impl<I: Iterator<Item = DataPoint>> Iterator for MergeIterator<I> {
+    type Item = DataPoint;
+
+    fn next(&mut self) -> Option<DataPoint> {
+        let Reverse((_, index)) = self.heap.pop()?;
+        let next = self.sources[index].next();
+        if let Some(point) = &next {
+            self.heap.push(Reverse((point.timestamp(), index)));
+        }
+        std::mem::replace(&mut self.heads[index], next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn point(timestamp: i64, value: f64) -> DataPoint {
+        DataPoint::new(timestamp, value, HashMap::new())
+    }
+
+    #[test]
+    fn test_merge_matches_stable_sort() {
+        let sources = vec![
+            vec![point(1, 0.0), point(4, 0.0), point(4, 1.0), point(9, 0.0)],
+            vec![],
+            vec![point(2, 2.0), point(4, 2.0), point(10, 2.0)],
+            vec![point(0, 3.0), point(4, 3.0)],
+        ];
+        let samples = |points: Vec<DataPoint>| -> Vec<(i64, f64)> {
+            points.iter().map(|point| (point.timestamp(), point.value())).collect()
+        };
+        let mut expected: Vec<DataPoint> = sources.iter().flatten().cloned().collect();
+        expected.sort_by_key(|point| point.timestamp());
+        let expected = samples(expected);
+
+        let merged = MergeIterator::new(sources.clone().into_iter().map(Vec::into_iter)).collect();
+        assert_eq!(samples(merged), expected);
+
+        let concatenated: Vec<DataPoint> = sources.into_iter().flatten().collect();
+        assert_eq!(samples(MergeIterator::from_runs(concatenated).collect()), expected);
+    }
+}
@@ -1,11 +1,21 @@
+pub mod cache;
 pub mod memtable;
+pub mod merge;
 pub mod sstable;
 pub mod catalog;
 pub mod query;
 pub mod flush;
+pub mod compaction;
+pub mod recovery;
+pub mod tombstone;
 
+pub use cache::{BlockCache, BlockCacheConfig};
 pub use catalog::SSTableCatalog;
-pub use flush::{FlushError, FlushManager};
-pub use memtable::{MemTable, MemTableError};
+pub use compaction::{CompactionError, Compactor, TRASH_DIR};
+pub use flush::{BlockOrdering, FlushConfig, FlushError, FlushManager};
+pub use memtable::{DuplicatePolicy, MemTable, MemTableError};
+pub use merge::MergeIterator;
 pub use query::{Query, QueryRouter, TimeRange};
-pub use sstable::{DataBlock, SSTable, SSTableError, SSTableMetadata};
+pub use recovery::{recover_from_wal, recover_into, RecoveryError, RecoveryOutcome};
+pub use sstable::{BlockStream, DataBlock, SSTable, SSTableError, SSTableMetadata};
+pub use tombstone::{delete_range, RangeTombstone, Tombstone, TombstoneSet};
@@ -3,9 +3,12 @@ use tokio::sync::RwLock;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
+use crate::query::parser::ast::FilterExpr;
 use crate::storage::data::{DataPoint, TimeSeries};
 use crate::storage::lsm::memtable::MemTable;
-use crate::storage::lsm::sstable::{SSTable, DataBlock};
+use crate::storage::lsm::merge::MergeIterator;
+use crate::storage::lsm::sstable::{SSTable, DataBlock, SSTableError};
+use crate::storage::tag_index::{BlockRef, TagIndex};
 
 /// Represents a time range with start and end timestamps
 #[derive(Debug, Clone, Copy)]
@@ -38,6 +41,8 @@ pub struct Query {
     pub time_range: TimeRange,
     /// Optional series name filter
     pub series_name: Option<String>,
+    /// Optional tag filter; regex comparisons are not evaluated and always pass
+    pub filter: Option<FilterExpr>,
 }
 
 impl Query {
@@ -46,6 +51,7 @@ impl Query {
         Self {
             time_range: TimeRange::new(start, end),
             series_name: None,
+            filter: None,
         }
     }
 
@@ -54,8 +60,21 @@ impl Query {
         Self {
             time_range: TimeRange::new(start, end),
             series_name: Some(series_name),
+            filter: None,
         }
     }
+
+    /// Restricts the query to points matching a tag filter
+    pub fn with_filter(mut self, filter: FilterExpr) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    fn admits(&self, point_tags: &HashMap<String, String>, value: f64) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(point_tags, value).unwrap_or(true))
+    }
 }
 
 /// Manages query routing to appropriate storage components
@@ -64,6 +83,8 @@ pub struct QueryRouter {
     memtable: Arc<RwLock<MemTable>>,
     /// The SSTable catalog
     sstables: Arc<RwLock<Vec<Arc<SSTable>>>>,
+    /// Inverted tag index used to skip blocks that cannot match a filter
+    tag_index: Option<Arc<RwLock<TagIndex>>>,
 }
 
 impl QueryRouter {
@@ -72,62 +93,117 @@ impl QueryRouter {
         Self {
             memtable,
             sstables,
+            tag_index: None,
         }
     }
 
+    /// Uses an inverted tag index to avoid scanning blocks for filtered queries
+    ///
+    /// SSTables the index does not cover are scanned in full.
+    pub fn with_tag_index(mut self, tag_index: Arc<RwLock<TagIndex>>) -> Self {
+        self.tag_index = Some(tag_index);
+        self
+    }
+
     /// Routes a query to appropriate storage components
-    pub async fn route_query(&self, query: &Query) -> Vec<DataPoint> {
-        let mut results = Vec::new();
-        let mut seen_timestamps = HashSet::new();
+    ///
+    /// Points from the MemTable and from SSTables alike carry their tags. A
+    /// series has at most one point per timestamp, the MemTable's taking
+    /// precedence and then the SSTable with the highest sequence; points of
+    /// different series at the same timestamp are all kept.
+    ///
+    /// Fails if a block of an SSTable the query reaches cannot be read, rather
+    /// than answering without its points.
+    pub async fn route_query(&self, query: &Query) -> Result<Vec<DataPoint>, SSTableError> {
+        // Each MemTable series and each block is ordered by timestamp, so they
+        // are kept apart as sources for the final merge
+        let mut sources: Vec<Vec<DataPoint>> = Vec::new();
+        let mut seen: HashSet<(String, i64)> = HashSet::new();
 
         // First, check MemTable for more recent data
         let memtable = self.memtable.read().await;
-        let memtable_points = if let Some(series_name) = &query.series_name {
+        let memtable_points: Vec<(String, DataPoint)> = if let Some(series_name) = &query.series_name {
             memtable.get_series_range(series_name, query.time_range.start, query.time_range.end).await
-        } else {
-            memtable.get_range(query.time_range.start, query.time_range.end).await
                 .into_iter()
-                .map(|(_, point)| point)
+                .map(|point| (series_name.clone(), point))
                 .collect()
+        } else {
+            memtable.get_range(query.time_range.start, query.time_range.end).await
         };
         
-        // Add MemTable points first
-        for point in memtable_points {
+        // Add MemTable points first, whose series come one after another. Every
+        // point in range shadows older copies of itself, even one the filter rejects
+        let mut current_series = None;
+        for (series_name, point) in memtable_points {
+            if current_series.as_ref() != Some(&series_name) {
+                sources.push(Vec::new());
+                current_series = Some(series_name.clone());
+            }
             if query.time_range.contains(point.timestamp()) {
-                seen_timestamps.insert(point.timestamp());
-                results.push(point);
+                seen.insert((series_name, point.timestamp()));
+                if query.admits(point.tags(), point.value()) {
+                    sources.last_mut().expect("a source exists for the series").push(point);
+                }
             }
         }
 
-        // Then check SSTables for older data
-        let sstables = self.sstables.read().await;
+        // Resolve the filter to candidate blocks when the tag index can answer it
+        let tag_index = match &self.tag_index {
+            Some(tag_index) if query.filter.is_some() => Some(tag_index.read().await),
+            _ => None,
+        };
+        let candidates = match (&tag_index, &query.filter) {
+            (Some(tag_index), Some(filter)) => tag_index.lookup(filter),
+            _ => None,
+        };
+
+        // Then check SSTables for older data, newest first so their points win
+        let mut sstables = self.sstables.read().await.clone();
+        sstables.sort_by_key(|sstable| std::cmp::Reverse(sstable.sequence));
         for sstable in sstables.iter() {
-            for block in sstable.scan_blocks().await {
+            let indexed = tag_index.as_ref().is_some_and(|index| index.contains_sstable(sstable));
+            let deletions = sstable.tombstones().await;
+            // Blocks overlapping a selected block of their series are read and
+            // merged with it, so a newer point in a pruned block still wins
+            let mut stream = sstable
+                .block_stream_selecting(|block_index, block| {
+                    block.start_timestamp <= query.time_range.end
+                        && block.max_timestamp >= query.time_range.start
+                        && query.series_name.as_ref().is_none_or(|name| block.series_names.contains(name))
+                        && candidates
+                            .as_ref()
+                            .filter(|_| indexed)
+                            .is_none_or(|candidates| candidates.contains(&BlockRef::new(sstable, block_index)))
+                })
+                .await;
+            while let Some(block) = stream.next().await {
+                let block = block?;
                 if block.start_timestamp <= query.time_range.end {
-                    let mut current_timestamp = block.start_timestamp;
-                    let filtered_points = block.timestamp_deltas.iter()
-                        .zip(block.values.iter())
-                        .zip(block.series_names.iter())
-                        .filter_map(|((&delta, &value), series_name)| {
-                            current_timestamp += delta;
+                    // Only the points within the time range are visited
+                    let range = block.search_range(query.time_range.start, query.time_range.end);
+                    let timestamps = block.timestamps();
+                    let filtered_points = timestamps[range.clone()].iter()
+                        .zip(&block.values[range.clone()])
+                        .zip(&block.series_names[range.clone()])
+                        .zip(&block.tags[range])
+                        .filter_map(|(((&current_timestamp, &value), series_name), point_tags)| {
                             if query.time_range.contains(current_timestamp) &&
-                               query.series_name.as_ref().map_or(true, |name| series_name == name) &&
-                               !seen_timestamps.contains(&current_timestamp) {
-                                seen_timestamps.insert(current_timestamp);
-                                Some(DataPoint::new(current_timestamp, value, HashMap::new()))
+                               query.series_name.as_ref().is_none_or(|name| series_name == name) &&
+                               seen.insert((series_name.clone(), current_timestamp)) &&
+                               query.admits(point_tags, value.as_f64()) &&
+                               !deletions.iter().any(|t| t.covers(series_name, current_timestamp)) {
+                                Some(DataPoint::with_value(current_timestamp, value, point_tags.clone()))
                             } else {
                                 None
                             }
                         })
                         .collect::<Vec<_>>();
-                    results.extend(filtered_points);
+                    sources.push(filtered_points);
                 }
             }
         }
 
-        // Sort results by timestamp
-        results.sort_by_key(|point| point.timestamp());
-        results
+        Ok(MergeIterator::new(sources.into_iter().map(Vec::into_iter)).collect())
     }
 }
 
@@ -179,13 +255,13 @@ mod tests {
 
         // Create an SSTable with older data
         let sstable = SSTable::new(&sstable_path).unwrap();
-        let block = DataBlock {
-            start_timestamp: 100,
-            timestamp_deltas: vec![0, 50],
-            values: vec![0.5, 1.5],
-            series_names: vec!["test_series".to_string(), "test_series".to_string()],
-            tags: vec![HashMap::new(), HashMap::new()],
-        };
+        let block = DataBlock::new(
+            100,
+            vec![0, 50],
+            vec![0.5.into(), 1.5.into()],
+            vec!["test_series".to_string(), "test_series".to_string()],
+            vec![HashMap::new(), HashMap::new()],
+        );
         sstable.write_block(block).await.unwrap();
 
         // Create query router
@@ -194,7 +270,7 @@ mod tests {
 
         // Query that spans both MemTable and SSTable
         let query = Query::with_series(90, 210, "test_series".to_string());
-        let results = router.route_query(&query).await;
+        let results = router.route_query(&query).await.unwrap();
 
         // Verify results
         assert_eq!(results.len(), 3);
@@ -223,13 +299,13 @@ mod tests {
 
         // Create an SSTable with older data
         let sstable = SSTable::new(&sstable_path).unwrap();
-        let block = DataBlock {
-            start_timestamp: 100,
-            timestamp_deltas: vec![0, 50],
-            values: vec![0.5, 1.5],
-            series_names: vec!["test_series".to_string(), "test_series".to_string()],
-            tags: vec![HashMap::new(), HashMap::new()],
-        };
+        let block = DataBlock::new(
+            100,
+            vec![0, 50],
+            vec![0.5.into(), 1.5.into()],
+            vec!["test_series".to_string(), "test_series".to_string()],
+            vec![HashMap::new(), HashMap::new()],
+        );
         sstable.write_block(block).await.unwrap();
 
         // Create query router
@@ -238,20 +314,20 @@ mod tests {
 
         // Test exact point queries
         let query1 = Query::with_series(150, 150, "test_series".to_string());
-        let results1 = router.route_query(&query1).await;
+        let results1 = router.route_query(&query1).await.unwrap();
         assert_eq!(results1.len(), 1);
         assert_eq!(results1[0].timestamp(), 150);
         assert_eq!(results1[0].value(), 1.0);
 
         let query2 = Query::with_series(100, 100, "test_series".to_string());
-        let results2 = router.route_query(&query2).await;
+        let results2 = router.route_query(&query2).await.unwrap();
         assert_eq!(results2.len(), 1);
         assert_eq!(results2[0].timestamp(), 100);
         assert_eq!(results2[0].value(), 0.5);
 
         // Test non-existent point
         let query3 = Query::with_series(300, 300, "test_series".to_string());
-        let results3 = router.route_query(&query3).await;
+        let results3 = router.route_query(&query3).await.unwrap();
         assert!(results3.is_empty());
     }
 
@@ -272,13 +348,13 @@ mod tests {
 
         // Create an SSTable with older data
         let sstable = SSTable::new(&sstable_path).unwrap();
-        let block = DataBlock {
-            start_timestamp: 100,
-            timestamp_deltas: vec![0, 50],
-            values: vec![0.5, 1.5],
-            series_names: vec!["test_series".to_string(), "test_series".to_string()],
-            tags: vec![HashMap::new(), HashMap::new()],
-        };
+        let block = DataBlock::new(
+            100,
+            vec![0, 50],
+            vec![0.5.into(), 1.5.into()],
+            vec!["test_series".to_string(), "test_series".to_string()],
+            vec![HashMap::new(), HashMap::new()],
+        );
         sstable.write_block(block).await.unwrap();
 
         // Create query router
@@ -287,7 +363,7 @@ mod tests {
 
         // Test complete range query
         let query = Query::with_series(90, 210, "test_series".to_string());
-        let results = router.route_query(&query).await;
+        let results = router.route_query(&query).await.unwrap();
 
         // Verify all points are present and in order
         assert_eq!(results.len(), 3);
@@ -300,7 +376,7 @@ mod tests {
 
         // Test partial range query
         let query2 = Query::with_series(120, 170, "test_series".to_string());
-        let results2 = router.route_query(&query2).await;
+        let results2 = router.route_query(&query2).await.unwrap();
         assert_eq!(results2.len(), 1);
         assert_eq!(results2[0].timestamp(), 150);
         assert_eq!(results2[0].value(), 1.0);
@@ -323,13 +399,13 @@ mod tests {
 
         // Create an SSTable with older data
         let sstable = SSTable::new(&sstable_path).unwrap();
-        let block = DataBlock {
-            start_timestamp: 100,
-            timestamp_deltas: vec![0, 50],
-            values: vec![0.5, 1.5],
-            series_names: vec!["test_series".to_string(), "test_series".to_string()],
-            tags: vec![HashMap::new(), HashMap::new()],
-        };
+        let block = DataBlock::new(
+            100,
+            vec![0, 50],
+            vec![0.5.into(), 1.5.into()],
+            vec!["test_series".to_string(), "test_series".to_string()],
+            vec![HashMap::new(), HashMap::new()],
+        );
         sstable.write_block(block).await.unwrap();
 
         // Create query router
@@ -338,7 +414,7 @@ mod tests {
 
         // Test initial state
         let query1 = Query::with_series(90, 210, "test_series".to_string());
-        let results1 = router.route_query(&query1).await;
+        let results1 = router.route_query(&query1).await.unwrap();
         assert_eq!(results1.len(), 3);
 
         // Add new data to MemTable
@@ -348,20 +424,20 @@ mod tests {
 
         // Verify new data is immediately available
         let query2 = Query::with_series(90, 260, "test_series".to_string());
-        let results2 = router.route_query(&query2).await;
+        let results2 = router.route_query(&query2).await.unwrap();
         assert_eq!(results2.len(), 4);
         assert_eq!(results2[3].timestamp(), 250);
         assert_eq!(results2[3].value(), 3.0);
 
         // Add new SSTable
         let sstable2 = SSTable::new(&temp_dir.path().join("test2.sst")).unwrap();
-        let block2 = DataBlock {
-            start_timestamp: 300,
-            timestamp_deltas: vec![0, 50],
-            values: vec![4.0, 5.0],
-            series_names: vec!["test_series".to_string(), "test_series".to_string()],
-            tags: vec![HashMap::new(), HashMap::new()],
-        };
+        let block2 = DataBlock::new(
+            300,
+            vec![0, 50],
+            vec![4.0.into(), 5.0.into()],
+            vec!["test_series".to_string(), "test_series".to_string()],
+            vec![HashMap::new(), HashMap::new()],
+        );
         sstable2.write_block(block2).await.unwrap();
 
         // Update SSTable list
@@ -371,11 +447,285 @@ mod tests {
 
         // Verify new SSTable data is available
         let query3 = Query::with_series(90, 360, "test_series".to_string());
-        let results3 = router.route_query(&query3).await;
+        let results3 = router.route_query(&query3).await.unwrap();
         assert_eq!(results3.len(), 6);
         assert_eq!(results3[4].timestamp(), 300);
         assert_eq!(results3[4].value(), 4.0);
         assert_eq!(results3[5].timestamp(), 350);
         assert_eq!(results3[5].value(), 5.0);
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_tag_index_skips_non_matching_blocks() {
+        use crate::query::parser::ast::{TagFilter, TagFilterOp};
+        use std::sync::atomic::Ordering;
+
+        let temp_dir = tempdir().unwrap();
+        let region_tags = |region: &str| {
+            let mut tags = HashMap::new();
+            tags.insert("region".to_string(), region.to_string());
+            tags
+        };
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let series = TimeSeries::new("cpu".to_string()).unwrap();
+            let guard = memtable.write().await;
+            guard.insert(&series, &DataPoint::new(500, 5.0, region_tags("us-west"))).await.unwrap();
+            guard.insert(&series, &DataPoint::new(510, 6.0, region_tags("us-east"))).await.unwrap();
+        }
+
+        let sstable = SSTable::new(temp_dir.path().join("1.sst")).unwrap();
+        for (start, region) in [(100, "us-west"), (200, "us-east"), (300, "us-east")] {
+            let points: Vec<DataPoint> = (0..3)
+                .map(|i| DataPoint::new(start + i, i as f64, region_tags(region)))
+                .collect();
+            sstable.write_block(DataBlock::from_points("cpu", &points)).await.unwrap();
+        }
+        let tag_index = crate::storage::tag_index::TagIndex::builder()
+            .add_sstable(&sstable)
+            .await
+            .unwrap()
+            .build();
+        sstable.blocks_read.store(0, Ordering::Relaxed);
+
+        let sstable = Arc::new(sstable);
+        let router = QueryRouter::new(memtable, Arc::new(RwLock::new(vec![sstable.clone()])))
+            .with_tag_index(Arc::new(RwLock::new(tag_index)));
+        let query = Query::new(0, 1000).with_filter(FilterExpr::TagFilter(TagFilter {
+            key: "region".to_string(),
+            op: TagFilterOp::Eq,
+            value: "us-west".to_string(),
+        }));
+        let timestamps: Vec<i64> = router
+            .route_query(&query)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.timestamp())
+            .collect();
+
+        assert_eq!(timestamps, vec![100, 101, 102, 500]);
+        // Only the us-west block was read
+        assert_eq!(sstable.blocks_read.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_single_series_query_touches_one_shard() {
+        use crate::storage::lsm::memtable::shard_for;
+        use std::sync::atomic::Ordering;
+
+        assert_eq!(shard_for("cpu.usage", 8), shard_for("cpu.usage", 8));
+        assert!(shard_for("cpu.usage", 8) < 8);
+
+        let memtable = MemTable::with_shards(1000, 8);
+        for (i, name) in ["cpu", "mem", "disk", "net"].iter().enumerate() {
+            let series = TimeSeries::new(name.to_string()).unwrap();
+            memtable.insert(&series, &DataPoint::new(i as i64, 1.0, HashMap::new())).await.unwrap();
+        }
+        let accesses = memtable.shard_accesses.clone();
+        for counter in accesses.iter() {
+            counter.store(0, Ordering::Relaxed);
+        }
+
+        let router = QueryRouter::new(Arc::new(RwLock::new(memtable)), Arc::new(RwLock::new(Vec::new())));
+        let results = router.route_query(&Query::with_series(0, 10, "disk".to_string())).await.unwrap();
+        assert_eq!(results.len(), 1);
+
+        let touched: Vec<usize> = (0..8).filter(|&i| accesses[i].load(Ordering::Relaxed) > 0).collect();
+        assert_eq!(touched, vec![shard_for("disk", 8)]);
+    }
+
+    #[tokio::test]
+    async fn test_and_filter_across_memtable_and_sstable() {
+        use crate::query::parser::ast::{TagFilter, TagFilterOp};
+
+        let temp_dir = tempdir().unwrap();
+        let tags = |region: &str, env: &str| {
+            let mut tags = HashMap::new();
+            tags.insert("region".to_string(), region.to_string());
+            tags.insert("env".to_string(), env.to_string());
+            tags
+        };
+
+        // Older points of two series in an SSTable
+        let sstable = SSTable::new(temp_dir.path().join("1.sst")).unwrap();
+        for (name, start, region, env) in [
+            ("cpu", 100, "us-west", "prod"),
+            ("cpu", 200, "us-east", "prod"),
+            ("mem", 300, "us-west", "prod"),
+            ("mem", 400, "us-west", "dev"),
+        ] {
+            let points: Vec<DataPoint> = (0..2)
+                .map(|i| DataPoint::new(start + i, i as f64, tags(region, env)))
+                .collect();
+            sstable.write_block(DataBlock::from_points(name, &points)).await.unwrap();
+        }
+
+        // Newer points of the same series in the MemTable
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        {
+            let guard = memtable.write().await;
+            for (name, ts, region, env) in [
+                ("cpu", 500, "us-west", "prod"),
+                ("mem", 510, "us-west", "dev"),
+                ("disk", 520, "us-west", "prod"),
+            ] {
+                let series = TimeSeries::new(name.to_string()).unwrap();
+                guard.insert(&series, &DataPoint::new(ts, 1.0, tags(region, env))).await.unwrap();
+            }
+        }
+
+        let router = QueryRouter::new(memtable, Arc::new(RwLock::new(vec![Arc::new(sstable)])));
+        let tag = |key: &str, value: &str| {
+            Box::new(FilterExpr::TagFilter(TagFilter {
+                key: key.to_string(),
+                op: TagFilterOp::Eq,
+                value: value.to_string(),
+            }))
+        };
+        let query = Query::new(0, 1000).with_filter(FilterExpr::And(tag("region", "us-west"), tag("env", "prod")));
+        let results = router.route_query(&query).await.unwrap();
+
+        let timestamps: Vec<i64> = results.iter().map(|p| p.timestamp()).collect();
+        assert_eq!(timestamps, vec![100, 101, 300, 301, 500, 520]);
+        // Points read back from the SSTable keep their tags
+        for point in &results {
+            assert_eq!(point.tags().get("region").map(String::as_str), Some("us-west"));
+            assert_eq!(point.tags().get("env").map(String::as_str), Some("prod"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_series_sharing_timestamps_are_not_collapsed() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let points = |value: f64| -> Vec<DataPoint> {
+            (1..=3).map(|i| DataPoint::new(i * 1000, value, HashMap::new())).collect()
+        };
+
+        // Both series in one SSTable, and both again in the MemTable at a later time
+        let sstable = SSTable::new(temp_dir.path().join("test.sst")).unwrap();
+        sstable.write_block(DataBlock::from_points("cpu", &points(1.0))).await.unwrap();
+        sstable.write_block(DataBlock::from_points("mem", &points(2.0))).await.unwrap();
+        {
+            let guard = memtable.write().await;
+            for name in ["cpu", "mem"] {
+                let series = TimeSeries::new(name.to_string()).unwrap();
+                guard.insert(&series, &DataPoint::new(3000, 9.0, HashMap::new())).await.unwrap();
+                guard.insert(&series, &DataPoint::new(4000, 9.0, HashMap::new())).await.unwrap();
+            }
+        }
+
+        let router = QueryRouter::new(memtable, Arc::new(RwLock::new(vec![Arc::new(sstable)])));
+        let results = router.route_query(&Query::new(0, 10_000)).await.unwrap();
+        let mut samples: Vec<(i64, f64)> = results.iter().map(|p| (p.timestamp(), p.value())).collect();
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            samples,
+            vec![(1000, 1.0), (1000, 2.0), (2000, 1.0), (2000, 2.0), (3000, 9.0), (3000, 9.0), (4000, 9.0), (4000, 9.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sstable_points_keep_their_tags() {
+        let temp_dir = tempdir().unwrap();
+        let points: Vec<DataPoint> = (0..4)
+            .map(|i| {
+                let mut tags = HashMap::new();
+                tags.insert("host".to_string(), format!("server{}", i % 2));
+                DataPoint::new(1000 * (i + 1), i as f64, tags)
+            })
+            .collect();
+        let sstable = SSTable::new(temp_dir.path().join("test.sst")).unwrap();
+        sstable.write_block(DataBlock::from_points("cpu", &points)).await.unwrap();
+
+        let router = QueryRouter::new(
+            Arc::new(RwLock::new(MemTable::new(1000))),
+            Arc::new(RwLock::new(vec![Arc::new(sstable)])),
+        );
+        let results = router.route_query(&Query::with_series(1500, 4000, "cpu".to_string())).await.unwrap();
+        let hosts: Vec<(i64, &str)> = results
+            .iter()
+            .map(|p| (p.timestamp(), p.tags()["host"].as_str()))
+            .collect();
+        assert_eq!(hosts, vec![(2000, "server1"), (3000, "server0"), (4000, "server1")]);
+    }
+
+    #[tokio::test]
+    async fn test_newest_sstable_wins_regardless_of_scan_order() {
+        let temp_dir = tempdir().unwrap();
+        let write_table = |name: &str, value: f64| {
+            let sstable = SSTable::new(temp_dir.path().join(name)).unwrap();
+            async move {
+                let point = DataPoint::new(1000, value, HashMap::new());
+                sstable.write_block(DataBlock::from_points("cpu", &[point])).await.unwrap();
+                Arc::new(sstable)
+            }
+        };
+        let older = write_table("older.sst", 1.0).await;
+        let newer = write_table("newer.sst", 2.0).await;
+
+        for tables in [vec![older.clone(), newer.clone()], vec![newer.clone(), older.clone()]] {
+            let router = QueryRouter::new(Arc::new(RwLock::new(MemTable::new(1000))), Arc::new(RwLock::new(tables)));
+            let results = router.route_query(&Query::with_series(0, 2000, "cpu".to_string())).await.unwrap();
+            let values: Vec<f64> = results.iter().map(|point| point.value()).collect();
+            assert_eq!(values, vec![2.0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filtered_out_points_still_shadow_older_copies() {
+        use crate::query::parser::ast::{ValueFilter, ValueFilterOp};
+
+        let temp_dir = tempdir().unwrap();
+        let write_table = |name: &str, timestamp: i64, value: f64| {
+            let sstable = SSTable::new(temp_dir.path().join(name)).unwrap();
+            async move {
+                let point = DataPoint::new(timestamp, value, HashMap::new());
+                sstable.write_block(DataBlock::from_points("cpu", &[point])).await.unwrap();
+                Arc::new(sstable)
+            }
+        };
+        let older = write_table("older.sst", 1000, 1.0).await;
+        let newer = write_table("newer.sst", 1000, 50.0).await;
+        let flushed = write_table("flushed.sst", 2000, 2.0).await;
+
+        // The MemTable holds the newest copy of the point at 2000
+        let memtable = MemTable::new(1000);
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        memtable.insert(&series, &DataPoint::new(2000, 60.0, HashMap::new())).await.unwrap();
+
+        let router = QueryRouter::new(
+            Arc::new(RwLock::new(memtable)),
+            Arc::new(RwLock::new(vec![older, newer, flushed])),
+        );
+        let query = Query::with_series(0, 3000, "cpu".to_string()).with_filter(FilterExpr::ValueFilter(ValueFilter {
+            op: ValueFilterOp::Lt,
+            value: 10.0,
+        }));
+        // Neither stale copy passes for the point that replaced it
+        assert!(router.route_query(&query).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_block_fails_the_query() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("corrupt.sst");
+        {
+            let sstable = SSTable::new(&path).unwrap();
+            let points: Vec<DataPoint> = (0..4).map(|i| DataPoint::new(i * 100, i as f64, HashMap::new())).collect();
+            sstable.write_block(DataBlock::from_points("cpu", &points)).await.unwrap();
+        }
+        // Flip the last byte of the block body, which its checksum covers
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let router = QueryRouter::new(
+            Arc::new(RwLock::new(MemTable::new(1000))),
+            Arc::new(RwLock::new(vec![Arc::new(SSTable::open(&path).unwrap())])),
+        );
+        assert!(router.route_query(&Query::with_series(0, 1000, "cpu".to_string())).await.is_err());
+    }
+}
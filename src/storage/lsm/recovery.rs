@@ -0,0 +1,258 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::storage::data::{DataError, DataPoint, TimeSeries};
+use crate::storage::lsm::catalog::SSTableCatalog;
+use crate::storage::lsm::flush::{FlushError, FlushManager};
+use crate::storage::lsm::memtable::{MemTable, MemTableError};
+use crate::storage::lsm::sstable::{SSTable, SSTableError};
+use crate::storage::lsm::tombstone::{self, RangeTombstone};
+use crate::storage::wal::{Replayed, WalError, WriteAheadLog};
+
+/// Error type for WAL recovery
+#[derive(Debug, thiserror::Error)]
+pub enum RecoveryError {
+    #[error("WAL error: {0}")]
+    Wal(#[from] WalError),
+    #[error("MemTable error: {0}")]
+    MemTable(#[from] MemTableError),
+    #[error("Flush error: {0}")]
+    Flush(#[from] FlushError),
+    #[error("Catalog error: {0}")]
+    Catalog(#[from] SSTableError),
+    #[error("Invalid series in WAL: {0}")]
+    Data(#[from] DataError),
+}
+
+/// Summary of a completed recovery
+#[derive(Debug, Default)]
+pub struct RecoveryOutcome {
+    /// Points replayed from the WAL
+    pub points: usize,
+    /// Points the MemTable rejected as duplicates or too far out of order
+    pub skipped: usize,
+    /// SSTables flushed while replaying, oldest first
+    pub sstables: Vec<Arc<SSTable>>,
+    /// Deletes replayed, in WAL order; they are already applied to the MemTable
+    /// and to `sstables`, but not to SSTables that existed before the recovery
+    pub deletes: Vec<RangeTombstone>,
+}
+
+/// Reads a segment's points and deletes in the order they were logged
+fn read_segment(wal: &WriteAheadLog, path: &std::path::Path) -> Result<Vec<(String, Replayed)>, WalError> {
+    let mut entries = Vec::new();
+    wal.replay_segment_entries(path, &mut |series_name, entry| {
+        entries.push((series_name.to_string(), entry));
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Replays the WAL into the MemTable, flushing it to SSTables whenever it fills
+///
+/// Segments are replayed one at a time, so memory use is bounded by the largest
+/// segment plus the MemTable capacity rather than by the size of the whole WAL.
+/// Every flushed SSTable is added to the catalog. Points replayed after the last
+/// flush stay in the MemTable, as they would have before the restart. Deletes
+/// are applied to the points replayed before them, see [`RecoveryOutcome::deletes`].
+/// Points are reordered and rejected points skipped as in [`recover_into`].
+pub async fn recover_from_wal(
+    wal: &WriteAheadLog,
+    memtable: Arc<RwLock<MemTable>>,
+    flush_manager: &mut FlushManager,
+    catalog: &SSTableCatalog,
+) -> Result<RecoveryOutcome, RecoveryError> {
+    let mut outcome = RecoveryOutcome::default();
+
+    for segment in wal.segments()? {
+        let mut points: Vec<(String, DataPoint)> = Vec::new();
+        for (series_name, entry) in read_segment(wal, &segment.path)? {
+            match entry {
+                Replayed::Point(point) => points.push((series_name, point)),
+                Replayed::Delete { start, end } => {
+                    let points = std::mem::take(&mut points);
+                    insert_and_flush(&memtable, flush_manager, catalog, points, &mut outcome).await?;
+                    tombstone::delete_range(&*memtable.read().await, &outcome.sstables, &series_name, start, end)
+                        .await?;
+                    outcome.deletes.push(RangeTombstone::new(&series_name, start, end));
+                }
+            }
+        }
+        insert_and_flush(&memtable, flush_manager, catalog, points, &mut outcome).await?;
+    }
+
+    info!(
+        "Recovered {} points from the WAL, flushing {} SSTables",
+        outcome.points,
+        outcome.sstables.len()
+    );
+    Ok(outcome)
+}
+
+/// Replays the WAL into a MemTable, without flushing it
+///
+/// Each segment's points are re-inserted in timestamp order (a stable sort, so
+/// repeated timestamps keep their WAL order), which lets points the MemTable
+/// originally accepted out of order replay cleanly. Points it still rejects as
+/// out of order, or as duplicates under its `DuplicatePolicy`, are skipped and
+/// counted rather than failing the recovery. Points are only reordered between
+/// deletes, which apply to the points replayed before them. The MemTable must
+/// be able to hold the whole WAL; use [`recover_from_wal`] to flush while replaying.
+pub async fn recover_into(
+    wal: &WriteAheadLog,
+    memtable: &MemTable,
+) -> Result<RecoveryOutcome, RecoveryError> {
+    let mut outcome = RecoveryOutcome::default();
+
+    for segment in wal.segments()? {
+        let mut points: Vec<(String, DataPoint)> = Vec::new();
+        for (series_name, entry) in read_segment(wal, &segment.path)? {
+            match entry {
+                Replayed::Point(point) => points.push((series_name, point)),
+                Replayed::Delete { start, end } => {
+                    insert_sorted(memtable, std::mem::take(&mut points), &mut outcome).await?;
+                    memtable.delete_range(&series_name, start, end).await;
+                    outcome.deletes.push(RangeTombstone::new(&series_name, start, end));
+                }
+            }
+        }
+        insert_sorted(memtable, points, &mut outcome).await?;
+    }
+
+    info!(
+        "Recovered {} points from the WAL into the MemTable, skipping {}",
+        outcome.points, outcome.skipped
+    );
+    Ok(outcome)
+}
+
+/// Inserts points in timestamp order like [`insert_sorted`], flushing whenever the MemTable fills
+async fn insert_and_flush(
+    memtable: &Arc<RwLock<MemTable>>,
+    flush_manager: &mut FlushManager,
+    catalog: &SSTableCatalog,
+    mut points: Vec<(String, DataPoint)>,
+    outcome: &mut RecoveryOutcome,
+) -> Result<(), RecoveryError> {
+    points.sort_by_key(|(_, point)| point.timestamp());
+    for (series_name, point) in points {
+        let series = TimeSeries::new(series_name)?;
+        let inserted = memtable.read().await.insert(&series, &point).await;
+        let needs_flush = match inserted {
+            Ok(needs_flush) => needs_flush,
+            Err(MemTableError::InvalidTimestampOrder | MemTableError::DuplicateTimestamp { .. }) => {
+                outcome.skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        outcome.points += 1;
+
+        if needs_flush {
+            flush_manager.start_flush(Arc::clone(memtable)).await?;
+            if let Some(sstable) = flush_manager.wait_for_flush().await? {
+                catalog.add_table(&sstable).await?;
+                memtable.read().await.release_flushed(&sstable).await?;
+                outcome.sstables.push(sstable);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Inserts points in timestamp order, counting the ones the MemTable rejects
+async fn insert_sorted(
+    memtable: &MemTable,
+    mut points: Vec<(String, DataPoint)>,
+    outcome: &mut RecoveryOutcome,
+) -> Result<(), RecoveryError> {
+    points.sort_by_key(|(_, point)| point.timestamp());
+    for (series_name, point) in points {
+        let series = TimeSeries::new(series_name)?;
+        match memtable.insert(&series, &point).await {
+            Ok(_) => outcome.points += 1,
+            Err(MemTableError::InvalidTimestampOrder | MemTableError::DuplicateTimestamp { .. }) => outcome.skipped += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+    use crate::query::executor::{ExecutionConfig, QueryExecutor};
+    use crate::query::parser::ast::{Query, TimeRange};
+
+    #[tokio::test]
+    async fn test_recovery_flushes_in_bounded_chunks() {
+        let wal_dir = tempdir().unwrap();
+        let sstable_dir = tempdir().unwrap();
+
+        // Small segments so the WAL spans many of them
+        let wal = WriteAheadLog::new(wal_dir.path()).unwrap().with_max_segment_size(2048);
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        for i in 0..250 {
+            wal.write(&series, &DataPoint::new(i, i as f64, HashMap::new())).await.unwrap();
+        }
+        drop(wal);
+
+        // Recover into a MemTable that only holds 100 points
+        let wal = WriteAheadLog::new(wal_dir.path()).unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(100)));
+        let mut flush_manager = FlushManager::new(sstable_dir.path().to_path_buf());
+        let catalog = SSTableCatalog::new(sstable_dir.path());
+        let outcome = recover_from_wal(&wal, memtable.clone(), &mut flush_manager, &catalog)
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.points, 250);
+        assert_eq!(outcome.sstables.len(), 2);
+        assert_eq!(catalog.get_all_tables().await.len(), 2);
+        assert_eq!(memtable.read().await.size().await, 50);
+
+        // Everything is queryable across the SSTables and the MemTable
+        let sstables = Arc::new(RwLock::new(outcome.sstables));
+        let executor = QueryExecutor::new(memtable, sstables, ExecutionConfig::default());
+        let mut query = Query::new();
+        query.from = "cpu".to_string();
+        query.time_range = Some(TimeRange::Absolute { start: 0, end: 1_000 });
+        let timestamps: Vec<i64> = executor
+            .execute(&query)
+            .await
+            .unwrap()
+            .points
+            .iter()
+            .map(|p| p.timestamp())
+            .collect();
+        assert_eq!(timestamps, (0..250).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_skips_points_the_memtable_rejects() {
+        let wal_dir = tempdir().unwrap();
+        let sstable_dir = tempdir().unwrap();
+
+        // Late and repeated points are logged before the MemTable sees them
+        let wal = WriteAheadLog::new(wal_dir.path()).unwrap();
+        let series = TimeSeries::new("cpu".to_string()).unwrap();
+        for timestamp in [3000, 1000, 2000, 2000] {
+            wal.write(&series, &DataPoint::new(timestamp, 1.0, HashMap::new())).await.unwrap();
+        }
+
+        let memtable = Arc::new(RwLock::new(MemTable::new(100)));
+        let mut flush_manager = FlushManager::new(sstable_dir.path().to_path_buf());
+        let catalog = SSTableCatalog::new(sstable_dir.path());
+        let outcome = recover_from_wal(&wal, memtable.clone(), &mut flush_manager, &catalog)
+            .await
+            .unwrap();
+        assert_eq!((outcome.points, outcome.skipped), (3, 1));
+
+        let replayed = memtable.read().await.get_series_range("cpu", 0, 5000).await;
+        let timestamps: Vec<i64> = replayed.iter().map(|p| p.timestamp()).collect();
+        assert_eq!(timestamps, vec![1000, 2000, 3000]);
+    }
+}
@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tracing::debug;
+
+use crate::query::parser::ast::FilterExpr;
+use std::sync::Arc;
+
+use crate::storage::data::DataPoint;
+use crate::storage::lsm::memtable::MemTable;
+use crate::storage::lsm::query::TimeRange;
+use crate::storage::lsm::sstable::{SSTable, SSTableError};
+
+/// Marks points in a time range matching a filter as deleted
+///
+/// Tombstones are evaluated against each point's tags and value when it is read
+/// or compacted. A point is only deleted when the filter definitely matches, so
+/// filters that cannot be evaluated (e.g. regex tag filters) delete nothing.
+#[derive(Debug, Clone)]
+pub struct Tombstone {
+    /// Inclusive time range the tombstone applies to
+    pub time_range: TimeRange,
+    /// Predicate a point must satisfy to be deleted
+    pub filter: FilterExpr,
+}
+
+impl Tombstone {
+    /// Checks whether the tombstone deletes a point with the given fields
+    pub fn covers(&self, timestamp: i64, value: f64, tags: &HashMap<String, String>) -> bool {
+        self.time_range.contains(timestamp) && self.filter.matches(tags, value) == Some(true)
+    }
+}
+
+/// Marks every point of one series within an inclusive time range as deleted
+///
+/// Unlike [`Tombstone`], range tombstones are persisted: `delete_range` records
+/// one alongside each SSTable holding affected points, and compaction drops the
+/// points it covers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeTombstone {
+    /// Series the deletion applies to
+    pub series: String,
+    /// First deleted timestamp
+    pub start: i64,
+    /// Last deleted timestamp
+    pub end: i64,
+}
+
+impl RangeTombstone {
+    /// Creates a tombstone for `series` over `start..=end`
+    pub fn new(series: &str, start: i64, end: i64) -> Self {
+        Self {
+            series: series.to_string(),
+            start,
+            end,
+        }
+    }
+
+    /// Checks whether the tombstone deletes the point of `series` at `timestamp`
+    pub fn covers(&self, series: &str, timestamp: i64) -> bool {
+        self.series == series && self.start <= timestamp && timestamp <= self.end
+    }
+}
+
+/// Deletes the points of `series` in `start..=end` from the MemTable and SSTables
+///
+/// Points are removed from the MemTable immediately. SSTables are immutable, so
+/// each table whose time range overlaps the deletion records a range tombstone
+/// that reads skip and compaction purges. Returns the number of points removed
+/// from the MemTable.
+pub async fn delete_range(
+    memtable: &MemTable,
+    sstables: &[Arc<SSTable>],
+    series: &str,
+    start: i64,
+    end: i64,
+) -> Result<usize, SSTableError> {
+    let removed = memtable.delete_range(series, start, end).await;
+    add_to_overlapping(sstables, &RangeTombstone::new(series, start, end)).await?;
+
+    debug!("Deleted {}..={} of series {}", start, end, series);
+    Ok(removed)
+}
+
+/// Records `tombstone` on each of `sstables` whose series and time range it overlaps
+pub async fn add_to_overlapping(sstables: &[Arc<SSTable>], tombstone: &RangeTombstone) -> Result<(), SSTableError> {
+    for sstable in sstables {
+        let overlaps = {
+            let metadata = sstable.metadata.read().await;
+            metadata.series_names.contains(&tombstone.series)
+                && metadata.min_timestamp <= tombstone.end
+                && tombstone.start <= metadata.max_timestamp
+        };
+        if overlaps {
+            sstable.add_tombstone(tombstone.clone()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The set of active tombstones
+#[derive(Debug, Default)]
+pub struct TombstoneSet {
+    tombstones: RwLock<Vec<Tombstone>>,
+}
+
+impl TombstoneSet {
+    /// Creates an empty tombstone set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deletes all points in `time_range` whose tags and value satisfy `filter`
+    pub async fn delete(&self, time_range: TimeRange, filter: FilterExpr) {
+        debug!(
+            "Adding tombstone for {}..={} with filter {:?}",
+            time_range.start, time_range.end, filter
+        );
+        self.tombstones.write().await.push(Tombstone { time_range, filter });
+    }
+
+    /// Checks whether any tombstone deletes the given point
+    pub async fn is_deleted(&self, point: &DataPoint) -> bool {
+        self.tombstones
+            .read()
+            .await
+            .iter()
+            .any(|t| t.covers(point.timestamp(), point.value(), point.tags()))
+    }
+
+    /// Returns a copy of the active tombstones
+    pub async fn snapshot(&self) -> Vec<Tombstone> {
+        self.tombstones.read().await.clone()
+    }
+
+    /// Returns the number of active tombstones
+    pub async fn len(&self) -> usize {
+        self.tombstones.read().await.len()
+    }
+
+    /// Checks whether there are no active tombstones
+    pub async fn is_empty(&self) -> bool {
+        self.tombstones.read().await.is_empty()
+    }
+}
+
+/// Checks whether any of the given tombstones deletes a point with the given fields
+pub fn is_covered(tombstones: &[Tombstone], timestamp: i64, value: f64, tags: &HashMap<String, String>) -> bool {
+    tombstones.iter().any(|t| t.covers(timestamp, value, tags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use crate::query::executor::{ExecutionConfig, QueryExecutor};
+    use crate::query::parser::ast::{Query, TagFilter, TagFilterOp, TimeRange as QueryTimeRange};
+    use crate::storage::data::TimeSeries;
+    use crate::storage::lsm::compaction::Compactor;
+    use crate::storage::lsm::memtable::MemTable;
+    use crate::storage::lsm::sstable::{DataBlock, SSTable};
+
+    fn point(timestamp: i64, user_id: &str) -> DataPoint {
+        let mut tags = HashMap::new();
+        tags.insert("user_id".to_string(), user_id.to_string());
+        DataPoint::new(timestamp, timestamp as f64, tags)
+    }
+
+    #[tokio::test]
+    async fn test_delete_by_tag_predicate() {
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstables = Arc::new(RwLock::new(Vec::new()));
+
+        // Older points in an SSTable, newer ones in the MemTable
+        let sstable = SSTable::new(temp_dir.path().join("1.sst")).unwrap();
+        let older = [point(100, "alice"), point(200, "bob"), point(300, "alice")];
+        sstable.write_block(DataBlock::from_points("logins", &older)).await.unwrap();
+        sstables.write().await.push(Arc::new(sstable));
+        {
+            let series = TimeSeries::new("logins".to_string()).unwrap();
+            let guard = memtable.write().await;
+            for p in [point(400, "bob"), point(500, "alice"), point(900, "alice")] {
+                guard.insert(&series, &p).await.unwrap();
+            }
+        }
+
+        // Delete alice's points up to 600; bob's points in the range survive
+        let tombstones = Arc::new(TombstoneSet::new());
+        let user_is_alice = FilterExpr::TagFilter(TagFilter {
+            key: "user_id".to_string(),
+            op: TagFilterOp::Eq,
+            value: "alice".to_string(),
+        });
+        tombstones.delete(TimeRange::new(0, 600), user_is_alice).await;
+
+        let executor = QueryExecutor::new(memtable, sstables.clone(), ExecutionConfig::default())
+            .with_tombstones(tombstones.clone());
+        let mut query = Query::new();
+        query.from = "logins".to_string();
+        query.time_range = Some(QueryTimeRange::Absolute { start: 0, end: 1000 });
+        let timestamps: Vec<i64> = executor
+            .execute(&query)
+            .await
+            .unwrap()
+            .points
+            .iter()
+            .map(|p| p.timestamp())
+            .collect();
+        assert_eq!(timestamps, vec![200, 400, 900]);
+
+        // Compaction drops the deleted points for good
+        let tables = sstables.read().await.clone();
+        let compacted = Compactor::new(temp_dir.path().to_path_buf())
+            .with_tombstones(tombstones)
+            .compact(&tables, &tables)
+            .await
+            .unwrap();
+        let remaining: Vec<(i64, String)> = compacted
+            .scan_blocks()
+            .await
+            .iter()
+            .flat_map(|block| block.to_points())
+            .map(|(_, p)| (p.timestamp(), p.tags()["user_id"].clone()))
+            .collect();
+        assert_eq!(remaining, vec![(200, "bob".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_range_hides_points_still_in_sstables() {
+        use crate::storage::lsm::query::{Query as RouterQuery, QueryRouter};
+
+        let temp_dir = tempdir().unwrap();
+        let memtable = Arc::new(RwLock::new(MemTable::new(1000)));
+        let sstable_path = temp_dir.path().join("1.sst");
+        {
+            let sstable = SSTable::new(&sstable_path).unwrap();
+            let cpu: Vec<DataPoint> = (1..=5).map(|i| point(i * 100, "alice")).collect();
+            sstable.write_block(DataBlock::from_points("cpu", &cpu)).await.unwrap();
+            sstable.write_block(DataBlock::from_points("mem", &cpu)).await.unwrap();
+        }
+        {
+            let series = TimeSeries::new("cpu".to_string()).unwrap();
+            let guard = memtable.write().await;
+            for p in [point(600, "alice"), point(700, "alice")] {
+                guard.insert(&series, &p).await.unwrap();
+            }
+        }
+
+        let sstable = Arc::new(SSTable::open(&sstable_path).unwrap());
+        let removed = delete_range(&*memtable.read().await, std::slice::from_ref(&sstable), "cpu", 200, 600)
+            .await
+            .unwrap();
+        assert_eq!(removed, 1);
+
+        // The tombstone survives reopening, and the points are still on disk
+        let sstables = Arc::new(RwLock::new(vec![Arc::new(SSTable::open(&sstable_path).unwrap())]));
+        let reopened = sstables.read().await[0].clone();
+        assert_eq!(reopened.tombstones().await, vec![RangeTombstone::new("cpu", 200, 600)]);
+        assert_eq!(reopened.metadata.read().await.point_count, 10);
+
+        let executor = QueryExecutor::new(memtable.clone(), sstables.clone(), ExecutionConfig::default());
+        let timestamps = |points: Vec<DataPoint>| -> Vec<i64> { points.iter().map(|p| p.timestamp()).collect() };
+        let mut query = Query::new();
+        query.from = "cpu".to_string();
+        query.time_range = Some(QueryTimeRange::Absolute { start: 0, end: 1000 });
+        assert_eq!(timestamps(executor.execute(&query).await.unwrap().points), vec![100, 700]);
+
+        // Other series are untouched
+        query.from = "mem".to_string();
+        assert_eq!(executor.execute_query(&query).await.unwrap().len(), 5);
+
+        let router = QueryRouter::new(memtable, sstables.clone());
+        let routed = router.route_query(&RouterQuery::with_series(0, 1000, "cpu".to_string())).await.unwrap();
+        assert_eq!(timestamps(routed), vec![100, 700]);
+
+        // Compaction purges the deleted points
+        let tables = sstables.read().await.clone();
+        let compacted = Compactor::new(temp_dir.path().to_path_buf()).compact(&tables, &tables).await.unwrap();
+        assert_eq!(compacted.metadata.read().await.point_count, 6);
+        assert!(compacted.tombstones().await.is_empty());
+    }
+}
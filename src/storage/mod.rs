@@ -2,14 +2,22 @@
 //! Handles the core storage functionality including data structures and persistence.
 
 pub mod data;
+pub mod engine;
 pub mod lsm;
 pub mod wal;
 pub mod index;
+pub mod last_value;
+pub mod recent_events;
+pub mod tag_index;
 
-pub use data::{DataError, DataPoint, TimeSeries};
+pub use data::{CharacterSet, DataError, DataPoint, TimeSeries};
+pub use engine::{EngineConfig, EngineError, StorageEngine};
 pub use lsm::{MemTable, SSTable, SSTableCatalog};
-pub use wal::WriteAheadLog;
+pub use wal::{Replayed, SegmentReport, SyncPolicy, VerifyReport, WriteAheadLog};
 pub use index::IndexInfo;
+pub use last_value::LastValueCache;
+pub use recent_events::RecentEvents;
+pub use tag_index::{BlockRef, TagIndex, TagIndexBuilder, TagIndexError};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::storage::data::DataPoint;
+use crate::storage::lsm::sstable::SSTable;
+
+/// Keeps the newest points of each series in memory, for "latest N events" lookups
+///
+/// Meant for event and annotation series, where the interesting part is the
+/// last few entries rather than a time range. Point values are numeric, so
+/// event text travels in the point's tags. A [`crate::storage::StorageEngine`]
+/// opened with [`crate::storage::EngineConfig::recent_events`] records every
+/// point it stores, and rebuilds the buffer from its newest SSTable on open.
+#[derive(Debug, Clone)]
+pub struct RecentEvents {
+    /// Maximum number of points retained per series
+    capacity: usize,
+    /// Retained points per series name, oldest first
+    entries: Arc<RwLock<HashMap<String, VecDeque<DataPoint>>>>,
+}
+
+impl RecentEvents {
+    /// Creates an empty buffer retaining up to `capacity` points per series
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the number of points retained per series
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Records a point for a series, evicting the oldest once the series is full
+    ///
+    /// Late points are slotted into timestamp order, and dropped if they are
+    /// older than everything a full buffer already holds.
+    pub async fn record(&self, series_name: &str, point: &DataPoint) {
+        let mut entries = self.entries.write().await;
+        let events = entries.entry(series_name.to_string()).or_default();
+        Self::insert(events, self.capacity, point);
+    }
+
+    fn insert(events: &mut VecDeque<DataPoint>, capacity: usize, point: &DataPoint) {
+        let position = events.partition_point(|event| event.timestamp() <= point.timestamp());
+        if position == 0 && events.len() >= capacity {
+            return;
+        }
+        events.insert(position, point.clone());
+        if events.len() > capacity {
+            events.pop_front();
+        }
+    }
+
+    /// Returns up to `n` of the newest points of a series, newest first
+    pub async fn latest_events(&self, series_name: &str, n: usize) -> Vec<DataPoint> {
+        self.entries
+            .read()
+            .await
+            .get(series_name)
+            .map(|events| events.iter().rev().take(n).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Records every point of an SSTable, typically the newest one on startup
+    pub async fn load_sstable(&self, sstable: &SSTable) {
+        let blocks = sstable.scan_blocks().await;
+        let mut entries = self.entries.write().await;
+        for block in blocks {
+            for (series_name, point) in block.to_points() {
+                let events = entries.entry(series_name).or_default();
+                Self::insert(events, self.capacity, &point);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::lsm::sstable::DataBlock;
+    use tempfile::tempdir;
+
+    fn event(timestamp: i64, message: &str) -> DataPoint {
+        let mut tags = HashMap::new();
+        tags.insert("message".to_string(), message.to_string());
+        DataPoint::new(timestamp, 1.0, tags)
+    }
+
+    #[tokio::test]
+    async fn test_latest_events_newest_first() {
+        let events = RecentEvents::new(10);
+        for i in 1..=100 {
+            events.record("deploys", &event(i * 1000, &format!("deploy {}", i))).await;
+        }
+        // A late event older than everything retained is ignored
+        events.record("deploys", &event(500, "late")).await;
+
+        let latest = events.latest_events("deploys", 5).await;
+        let timestamps: Vec<i64> = latest.iter().map(|p| p.timestamp()).collect();
+        assert_eq!(timestamps, vec![100_000, 99_000, 98_000, 97_000, 96_000]);
+        assert_eq!(latest[0].tags()["message"], "deploy 100");
+
+        assert_eq!(events.latest_events("deploys", 50).await.len(), 10);
+        assert!(events.latest_events("unknown", 5).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_latest_events_reloaded_from_sstable() {
+        let temp_dir = tempdir().unwrap();
+        let sstable = SSTable::new(temp_dir.path().join("1.sst")).unwrap();
+        let points: Vec<DataPoint> = (1..=20).map(|i| event(i * 1000, &format!("alert {}", i))).collect();
+        for chunk in points.chunks(8) {
+            sstable.write_block(DataBlock::from_points("alerts", chunk)).await.unwrap();
+        }
+
+        let events = RecentEvents::new(5);
+        events.load_sstable(&sstable).await;
+        let latest = events.latest_events("alerts", 3).await;
+        let messages: Vec<&str> = latest.iter().map(|p| p.tags()["message"].as_str()).collect();
+        assert_eq!(messages, vec!["alert 20", "alert 19", "alert 18"]);
+    }
+}
@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::query::parser::ast::{FilterExpr, TagFilterOp};
+use crate::storage::data::series_key;
+use crate::storage::lsm::sstable::{DataBlock, SSTable, SSTableError};
+
+/// File the tag index is persisted to, inside the SSTable directory
+pub const TAG_INDEX_FILE: &str = "tag_index.json";
+
+/// Error type for tag index operations
+#[derive(Debug, thiserror::Error)]
+pub enum TagIndexError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("SSTable error: {0}")]
+    SSTable(#[from] SSTableError),
+}
+
+/// Identifies one block of one SSTable
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct BlockRef {
+    /// File name of the SSTable, relative to the SSTable directory
+    pub table: String,
+    /// Index of the block within the SSTable
+    pub block: usize,
+}
+
+impl BlockRef {
+    /// Creates a reference to a block of the given SSTable
+    pub fn new(sstable: &SSTable, block: usize) -> Self {
+        Self {
+            table: table_name(sstable),
+            block,
+        }
+    }
+}
+
+/// Postings and point count for one `(tag_key, tag_value)` pair
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Posting {
+    blocks: BTreeSet<BlockRef>,
+    points: usize,
+}
+
+/// Inverted index from `(tag_key, tag_value)` to the SSTable blocks containing it
+///
+/// Lookups resolve a filter to the set of blocks that may hold matching points;
+/// points within those blocks still need to be checked against the filter.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TagIndex {
+    postings: BTreeMap<String, BTreeMap<String, Posting>>,
+    /// Blocks holding each series, keyed by [`series_key`]
+    #[serde(default)]
+    series: BTreeMap<String, BTreeSet<BlockRef>>,
+    blocks: BTreeSet<BlockRef>,
+    total_points: usize,
+}
+
+impl TagIndex {
+    /// Creates an empty tag index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a builder that indexes existing SSTables
+    pub fn builder() -> TagIndexBuilder {
+        TagIndexBuilder::default()
+    }
+
+    /// Indexes the tags of one block; called as blocks are written
+    pub fn insert_block(&mut self, block_ref: BlockRef, block: &DataBlock) {
+        for (series_name, tags) in block.series_names.iter().zip(&block.tags) {
+            self.series
+                .entry(series_key(series_name, tags))
+                .or_default()
+                .insert(block_ref.clone());
+            for (key, value) in tags {
+                let posting = self
+                    .postings
+                    .entry(key.clone())
+                    .or_default()
+                    .entry(value.clone())
+                    .or_default();
+                posting.blocks.insert(block_ref.clone());
+                posting.points += 1;
+            }
+        }
+        self.total_points += block.values.len();
+        self.blocks.insert(block_ref);
+    }
+
+    /// Indexes every block of an SSTable
+    pub async fn insert_sstable(&mut self, sstable: &SSTable) -> Result<(), TagIndexError> {
+        let block_count = sstable.metadata.read().await.blocks.len();
+        for i in 0..block_count {
+            let block = sstable.read_block(i).await?;
+            self.insert_block(BlockRef::new(sstable, i), &block);
+        }
+        Ok(())
+    }
+
+    /// Drops the postings of an SSTable, e.g. once compaction has removed it
+    ///
+    /// Point counts are not adjusted, so selectivity estimates drift until the
+    /// index is rebuilt.
+    pub fn remove_sstable(&mut self, sstable: &SSTable) {
+        let table = table_name(sstable);
+        self.retain_blocks(|block| block.table != table);
+    }
+
+    /// Drops the postings of every SSTable not among `sstables`, e.g. tables
+    /// compacted away after the index was last saved
+    pub fn retain_sstables(&mut self, sstables: &[Arc<SSTable>]) {
+        let live: BTreeSet<String> = sstables.iter().map(|sstable| table_name(sstable)).collect();
+        self.retain_blocks(|block| live.contains(&block.table));
+    }
+
+    /// Keeps the postings of the blocks `keep` accepts, dropping series left without blocks
+    fn retain_blocks(&mut self, keep: impl Fn(&BlockRef) -> bool) {
+        for values in self.postings.values_mut() {
+            for posting in values.values_mut() {
+                posting.blocks.retain(&keep);
+            }
+        }
+        self.series.retain(|_, blocks| {
+            blocks.retain(&keep);
+            !blocks.is_empty()
+        });
+        self.blocks.retain(&keep);
+    }
+
+    /// Returns whether the index has postings for any block of the SSTable
+    pub fn contains_sstable(&self, sstable: &SSTable) -> bool {
+        let table = table_name(sstable);
+        self.blocks.iter().any(|block| block.table == table)
+    }
+
+    /// Resolves a filter to the blocks that may contain matching points
+    ///
+    /// Equality filters read their posting list, `And` intersects and `Or`
+    /// unions. Returns `None` when the filter cannot be answered from the index
+    /// (negations, regexes, value filters), meaning every block is a candidate.
+    pub fn lookup(&self, filter: &FilterExpr) -> Option<BTreeSet<BlockRef>> {
+        match filter {
+            FilterExpr::TagFilter(tag_filter) if tag_filter.op == TagFilterOp::Eq => Some(
+                self.posting(&tag_filter.key, &tag_filter.value)
+                    .map(|posting| posting.blocks.clone())
+                    .unwrap_or_default(),
+            ),
+            FilterExpr::And(left, right) => match (self.lookup(left), self.lookup(right)) {
+                (Some(left), Some(right)) => Some(left.intersection(&right).cloned().collect()),
+                (Some(blocks), None) | (None, Some(blocks)) => Some(blocks),
+                (None, None) => None,
+            },
+            FilterExpr::Or(left, right) => {
+                let mut blocks = self.lookup(left)?;
+                blocks.extend(self.lookup(right)?);
+                Some(blocks)
+            }
+            _ => None,
+        }
+    }
+
+    /// Fraction of indexed points carrying `key = value`, if the index has seen any points
+    pub fn selectivity(&self, key: &str, value: &str) -> Option<f64> {
+        if self.total_points == 0 {
+            return None;
+        }
+        let points = self.posting(key, value).map_or(0, |posting| posting.points);
+        Some(points as f64 / self.total_points as f64)
+    }
+
+    /// Returns the number of distinct series, by name and tag set, in the indexed blocks
+    pub fn series_count(&self) -> usize {
+        self.series.len()
+    }
+
+    /// Returns the number of distinct values seen for a tag key
+    pub fn cardinality(&self, key: &str) -> usize {
+        self.postings.get(key).map_or(0, |values| values.len())
+    }
+
+    /// Persists the index to `TAG_INDEX_FILE` in the SSTable directory
+    pub fn save<P: AsRef<Path>>(&self, sstable_dir: P) -> Result<(), TagIndexError> {
+        let path = sstable_dir.as_ref().join(TAG_INDEX_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Loads a persisted index, returning an empty one if none has been saved
+    pub fn load<P: AsRef<Path>>(sstable_dir: P) -> Result<Self, TagIndexError> {
+        let path = sstable_dir.as_ref().join(TAG_INDEX_FILE);
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    fn posting(&self, key: &str, value: &str) -> Option<&Posting> {
+        self.postings.get(key).and_then(|values| values.get(value))
+    }
+}
+
+/// Builds a [`TagIndex`] over existing SSTables
+#[derive(Debug, Default)]
+pub struct TagIndexBuilder {
+    index: TagIndex,
+}
+
+impl TagIndexBuilder {
+    /// Starts from a previously persisted index; SSTables it already covers are skipped
+    pub fn with_index(mut self, index: TagIndex) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Indexes an SSTable unless the index already covers it
+    pub async fn add_sstable(mut self, sstable: &SSTable) -> Result<Self, TagIndexError> {
+        if !self.index.contains_sstable(sstable) {
+            self.index.insert_sstable(sstable).await?;
+        }
+        Ok(self)
+    }
+
+    /// Returns the built index
+    pub fn build(self) -> TagIndex {
+        self.index
+    }
+}
+
+fn table_name(sstable: &SSTable) -> String {
+    sstable
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| sstable.path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+    use crate::query::parser::ast::TagFilter;
+    use crate::storage::data::DataPoint;
+
+    fn tag_filter(key: &str, value: &str) -> FilterExpr {
+        FilterExpr::TagFilter(TagFilter {
+            key: key.to_string(),
+            op: TagFilterOp::Eq,
+            value: value.to_string(),
+        })
+    }
+
+    fn block(region: &str, env: &str, points: usize) -> DataBlock {
+        let mut tags = HashMap::new();
+        tags.insert("region".to_string(), region.to_string());
+        tags.insert("env".to_string(), env.to_string());
+        let points: Vec<DataPoint> = (0..points as i64)
+            .map(|i| DataPoint::new(i, i as f64, tags.clone()))
+            .collect();
+        DataBlock::from_points("cpu", &points)
+    }
+
+    #[tokio::test]
+    async fn test_lookup_intersects_and_unions_postings() {
+        let dir = tempdir().unwrap();
+        let sstable = SSTable::new(dir.path().join("1.sst")).unwrap();
+        sstable.write_block(block("us-west", "prod", 6)).await.unwrap();
+        sstable.write_block(block("us-east", "prod", 2)).await.unwrap();
+        sstable.write_block(block("us-west", "dev", 2)).await.unwrap();
+
+        let index = TagIndex::builder().add_sstable(&sstable).await.unwrap().build();
+        let blocks = |filter: &FilterExpr| -> Option<Vec<usize>> {
+            index.lookup(filter).map(|refs| refs.into_iter().map(|r| r.block).collect())
+        };
+
+        assert_eq!(blocks(&tag_filter("region", "us-west")), Some(vec![0, 2]));
+        assert_eq!(blocks(&tag_filter("region", "eu")), Some(vec![]));
+        let and = FilterExpr::And(
+            Box::new(tag_filter("region", "us-west")),
+            Box::new(tag_filter("env", "prod")),
+        );
+        assert_eq!(blocks(&and), Some(vec![0]));
+        let or = FilterExpr::Or(
+            Box::new(tag_filter("region", "us-east")),
+            Box::new(tag_filter("env", "dev")),
+        );
+        assert_eq!(blocks(&or), Some(vec![1, 2]));
+        // Negations are not answerable from postings
+        assert_eq!(blocks(&FilterExpr::Not(Box::new(tag_filter("env", "dev")))), None);
+
+        assert_eq!(index.selectivity("region", "us-west"), Some(0.8));
+        assert_eq!(index.cardinality("region"), 2);
+
+        // The index round-trips through its file, and rebuilding skips covered tables
+        index.save(dir.path()).unwrap();
+        let loaded = TagIndex::load(dir.path()).unwrap();
+        let rebuilt = TagIndex::builder()
+            .with_index(loaded)
+            .add_sstable(&sstable)
+            .await
+            .unwrap()
+            .build();
+        assert_eq!(rebuilt.lookup(&and), index.lookup(&and));
+        assert_eq!(rebuilt.selectivity("region", "us-west"), Some(0.8));
+    }
+}
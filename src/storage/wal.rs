@@ -2,17 +2,19 @@ use crc::{Crc, CRC_32_ISCSI};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fs::{self, File, OpenOptions};
+use std::future::Future;
 use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 use tokio::sync::RwLock;
 
 use tracing::{error, warn};
 use uuid::Uuid;
 
-use crate::storage::data::{DataPoint, TimeSeries};
+use crate::storage::data::{DataPoint, TimeSeries, Value};
 
 const WAL_MAGIC: u32 = 0x57414C00; // "WAL\0"
 const WAL_VERSION: u32 = 1;
@@ -33,6 +35,86 @@ pub enum WalError {
     CorruptedEntry,
     #[error("No valid segments found")]
     NoValidSegments,
+    #[error("WAL write timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("WAL write cancelled")]
+    Cancelled,
+}
+
+/// When the WAL forces written entries to stable storage with `fsync`
+///
+/// Every write reaches the operating system before it returns, so a process
+/// crash loses nothing; the policy only matters for power loss or a kernel
+/// crash. `Always` makes each acknowledged write durable but pays one disk sync
+/// per call, which typically caps throughput at a few thousand writes per
+/// second. `Periodic` and `OnBatch` amortize the sync across many writes, in
+/// exchange for losing up to one interval or batch of acknowledged writes.
+/// Under any policy, [`WriteAheadLog::write_batch`] syncs at most once for the
+/// whole batch, and [`WriteAheadLog::sync`] forces pending entries out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Sync after every write call
+    #[default]
+    Always,
+    /// Sync on the first write at least this long after the previous sync
+    ///
+    /// Checked when writing; entries written just before a quiet period stay
+    /// unsynced until the next write or an explicit [`WriteAheadLog::sync`].
+    Periodic(Duration),
+    /// Sync once at least this many entries have been written since the previous sync
+    OnBatch(usize),
+}
+
+/// Entries appended since the last sync, guarded by the append lock
+#[derive(Debug)]
+struct SyncState {
+    /// Segment holding the unsynced entries
+    pending: Option<PathBuf>,
+    /// Number of entries appended since the last sync
+    unsynced: usize,
+    last_sync: Instant,
+    /// Number of syncs performed, for tests
+    #[cfg(test)]
+    syncs: usize,
+}
+
+impl SyncState {
+    fn new() -> Self {
+        Self {
+            pending: None,
+            unsynced: 0,
+            last_sync: Instant::now(),
+            #[cfg(test)]
+            syncs: 0,
+        }
+    }
+
+    fn is_due(&self, policy: SyncPolicy) -> bool {
+        if self.unsynced == 0 {
+            return false;
+        }
+        match policy {
+            SyncPolicy::Always => true,
+            SyncPolicy::Periodic(interval) => self.last_sync.elapsed() >= interval,
+            SyncPolicy::OnBatch(entries) => self.unsynced >= entries,
+        }
+    }
+
+    /// Syncs the pending segment, if any entries are waiting
+    fn sync(&mut self) -> io::Result<()> {
+        if let Some(path) = self.pending.take() {
+            if self.unsynced > 0 {
+                OpenOptions::new().append(true).open(path)?.sync_data()?;
+                #[cfg(test)]
+                {
+                    self.syncs += 1;
+                }
+            }
+        }
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,17 +122,46 @@ struct WalHeader {
     magic: u32,
     version: u32,
     created_at: u64,
+    /// Position of the segment among those created by this WAL
+    #[serde(default)]
+    sequence: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct WalEntry {
     series_name: String,
     timestamp: i64,
-    value: f64,
+    value: Value,
     tags: std::collections::HashMap<String, String>,
     crc: u32,
 }
 
+/// Deletion of a series' points in `delete_start..=delete_end`
+#[derive(Debug, Serialize, Deserialize)]
+struct WalDelete {
+    series_name: String,
+    delete_start: i64,
+    delete_end: i64,
+}
+
+/// A line of a segment; deletes have their own shape so segments written
+/// before they were logged still read unchanged
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum WalRecord {
+    Point(WalEntry),
+    Delete(WalDelete),
+}
+
+/// An entry read back from the WAL
+#[derive(Debug, Clone)]
+pub enum Replayed {
+    /// A written point
+    Point(DataPoint),
+    /// A deletion of the series' points in `start..=end`
+    Delete { start: i64, end: i64 },
+}
+
 /// Represents a WAL segment file
 #[derive(Debug)]
 struct Segment {
@@ -94,13 +205,78 @@ impl Segment {
     }
 }
 
+/// Identifies a WAL segment file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// Segment id; ids increase in the order segments were created
+    pub id: u64,
+    /// Sequence number, breaking ties between segments created at the same instant
+    pub sequence: u64,
+    /// Path to the segment file
+    pub path: PathBuf,
+    /// Size of the segment file in bytes
+    pub size: u64,
+}
+
+/// Result of verifying one WAL segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentReport {
+    /// Path to the segment file
+    pub path: PathBuf,
+    /// Number of entries that passed verification
+    pub entries: usize,
+    /// Why verification stopped, if the segment is damaged
+    pub error: Option<String>,
+}
+
+impl SegmentReport {
+    /// Returns true if the whole segment verified
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Result of verifying every segment of a WAL
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VerifyReport {
+    /// One report per segment, in replay order
+    pub segments: Vec<SegmentReport>,
+}
+
+impl VerifyReport {
+    /// Returns true if every segment verified
+    pub fn is_valid(&self) -> bool {
+        self.segments.iter().all(SegmentReport::is_valid)
+    }
+
+    /// Returns the damaged segments, in segment order
+    pub fn corrupt_segments(&self) -> impl Iterator<Item = &SegmentReport> {
+        self.segments.iter().filter(|segment| !segment.is_valid())
+    }
+}
+
 /// Manages the Write-Ahead Log
 pub struct WriteAheadLog {
     directory: PathBuf,
     current_segment: Arc<RwLock<Option<Segment>>>,
     max_segment_size: u64,
     max_segment_age: u64,
+    write_timeout: Option<Duration>,
+    sync_policy: SyncPolicy,
+    /// Maximum number of segments verified at once
+    verify_parallelism: usize,
+    /// Serializes file appends, including those still running after being
+    /// abandoned, and tracks what still needs syncing
+    append_lock: Arc<Mutex<SyncState>>,
+    /// Sequence number of the next segment created
+    next_sequence: AtomicU64,
     crc: Crc<u32>,
+    /// Fixed time used to name new segments, used by tests to force ties
+    #[cfg(test)]
+    frozen_clock: Option<Duration>,
+    /// Invoked while holding the append lock, used by tests to simulate a stalled disk
+    #[cfg(test)]
+    append_hook: Option<Arc<dyn Fn() + Send + Sync>>,
 }
 
 impl WriteAheadLog {
@@ -109,12 +285,30 @@ impl WriteAheadLog {
         let directory = directory.as_ref().to_path_buf();
         fs::create_dir_all(&directory)?;
 
+        // Continue the sequence of any segments already in the directory
+        let mut next_sequence = 0;
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if segment_id(&path).is_some() {
+                next_sequence = next_sequence.max(segment_sequence(&path) + 1);
+            }
+        }
+
         Ok(Self {
             directory,
             current_segment: Arc::new(RwLock::new(None)),
             max_segment_size: DEFAULT_SEGMENT_SIZE,
             max_segment_age: DEFAULT_SEGMENT_DURATION,
+            write_timeout: None,
+            sync_policy: SyncPolicy::default(),
+            verify_parallelism: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            append_lock: Arc::new(Mutex::new(SyncState::new())),
+            next_sequence: AtomicU64::new(next_sequence),
             crc: Crc::<u32>::new(&CRC_32_ISCSI),
+            #[cfg(test)]
+            append_hook: None,
+            #[cfg(test)]
+            frozen_clock: None,
         })
     }
 
@@ -130,8 +324,107 @@ impl WriteAheadLog {
         self
     }
 
+    /// Sets the maximum time a single write may take before it is abandoned
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets when written entries are synced to disk; see [`SyncPolicy`]
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Returns the sync policy
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    /// Sets how many segments [`WriteAheadLog::verify`] checks at once
+    ///
+    /// Defaults to the number of available CPUs.
+    pub fn with_verify_parallelism(mut self, parallelism: usize) -> Self {
+        self.verify_parallelism = parallelism.max(1);
+        self
+    }
+
     /// Writes a data point to the WAL
     pub async fn write(&self, series: &TimeSeries, point: &DataPoint) -> Result<(), WalError> {
+        self.write_cancellable(series, point, std::future::pending()).await
+    }
+
+    /// Writes a data point to the WAL, abandoning the write if `cancel` completes first
+    ///
+    /// The configured write timeout applies as well. An abandoned write is reported
+    /// as an error but may still reach the disk later; subsequent appends wait for
+    /// it, so entries are never interleaved.
+    pub async fn write_cancellable<C>(
+        &self,
+        series: &TimeSeries,
+        point: &DataPoint,
+        cancel: C,
+    ) -> Result<(), WalError>
+    where
+        C: Future<Output = ()>,
+    {
+        let buffer = self.encode_entries(&[(series, point)])?;
+        tokio::select! {
+            result = self.append_with_timeout(buffer, 1) => result,
+            _ = cancel => Err(WalError::Cancelled),
+        }
+    }
+
+    /// Writes several data points with a single append and at most one sync
+    ///
+    /// The batch lands in one segment and counts as a single write for
+    /// [`SyncPolicy::Always`], which makes it the cheap way to get durable
+    /// writes. The write timeout applies to the batch as a whole.
+    pub async fn write_batch(&self, entries: &[(&TimeSeries, &DataPoint)]) -> Result<(), WalError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let buffer = self.encode_entries(entries)?;
+        self.append_with_timeout(buffer, entries.len()).await
+    }
+
+    /// Logs the deletion of a series' points in `start..=end`
+    ///
+    /// Replay hands the deletion back in order with the points, so points it
+    /// removed are not brought back by a restart.
+    pub async fn write_delete(&self, series_name: &str, start: i64, end: i64) -> Result<(), WalError> {
+        let record = WalRecord::Delete(WalDelete {
+            series_name: series_name.to_string(),
+            delete_start: start,
+            delete_end: end,
+        });
+        let mut buffer = Vec::new();
+        self.encode_line(&serde_json::to_string(&record)?, &mut buffer);
+        self.append_with_timeout(buffer, 1).await
+    }
+
+    /// Syncs any entries written since the last sync, regardless of policy
+    pub async fn sync(&self) -> Result<(), WalError> {
+        let append_lock = self.append_lock.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), WalError> {
+            append_lock.lock().unwrap_or_else(|e| e.into_inner()).sync()?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| WalError::Io(io::Error::other(e)))?
+    }
+
+    async fn append_with_timeout(&self, buffer: Vec<u8>, count: usize) -> Result<(), WalError> {
+        match self.write_timeout {
+            Some(limit) => tokio::time::timeout(limit, self.append(buffer, count))
+                .await
+                .map_err(|_| WalError::Timeout(limit))?,
+            None => self.append(buffer, count).await,
+        }
+    }
+
+    /// Appends `count` encoded entries to the current segment, rotating it first if needed
+    async fn append(&self, buffer: Vec<u8>, count: usize) -> Result<(), WalError> {
         let mut segment_guard = self.current_segment.write().await;
 
         // Create new segment if needed
@@ -150,19 +443,96 @@ impl WriteAheadLog {
 
         // Write to the current segment
         let segment = segment_guard.as_mut().unwrap();
-        self.write_entry(series.name(), point, &segment.path)?;
+        self.write_entries(buffer, count, &segment.path).await?;
         segment.update_size()?;
 
         Ok(())
     }
 
+    /// Seals the current segment so the next write starts a new one
+    ///
+    /// Returns the id of the sealed segment, or `None` if no segment was open.
+    /// Call this before flushing the MemTable, then pass the id to
+    /// [`WriteAheadLog::checkpoint`] once the flush is durable.
+    pub async fn rotate(&self) -> Result<Option<u64>, WalError> {
+        let mut segment_guard = self.current_segment.write().await;
+        Ok(segment_guard.take().and_then(|segment| segment_id(&segment.path)))
+    }
+
+    /// Deletes every sealed segment with an id up to and including `up_to`
+    ///
+    /// The segment currently being written is never removed. Returns the number
+    /// of segments deleted.
+    pub async fn checkpoint(&self, up_to: u64) -> Result<usize, WalError> {
+        // Hold the segment lock so no write can rotate into a segment we delete
+        let segment_guard = self.current_segment.read().await;
+        let active = segment_guard.as_ref().map(|segment| segment.path.clone());
+
+        let mut removed = 0;
+        for segment in self.segments()? {
+            if segment.id > up_to || Some(&segment.path) == active.as_ref() {
+                continue;
+            }
+            fs::remove_file(&segment.path)?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    /// Deletes every sealed segment whose points are all older than `cutoff`
+    ///
+    /// Used to trim the WAL along with expired SSTables. The segment currently
+    /// being written is never removed. Returns the number of segments deleted.
+    pub async fn expire_before(&self, cutoff: i64) -> Result<usize, WalError> {
+        let segment_guard = self.current_segment.read().await;
+        let active = segment_guard.as_ref().map(|segment| segment.path.clone());
+
+        let mut removed = 0;
+        for segment in self.segments()? {
+            if Some(&segment.path) == active.as_ref() {
+                continue;
+            }
+            let mut newest = i64::MIN;
+            self.replay_segment(&segment.path, &mut |_, point| {
+                newest = newest.max(point.timestamp());
+                Ok(())
+            })?;
+            if newest < cutoff {
+                fs::remove_file(&segment.path)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Lists the segment files in the WAL directory, ordered by id then sequence
+    pub fn segments(&self) -> Result<Vec<SegmentInfo>, WalError> {
+        let mut segments: Vec<SegmentInfo> = self
+            .get_segments()?
+            .into_iter()
+            .filter_map(|segment| {
+                segment_id(&segment.path).map(|id| SegmentInfo {
+                    id,
+                    sequence: segment_sequence(&segment.path),
+                    path: segment.path,
+                    size: segment.size,
+                })
+            })
+            .collect();
+        segments.sort_by(|a, b| (a.id, a.sequence, &a.path).cmp(&(b.id, b.sequence, &b.path)));
+        Ok(segments)
+    }
+
     /// Rotates the current segment and creates a new one
     fn rotate_segment(&self) -> Result<Segment, WalError> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let filename = format!("segment_{}_{}.wal", timestamp, Uuid::new_v4());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        #[cfg(test)]
+        let now = self.frozen_clock.unwrap_or(now);
+        let timestamp = now.as_secs();
+        let sequence = self.next_sequence.fetch_add(1, Ordering::SeqCst);
+        let filename = format!("segment_{}_{}_{}.wal", now.as_nanos(), sequence, Uuid::new_v4());
         let path = self.directory.join(filename);
 
         // Create new segment file with header
@@ -172,6 +542,7 @@ impl WriteAheadLog {
             magic: WAL_MAGIC,
             version: WAL_VERSION,
             created_at: timestamp,
+            sequence,
         };
 
         let mut writer = BufWriter::new(file);
@@ -182,39 +553,90 @@ impl WriteAheadLog {
         Ok(Segment::new(path))
     }
 
-    /// Writes a single entry to the WAL file
-    fn write_entry(
-        &self,
-        series_name: &str,
-        point: &DataPoint,
-        path: &Path,
-    ) -> Result<(), WalError> {
+    /// Encodes an entry as its JSON line followed by the CRC line
+    fn encode_entry(&self, series_name: &str, point: &DataPoint, buffer: &mut Vec<u8>) -> Result<(), WalError> {
         let entry = WalEntry {
             series_name: series_name.to_string(),
             timestamp: point.timestamp(),
-            value: point.value(),
+            value: point.typed_value(),
             tags: point.tags().clone(),
             crc: 0, // Will be calculated below
         };
-
-        let mut writer = BufWriter::new(OpenOptions::new().append(true).open(path)?);
-
-        // Write entry without CRC
         let entry_json = serde_json::to_string(&entry)?;
-        writer.write_all(entry_json.as_bytes())?;
-        writer.write_all(b"\n")?;
-        writer.flush()?;
+        self.encode_line(&entry_json, buffer);
+        Ok(())
+    }
 
-        // Calculate and write CRC
+    /// Encodes points as consecutive entries
+    fn encode_entries(&self, entries: &[(&TimeSeries, &DataPoint)]) -> Result<Vec<u8>, WalError> {
+        let mut buffer = Vec::new();
+        for (series, point) in entries {
+            self.encode_entry(series.name(), point, &mut buffer)?;
+        }
+        Ok(buffer)
+    }
+
+    /// Appends a JSON line followed by its CRC line
+    fn encode_line(&self, json: &str, buffer: &mut Vec<u8>) {
+        // Calculate CRC over the entry without CRC
         let mut digest = self.crc.digest();
-        digest.update(&entry_json.as_bytes());
+        digest.update(json.as_bytes());
         let crc = digest.finalize();
 
-        writer.write_all(&crc.to_le_bytes())?;
-        writer.write_all(b"\n")?;
-        writer.flush()?;
+        buffer.extend_from_slice(json.as_bytes());
+        buffer.push(b'\n');
+        buffer.extend_from_slice(&crc.to_le_bytes());
+        buffer.push(b'\n');
+    }
 
-        Ok(())
+    /// Appends entries to the WAL file and syncs it if the policy says so
+    ///
+    /// The file I/O runs on the blocking pool so a stalled disk cannot block the
+    /// async runtime, and so the caller can stop waiting on it.
+    async fn write_entries(
+        &self,
+        buffer: Vec<u8>,
+        count: usize,
+        path: &Path,
+    ) -> Result<(), WalError> {
+        let policy = self.sync_policy;
+        let path = path.to_path_buf();
+        let append_lock = self.append_lock.clone();
+        #[cfg(test)]
+        let append_hook = self.append_hook.clone();
+
+        tokio::task::spawn_blocking(move || -> Result<(), WalError> {
+            let mut sync_state = append_lock.lock().unwrap_or_else(|e| e.into_inner());
+            #[cfg(test)]
+            if let Some(hook) = append_hook {
+                hook();
+            }
+
+            // Entries left unsynced in a segment we rotated away from go out first
+            if sync_state.pending.as_ref().is_some_and(|pending| *pending != path) {
+                sync_state.sync()?;
+            }
+
+            let mut writer = BufWriter::new(OpenOptions::new().append(true).open(&path)?);
+            writer.write_all(&buffer)?;
+            writer.flush()?;
+
+            sync_state.pending = Some(path);
+            sync_state.unsynced += count;
+            if sync_state.is_due(policy) {
+                sync_state.sync()?;
+            }
+
+            Ok(())
+        })
+        .await
+        .map_err(|e| WalError::Io(io::Error::other(e)))?
+    }
+
+    /// Number of syncs performed so far
+    #[cfg(test)]
+    fn sync_count(&self) -> usize {
+        self.append_lock.lock().unwrap().syncs
     }
 
     /// Reads and validates a WAL entry
@@ -255,14 +677,12 @@ impl WriteAheadLog {
     where
         F: FnMut(&str, &DataPoint) -> Result<(), WalError>,
     {
-        let mut segments = self.get_segments()?;
+        // Segments are ordered by id, i.e. creation order
+        let segments = self.segments()?;
         if segments.is_empty() {
             return Err(WalError::NoValidSegments);
         }
 
-        // Sort segments by creation time to ensure correct replay order
-        segments.sort_by_key(|s| s.created_at);
-
         for segment in segments {
             self.replay_segment(&segment.path, &mut callback)?;
         }
@@ -270,10 +690,26 @@ impl WriteAheadLog {
         Ok(())
     }
 
-    /// Replays a single segment
-    fn replay_segment<F>(&self, path: &Path, callback: &mut F) -> Result<(), WalError>
+    /// Replays the points of a single segment, e.g. one returned by [`WriteAheadLog::segments`]
+    ///
+    /// An entry cut short by a crash mid-write ends the segment: the entries
+    /// before it are replayed and the partial record is skipped with a warning.
+    /// Deletes are skipped; use [`WriteAheadLog::replay_segment_entries`] to
+    /// see them.
+    pub fn replay_segment<F>(&self, path: &Path, callback: &mut F) -> Result<(), WalError>
     where
         F: FnMut(&str, &DataPoint) -> Result<(), WalError>,
+    {
+        self.replay_segment_entries(path, &mut |series_name, entry| match entry {
+            Replayed::Point(point) => callback(series_name, &point),
+            Replayed::Delete { .. } => Ok(()),
+        })
+    }
+
+    /// Replays the points and deletes of a single segment, in the order they were logged
+    pub fn replay_segment_entries<F>(&self, path: &Path, callback: &mut F) -> Result<(), WalError>
+    where
+        F: FnMut(&str, Replayed) -> Result<(), WalError>,
     {
         let file = File::open(path)?;
         let mut reader = BufReader::new(file);
@@ -301,7 +737,7 @@ impl WriteAheadLog {
             }
 
             // Read entry JSON
-            let entry: WalEntry = match serde_json::from_str(line.trim()) {
+            let record: WalRecord = match serde_json::from_str(line.trim()) {
                 Ok(e) => e,
                 Err(e) => {
                     warn!("Failed to parse WAL entry: {}", e);
@@ -310,14 +746,18 @@ impl WriteAheadLog {
                 }
             };
 
-            // Read CRC
-            let mut crc_bytes = [0u8; 4];
-            reader.read_exact(&mut crc_bytes)?;
-            let expected_crc = u32::from_le_bytes(crc_bytes);
-
-            // Skip newline after CRC
-            let mut newline = [0u8; 1];
-            reader.read_exact(&mut newline)?;
+            // Read CRC and the newline after it; running out here means the
+            // final entry was only partially written
+            let mut crc_bytes = [0u8; 5];
+            match reader.read_exact(&mut crc_bytes) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    warn!("Truncated final entry in WAL segment {}, skipping it", path.display());
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let expected_crc = u32::from_le_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
 
             // Verify CRC
             let mut digest = self.crc.digest();
@@ -329,15 +769,20 @@ impl WriteAheadLog {
                 return Err(WalError::CorruptedEntry);
             }
 
-            // Create DataPoint and call callback
-            let mut tags = std::collections::HashMap::new();
-            for (k, v) in entry.tags {
-                tags.insert(k, v);
+            match record {
+                WalRecord::Point(entry) => {
+                    let point = DataPoint::with_value(entry.timestamp, entry.value, entry.tags);
+                    callback(&entry.series_name, Replayed::Point(point))?;
+                }
+                WalRecord::Delete(delete) => {
+                    let replayed = Replayed::Delete {
+                        start: delete.delete_start,
+                        end: delete.delete_end,
+                    };
+                    callback(&delete.series_name, replayed)?;
+                }
             }
 
-            let point = DataPoint::new(entry.timestamp, entry.value, tags);
-            callback(&entry.series_name, &point)?;
-
             line.clear();
         }
 
@@ -346,69 +791,107 @@ impl WriteAheadLog {
 
     /// Verifies WAL integrity
     pub fn verify(&self) -> Result<bool, WalError> {
-        let segments = self.get_segments()?;
-        if segments.is_empty() {
-            return Ok(true);
-        }
-
-        for segment in segments {
-            if !self.verify_segment(&segment.path)? {
-                return Ok(false);
-            }
-        }
+        Ok(self.verify_report()?.is_valid())
+    }
 
-        Ok(true)
+    /// Verifies every segment and reports on each, in replay order; see
+    /// [`WriteAheadLog::segments`]
+    ///
+    /// Up to the configured verify parallelism segments are checked at once;
+    /// the report does not depend on which finishes first.
+    pub fn verify_report(&self) -> Result<VerifyReport, WalError> {
+        let mut paths: Vec<PathBuf> = self.get_segments()?.into_iter().map(|segment| segment.path).collect();
+        paths.sort_by_cached_key(|path| (segment_id(path), segment_sequence(path), path.clone()));
+
+        let workers = self.verify_parallelism.min(paths.len()).max(1);
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<(usize, Result<SegmentReport, WalError>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(path) = paths.get(index) else { break };
+                            done.push((index, self.verify_segment(path)));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("WAL verify worker panicked"))
+                .collect()
+        });
+        results.sort_by_key(|(index, _)| *index);
+
+        let segments = results
+            .into_iter()
+            .map(|(_, result)| result)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(VerifyReport { segments })
     }
 
-    /// Verifies a single segment
-    fn verify_segment(&self, path: &Path) -> Result<bool, WalError> {
+    /// Verifies a single segment, stopping at the first bad entry
+    fn verify_segment(&self, path: &Path) -> Result<SegmentReport, WalError> {
         let file = File::open(path)?;
         let mut reader = BufReader::new(file);
+        let mut report = SegmentReport {
+            path: path.to_path_buf(),
+            entries: 0,
+            error: None,
+        };
 
         // Verify header
         let mut header_line = String::new();
         reader.read_line(&mut header_line)?;
-        let header: WalHeader = match serde_json::from_str(&header_line) {
-            Ok(h) => h,
-            Err(_) => return Ok(false),
-        };
-
-        if header.magic != WAL_MAGIC || header.version != WAL_VERSION {
-            return Ok(false);
+        match serde_json::from_str::<WalHeader>(&header_line) {
+            Ok(header) if header.magic == WAL_MAGIC && header.version == WAL_VERSION => {}
+            _ => {
+                report.error = Some("invalid header".to_string());
+                return Ok(report);
+            }
         }
 
         // Verify entries
         let mut line = String::new();
-        while reader.read_line(&mut line)? > 0 {
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
             if line.trim().is_empty() {
                 continue;
             }
+            let entry = report.entries + 1;
 
             // Verify entry JSON
-            if serde_json::from_str::<WalEntry>(&line).is_err() {
-                println!("error: {:?}", line);
-                return Ok(false);
+            if serde_json::from_str::<WalRecord>(line.trim()).is_err() {
+                report.error = Some(format!("entry {} is not valid JSON", entry));
+                return Ok(report);
             }
 
-            // Verify CRC
+            // Verify CRC and the newline after it
             let mut crc_bytes = [0u8; 4];
-            if reader.read_exact(&mut crc_bytes).is_err() {
-                return Ok(false);
+            let mut newline = [0u8; 1];
+            if reader.read_exact(&mut crc_bytes).is_err() || reader.read_exact(&mut newline).is_err() {
+                report.error = Some(format!("entry {} is truncated", entry));
+                return Ok(report);
             }
 
             let expected_crc = u32::from_le_bytes(crc_bytes);
             let mut digest = self.crc.digest();
-            digest.update(line.as_bytes());
-            let actual_crc = digest.finalize();
-
-            if actual_crc != expected_crc {
-                return Ok(false);
+            digest.update(line.trim().as_bytes());
+            if digest.finalize() != expected_crc {
+                report.error = Some(format!("entry {} fails its CRC check", entry));
+                return Ok(report);
             }
 
-            line.clear();
+            report.entries = entry;
         }
 
-        Ok(true)
+        Ok(report)
     }
 
     /// Gets all valid WAL segments
@@ -426,6 +909,29 @@ impl WriteAheadLog {
     }
 }
 
+/// Extracts the id from a segment file name of the form
+/// `segment_<id>_<sequence>_<uuid>.wal`, or `segment_<id>_<uuid>.wal` for
+/// segments written before sequence numbers
+fn segment_id(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix("segment_")?
+        .split('_')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Extracts the sequence number from a segment file name; segments without
+/// one sort first
+fn segment_sequence(path: &Path) -> u64 {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('_').nth(2))
+        .and_then(|sequence| sequence.parse().ok())
+        .unwrap_or(0)
+}
+
 impl fmt::Debug for WriteAheadLog {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let current_segment = self
@@ -455,6 +961,9 @@ impl fmt::Debug for WriteAheadLog {
                 "max_segment_age",
                 &format!("{} seconds", self.max_segment_age),
             )
+            .field("write_timeout", &self.write_timeout)
+            .field("sync_policy", &self.sync_policy)
+            .field("verify_parallelism", &self.verify_parallelism)
             .finish()
     }
 }
@@ -463,16 +972,9 @@ impl fmt::Debug for WriteAheadLog {
 mod tests {
     use super::*;
     use crate::storage::data::{DataPoint, TimeSeries};
-    
     use std::fs::{self, File, OpenOptions};
     use std::io::{Read, Seek, SeekFrom};
-    
-    
-    use tempfile::{tempdir};
-    
-    
-    
-    
+    use tempfile::tempdir;
 
     #[tokio::test]
     async fn test_wal_creation_and_write() {
@@ -548,7 +1050,7 @@ mod tests {
         let entry = WriteAheadLog::read_entry(&mut reader).unwrap();
         assert_eq!(entry.series_name, "test_series");
         assert_eq!(entry.timestamp, 1000);
-        assert_eq!(entry.value, 42.0);
+        assert_eq!(entry.value, Value::F64(42.0));
         assert_eq!(entry.tags.get("host").unwrap(), "server1");
     }
 
@@ -619,4 +1121,236 @@ mod tests {
         // Verify corruption is detected
         assert!(!wal.verify().unwrap());
     }
+
+    #[tokio::test]
+    async fn test_wal_write_times_out_on_stalled_disk() {
+        let dir = tempdir().unwrap();
+        let mut wal = WriteAheadLog::new(dir.path())
+            .unwrap()
+            .with_write_timeout(std::time::Duration::from_millis(50));
+
+        // Blocking-writer shim: every append stalls well past the timeout
+        wal.append_hook = Some(Arc::new(|| {
+            std::thread::sleep(std::time::Duration::from_millis(500))
+        }));
+
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+        let point = DataPoint::new(1000, 42.0, std::collections::HashMap::new());
+
+        let started = std::time::Instant::now();
+        let result = wal.write(&series, &point).await;
+        assert!(matches!(result, Err(WalError::Timeout(_))));
+        assert!(started.elapsed() < std::time::Duration::from_millis(400));
+
+        // A cancellation signal abandons the write as well
+        let result = wal
+            .write_cancellable(&series, &point, tokio::time::sleep(std::time::Duration::from_millis(10)))
+            .await;
+        assert!(matches!(result, Err(WalError::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn test_wal_checkpoint_after_flush() {
+        let dir = tempdir().unwrap();
+        let wal = WriteAheadLog::new(dir.path()).unwrap();
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+
+        for ts in [1000, 2000] {
+            let point = DataPoint::new(ts, 1.0, std::collections::HashMap::new());
+            wal.write(&series, &point).await.unwrap();
+        }
+
+        // Seal the segment holding the points about to be flushed
+        let flushed_up_to = wal.rotate().await.unwrap().unwrap();
+
+        // Writes during the flush go to a new segment
+        let point = DataPoint::new(3000, 2.0, std::collections::HashMap::new());
+        wal.write(&series, &point).await.unwrap();
+        assert_eq!(wal.segments().unwrap().len(), 2);
+
+        // Once the flush is durable the sealed segment can go
+        assert_eq!(wal.checkpoint(flushed_up_to).await.unwrap(), 1);
+        let remaining = wal.segments().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].id > flushed_up_to);
+
+        let mut replayed = Vec::new();
+        wal.replay(|_, point| {
+            replayed.push(point.timestamp());
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(replayed, vec![3000]);
+
+        // The active segment survives even a checkpoint past its id
+        assert_eq!(wal.checkpoint(u64::MAX).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wal_sync_policies_and_batch_writes() {
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+        let points: Vec<DataPoint> = (1..=5)
+            .map(|i| DataPoint::new(i * 1000, i as f64, std::collections::HashMap::new()))
+            .collect();
+
+        // Always: one sync per write call, and a batch is a single call
+        let dir = tempdir().unwrap();
+        let wal = WriteAheadLog::new(dir.path()).unwrap();
+        assert_eq!(wal.sync_policy(), SyncPolicy::Always);
+        wal.write(&series, &points[0]).await.unwrap();
+        assert_eq!(wal.sync_count(), 1);
+        let batch: Vec<(&TimeSeries, &DataPoint)> = points[1..].iter().map(|p| (&series, p)).collect();
+        wal.write_batch(&batch).await.unwrap();
+        assert_eq!(wal.sync_count(), 2);
+        wal.write_batch(&[]).await.unwrap();
+        assert_eq!(wal.sync_count(), 2);
+
+        let mut replayed = Vec::new();
+        wal.replay(|_, point| {
+            replayed.push(point.timestamp());
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(replayed, vec![1000, 2000, 3000, 4000, 5000]);
+
+        // OnBatch: syncs once enough entries have accumulated
+        let dir = tempdir().unwrap();
+        let wal = WriteAheadLog::new(dir.path()).unwrap().with_sync_policy(SyncPolicy::OnBatch(3));
+        wal.write(&series, &points[0]).await.unwrap();
+        wal.write(&series, &points[1]).await.unwrap();
+        assert_eq!(wal.sync_count(), 0);
+        wal.write(&series, &points[2]).await.unwrap();
+        assert_eq!(wal.sync_count(), 1);
+
+        // Periodic: nothing until the interval passes, but sync() forces it
+        let dir = tempdir().unwrap();
+        let wal = WriteAheadLog::new(dir.path())
+            .unwrap()
+            .with_sync_policy(SyncPolicy::Periodic(std::time::Duration::from_secs(3600)));
+        wal.write(&series, &points[0]).await.unwrap();
+        assert_eq!(wal.sync_count(), 0);
+        wal.sync().await.unwrap();
+        assert_eq!(wal.sync_count(), 1);
+        wal.sync().await.unwrap();
+        assert_eq!(wal.sync_count(), 1, "nothing pending");
+
+        // Unsynced entries in a sealed segment are synced before the next one is written
+        wal.write(&series, &points[1]).await.unwrap();
+        wal.rotate().await.unwrap();
+        wal.write(&series, &points[2]).await.unwrap();
+        assert_eq!(wal.sync_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wal_parallel_verify_identifies_corrupt_segment() {
+        let dir = tempdir().unwrap();
+        let wal = WriteAheadLog::new(dir.path()).unwrap().with_verify_parallelism(3);
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+
+        // Five segments of three entries each
+        let mut paths = Vec::new();
+        for segment in 0..5 {
+            for i in 0..3 {
+                let point = DataPoint::new(segment * 100 + i, 1.0, std::collections::HashMap::new());
+                wal.write(&series, &point).await.unwrap();
+            }
+            paths.push(wal.current_segment.read().await.as_ref().unwrap().path.clone());
+            wal.rotate().await.unwrap();
+        }
+
+        let report = wal.verify_report().unwrap();
+        assert!(report.is_valid());
+        assert!(wal.verify().unwrap());
+        assert!(report.segments.iter().all(|segment| segment.entries == 3));
+
+        // Damage the last entry of the third segment
+        let mut file = OpenOptions::new().write(true).open(&paths[2]).unwrap();
+        file.seek(SeekFrom::End(-10)).unwrap();
+        file.write_all(b"corrupted").unwrap();
+
+        let report = wal.verify_report().unwrap();
+        assert!(!wal.verify().unwrap());
+        let reported: Vec<&PathBuf> = report.segments.iter().map(|segment| &segment.path).collect();
+        assert_eq!(reported, paths.iter().collect::<Vec<_>>());
+        let corrupt: Vec<&SegmentReport> = report.corrupt_segments().collect();
+        assert_eq!(corrupt.len(), 1);
+        assert_eq!(corrupt[0].path, paths[2]);
+        assert_eq!(corrupt[0].entries, 2);
+
+        // The report is the same however many workers run
+        let sequential = WriteAheadLog::new(dir.path()).unwrap().with_verify_parallelism(1);
+        assert_eq!(sequential.verify_report().unwrap(), report);
+    }
+
+    #[tokio::test]
+    async fn test_wal_replay_skips_truncated_final_entry() {
+        let dir = tempdir().unwrap();
+        let wal = WriteAheadLog::new(dir.path()).unwrap();
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+
+        for i in 0..2 {
+            wal.write(&series, &DataPoint::new(i, 1.0, std::collections::HashMap::new())).await.unwrap();
+        }
+        let path = wal.current_segment.read().await.as_ref().unwrap().path.clone();
+        wal.rotate().await.unwrap();
+        wal.write(&series, &DataPoint::new(2, 1.0, std::collections::HashMap::new())).await.unwrap();
+
+        // A crash left the first segment's last entry without the end of its CRC
+        let mut partial = Vec::new();
+        wal.encode_entry("test_series", &DataPoint::new(99, 1.0, std::collections::HashMap::new()), &mut partial)
+            .unwrap();
+        partial.truncate(partial.len() - 3);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&partial).unwrap();
+        drop(file);
+
+        let mut timestamps = Vec::new();
+        wal.replay(|_, point| {
+            timestamps.push(point.timestamp());
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(timestamps, vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_wal_replay_order_is_stable_within_one_instant() {
+        let dir = tempdir().unwrap();
+        let mut wal = WriteAheadLog::new(dir.path()).unwrap();
+        // Every segment gets the same creation time
+        wal.frozen_clock = Some(Duration::from_secs(1_700_000_000));
+        let series = TimeSeries::new("test_series".to_string()).unwrap();
+
+        for i in 0..20 {
+            wal.write(&series, &DataPoint::new(i, i as f64, std::collections::HashMap::new())).await.unwrap();
+            wal.rotate().await.unwrap();
+        }
+
+        let segments = wal.segments().unwrap();
+        assert_eq!(segments.len(), 20);
+        assert!(segments.iter().all(|segment| segment.id == segments[0].id));
+        let sequences: Vec<u64> = segments.iter().map(|segment| segment.sequence).collect();
+        assert_eq!(sequences, (0..20).collect::<Vec<_>>());
+
+        let mut timestamps = Vec::new();
+        wal.replay(|_, point| {
+            timestamps.push(point.timestamp());
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(timestamps, (0..20).collect::<Vec<_>>());
+
+        // Verification reports the segments in the same order
+        let report = wal.verify_report().unwrap();
+        let reported: Vec<&PathBuf> = report.segments.iter().map(|segment| &segment.path).collect();
+        assert_eq!(reported, segments.iter().map(|segment| &segment.path).collect::<Vec<_>>());
+
+        // A reopened WAL continues the sequence
+        let reopened = WriteAheadLog::new(dir.path()).unwrap();
+        assert_eq!(reopened.next_sequence.load(Ordering::SeqCst), 20);
+    }
 }